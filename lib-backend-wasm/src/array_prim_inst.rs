@@ -0,0 +1,508 @@
+/*
+This module contains WebAssembly instruction sequences for the three array primitives:
+- ArrayNew, ArrayGet, ArraySet
+
+ArrayNew will allocate new memory for the returned array.
+ArraySet will allocate new memory for the storage if the index is past the capacity of the array.
+ArrayGet does not allocate any memory.
+
+See the note on Array in lib.rs for the memory layout.
+*/
+
+use super::gc::HeapManager;
+use super::mutcontext::MutContext;
+use super::var_conv::*;
+use ir::VarType;
+use wasmgen::ExprBuilder;
+use wasmgen::FuncIdx;
+use wasmgen::LocalIdx;
+use wasmgen::MemArg;
+use wasmgen::MemIdx;
+
+// Largest number of elements that an array may hold, so that the size of the storage (in bytes) never overflows an i32.
+const MAX_ARRAY_CAPACITY: i32 = 1 << 27;
+
+// Calls the error function with the given error code.  Array primitives do not know their source location,
+// so the frontend checks the index itself before calling them (see post_parse_member_expr()).
+// net wasm stack: [] -> []
+fn encode_error(code: u32, error_func: FuncIdx, expr_builder: &mut ExprBuilder) {
+    expr_builder.i32_const(code as i32);
+    expr_builder.i32_const(0);
+    expr_builder.i32_const(0);
+    expr_builder.i32_const(0);
+    expr_builder.i32_const(0);
+    expr_builder.i32_const(0);
+    expr_builder.i32_const(0);
+    expr_builder.call(error_func);
+    expr_builder.unreachable();
+}
+
+// Converts an index to an i32, raising an error if it is not a non-negative integer.
+// Indices that are too large to fit in an i32 are clamped to i32::MAX (they are past the end of every array anyway).
+// net wasm stack: [f64(idx)] -> [i32(idx)]
+fn encode_index_conv(error_func: FuncIdx, mutctx: &mut MutContext, expr_builder: &mut ExprBuilder) {
+    /*
+    if (trunc(idx) != idx || idx < 0) error(); // also catches NaN
+    return i32(min(idx, I32_MAX));
+    */
+    mutctx.with_scratch_f64(|_mutctx, idx| {
+        // net wasm stack: [f64(idx)] -> [cond(i32)]
+        expr_builder.local_tee(idx);
+        expr_builder.f64_trunc();
+        expr_builder.local_get(idx);
+        expr_builder.f64_ne();
+        expr_builder.local_get(idx);
+        expr_builder.f64_const(0.0);
+        expr_builder.f64_lt();
+        expr_builder.i32_or();
+
+        // net wasm stack: [cond(i32)] -> []
+        expr_builder.if_(&[]);
+        encode_error(
+            ir::error::ERROR_CODE_ARRAY_INDEX_NOT_NONNEGATIVE_INTEGER,
+            error_func,
+            expr_builder,
+        );
+        expr_builder.end();
+
+        // net wasm stack: [] -> [i32(idx)]
+        expr_builder.local_get(idx);
+        expr_builder.f64_const(i32::MAX as f64);
+        expr_builder.f64_min();
+        expr_builder.i32_trunc_f64_u();
+    });
+}
+
+// Raises an out of memory error if `capacity` is larger than MAX_ARRAY_CAPACITY.
+// net wasm stack: [] -> []
+fn encode_check_capacity(capacity: LocalIdx, error_func: FuncIdx, expr_builder: &mut ExprBuilder) {
+    expr_builder.local_get(capacity);
+    expr_builder.i32_const(MAX_ARRAY_CAPACITY);
+    expr_builder.i32_gt_u();
+    expr_builder.if_(&[]);
+    encode_error(
+        ir::error::ERROR_CODE_OUT_OF_MEMORY,
+        error_func,
+        expr_builder,
+    );
+    expr_builder.end();
+}
+
+// net wasm stack: [f64(len)] -> [i32(array)]
+pub fn encode_array_new<H: HeapManager>(
+    heap: &H,
+    error_func: FuncIdx,
+    mutctx: &mut MutContext,
+    expr_builder: &mut ExprBuilder,
+) {
+    /*
+    let len = to_index(len);
+    if (len > MAX_ARRAY_CAPACITY) error(OUT_OF_MEMORY);
+    let array = new_array(len); // all elements will be undefined, but length is zero
+    array->length = len;
+    return array;
+    */
+
+    // net wasm stack: [f64(len)] -> [i32(len)]
+    encode_index_conv(error_func, mutctx, expr_builder);
+
+    mutctx.with_scratch_i32(|mutctx, len| {
+        mutctx.with_scratch_i32(|mutctx, array| {
+            // net wasm stack: [i32(len)] -> []
+            expr_builder.local_set(len);
+            encode_check_capacity(len, error_func, expr_builder);
+
+            // net wasm stack: [] -> [i32(array)]
+            expr_builder.local_get(len);
+            mutctx.heap_encode_dynamic_allocation(heap, VarType::Array, expr_builder);
+            expr_builder.local_tee(array);
+
+            // array->length = len;
+            // net wasm stack: [i32(array)] -> [i32(array)]
+            expr_builder.local_get(len);
+            expr_builder.i32_store(MemArg::new4(0));
+            expr_builder.local_get(array);
+        });
+    });
+}
+
+// net wasm stack: [i32(array), f64(idx)] -> [<Any>]
+pub fn encode_array_get(
+    error_func: FuncIdx,
+    mutctx: &mut MutContext,
+    expr_builder: &mut ExprBuilder,
+) {
+    /*
+    let idx = to_index(idx);
+    if (idx < array->length) {
+        return *(array->storage + 4 + idx * sizeof(Any));
+    } else {
+        return undefined;
+    }
+    */
+
+    // net wasm stack: [i32(array), f64(idx)] -> [i32(array), i32(idx)]
+    encode_index_conv(error_func, mutctx, expr_builder);
+
+    mutctx.with_scratch_i32(|mutctx, array| {
+        mutctx.with_scratch_i32(|mutctx, idx| {
+            mutctx.with_scratch_i32(|mutctx, tag| {
+                mutctx.with_scratch_i64(|_mutctx, data| {
+                    // net wasm stack: [i32(array), i32(idx)] -> []
+                    expr_builder.local_set(idx);
+                    expr_builder.local_set(array);
+
+                    // net wasm stack: [] -> []
+                    expr_builder.i32_const(VarType::Undefined.tag());
+                    expr_builder.local_set(tag);
+
+                    // if (idx < array->length)
+                    // net wasm stack: [] -> []
+                    expr_builder.local_get(idx);
+                    expr_builder.local_get(array);
+                    expr_builder.i32_load(MemArg::new4(0));
+                    expr_builder.i32_lt_u();
+                    expr_builder.if_(&[]);
+                    {
+                        // let entry = array->storage + 4 + idx * sizeof(Any);
+                        // net wasm stack: [] -> [i32(entry)]
+                        expr_builder.local_get(array);
+                        expr_builder.i32_load(MemArg::new4(4));
+                        expr_builder.local_get(idx);
+                        expr_builder.i32_const(size_in_memory(VarType::Any) as i32);
+                        expr_builder.i32_mul();
+                        expr_builder.i32_add();
+                        expr_builder.local_tee(array); // reuse the local, since we don't need the array any more
+
+                        // net wasm stack: [i32(entry)] -> []
+                        expr_builder.i64_load(MemArg::new4(4 + 4));
+                        expr_builder.local_set(data);
+                        expr_builder.local_get(array);
+                        expr_builder.i32_load(MemArg::new4(4));
+                        expr_builder.local_set(tag);
+                    }
+                    expr_builder.end();
+
+                    // net wasm stack: [] -> [<Any>]
+                    expr_builder.local_get(data);
+                    expr_builder.local_get(tag);
+                });
+            });
+        });
+    });
+}
+
+// net wasm stack: [i32(array), f64(idx), <Any>] -> []
+pub fn encode_array_set<H: HeapManager>(
+    memidx: MemIdx,
+    heap: &H,
+    error_func: FuncIdx,
+    use_wasm_bulk_memory_feature: bool,
+    mutctx: &mut MutContext,
+    expr_builder: &mut ExprBuilder,
+) {
+    /*
+    let idx = to_index(idx);
+    if (idx >= *(array->storage)) { // past the capacity
+        if (idx >= MAX_ARRAY_CAPACITY) error(OUT_OF_MEMORY);
+        let new_capacity = min(max(idx + 1, *(array->storage) * 2), MAX_ARRAY_CAPACITY);
+        let new_storage = new_array(new_capacity)->storage; // the rest of the new array is garbage
+        memcpy(new_storage + 4, array->storage + 4, array->length * sizeof(Any));
+        array->storage = new_storage;
+    }
+    if (idx >= array->length) {
+        array->length = idx + 1;
+    }
+    *(array->storage + 4 + idx * sizeof(Any)) = val;
+    */
+
+    // The array and the value are stored in shadow locals,
+    // because they need to be updated by the GC if the allocation causes a collection.
+    mutctx.with_uninitialized_shadow_locals(
+        &[VarType::Array, VarType::Any],
+        |mutctx, ir_localidx| {
+            mutctx.with_scratch_i32(|mutctx, idx| {
+                mutctx.with_scratch_i32(|mutctx, new_capacity| {
+                    // net wasm stack: [i32(array), f64(idx), <Any>] -> [i32(array), i32(idx)]
+                    encode_store_local(
+                        mutctx.wasm_local_slice(ir_localidx + 1),
+                        VarType::Any,
+                        VarType::Any,
+                        expr_builder,
+                    );
+                    encode_index_conv(error_func, mutctx, expr_builder);
+
+                    // net wasm stack: [i32(array), i32(idx)] -> []
+                    expr_builder.local_set(idx);
+                    encode_store_local(
+                        mutctx.wasm_local_slice(ir_localidx),
+                        VarType::Array,
+                        VarType::Array,
+                        expr_builder,
+                    );
+                    let array: LocalIdx = mutctx.wasm_local_slice(ir_localidx)[0];
+
+                    // if (idx >= *(array->storage))
+                    // net wasm stack: [] -> []
+                    expr_builder.local_get(idx);
+                    expr_builder.local_get(array);
+                    expr_builder.i32_load(MemArg::new4(4));
+                    expr_builder.i32_load(MemArg::new4(0));
+                    expr_builder.i32_ge_u();
+                    expr_builder.if_(&[]);
+                    {
+                        // if (idx >= MAX_ARRAY_CAPACITY) error(OUT_OF_MEMORY);
+                        // net wasm stack: [] -> []
+                        expr_builder.local_get(idx);
+                        expr_builder.i32_const(MAX_ARRAY_CAPACITY);
+                        expr_builder.i32_ge_u();
+                        expr_builder.if_(&[]);
+                        encode_error(
+                            ir::error::ERROR_CODE_OUT_OF_MEMORY,
+                            error_func,
+                            expr_builder,
+                        );
+                        expr_builder.end();
+
+                        // let new_capacity = min(max(idx + 1, *(array->storage) * 2), MAX_ARRAY_CAPACITY);
+                        // net wasm stack: [] -> []
+                        mutctx.with_scratch_i32(|_mutctx, doubled| {
+                            expr_builder.local_get(idx);
+                            expr_builder.i32_const(1);
+                            expr_builder.i32_add();
+                            expr_builder.local_tee(new_capacity);
+                            expr_builder.local_get(array);
+                            expr_builder.i32_load(MemArg::new4(4));
+                            expr_builder.i32_load(MemArg::new4(0));
+                            expr_builder.i32_const(1);
+                            expr_builder.i32_shl();
+                            expr_builder.local_tee(doubled);
+                            expr_builder.local_get(new_capacity);
+                            expr_builder.local_get(doubled);
+                            expr_builder.i32_gt_u();
+                            expr_builder.select();
+                            expr_builder.local_tee(new_capacity);
+                            expr_builder.i32_const(MAX_ARRAY_CAPACITY);
+                            expr_builder.local_get(new_capacity);
+                            expr_builder.i32_const(MAX_ARRAY_CAPACITY);
+                            expr_builder.i32_lt_u();
+                            expr_builder.select();
+                            expr_builder.local_set(new_capacity);
+                        });
+
+                        // let new_storage = new_array(new_capacity)->storage;
+                        // net wasm stack: [] -> [i32(new_storage)]
+                        expr_builder.local_get(new_capacity);
+                        mutctx.heap_encode_dynamic_allocation(heap, VarType::Array, expr_builder);
+                        expr_builder.i32_load(MemArg::new4(4));
+
+                        mutctx.with_scratch_i32(|mutctx, new_storage| {
+                            // net wasm stack: [i32(new_storage)] -> []
+                            expr_builder.local_set(new_storage);
+
+                            // memcpy(new_storage + 4, array->storage + 4, array->length * sizeof(Any));
+                            // net wasm stack: [] -> []
+                            if use_wasm_bulk_memory_feature {
+                                expr_builder.local_get(new_storage);
+                                expr_builder.i32_const(4);
+                                expr_builder.i32_add();
+                                expr_builder.local_get(array);
+                                expr_builder.i32_load(MemArg::new4(4));
+                                expr_builder.i32_const(4);
+                                expr_builder.i32_add();
+                                expr_builder.local_get(array);
+                                expr_builder.i32_load(MemArg::new4(0));
+                                expr_builder.i32_const(size_in_memory(VarType::Any) as i32);
+                                expr_builder.i32_mul();
+                                expr_builder.memory_copy(memidx, memidx);
+                            } else {
+                                // we actually do (the entries are multiples of 4 bytes, so we move 4 bytes at a time):
+                                /*
+                                let it = new_storage + 4;
+                                let src = array->storage + 4;
+                                let it_end = it + array->length * sizeof(Any);
+                                while (it != it_end) {
+                                    *it = *src;
+                                    it += 4;
+                                    src += 4;
+                                }
+                                */
+                                mutctx.with_scratch_i32(|mutctx, it| {
+                                    mutctx.with_scratch_i32(|mutctx, src| {
+                                        mutctx.with_scratch_i32(|_mutctx, it_end| {
+                                            // net wasm stack: [] -> []
+                                            expr_builder.local_get(new_storage);
+                                            expr_builder.i32_const(4);
+                                            expr_builder.i32_add();
+                                            expr_builder.local_tee(it);
+                                            expr_builder.local_get(array);
+                                            expr_builder.i32_load(MemArg::new4(0));
+                                            expr_builder
+                                                .i32_const(size_in_memory(VarType::Any) as i32);
+                                            expr_builder.i32_mul();
+                                            expr_builder.i32_add();
+                                            expr_builder.local_set(it_end);
+                                            expr_builder.local_get(array);
+                                            expr_builder.i32_load(MemArg::new4(4));
+                                            expr_builder.i32_const(4);
+                                            expr_builder.i32_add();
+                                            expr_builder.local_set(src);
+
+                                            // net wasm stack: [] -> []
+                                            expr_builder.block(&[]);
+                                            expr_builder.loop_(&[]);
+                                            {
+                                                expr_builder.local_get(it);
+                                                expr_builder.local_get(it_end);
+                                                expr_builder.i32_eq();
+                                                expr_builder.br_if(1);
+                                                expr_builder.local_get(it);
+                                                expr_builder.local_get(src);
+                                                expr_builder.i32_load(MemArg::new4(0));
+                                                expr_builder.i32_store(MemArg::new4(0));
+                                                expr_builder.local_get(it);
+                                                expr_builder.i32_const(4);
+                                                expr_builder.i32_add();
+                                                expr_builder.local_set(it);
+                                                expr_builder.local_get(src);
+                                                expr_builder.i32_const(4);
+                                                expr_builder.i32_add();
+                                                expr_builder.local_set(src);
+                                                expr_builder.br(0);
+                                            }
+                                            expr_builder.end();
+                                            expr_builder.end();
+                                        });
+                                    });
+                                });
+                            }
+
+                            // array->storage = new_storage;
+                            // net wasm stack: [] -> []
+                            expr_builder.local_get(array);
+                            expr_builder.local_get(new_storage);
+                            expr_builder.i32_store(MemArg::new4(4));
+                        });
+                    }
+                    expr_builder.end();
+
+                    // if (idx >= array->length) array->length = idx + 1;
+                    // net wasm stack: [] -> []
+                    expr_builder.local_get(idx);
+                    expr_builder.local_get(array);
+                    expr_builder.i32_load(MemArg::new4(0));
+                    expr_builder.i32_ge_u();
+                    expr_builder.if_(&[]);
+                    {
+                        expr_builder.local_get(array);
+                        expr_builder.local_get(idx);
+                        expr_builder.i32_const(1);
+                        expr_builder.i32_add();
+                        expr_builder.i32_store(MemArg::new4(0));
+                    }
+                    expr_builder.end();
+
+                    // *(array->storage + 4 + idx * sizeof(Any)) = val;
+                    // net wasm stack: [] -> []
+                    expr_builder.local_get(array);
+                    expr_builder.i32_load(MemArg::new4(4));
+                    expr_builder.local_get(idx);
+                    expr_builder.i32_const(size_in_memory(VarType::Any) as i32);
+                    expr_builder.i32_mul();
+                    expr_builder.i32_add();
+                    encode_load_local(
+                        mutctx.wasm_local_slice(ir_localidx + 1),
+                        VarType::Any,
+                        VarType::Any,
+                        expr_builder,
+                    );
+                    encode_store_memory(
+                        4,
+                        VarType::Any,
+                        VarType::Any,
+                        mutctx.scratch_mut(),
+                        expr_builder,
+                    );
+                });
+            });
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    #[test]
+    fn arrays_grow_and_survive_gc() {
+        // const a = []; for (let i = 0; i < 1000; i = i + 1) { a[i] = i; }
+        // a[5000] = 7; const b = []; b[0] = 42; a[1001] = b;
+        // for (let i = 0; i < 20; i = i + 1) { const garbage = []; garbage[29999] = 0; }
+        // let sum = 0; for (let i = 0; i < 1000; i = i + 1) { sum = sum + a[i]; }
+        // sum + a[5000] + a[1001][0] + (a[3000] === undefined ? 1000000 : 0) + (a[6000] === undefined ? 2000000 : 0);
+        // (the GC moves a, its storage and b while allocating the garbage)
+        let get_number = |index: &str| {
+            format!(
+                "(typecast:number number narrow (prim:any array_get (var:array local 0) {}) (var:number local 3) (trap:void 17))",
+                index
+            )
+        };
+        let is_undefined = |index: f64, value: f64| {
+            format!(
+                "(typecast:number undefined narrow (prim:any array_get (var:array local 0) (number:number {:?}))
+                  (number:number {:?})
+                  (number:number 0.0))",
+                index, value
+            )
+        };
+        let text = format!(
+            r#"
+            func () -> number {{
+              (let:void array = (prim:array array_new (number:number 0.0))
+                (let:void number = (number:number 0.0)
+                  (let:void number = (number:number 0.0)
+                    (seq:void
+                      {}
+                      (prim:undefined array_set (var:array local 0) (number:number 5000.0) (number:number 7.0))
+                      (let:undefined array = (prim:array array_new (number:number 0.0))
+                        (seq:undefined
+                          (prim:undefined array_set (var:array local 3) (number:number 0.0) (number:number 42.0))
+                          (prim:undefined array_set (var:array local 0) (number:number 1001.0) (var:array local 3))))
+                      {}
+                      {}
+                      (return:void (prim:number number_add
+                        (prim:number number_add
+                          (prim:number number_add
+                            (prim:number number_add (var:number local 2) {})
+                            (typecast:number array narrow (prim:any array_get (var:array local 0) (number:number 1001.0))
+                              (typecast:number number narrow (prim:any array_get (var:array local 3) (number:number 0.0))
+                                (var:number local 4)
+                                (trap:void 17))
+                              (trap:void 27)))
+                          {})
+                        {}))))))
+            }}
+            entry 0
+            "#,
+            counted_loop(
+                1,
+                1000,
+                "(prim:undefined array_set (var:array local 0) (var:number local 1) (var:number local 1))"
+            ),
+            allocate_garbage(1, 3),
+            counted_loop(
+                1,
+                1000,
+                &format!(
+                    "(assign:undefined local 2 (prim:number number_add (var:number local 2) {}))",
+                    get_number("(var:number local 1)")
+                )
+            ),
+            get_number("(number:number 5000.0)"),
+            is_undefined(3000.0, 1000000.0),
+            is_undefined(6000.0, 2000000.0)
+        );
+        let program = parse_and_optimize(&text, ir::opt::OptLevel::O0);
+        assert_result_for_all_options(&program, (number(3499549.0), vec![]));
+    }
+}
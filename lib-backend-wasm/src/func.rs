@@ -6,10 +6,12 @@ use super::gc::HeapManager;
  */
 use wasmgen::Scratch;
 
+use crate::array_prim_inst;
 use crate::global_var::*;
 use crate::multi_value_polyfill;
 use crate::pre_traverse::ShiftedStringPool;
 use crate::string_prim_inst;
+use crate::trampoline::Trampoline;
 use crate::Options;
 
 use super::opt_var_conv::*;
@@ -19,11 +21,13 @@ use projstd::searchablevec::SearchableVec;
 
 use boolinator::*;
 
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 
-struct EncodeContext<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, Heap: HeapManager> {
+struct EncodeContext<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, 'j, Heap: HeapManager> {
     // Local to this function
     return_type: Option<ir::VarType>,
+    can_return_continuation: bool, // false for the entry point, because the host that calls it does not run the trampoline
 
     // Global for whole program
     struct_types: &'a [Box<[ir::VarType]>],
@@ -36,22 +40,24 @@ struct EncodeContext<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, Heap: HeapManager> {
 
     // Other things
     stackptr: wasmgen::GlobalIdx,
+    trampoline: Option<Trampoline>, // globals for the pending continuation (only if tail calls are encoded using trampolines)
     memidx: wasmgen::MemIdx,
     thunk_map: &'f HashMap<Box<[ir::OverloadEntry]>, u32>, // map from overloads to elemidx
     appl_data_encoder: &'g HashMap<ir::SourceLocation, u32>, // map from source location to the location in memory of the args
     heap: &'h Heap,
     string_pool: &'i ShiftedStringPool,
+    marker_encoder: &'j HashMap<ir::SourceLocation, u32>, // map from source location of a SourceMarker to its marker id (for source maps)
     error_func: wasmgen::FuncIdx, // imported function to call to error out (e.g. runtime type errors)
     options: Options,             // Compilation options (it implements Copy)
 }
 
 // Have to implement Copy and Clone manually, because #[derive(Copy, Clone)] doesn't work for generic types like Heap
-impl<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, Heap: HeapManager> Copy
-    for EncodeContext<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, Heap>
+impl<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, 'j, Heap: HeapManager> Copy
+    for EncodeContext<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, 'j, Heap>
 {
 }
-impl<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, Heap: HeapManager> Clone
-    for EncodeContext<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, Heap>
+impl<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, 'j, Heap: HeapManager> Clone
+    for EncodeContext<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, 'i, 'j, Heap>
 {
     fn clone(&self) -> Self {
         *self
@@ -67,7 +73,7 @@ pub struct ModuleEncodeWrapper<'a> {
     wasm_module: &'a mut wasmgen::WasmModule,
 }
 impl<'a> ModuleEncodeWrapper<'a> {
-    fn add_wasm_type(&mut self, wasm_functype: wasmgen::FuncType) -> wasmgen::TypeIdx {
+    pub fn add_wasm_type(&mut self, wasm_functype: wasmgen::FuncType) -> wasmgen::TypeIdx {
         self.wasm_module.insert_type_into(wasm_functype)
     }
     fn add_ir_type_with_closure(
@@ -96,8 +102,17 @@ pub struct Signature {
     pub result: Option<ir::VarType>,
 }
 
+// How a function call should be encoded
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum CallKind {
+    Normal,     // call the function and wait for it to return
+    ReturnCall, // replace the current function with the callee (using the tail call proposal)
+    Trampoline, // return a continuation that calls the callee (see trampoline.rs)
+}
+
 pub fn encode_funcs<'a, Heap: HeapManager>(
     ir_signature_list: &[Signature], // direct mapping from ir::FuncIdx: includes both imports and funcs
+    ir_func_names: &[String], // direct mapping from ir::FuncIdx: names for the wasm name section
     ir_funcs: &[ir::Func],
     ir_struct_types: &[Box<[ir::VarType]>],
     ir_struct_field_byte_offsets: &[Box<[u32]>],
@@ -105,9 +120,11 @@ pub fn encode_funcs<'a, Heap: HeapManager>(
     ir_entry_point_funcidx: ir::FuncIdx,
     global_var_manager: GlobalVarManagerRef<'a>,
     globalidx_stackptr: wasmgen::GlobalIdx,
+    trampoline: Option<Trampoline>,
     memidx: wasmgen::MemIdx,
     thunk_sv: SearchableVec<Box<[ir::OverloadEntry]>>,
     appl_data_encoder: HashMap<ir::SourceLocation, u32>,
+    marker_encoder: HashMap<ir::SourceLocation, u32>,
     heap: &Heap,
     string_pool: &ShiftedStringPool,
     error_func: wasmgen::FuncIdx,
@@ -134,7 +151,8 @@ pub fn encode_funcs<'a, Heap: HeapManager>(
     let (registry_list, code_builder_list): (Vec<WasmRegistry>, Vec<wasmgen::CodeBuilder>) =
         ir_funcs
             .iter()
-            .map(|ir_func| {
+            .enumerate()
+            .map(|(i, ir_func)| {
                 let (wasm_param_valtypes, wasm_param_map, param_map) =
                     encode_param_list(&ir_func.params);
                 let wasm_functype = wasmgen::FuncType::new(
@@ -142,6 +160,28 @@ pub fn encode_funcs<'a, Heap: HeapManager>(
                     encode_result(ir_func.result, options.wasm_multi_value),
                 );
                 let (_, wasm_funcidx) = wasm_module.register_func(&wasm_functype);
+                wasm_module.set_func_name(
+                    wasm_funcidx,
+                    ir_func_names[imported_funcs.len() + i].clone(),
+                );
+                for (paramidx, ir_param) in ir_func.params.iter().enumerate() {
+                    let name: String = ir_func
+                        .param_names
+                        .get(paramidx)
+                        .cloned()
+                        .unwrap_or_else(|| format!("param{}", paramidx));
+                    for (j, wasm_name) in encode_local_names(&name, *ir_param)
+                        .into_vec()
+                        .into_iter()
+                        .enumerate()
+                    {
+                        wasm_module.set_local_name(
+                            wasm_funcidx,
+                            wasm_param_map[param_map[paramidx] + j],
+                            wasm_name,
+                        );
+                    }
+                }
                 let code_builder = wasmgen::CodeBuilder::new(wasm_functype);
                 (
                     WasmRegistry {
@@ -155,6 +195,8 @@ pub fn encode_funcs<'a, Heap: HeapManager>(
             })
             .unzip();
 
+    // the ir funcs come after the imports in the ir::FuncIdx space
+    let num_imports: usize = imported_funcs.len();
     let wasm_funcidxs: Box<[wasmgen::FuncIdx]> = imported_funcs
         .into_iter()
         .copied()
@@ -179,23 +221,44 @@ pub fn encode_funcs<'a, Heap: HeapManager>(
                 encode_result(Some(ir::VarType::Any), options.wasm_multi_value),
             );
             let (_, wasm_funcidx) = wasm_module.register_func(&wasm_functype);
+            wasm_module.set_func_name(
+                wasm_funcidx,
+                format!(
+                    "thunk[{}]",
+                    overload_entries
+                        .iter()
+                        .map(|oe| ir_func_names[oe.funcidx].as_str())
+                        .collect::<Vec<&str>>()
+                        .join(", ")
+                ),
+            );
+            for (idx, name) in ["closure", "num_params", "callerid"].iter().enumerate() {
+                wasm_module.set_local_name(
+                    wasm_funcidx,
+                    wasmgen::LocalIdx { idx: idx as u32 },
+                    name.to_string(),
+                );
+            }
             let mut code_builder = wasmgen::CodeBuilder::new(wasm_functype);
             {
                 let (locals_builder, expr_builder) = code_builder.split();
                 let scratch: Scratch = Scratch::new(locals_builder);
                 let ctx = EncodeContext {
                     return_type: Some(ir::VarType::Any),
+                    can_return_continuation: true,
                     struct_types: ir_struct_types,
                     struct_field_byte_offsets: ir_struct_field_byte_offsets,
                     ir_signature_list: ir_signature_list,
                     wasm_funcidxs: &wasm_funcidxs,
                     globals: global_var_manager,
                     stackptr: globalidx_stackptr,
+                    trampoline: trampoline,
                     memidx: memidx,
                     heap: heap,
                     thunk_map: &new_thunk_map,
                     appl_data_encoder: &appl_data_encoder,
                     string_pool: string_pool,
+                    marker_encoder: &marker_encoder,
                     error_func: error_func,
                     options: options,
                 };
@@ -227,22 +290,26 @@ pub fn encode_funcs<'a, Heap: HeapManager>(
         .enumerate()
         .for_each(|(ir_funcidx, (ir_func, mut code_builder))| {
             let registry: &WasmRegistry = &registry_list[ir_funcidx];
+            let mut local_names: BTreeMap<u32, Vec<String>> = BTreeMap::new();
             {
                 let (locals_builder, expr_builder) = code_builder.split();
                 let scratch: Scratch = Scratch::new(locals_builder);
                 let ctx = EncodeContext {
                     return_type: ir_func.result,
+                    can_return_continuation: num_imports + ir_funcidx != ir_entry_point_funcidx,
                     struct_types: ir_struct_types,
                     struct_field_byte_offsets: ir_struct_field_byte_offsets,
                     ir_signature_list: ir_signature_list,
                     wasm_funcidxs: &wasm_funcidxs,
                     globals: global_var_manager,
                     stackptr: globalidx_stackptr,
+                    trampoline: trampoline,
                     memidx: memidx,
                     heap: heap,
                     thunk_map: &new_thunk_map,
                     appl_data_encoder: &appl_data_encoder,
                     string_pool: string_pool,
+                    marker_encoder: &marker_encoder,
                     error_func: error_func,
                     options: options,
                 };
@@ -255,6 +322,14 @@ pub fn encode_funcs<'a, Heap: HeapManager>(
                 );
                 let wasm_reachable = encode_expr(&ir_func.expr, ctx, &mut mutctx, expr_builder);
 
+                // a wasm local that was reused by several named ir locals gets all their names
+                for (wasm_localidx, name) in mutctx.take_local_names() {
+                    let names = local_names.entry(wasm_localidx.idx).or_default();
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+
                 if let Some(vartype) = ir_func.expr.vartype {
                     assert!(wasm_reachable);
                     encode_return_calling_conv(
@@ -273,6 +348,14 @@ pub fn encode_funcs<'a, Heap: HeapManager>(
                 // append the end instruction to end of the function
                 expr_builder.end();
             }
+            // name the wasm locals that held named ir locals
+            for (idx, names) in local_names {
+                wasm_module.set_local_name(
+                    registry.funcidx,
+                    wasmgen::LocalIdx { idx: idx },
+                    names.join("/"),
+                );
+            }
             // commit the function:
             wasm_module.commit_func(registry.funcidx, code_builder);
         });
@@ -283,11 +366,172 @@ pub fn encode_funcs<'a, Heap: HeapManager>(
     wasm_module.export_func(wasm_funcidxs[ir_entry_point_funcidx], "main".to_string());
 }
 
+/**
+ * Encodes a function that calls an import that takes a Func or Any, and returns its funcidx.
+ * It has the same params and result as the import's translated signature, so it is called in place of the import.
+ * The Func and Any args are pushed onto the gc_roots stack for the duration of the import call,
+ * so that they stay alive (and the host can still call them through their handles) even if the host calls back into the module.
+ * The import receives a handle instead of each Func, and a handle after the data and tag of each Any (see the note on host imports in lib.rs).
+ */
+pub fn encode_import_wrapper<H: HeapManager>(
+    import_funcidx: wasmgen::FuncIdx,
+    name: &str,
+    params: &[ir::VarType],
+    result: Option<ir::VarType>,
+    heap: &H,
+    options: Options,
+    wasm_module: &mut wasmgen::WasmModule,
+) -> wasmgen::FuncIdx {
+    let (wasm_param_valtypes, wasm_param_map, param_map) = encode_param_list(params);
+    let wasm_functype = wasmgen::FuncType::new(
+        wasm_param_valtypes,
+        encode_result(result, options.wasm_multi_value),
+    );
+    let (_, wasm_funcidx) = wasm_module.register_func(&wasm_functype);
+    wasm_module.set_func_name(wasm_funcidx, format!("handles[{}]", name));
+    let mut code_builder = wasmgen::CodeBuilder::new(wasm_functype);
+    {
+        let (locals_builder, expr_builder) = code_builder.split();
+        let mut scratch: Scratch = Scratch::new(locals_builder);
+        let mut num_handles: u32 = 0;
+
+        // net wasm stack: [] -> [<import params>]
+        for (i, param) in params.iter().copied().enumerate() {
+            let wasm_local_slice: &[wasmgen::LocalIdx] =
+                &wasm_param_map[param_map[i]..param_map[i] + encode_vartype(param).len()];
+            match param {
+                ir::VarType::Func => {
+                    // net wasm stack: [] -> [i32(handle)]
+                    heap.encode_handle_push((param, wasm_local_slice), &mut scratch, expr_builder);
+                    num_handles += 1;
+                }
+                ir::VarType::Any => {
+                    // net wasm stack: [] -> [i64(data), i32(tag), i32(handle)]
+                    encode_load_local(wasm_local_slice, param, param, expr_builder);
+                    heap.encode_handle_push((param, wasm_local_slice), &mut scratch, expr_builder);
+                    num_handles += 1;
+                }
+                _ => {
+                    // net wasm stack: [] -> [<param>]
+                    encode_load_local(wasm_local_slice, param, param, expr_builder);
+                }
+            }
+        }
+
+        // the result (if any) stays on the stack (or on the unprotected stack) to be returned
+        // net wasm stack: [<import params>] -> [return_calling_conv(result)]
+        expr_builder.call(import_funcidx);
+
+        // net wasm stack: [] -> []
+        heap.encode_handles_pop(num_handles, expr_builder);
+
+        expr_builder.end();
+    }
+    wasm_module.commit_func(wasm_funcidx, code_builder);
+    wasm_funcidx
+}
+
+/**
+ * Encodes a function that lets the host call a Func that it was given as an argument of an import,
+ * and exports it as "call_function".
+ * Its params are the handle of the Func (see encode_import_wrapper()) followed by i32(num_args).
+ * The args are passed as Anys using the uniform calling convention,
+ * i.e. the host writes the ith arg (counting from 0) to [stackptr - 12 * (i + 1), stackptr - 12 * i) before the call.
+ * The result is an Any, which is returned in the same way as from a function that uses the uniform calling convention.
+ * `callerid` is the location reported if the Func cannot be called with the given number of args.
+ * If the handle refers to an Any that is not a Func, it raises a not callable error.
+ */
+pub fn encode_call_function_export<H: HeapManager>(
+    callerid: u32,
+    heap: &H,
+    error_func: wasmgen::FuncIdx,
+    trampoline: Option<Trampoline>,
+    options: Options,
+    wasm_module: &mut wasmgen::WasmModule,
+) {
+    let wasm_functype = wasmgen::FuncType::new(
+        // handle, num_args
+        Box::new([wasmgen::ValType::I32, wasmgen::ValType::I32]),
+        encode_result(Some(ir::VarType::Any), options.wasm_multi_value),
+    );
+    let (_, wasm_funcidx) = wasm_module.register_func(&wasm_functype);
+    wasm_module.set_func_name(wasm_funcidx, "call_function".to_string());
+    for (idx, name) in ["handle", "num_args"].iter().enumerate() {
+        wasm_module.set_local_name(
+            wasm_funcidx,
+            wasmgen::LocalIdx { idx: idx as u32 },
+            name.to_string(),
+        );
+    }
+    let mut code_builder = wasmgen::CodeBuilder::new(wasm_functype);
+    {
+        let (locals_builder, expr_builder) = code_builder.split();
+        let scratch: Scratch = Scratch::new(locals_builder);
+        let mut mutctx = MutContext::new(
+            scratch,
+            &[],
+            &[],
+            &[],
+            ModuleEncodeWrapper {
+                wasm_module: wasm_module,
+            },
+        );
+        let handle = wasmgen::LocalIdx { idx: 0 };
+        let num_args = wasmgen::LocalIdx { idx: 1 };
+
+        // read the Func (which might have been moved by the GC since it was given to the host)
+        // net wasm stack: [] -> [i32(closure), i32(tableidx)]
+        expr_builder.local_get(handle);
+        heap.encode_handle_read(mutctx.scratch_mut(), expr_builder);
+        encode_narrowing_operation(
+            ir::VarType::Func,
+            ir::VarType::Any,
+            |expr_builder| {
+                // the host does not have a source location
+                expr_builder
+                    .i32_const(ir::error::ERROR_CODE_FUNCTION_APPLICATION_NOT_CALLABLE_TYPE as i32);
+                expr_builder.i32_const(0);
+                expr_builder.i32_const(0);
+                expr_builder.i32_const(0);
+                expr_builder.i32_const(0);
+                expr_builder.i32_const(0);
+                expr_builder.i32_const(0);
+                expr_builder.call(error_func);
+                expr_builder.unreachable();
+            },
+            mutctx.scratch_mut(),
+            expr_builder,
+        );
+
+        mutctx.with_scratch_i32(|mutctx, tableidx| {
+            // net wasm stack: [i32(closure), i32(tableidx)] -> [i32(closure), i32(num_args), i32(callerid), i32(tableidx)]
+            expr_builder.local_set(tableidx);
+            expr_builder.local_get(num_args);
+            expr_builder.i32_const(callerid as i32);
+            expr_builder.local_get(tableidx);
+
+            // call the function (indirectly, using uniform calling convention)
+            // net wasm stack: [i32(closure), i32(num_args), i32(callerid), i32(tableidx)] -> [<Any>]
+            let typeidx = add_uniform_wasm_type(options.wasm_multi_value, mutctx);
+            expr_builder.call_indirect(typeidx, wasmgen::TableIdx { idx: 0 });
+
+            // run the trampoline, because the callee might have returned a continuation
+            if let Some(trampoline) = trampoline {
+                trampoline.encode_loop(typeidx, options.wasm_multi_value, mutctx, expr_builder);
+            }
+        });
+
+        expr_builder.end();
+    }
+    wasm_module.commit_func(wasm_funcidx, code_builder);
+    wasm_module.export_func(wasm_funcidx, "call_function".to_string());
+}
+
 // returns (wasm_param_valtypes, wasm_param_map, param_map)
 // where param_map[i] is an index into wasm_param_map; it is the wasm param index of the beginning of the ith ir param
 // e.g. if param_map[i] == 5 and this ir param actually converts to two wasm params, then the wasm params are at wasm_param_map[5] and wasm_param_map[6].
 // (they may not actually be placed contiguously in the real wasm param indices, due to the coalescing allocations provided by scratch)
-fn encode_param_list(
+pub fn encode_param_list(
     ir_params: &[ir::VarType],
 ) -> (
     Box<[wasmgen::ValType]>,
@@ -317,13 +561,14 @@ fn encode_param_list(
     )
 }
 
-fn encode_result(
+pub fn encode_result(
     ir_results: Option<ir::VarType>,
     use_wasm_multi_value_feature: bool,
 ) -> Box<[wasmgen::ValType]> {
+    // note: encode_vartype() lists the top of the stack first, but wasm result types list the bottom of the stack first
     let ret: Box<[wasmgen::ValType]> = ir_results
         .into_iter()
-        .flat_map(|ir_result| encode_vartype(ir_result).iter())
+        .flat_map(|ir_result| encode_vartype(ir_result).iter().rev())
         .copied()
         .collect();
     if ret.len() <= 1 || use_wasm_multi_value_feature {
@@ -355,8 +600,8 @@ fn encode_drop_value(ir_vartype: ir::VarType, expr_builder: &mut wasmgen::ExprBu
             expr_builder.drop();
         }
         ir::VarType::Number | ir::VarType::Boolean | ir::VarType::String => expr_builder.drop(),
-        ir::VarType::StructT { typeidx: _ } => expr_builder.drop(),
-        ir::VarType::Undefined => {}
+        ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => expr_builder.drop(),
+        ir::VarType::Undefined | ir::VarType::Null => {}
         ir::VarType::Unassigned => panic!("Unassigned variable must not exist on the stack"),
     }
 }
@@ -540,6 +785,15 @@ fn encode_expr<H: HeapManager>(
             // Don't do anything, because undefined is encoded as <nothing>
             true
         }
+        ir::ExprKind::PrimNull => {
+            // encodes the 'null' value
+            assert!(
+                expr.vartype == Some(ir::VarType::Null),
+                "ICE: IR->Wasm: PrimNull does not have type null"
+            );
+            // Don't do anything, because null is encoded as <nothing>
+            true
+        }
         ir::ExprKind::PrimNumber { val } => {
             // encodes a literal number
             assert!(
@@ -770,6 +1024,7 @@ fn encode_expr<H: HeapManager>(
                 func,
                 args,
                 location,
+                CallKind::Normal,
                 ctx,
                 mutctx,
                 expr_builder,
@@ -778,7 +1033,15 @@ fn encode_expr<H: HeapManager>(
         }
         ir::ExprKind::DirectAppl { funcidx, args } => {
             // encodes a function call
-            encode_direct_appl(expr.vartype, *funcidx, args, ctx, mutctx, expr_builder);
+            encode_direct_appl(
+                expr.vartype,
+                *funcidx,
+                args,
+                CallKind::Normal,
+                ctx,
+                mutctx,
+                expr_builder,
+            );
             true
         }
         ir::ExprKind::Conditional {
@@ -894,24 +1157,12 @@ fn encode_expr<H: HeapManager>(
             // - the return type is at least as wide as the inner expr type
             match inner_expr.vartype {
                 None => panic!("ICE: IR->Wasm: expression in return statement cannot be Void"),
-                Some(inner_type) => {
+                Some(_) => {
                     match ctx.return_type {
                         None => panic!("ICE: IR->Wasm: cannot have return expression in a function that returns Void"),
                         Some(ret_type) => {
-                            // net wasm stack: [] -> [<expr.vartype>]
-                            encode_expr(inner_expr, ctx, mutctx, expr_builder);
-
-                            // net wasm stack: [<expr.vartype>] -> [<return_calling_conv(ctx.return_type.unwrap())>]
-                            encode_return_calling_conv(
-                                ret_type,
-                                inner_type,
-                                ctx.options.wasm_multi_value,
-                                ctx.stackptr,
-                                mutctx.scratch_mut(),
-                                expr_builder,
-                            );
-                            // return the value on the stack (or in the unprotected stack) (which now has the correct type)
-                            expr_builder.return_();
+                            // net wasm stack: [] -> [stack-polymorphic]
+                            encode_return(inner_expr, ret_type, ctx, mutctx, expr_builder);
                         }
                     };
                 }
@@ -963,14 +1214,62 @@ fn encode_expr<H: HeapManager>(
                 mutctx,
                 expr_builder,
                 |mutctx, landing_ctx, expr_builder| {
-                    mutctx.with_landing(expr.vartype.unwrap(), landing_ctx, |mutctx| {
-                        encode_expr(inner_expr, ctx, mutctx, expr_builder);
+                    // a void Block never completes, so no Break can land here and the landing type does not matter
+                    let landing_vartype = expr.vartype.unwrap_or(ir::VarType::Undefined);
+                    mutctx.with_landing(landing_vartype, landing_ctx, |mutctx| {
+                        let wasm_reachable = encode_expr(inner_expr, ctx, mutctx, expr_builder);
+                        // the inner expr might be narrower than the block (e.g. if some Breaks carry a wider type)
+                        // net wasm stack: [<inner_expr.vartype>] -> [<expr.vartype>]
+                        encode_opt_result_widening_operation(
+                            expr.vartype,
+                            inner_expr.vartype,
+                            wasm_reachable,
+                            mutctx.scratch_mut(),
+                            expr_builder,
+                        );
                     })
                 },
             );
 
-            // returns true, because WebAssembly never regards a block as stack-polymorphic even if it is actually the case
-            true
+            if expr.vartype.is_none() {
+                // the block can only be exited by a Break to an outer landing (or a Return or Trap)
+                expr_builder.unreachable();
+                false
+            } else {
+                // returns true, because WebAssembly never regards a block as stack-polymorphic even if it is actually the case
+                true
+            }
+        }
+        ir::ExprKind::Loop { expr: inner_expr } => {
+            assert!(
+                inner_expr.vartype == expr.vartype,
+                "ICE: IR->Wasm: loop must have the type of its inner expression"
+            );
+            // register that a Break can land here (breaks to a loop always carry undefined, which is encoded as <nothing>)
+            multi_value_polyfill::loop_(
+                encode_opt_vartype(expr.vartype),
+                ctx.options.wasm_multi_value,
+                mutctx,
+                expr_builder,
+                |mutctx, expr_builder| {
+                    mutctx.with_landing(ir::VarType::Undefined, &[], |mutctx| {
+                        if encode_expr(inner_expr, ctx, mutctx, expr_builder)
+                            && inner_expr.vartype.is_none()
+                        {
+                            expr_builder.unreachable();
+                        }
+                    })
+                },
+            );
+
+            if expr.vartype.is_none() {
+                // the loop can only be exited by a Break to an outer landing (or a Return)
+                expr_builder.unreachable();
+                false
+            } else {
+                // returns true, because WebAssembly never regards a loop as stack-polymorphic
+                true
+            }
         }
         ir::ExprKind::Sequence { content } => {
             if content.is_empty() {
@@ -1014,6 +1313,124 @@ fn encode_expr<H: HeapManager>(
             // and call a noreturn function to the embedder (JavaScript).
             false
         }
+        ir::ExprKind::SourceMarker { location } => {
+            // Doesn't emit any instructions, only records the current code offset for the source map
+            expr_builder.mark(ctx.marker_encoder[location]);
+            true
+        }
+    }
+}
+
+// Encodes returning the value of `expr` from a function that returns `ret_type`.
+// If tail calls are enabled, function calls in tail position (including those in the arms of a conditional) replace the current function instead
+// (either directly, or by returning a continuation to the trampoline).
+// net wasm stack: [] -> [stack-polymorphic]
+fn encode_return<H: HeapManager>(
+    expr: &ir::Expr,
+    ret_type: ir::VarType,
+    ctx: EncodeContext<H>,
+    mutctx: &mut MutContext,
+    expr_builder: &mut wasmgen::ExprBuilder,
+) {
+    let call_kind: CallKind = tail_call_kind(ret_type, ctx);
+    if call_kind != CallKind::Normal && is_tail_callable(expr, ret_type, call_kind) {
+        match &expr.kind {
+            ir::ExprKind::Appl {
+                func,
+                args,
+                location,
+            } => {
+                // the callee returns exactly what we would have returned, so we can replace our own frame with the callee
+                encode_appl(
+                    expr.vartype,
+                    func,
+                    args,
+                    location,
+                    call_kind,
+                    ctx,
+                    mutctx,
+                    expr_builder,
+                );
+            }
+            ir::ExprKind::DirectAppl { funcidx, args } => {
+                // the callee returns exactly what we would have returned, so we can replace our own frame with the callee
+                encode_direct_appl(
+                    expr.vartype,
+                    *funcidx,
+                    args,
+                    call_kind,
+                    ctx,
+                    mutctx,
+                    expr_builder,
+                );
+            }
+            ir::ExprKind::Conditional {
+                cond,
+                true_expr,
+                false_expr,
+            } => {
+                // both arms are in tail position, so we return from inside each arm instead of after the conditional
+                // net wasm stack: [] -> [<cond.vartype>(bool)]
+                encode_expr(cond, ctx, mutctx, expr_builder);
+                // net wasm stack: [i32 result] -> []
+                mutctx.with_unused_landing(|mutctx| {
+                    multi_value_polyfill::if_(
+                        &[],
+                        ctx.options.wasm_multi_value,
+                        mutctx,
+                        expr_builder,
+                        |mutctx, expr_builder| {
+                            encode_return(true_expr, ret_type, ctx, mutctx, expr_builder);
+                        },
+                        |mutctx, expr_builder| {
+                            encode_return(false_expr, ret_type, ctx, mutctx, expr_builder);
+                        },
+                    );
+                });
+                // both arms have returned, but WebAssembly does not know that
+                expr_builder.unreachable();
+            }
+            ir::ExprKind::Declaration {
+                local,
+                init: Some(init_expr),
+                contained_expr,
+            } => {
+                // the contained expr is in tail position (this is how the optimiser passes args to direct calls)
+                // net wasm stack: [] -> [<init_expr.vartype>]
+                encode_expr(init_expr, ctx, mutctx, expr_builder);
+                mutctx.with_uninitialized_named_local(*local, |mutctx, named_localidx| {
+                    // net wasm stack: [<init_expr.vartype>] -> []
+                    encode_store_local(
+                        mutctx.named_wasm_local_slice(named_localidx),
+                        *local,
+                        init_expr.vartype.unwrap(),
+                        expr_builder,
+                    );
+                    encode_return(contained_expr, ret_type, ctx, mutctx, expr_builder);
+                });
+            }
+            _ => unreachable!(),
+        }
+    } else if let Some(expr_type) = expr.vartype {
+        // net wasm stack: [] -> [<expr_type>]
+        encode_expr(expr, ctx, mutctx, expr_builder);
+
+        // net wasm stack: [<expr_type>] -> [<return_calling_conv(ret_type)>]
+        encode_return_calling_conv(
+            ret_type,
+            expr_type,
+            ctx.options.wasm_multi_value,
+            ctx.stackptr,
+            mutctx.scratch_mut(),
+            expr_builder,
+        );
+        // return the value on the stack (or in the unprotected stack) (which now has the correct type)
+        expr_builder.return_();
+    } else {
+        // a Void expression (e.g. a trap) never produces a value to return
+        if encode_expr(expr, ctx, mutctx, expr_builder) {
+            expr_builder.unreachable();
+        }
     }
 }
 
@@ -1229,6 +1646,22 @@ fn encode_prim_inst<H: HeapManager>(
         ir::PrimInst::StringLe => {
             string_prim_inst::encode_string_le(mutctx.scratch_mut(), expr_builder);
         }
+        ir::PrimInst::ArrayNew => {
+            array_prim_inst::encode_array_new(ctx.heap, ctx.error_func, mutctx, expr_builder);
+        }
+        ir::PrimInst::ArrayGet => {
+            array_prim_inst::encode_array_get(ctx.error_func, mutctx, expr_builder);
+        }
+        ir::PrimInst::ArraySet => {
+            array_prim_inst::encode_array_set(
+                ctx.memidx,
+                ctx.heap,
+                ctx.error_func,
+                ctx.options.wasm_bulk_memory,
+                mutctx,
+                expr_builder,
+            );
+        }
     }
 }
 
@@ -1236,12 +1669,14 @@ fn encode_prim_inst<H: HeapManager>(
 // and the func_expr has type VarType::Func or VarType::Any
 // and the callee must have all params of type Any, and return type must also be Any.
 // (to use more specific types, we must know the target function at compilation time, and hence use the DirectAppl)
-// net wasm stack: [] -> [<return_type>]
+// If `call_kind` is not Normal, this call must be in tail position (see is_tail_callable()).
+// net wasm stack: [] -> [<return_type>] (or [] -> [stack-polymorphic] if `call_kind` is not Normal)
 fn encode_appl<H: HeapManager>(
     return_type: Option<ir::VarType>,
     func_expr: &ir::Expr,
     args: &[ir::Expr],
     location: &ir::SourceLocation,
+    call_kind: CallKind,
     ctx: EncodeContext<H>,
     mutctx: &mut MutContext,
     expr_builder: &mut wasmgen::ExprBuilder,
@@ -1275,14 +1710,12 @@ fn encode_appl<H: HeapManager>(
         // Encode all the arguments
         // This will ensure that all args are evaluated before placing them onto the unprotected stack
         // and be careful that if the GC is triggered while evaluating an arg, we will be using the new value of the pointer-type args.
+        // net wasm stack: [] -> []
+        encode_args_to_call_indirect_function(args, ctx, mutctx, expr_builder);
+
         // net wasm stack: [] -> [i32(closure), i32(num_args)]
-        encode_args_to_call_indirect_function(
-            args,
-            mutctx.wasm_local_slice(localidx_func)[1],
-            ctx,
-            mutctx,
-            expr_builder,
-        );
+        expr_builder.local_get(mutctx.wasm_local_slice(localidx_func)[1]);
+        expr_builder.i32_const(args.len() as i32);
 
         // encode the proper caller id (which is the memory location of the SourceLocation)
         // net wasm stack: [] -> [i32(callerid)]
@@ -1292,6 +1725,25 @@ fn encode_appl<H: HeapManager>(
         // net wasm stack: [] -> [tableidx]
         expr_builder.local_get(mutctx.wasm_local_slice(localidx_func)[0]);
 
+        match call_kind {
+            CallKind::Normal => {}
+            CallKind::ReturnCall => {
+                // None of our locals are used after the call, so they don't need to go onto the gc_roots stack.
+
+                // replace the current function with the callee (indirectly, using uniform calling convention)
+                let typeidx = add_uniform_wasm_type(ctx.options.wasm_multi_value, mutctx);
+                expr_builder.return_call_indirect(typeidx, wasmgen::TableIdx { idx: 0 });
+                return;
+            }
+            CallKind::Trampoline => {
+                // the args are already on the unprotected stack, so the continuation is ready
+                ctx.trampoline
+                    .unwrap()
+                    .encode_return_continuation(ctx.options.wasm_multi_value, expr_builder);
+                return;
+            }
+        }
+
         // todo!(For optimisation, heap_encode_prologue_epilogue should only be called if the callee might allocate)
         // Note: encode_args_to_call_function should be *before* encode_local_roots_prologue, since the args themselves might make function calls.
         if true {
@@ -1300,37 +1752,31 @@ fn encode_appl<H: HeapManager>(
             // call the function with gc prologue and epilogue
             mutctx.heap_encode_prologue_epilogue(ctx.heap, expr_builder, |mutctx, expr_builder| {
                 // call the function (indirectly, using uniform calling convention)
-                expr_builder.call_indirect(
-                    mutctx
-                        .module_wrapper()
-                        .add_wasm_type(wasmgen::FuncType::new(
-                            Box::new([
-                                wasmgen::ValType::I32,
-                                wasmgen::ValType::I32,
-                                wasmgen::ValType::I32,
-                            ]),
-                            encode_result(Some(ir::VarType::Any), ctx.options.wasm_multi_value),
-                        )),
-                    wasmgen::TableIdx { idx: 0 },
-                );
+                let typeidx = add_uniform_wasm_type(ctx.options.wasm_multi_value, mutctx);
+                expr_builder.call_indirect(typeidx, wasmgen::TableIdx { idx: 0 });
+
+                // run the trampoline, because the callee might have returned a continuation
+                // (this must be done before the gc epilogue, because the continuation might allocate memory)
+                if let Some(trampoline) = ctx.trampoline {
+                    trampoline.encode_loop(
+                        typeidx,
+                        ctx.options.wasm_multi_value,
+                        mutctx,
+                        expr_builder,
+                    );
+                }
             });
         } else {
             // This function is guaranteed not to allocate memory, so we don't need to put the locals on the gc_roots stack.
 
             // call the function (indirectly)
-            expr_builder.call_indirect(
-                mutctx
-                    .module_wrapper()
-                    .add_wasm_type(wasmgen::FuncType::new(
-                        Box::new([
-                            wasmgen::ValType::I32,
-                            wasmgen::ValType::I32,
-                            wasmgen::ValType::I32,
-                        ]),
-                        encode_result(Some(ir::VarType::Any), ctx.options.wasm_multi_value),
-                    )),
-                wasmgen::TableIdx { idx: 0 },
-            );
+            let typeidx = add_uniform_wasm_type(ctx.options.wasm_multi_value, mutctx);
+            expr_builder.call_indirect(typeidx, wasmgen::TableIdx { idx: 0 });
+
+            // run the trampoline, because the callee might have returned a continuation
+            if let Some(trampoline) = ctx.trampoline {
+                trampoline.encode_loop(typeidx, ctx.options.wasm_multi_value, mutctx, expr_builder);
+            }
         }
 
         // fetch return values from the location prescribed by the calling convention back to the stack
@@ -1347,11 +1793,13 @@ fn encode_appl<H: HeapManager>(
 // Requires: the callee actually has the correct number of parameters,
 // and the each parameter of the callee must have a type at least as wide as (i.e. be a supertype of) the corresponding args[i].vartype,
 // and the return type of the callee is exactly return_type.
-// net wasm stack: [] -> [<return_type>]
+// If `call_kind` is not Normal, this call must be in tail position (see is_tail_callable()).
+// net wasm stack: [] -> [<return_type>] (or [] -> [stack-polymorphic] if `call_kind` is not Normal)
 fn encode_direct_appl<H: HeapManager>(
     return_type: Option<ir::VarType>,
     funcidx: ir::FuncIdx,
     args: &[ir::Expr],
+    call_kind: CallKind,
     ctx: EncodeContext<H>,
     mutctx: &mut MutContext,
     expr_builder: &mut wasmgen::ExprBuilder,
@@ -1364,24 +1812,66 @@ fn encode_direct_appl<H: HeapManager>(
     // Assert that the function has correct return type
     assert!(return_type == signature.result);
 
+    if call_kind == CallKind::Trampoline {
+        // The continuation uses the uniform calling convention, so we call the callee through a thunk
+        // (pre_traverse has made a thunk for every DirectAppl in tail position).
+        let tableidx: u32 = *ctx
+            .thunk_map
+            .get(&[ir::OverloadEntry {
+                funcidx: funcidx,
+                has_closure_param: false,
+            }] as &[ir::OverloadEntry])
+            .expect("ICE: IR->Wasm: missing thunk for DirectAppl in tail position");
+
+        // net wasm stack: [] -> []
+        encode_args_to_call_indirect_function(args, ctx, mutctx, expr_builder);
+
+        // net wasm stack: [] -> [i32(closure), i32(num_args), i32(callerid), i32(tableidx)]
+        // (the closure is ignored by the thunk, and the callerid is only used for type errors, which cannot happen because the args already have the correct types)
+        expr_builder.i32_const(0);
+        expr_builder.i32_const(args.len() as i32);
+        expr_builder.i32_const(0);
+        expr_builder.i32_const(tableidx as i32);
+
+        ctx.trampoline
+            .unwrap()
+            .encode_return_continuation(ctx.options.wasm_multi_value, expr_builder);
+        return;
+    }
+
     // Encode all the arguments
     encode_args_to_call_function(&signature.params, args, ctx, mutctx, expr_builder);
 
+    if call_kind == CallKind::ReturnCall {
+        // None of our locals are used after the call, so they don't need to go onto the gc_roots stack.
+
+        // replace the current function with the callee
+        expr_builder.return_call(ctx.wasm_funcidxs[funcidx]);
+        return;
+    }
+
     // todo!(For optimisation, heap_encode_prologue_epilogue should only be called if the callee might allocate)
     // Note: encode_args_to_call_function should be *before* encode_local_roots_prologue, since the args themselves might make function calls.
     if true {
         // This function might allocate memory, so we need to store the locals in the gc_roots stack first.
 
         // call the function with gc prologue and epilogue
-        mutctx.heap_encode_prologue_epilogue(ctx.heap, expr_builder, |_mutctx, expr_builder| {
+        mutctx.heap_encode_prologue_epilogue(ctx.heap, expr_builder, |mutctx, expr_builder| {
             // call the function
             expr_builder.call(ctx.wasm_funcidxs[funcidx]);
+
+            // run the trampoline, because the callee might have returned a continuation
+            // (this must be done before the gc epilogue, because the continuation might allocate memory)
+            encode_opt_trampoline_loop(return_type, ctx, mutctx, expr_builder);
         });
     } else {
         // This function is guaranteed not to allocate memory, so we don't need to put the locals on the gc_roots stack.
 
         // call the function
         expr_builder.call(ctx.wasm_funcidxs[funcidx]);
+
+        // run the trampoline, because the callee might have returned a continuation
+        encode_opt_trampoline_loop(return_type, ctx, mutctx, expr_builder);
     }
 
     // fetch return values from the location prescribed by the calling convention back to the stack
@@ -1394,6 +1884,82 @@ fn encode_direct_appl<H: HeapManager>(
     );
 }
 
+// Runs the trampoline after a direct call, if trampolines are used and the callee might return a continuation (i.e. it returns Any).
+// net wasm stack: [return_calling_conv(return_type)] -> [return_calling_conv(return_type)]
+fn encode_opt_trampoline_loop<H: HeapManager>(
+    return_type: Option<ir::VarType>,
+    ctx: EncodeContext<H>,
+    mutctx: &mut MutContext,
+    expr_builder: &mut wasmgen::ExprBuilder,
+) {
+    if let Some(trampoline) = ctx.trampoline {
+        if return_type == Some(ir::VarType::Any) {
+            let typeidx = add_uniform_wasm_type(ctx.options.wasm_multi_value, mutctx);
+            trampoline.encode_loop(typeidx, ctx.options.wasm_multi_value, mutctx, expr_builder);
+        }
+    }
+}
+
+// Returns the way to encode calls in tail position of a function that returns `return_type`.
+fn tail_call_kind<H: HeapManager>(return_type: ir::VarType, ctx: EncodeContext<H>) -> CallKind {
+    if ctx.options.wasm_tail_call {
+        CallKind::ReturnCall
+    } else if ctx.trampoline.is_some()
+        && ctx.can_return_continuation
+        && return_type == ir::VarType::Any
+    {
+        // continuations always return Any, so only functions that return Any can return a continuation
+        CallKind::Trampoline
+    } else {
+        CallKind::Normal
+    }
+}
+
+// Returns true if `expr` contains a function call in tail position that can be encoded as a tail call (using `call_kind`) from a function returning `return_type`.
+// For ReturnCall, the callee must return exactly `return_type`, because wasm requires the result types of the caller and callee to be identical.
+// For Trampoline, the callee is called with the uniform calling convention, so it can return anything.
+// Note: pre_traverse.rs must register a thunk for every DirectAppl that is tail callable with Trampoline.
+fn is_tail_callable(expr: &ir::Expr, return_type: ir::VarType, call_kind: CallKind) -> bool {
+    match &expr.kind {
+        ir::ExprKind::Appl { .. } | ir::ExprKind::DirectAppl { .. } => match call_kind {
+            CallKind::Normal => false,
+            CallKind::ReturnCall => expr.vartype == Some(return_type),
+            CallKind::Trampoline => expr.vartype.is_some(),
+        },
+        ir::ExprKind::Conditional {
+            true_expr,
+            false_expr,
+            ..
+        } => {
+            is_tail_callable(true_expr, return_type, call_kind)
+                || is_tail_callable(false_expr, return_type, call_kind)
+        }
+        ir::ExprKind::Declaration {
+            local: _,
+            init: Some(_),
+            contained_expr,
+        } => is_tail_callable(contained_expr, return_type, call_kind),
+        _ => false,
+    }
+}
+
+// Registers the wasm type of functions that use the uniform calling convention (i.e. thunks), and returns its index.
+fn add_uniform_wasm_type(
+    use_wasm_multi_value_feature: bool,
+    mutctx: &mut MutContext,
+) -> wasmgen::TypeIdx {
+    mutctx
+        .module_wrapper()
+        .add_wasm_type(wasmgen::FuncType::new(
+            Box::new([
+                wasmgen::ValType::I32,
+                wasmgen::ValType::I32,
+                wasmgen::ValType::I32,
+            ]),
+            encode_result(Some(ir::VarType::Any), use_wasm_multi_value_feature),
+        ))
+}
+
 // This function prepares subexpressions when calling a function.
 // It evaluates arguments in left-to-right order, which is required for Source.
 // Each `expected_param_types` must be at least as wide as each `[args[i].vartype, ...]`
@@ -1434,11 +2000,11 @@ fn encode_args_to_call_function<H: HeapManager>(
 // It is like encode_args_to_call_function(), but instead it calls a function indirectly,
 // and uses the uniform calling convention for it.
 // As such, all the parameters are implicitly encoded as Any.
-// The closure is an i32.  It should be written *after* evaluating all the may_allocate params.
-// net wasm stack: [] -> [i32(num_params)]
+// The caller should push the closure (an i32) and the number of args afterwards.
+// The closure should be read *after* evaluating all the may_allocate params.
+// net wasm stack: [] -> []
 fn encode_args_to_call_indirect_function<H: HeapManager>(
     args: &[ir::Expr],
-    closure_local: wasmgen::LocalIdx,
     ctx: EncodeContext<H>,
     mutctx: &mut MutContext,
     expr_builder: &mut wasmgen::ExprBuilder,
@@ -1543,12 +2109,6 @@ fn encode_args_to_call_indirect_function<H: HeapManager>(
             );
         }
     }
-
-    // net wasm stack: [] -> [i32(closure)]
-    expr_builder.local_get(closure_local);
-
-    // net wasm stack: [] -> [i32(num_params)]
-    expr_builder.i32_const(args.len() as i32);
 }
 
 // Encodes a small function that uses uniform calling convention and
@@ -2048,3 +2608,402 @@ fn encode_post_appl_calling_conv(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+    use wasmgen::interp::{Caller, Host, Instance, Trap, Value};
+    use wasmgen::ValType;
+
+    #[test]
+    fn encode_result_lists_bottom_of_stack_first() {
+        // an Any is pushed as [i64 data, i32 tag] (see encode_load_local), so that is the order of the wasm result types,
+        // which is also the order hosts receive them in (e.g. read_js_result in sourceror-driver)
+        assert!(*encode_vartype(ir::VarType::Any) == [ValType::I32, ValType::I64]);
+        assert!(*encode_result(Some(ir::VarType::Any), true) == [ValType::I64, ValType::I32]);
+        assert!(*encode_result(Some(ir::VarType::Func), true) == [ValType::I32, ValType::I32]);
+        assert!(*encode_result(Some(ir::VarType::Number), true) == [ValType::F64]);
+        // without multi-value, results with more than one value go through the unprotected stack instead
+        assert!(encode_result(Some(ir::VarType::Any), false).is_empty());
+        assert!(*encode_result(Some(ir::VarType::Number), false) == [ValType::F64]);
+        assert!(encode_result(None, true).is_empty());
+    }
+
+    #[test]
+    fn deep_tail_recursion_with_return_call() {
+        for text in &[DEEP_TAIL_RECURSION_APPL, DEEP_TAIL_RECURSION_DIRECT] {
+            let program = parse_and_optimize(text, ir::opt::OptLevel::default());
+            for flags in all_feature_flags().filter(|flags| flags & crate::FEATURE_TAIL_CALL != 0) {
+                assert_eq!(
+                    run_with_options(&program, Options::from_feature_flags(flags)),
+                    (number(100000.0), vec![]),
+                    "wrong result with feature flags {}",
+                    flags
+                );
+            }
+            // without tail calls, the recursion runs out of stack
+            assert!(run_with_options(&program, Options::default()).0.is_err());
+        }
+    }
+
+    #[test]
+    fn break_and_continue_with_per_iteration_closures() {
+        // const fs = []; let n = 0;
+        // for (let i = 0; i < 10; i = i + 1) {
+        //   if (i === 2) { continue; }
+        //   if (i === 5) { break; }
+        //   fs[n] = () => i; n = n + 1;
+        // }
+        // fs[0]() * 1000 + fs[1]() * 100 + fs[2]() * 10 + fs[3]();
+        // (like the frontend, each iteration copies i into a new struct before the update, so each closure sees its own i)
+        let call = |idx: usize| {
+            format!(
+                "(typecast:number func narrow (prim:any array_get (var:array local 0) (number:number {}.0))
+                  (typecast:number number narrow (appl:any (var:func local 3) []) (var:number local 4) (trap:void 17))
+                  (trap:void 22))",
+                idx
+            )
+        };
+        let text = format!(
+            r#"
+            struct (any)
+            struct (struct#0)
+            func "anonymous" (struct#1) -> any {{
+              (return:void (var:any local 0 .1.0 .0.0))
+            }}
+            func "main" () -> number {{
+              (let:void array = (prim:array array_new (number:number 0.0))
+                (let:void number = (number:number 0.0)
+                  (let:void struct#0 = (struct:struct#0 0)
+                    (seq:void
+                      (assign:undefined local 2 .0.0 (number:number 0.0))
+                      (block:undefined
+                        (loop:undefined
+                          (if:undefined
+                            (typecast:boolean number narrow (var:any local 2 .0.0)
+                              (prim:boolean number_lt (var:number local 3) (number:number 10.0))
+                              (trap:void 17))
+                            (seq:void
+                              (block:undefined
+                                (seq:undefined
+                                  (if:undefined
+                                    (typecast:boolean number narrow (var:any local 2 .0.0)
+                                      (prim:boolean number_eq (var:number local 3) (number:number 2.0))
+                                      (trap:void 17))
+                                    (break:void 0 (undefined:undefined))
+                                    (undefined:undefined))
+                                  (if:undefined
+                                    (typecast:boolean number narrow (var:any local 2 .0.0)
+                                      (prim:boolean number_eq (var:number local 3) (number:number 5.0))
+                                      (trap:void 17))
+                                    (break:void 2 (undefined:undefined))
+                                    (undefined:undefined))
+                                  (prim:undefined array_set (var:array local 0) (var:number local 1)
+                                    (let:func struct#1 = (struct:struct#1 1)
+                                      (seq:func
+                                        (assign:undefined local 3 .1.0 (var:struct#0 local 2))
+                                        (func:func [0 closure] (var:struct#1 local 3)))))
+                                  (assign:undefined local 1 (prim:number number_add (var:number local 1) (number:number 1.0)))))
+                              (let:undefined struct#0 = (struct:struct#0 0)
+                                (seq:undefined
+                                  (assign:undefined local 3 .0.0 (var:any local 2 .0.0))
+                                  (assign:undefined local 2 (var:struct#0 local 3))))
+                              (assign:undefined local 2 .0.0
+                                (typecast:number number narrow (var:any local 2 .0.0)
+                                  (prim:number number_add (var:number local 3) (number:number 1.0))
+                                  (trap:void 17)))
+                              (break:void 0 (undefined:undefined)))
+                            (undefined:undefined))))
+                      (return:void (prim:number number_add
+                        (prim:number number_add
+                          (prim:number number_add
+                            (prim:number number_mul {} (number:number 1000.0))
+                            (prim:number number_mul {} (number:number 100.0)))
+                          (prim:number number_mul {} (number:number 10.0)))
+                        {}))))))
+            }}
+            entry 1
+            "#,
+            call(0),
+            call(1),
+            call(2),
+            call(3)
+        );
+        assert_eq!(run_for_all_options(&text), (number(134.0), vec![]));
+        let program = parse_and_optimize(&text, ir::opt::OptLevel::O0);
+        assert_eq!(
+            run_with_options(&program, Options::default()),
+            (number(134.0), vec![])
+        );
+    }
+
+    #[test]
+    fn fallthrough_from_function_with_return() {
+        // function f(x) { if (x === 7) { return x * 2; } else {} } f(7);
+        // (after inlining, the Block has a wider type than the value that falls through it)
+        let f = r#"
+            func "f" (any) -> any {
+              (seq:undefined
+                (typecast:undefined number narrow (var:any local 0)
+                  (if:undefined (prim:boolean number_eq (var:number local 1) (number:number 7.0))
+                    (return:void (prim:number number_mul (var:number local 1) (number:number 2.0)))
+                    (undefined:undefined))
+                  (trap:void 17 @0:1:12-1:19))
+                (undefined:undefined))
+            }
+            "#;
+        assert_eq!(
+            run_for_all_options(&format!(
+                "{}func () -> any {{ (return:void (direct:any 0 (number:number 7.0))) }}\nentry 1",
+                f
+            )),
+            (number(14.0), vec![])
+        );
+        assert_eq!(
+            run_for_all_options(&format!(
+                "{}func () -> any {{ (return:void (direct:any 0 (number:number 8.0))) }}\nentry 1",
+                f
+            )),
+            (Ok((ir::VarType::Undefined.tag(), 0)), vec![])
+        );
+    }
+
+    #[test]
+    fn return_from_loop() {
+        // function f(n) { while (n > 0) { return n; } } f(3);
+        assert_eq!(
+            run_for_all_options(
+                r#"
+                func "f" (any) -> any {
+                  (seq:undefined
+                    (loop:undefined
+                      (typecast:undefined number narrow (var:any local 0)
+                        (if:undefined (prim:boolean number_gt (var:number local 1) (number:number 0.0))
+                          (return:void (var:any local 0))
+                          (undefined:undefined))
+                        (trap:void 17 @0:1:22-1:27)))
+                    (undefined:undefined))
+                }
+                func () -> any {
+                  (return:void (direct:any 0 (number:number 3.0)))
+                }
+                entry 1
+                "#
+            ),
+            (number(3.0), vec![])
+        );
+    }
+
+    #[test]
+    fn call_that_always_traps() {
+        // function f(x) { return x + 1; } f(true);
+        // (after specialising f for a boolean, the inlined body always traps)
+        let program = |args: &str| {
+            format!(
+                r#"
+                struct ()
+                global any
+                func "f" (struct#0, any) -> any {{
+                  (return:void (typecast:number number narrow (var:any local 1)
+                    (prim:number number_add (var:number local 2) (number:number 1.0))
+                    (trap:void 17 @0:1:24-1:29)))
+                }}
+                func () -> any {{
+                  (seq:any
+                    (assign:undefined global 0 (let:func struct#0 = (struct:struct#0 0)
+                      (func:func [0 closure] (var:struct#0 local 0))))
+                    {})
+                }}
+                entry 1
+                "#,
+                args
+            )
+        };
+        let call = |arg: &str| {
+            format!(
+                "(appl:any (typecast:func func narrow (var:any global 0) (var:func local 0) (trap:void 22 @0:1:32-1:33)) [{}] @0:1:32-1:39)",
+                arg
+            )
+        };
+        assert_eq!(
+            run_for_all_options(&program(&call("(boolean:boolean true)"))),
+            (Err("error 17".to_owned()), vec![])
+        );
+        // f(1); f("a");
+        assert_eq!(
+            run_for_all_options(&program(&format!(
+                "{} {}",
+                call("(number:number 1.0)"),
+                call("(string:string \"a\")")
+            ))),
+            (Err("error 17".to_owned()), vec![])
+        );
+    }
+
+    /**
+     * Records the args of the import, so that the test can check how they were passed,
+     * and calls back both the func and the any that it was given.
+     */
+    struct RecordingHost {
+        args: Vec<Value>,
+        results: Vec<Result<(u64, i32), String>>,
+    }
+
+    impl Host for RecordingHost {
+        fn call_import(
+            &mut self,
+            module_name: &str,
+            entity_name: &str,
+            args: &[Value],
+            caller: &mut Caller,
+        ) -> Result<Box<[Value]>, Trap> {
+            match (module_name, entity_name, args) {
+                ("host", "check", [.., Value::I32(any_handle), Value::I32(func_handle)]) => {
+                    self.args = args.to_vec();
+                    let (any_handle, func_handle) = (*any_handle, *func_handle);
+                    let result = call_back(caller, self, func_handle, &[]);
+                    self.results.push(result);
+                    let result = call_back(caller, self, any_handle, &[]);
+                    self.results.push(result);
+                    Ok(Box::new([Value::F64(1.0)]))
+                }
+                _ => unexpected_import(module_name, entity_name, args),
+            }
+        }
+    }
+
+    #[test]
+    fn host_imports_and_callbacks() {
+        let program = parse_and_optimize(
+            r#"
+            struct ()
+            import "host" "check" (boolean, any, func) -> number
+            func (struct#0) -> number {
+              (return:void (number:number 42.0))
+            }
+            func () -> number {
+              (return:void (direct:number 0 (boolean:boolean true) (number:number 2.5)
+                (func:func [1 closure] (struct:struct#0 0))))
+            }
+            entry 2
+            "#,
+            ir::opt::OptLevel::default(),
+        );
+        for flags in all_feature_flags() {
+            let module = encode_and_decode(&program, Options::from_feature_flags(flags));
+            let mut host = RecordingHost {
+                args: Vec::new(),
+                results: Vec::new(),
+            };
+            let mut instance = Instance::new(&module, &mut host).unwrap();
+            assert_eq!(
+                &*instance.invoke("main", &[], &mut host).unwrap(),
+                &[Value::F64(1.0)]
+            );
+
+            // boolean, then any (data before tag, then its handle), then the handle of the func
+            match host.args[..] {
+                [Value::I32(1), Value::I64(data), Value::I32(tag), Value::I32(_), Value::I32(_)] => {
+                    assert_eq!(tag, ir::VarType::Number.tag());
+                    assert_eq!(f64::from_bits(data as u64), 2.5);
+                }
+                _ => panic!(
+                    "wrong import args with feature flags {}: {:?}",
+                    flags, host.args
+                ),
+            }
+
+            // the func returns 42, but the any is not callable
+            assert_eq!(
+                host.results,
+                vec![
+                    Ok((42.0f64.to_bits(), ir::VarType::Number.tag())),
+                    Err(format!(
+                        "error {}",
+                        ir::error::ERROR_CODE_FUNCTION_APPLICATION_NOT_CALLABLE_TYPE
+                    ))
+                ],
+                "wrong callback results with feature flags {}",
+                flags
+            );
+        }
+    }
+
+    /**
+     * Calls the func that it was given several times (with the args 0, 1, 2, ...), and returns the sum of the results.
+     */
+    struct RepeatingHost {
+        num_calls: usize,
+        results: Vec<f64>,
+    }
+
+    impl Host for RepeatingHost {
+        fn call_import(
+            &mut self,
+            module_name: &str,
+            entity_name: &str,
+            args: &[Value],
+            caller: &mut Caller,
+        ) -> Result<Box<[Value]>, Trap> {
+            match (module_name, entity_name, args) {
+                ("host", "repeat", [Value::I32(handle)]) => {
+                    let handle = *handle;
+                    for i in 0..self.num_calls {
+                        let (data, tag) = call_back(caller, self, handle, &[i as f64])
+                            .map_err(|message| Trap { message: message })?;
+                        assert_eq!(tag, ir::VarType::Number.tag());
+                        self.results.push(f64::from_bits(data));
+                    }
+                    Ok(Box::new([Value::F64(self.results.iter().sum())]))
+                }
+                _ => unexpected_import(module_name, entity_name, args),
+            }
+        }
+    }
+
+    #[test]
+    fn host_callbacks_survive_gc() {
+        // The func allocates more than the usable heap over the callbacks,
+        // so the GC moves its closure between the calls.
+        let program = parse_and_optimize(
+            r#"
+            struct (number)
+            import "host" "repeat" (func) -> number
+            func (struct#0, number) -> number {
+              (let:void array = (prim:array array_new (number:number 30000.0))
+                (let:void array = (prim:array array_new (number:number 30000.0))
+                  (return:void (prim:number number_add (var:number local 0 .0.0) (var:number local 1)))))
+            }
+            func () -> number {
+              (let:void struct#0 = (struct:struct#0 0)
+                (seq:void
+                  (assign:undefined local 0 .0.0 (number:number 100.0))
+                  (return:void (prim:number number_add
+                    (direct:number 0 (func:func [1 closure] (var:struct#0 local 0)))
+                    (var:number local 0 .0.0)))))
+            }
+            entry 2
+            "#,
+            ir::opt::OptLevel::O0,
+        );
+        for flags in all_feature_flags() {
+            let module = encode_and_decode(&program, Options::from_feature_flags(flags));
+            let mut host = RepeatingHost {
+                num_calls: 8,
+                results: Vec::new(),
+            };
+            let mut instance = Instance::new(&module, &mut host).unwrap();
+            assert_eq!(
+                &*instance.invoke("main", &[], &mut host).unwrap(),
+                &[Value::F64(928.0)],
+                "wrong result with feature flags {}",
+                flags
+            );
+            assert_eq!(
+                host.results,
+                (0..8).map(|i| 100.0 + i as f64).collect::<Vec<f64>>()
+            );
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use wasmgen::Scratch;
 
 use super::WASM_PAGE_BITS;
+use crate::ARRAY_HEADER_SIZE;
 
 // returns the base table element index from which indirect access should be calculated (i.e. the "table offset")
 // e.g. if we want to access copy_children_$i, we should call_indirect with index = (table_offset+i)
@@ -22,6 +23,7 @@ pub fn make_copy_children_elements(
             Box::new([wasmgen::ValType::I32]),
         );
         let (_type_idx, func_idx) = wasm_module.register_func(&functype);
+        wasm_module.set_func_name(func_idx, "gc_copy_children_string".to_string());
         let mut code_builder = wasmgen::CodeBuilder::new(functype);
         {
             let (_locals_builder, expr_builder) = code_builder.split();
@@ -48,9 +50,160 @@ pub fn make_copy_children_elements(
         func_idx
     }
 
+    // make the array version of copy_children
+    // the only pointer in the header is the storage, which is always a valid heap pointer
+    fn make_array_function(
+        wasm_module: &mut wasmgen::WasmModule,
+        tableidx: wasmgen::TableIdx,
+        copy_funcs: &[Option<wasmgen::FuncIdx>],
+        heap_begin: u32,
+    ) -> wasmgen::FuncIdx {
+        let functype = wasmgen::FuncType::new(
+            Box::new([wasmgen::ValType::I32]),
+            Box::new([wasmgen::ValType::I32]),
+        );
+        let (_type_idx, func_idx) = wasm_module.register_func(&functype);
+        wasm_module.set_func_name(func_idx, "gc_copy_children_array".to_string());
+        let mut code_builder = wasmgen::CodeBuilder::new(functype);
+        {
+            let (locals_builder, expr_builder) = code_builder.split();
+            let localidx_param = wasmgen::LocalIdx { idx: 0 };
+            let mut scratch = Scratch::new(locals_builder);
+
+            // net wasm stack: [] -> []
+            gen(
+                expr_builder,
+                &mut scratch,
+                localidx_param,
+                4, // the storage ptr is at offset 4
+                tableidx,
+                copy_funcs[ir::ARRAY_STORAGE_TAG as usize].unwrap(),
+                heap_begin,
+                false,
+            );
+
+            // net wasm stack: [] -> [i32(ptr to past-the-end)]
+            expr_builder.local_get(localidx_param);
+            expr_builder.i32_const(ARRAY_HEADER_SIZE as i32);
+            expr_builder.i32_add();
+
+            expr_builder.end(); // return it
+        }
+        wasm_module.commit_func(func_idx, code_builder);
+        func_idx
+    }
+
+    // make the array storage version of copy_children
+    // it is like the struct version, but the number of Any entries is only known at runtime
+    fn make_array_storage_function(
+        wasm_module: &mut wasmgen::WasmModule,
+        tableidx: wasmgen::TableIdx,
+        copy_indirect_table_offset: u32,
+    ) -> wasmgen::FuncIdx {
+        let functype = wasmgen::FuncType::new(
+            Box::new([wasmgen::ValType::I32]),
+            Box::new([wasmgen::ValType::I32]),
+        );
+        let (_type_idx, func_idx) = wasm_module.register_func(&functype);
+        wasm_module.set_func_name(func_idx, "gc_copy_children_array_storage".to_string());
+        let mut code_builder = wasmgen::CodeBuilder::new(functype);
+        {
+            let (locals_builder, expr_builder) = code_builder.split();
+            let localidx_param = wasmgen::LocalIdx { idx: 0 };
+            let mut scratch = Scratch::new(locals_builder);
+
+            /*
+            // Algorithm:
+            let end = ptr + 4 + (*ptr) * sizeof(Any);
+            ptr += 4;
+            while (ptr != end) {
+                let f = &mut *ptr;
+                f.data = (*(GC_TABLE_PTR_COPY_INDIRECT_OFFSET + f.tag))(f.data);
+                ptr += 12;
+            }
+            return end;
+            */
+
+            let localidx_end = scratch.push_i32();
+
+            // let end = ptr + 4 + (*ptr) * sizeof(Any);
+            // net wasm stack: [] -> []
+            expr_builder.local_get(localidx_param);
+            expr_builder.local_get(localidx_param);
+            expr_builder.i32_load(wasmgen::MemArg::new4(0));
+            expr_builder.i32_const(12);
+            expr_builder.i32_mul();
+            expr_builder.i32_add();
+            expr_builder.i32_const(4);
+            expr_builder.i32_add();
+            expr_builder.local_set(localidx_end);
+
+            // ptr += 4;
+            // net wasm stack: [] -> []
+            expr_builder.local_get(localidx_param);
+            expr_builder.i32_const(4);
+            expr_builder.i32_add();
+            expr_builder.local_set(localidx_param);
+
+            // net wasm stack: [] -> []
+            expr_builder.block(&[]);
+            expr_builder.loop_(&[]);
+            {
+                // while (ptr != end)
+                // net wasm stack: [] -> []
+                expr_builder.local_get(localidx_param);
+                expr_builder.local_get(localidx_end);
+                expr_builder.i32_eq();
+                expr_builder.br_if(1);
+
+                // f.data = (*(GC_TABLE_PTR_COPY_INDIRECT_OFFSET + f.tag))(f.data);
+                // net wasm stack: [] -> []
+                expr_builder.local_get(localidx_param);
+                expr_builder.local_get(localidx_param);
+                expr_builder.i64_load(wasmgen::MemArg::new4(4)); // the `data` of the Any is at offset 4
+                expr_builder.local_get(localidx_param);
+                expr_builder.i32_load(wasmgen::MemArg::new4(0)); // the `tag` of the Any is at offset 0
+                if copy_indirect_table_offset != 0 {
+                    expr_builder.i32_const(copy_indirect_table_offset as i32);
+                    expr_builder.i32_add();
+                }
+                expr_builder.call_indirect(
+                    wasm_module.insert_type_into(wasmgen::FuncType::new(
+                        Box::new([wasmgen::ValType::I64]),
+                        Box::new([wasmgen::ValType::I64]),
+                    )),
+                    tableidx,
+                );
+                expr_builder.i64_store(wasmgen::MemArg::new4(4));
+
+                // ptr += 12;
+                // net wasm stack: [] -> []
+                expr_builder.local_get(localidx_param);
+                expr_builder.i32_const(12);
+                expr_builder.i32_add();
+                expr_builder.local_set(localidx_param);
+
+                expr_builder.br(0);
+            }
+            expr_builder.end();
+            expr_builder.end();
+
+            // return end;
+            // net wasm stack: [] -> [i32(ptr to past-the-end)]
+            expr_builder.local_get(localidx_end);
+
+            scratch.pop_i32();
+
+            expr_builder.end(); // return it
+        }
+        wasm_module.commit_func(func_idx, code_builder);
+        func_idx
+    }
+
     // make the struct version of copy_children
     fn make_struct_function(
         wasm_module: &mut wasmgen::WasmModule,
+        typeidx: usize,
         ir_vartypes: &[ir::VarType],
         byte_offsets: &[u32],
         struct_size: u32,
@@ -64,6 +217,7 @@ pub fn make_copy_children_elements(
             Box::new([wasmgen::ValType::I32]),
         );
         let (_type_idx, func_idx) = wasm_module.register_func(&functype);
+        wasm_module.set_func_name(func_idx, format!("gc_copy_children_struct#{}", typeidx));
         let mut code_builder = wasmgen::CodeBuilder::new(functype);
         {
             let (locals_builder, expr_builder) = code_builder.split();
@@ -100,7 +254,7 @@ pub fn make_copy_children_elements(
                             // the `data` of the Any is at offset 4
                         }
                         ir::VarType::Unassigned => {}
-                        ir::VarType::Undefined => {}
+                        ir::VarType::Undefined | ir::VarType::Null => {}
                         ir::VarType::Number => {}
                         ir::VarType::Boolean => {}
                         ir::VarType::String => {
@@ -188,7 +342,7 @@ pub fn make_copy_children_elements(
                             scratch.pop_i32();
                             scratch.pop_i32();
                         }
-                        ir::VarType::StructT { typeidx } => {
+                        ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                             // net wasm stack: [] -> []
                             gen(
                                 expr_builder,
@@ -196,8 +350,7 @@ pub fn make_copy_children_elements(
                                 localidx_param,
                                 byte_offset,
                                 tableidx,
-                                copy_funcs[ir::VarType::StructT { typeidx }.tag() as usize]
-                                    .unwrap(),
+                                copy_funcs[ir_vartype.tag() as usize].unwrap(),
                                 heap_begin,
                                 false,
                             );
@@ -205,81 +358,6 @@ pub fn make_copy_children_elements(
                     }
                 });
 
-            fn gen(
-                expr_builder: &mut wasmgen::ExprBuilder,
-                scratch: &mut Scratch,
-                localidx_param: wasmgen::LocalIdx,
-                byte_offset: u32,
-                tableidx: wasmgen::TableIdx,
-                copy_func: wasmgen::FuncIdx,
-                heap_begin: u32,
-                is_string: bool,
-            ) {
-                /*
-                if (ptr != -1 && (f is not String || ptr > heap_begin * WASM_PAGE_SIZE)) {
-                    if (*(ptr-4)) & I32_MIN { // already copied (we multiplex the MSB of the tag field, since there shouldn't be more than 2^31 types)
-                        f.ptr = (*(ptr-4)) << 1; // we store the ptr in the tag, but shifted right by one bit position (valid since ptr are all multiple of 4)
-                    } else {
-                        f.ptr = copy_${tag of f}(f.ptr);
-                    }
-                }
-                */
-                let localidx_ptr = scratch.push_i32(); // from_any_data(data)
-                let localidx_val = scratch.push_i32(); // *(from_any_data(data)-4)
-
-                // net wasm stack: [] -> [ptr(i32)]
-                expr_builder.local_get(localidx_param);
-                expr_builder.i32_load(wasmgen::MemArg::new4(byte_offset));
-                expr_builder.local_tee(localidx_ptr);
-
-                // net wasm stack: [ptr(i32)] -> [cond(i32)]
-                expr_builder.i32_const(-1);
-                expr_builder.i32_ne();
-                if is_string {
-                    expr_builder.local_get(localidx_ptr);
-                    expr_builder.i32_const((heap_begin << WASM_PAGE_BITS) as i32);
-                    expr_builder.i32_gt_u();
-                    expr_builder.i32_and();
-                }
-
-                // net wasm stack: [cond(i32)] -> []
-                expr_builder.if_(&[]);
-                {
-                    // net wasm stack: [] -> [param(i32)]
-                    expr_builder.local_get(localidx_param);
-
-                    // net wasm stack: [] -> [ptr_minus_4(i32)]
-                    expr_builder.local_get(localidx_ptr);
-                    expr_builder.i32_const(4);
-                    expr_builder.i32_sub();
-
-                    // net wasm stack: [ptr_minus_4(i32)] -> [val(i32)]
-                    expr_builder.i32_load(wasmgen::MemArg::new4(0));
-                    expr_builder.local_tee(localidx_val);
-
-                    // net wasm stack: [val(i32)] -> [cond(i32)]
-                    expr_builder.i32_const(i32::min_value());
-                    expr_builder.i32_and();
-
-                    // net wasm stack: [cond(i32)] -> [ret(i32)]
-                    expr_builder.if_(&[wasmgen::ValType::I32]);
-                    expr_builder.local_get(localidx_val);
-                    expr_builder.i32_const(1);
-                    expr_builder.i32_shl();
-                    expr_builder.else_();
-                    expr_builder.local_get(localidx_ptr);
-                    expr_builder.call(copy_func);
-                    expr_builder.end();
-
-                    // net wasm stack: [param(i32), ret(i32)] -> []
-                    expr_builder.i32_store(wasmgen::MemArg::new4(byte_offset));
-                }
-                expr_builder.end();
-
-                scratch.pop_i32();
-                scratch.pop_i32();
-            }
-
             // net wasm stack: [] -> [i32(ptr to past-the-end)]
             expr_builder.local_get(localidx_param);
             expr_builder.i32_const(struct_size as i32);
@@ -304,13 +382,29 @@ pub fn make_copy_children_elements(
         copy_children_table_offset + ir::VarType::String.tag() as u32,
         Box::new([funcidx_string]),
     );
+    let funcidx_array: wasmgen::FuncIdx =
+        make_array_function(wasm_module, tableidx, copy_funcs, heap_begin);
+    let funcidx_array_storage: wasmgen::FuncIdx =
+        make_array_storage_function(wasm_module, tableidx, copy_indirect_table_offset);
+    wasm_module.commit_table_elements(
+        tableidx,
+        copy_children_table_offset + ir::VarType::Array.tag() as u32,
+        Box::new([funcidx_array]),
+    );
+    wasm_module.commit_table_elements(
+        tableidx,
+        copy_children_table_offset + ir::ARRAY_STORAGE_TAG as u32,
+        Box::new([funcidx_array_storage]),
+    );
     let funcidxs_structs: Box<[wasmgen::FuncIdx]> = struct_types
         .iter()
         .zip(struct_field_byte_offsets.iter())
         .zip(struct_sizes.iter().cloned())
-        .map(|((ir_vartypes, byte_offsets), struct_size)| {
+        .enumerate()
+        .map(|(typeidx, ((ir_vartypes, byte_offsets), struct_size))| {
             make_struct_function(
                 wasm_module,
+                typeidx,
                 ir_vartypes,
                 byte_offsets,
                 struct_size,
@@ -329,3 +423,81 @@ pub fn make_copy_children_elements(
 
     copy_children_table_offset
 }
+
+// Encodes the copying of the pointer field at `byte_offset` of the object at `localidx_param`.
+// It is used by copy_children_$i for each field that is a pointer (except Func).
+// net wasm stack: [] -> []
+fn gen(
+    expr_builder: &mut wasmgen::ExprBuilder,
+    scratch: &mut Scratch,
+    localidx_param: wasmgen::LocalIdx,
+    byte_offset: u32,
+    tableidx: wasmgen::TableIdx,
+    copy_func: wasmgen::FuncIdx,
+    heap_begin: u32,
+    is_string: bool,
+) {
+    /*
+    if (ptr != -1 && (f is not String || ptr > heap_begin * WASM_PAGE_SIZE)) {
+        if (*(ptr-4)) & I32_MIN { // already copied (we multiplex the MSB of the tag field, since there shouldn't be more than 2^31 types)
+            f.ptr = (*(ptr-4)) << 1; // we store the ptr in the tag, but shifted right by one bit position (valid since ptr are all multiple of 4)
+        } else {
+            f.ptr = copy_${tag of f}(f.ptr);
+        }
+    }
+    */
+    let localidx_ptr = scratch.push_i32(); // from_any_data(data)
+    let localidx_val = scratch.push_i32(); // *(from_any_data(data)-4)
+
+    // net wasm stack: [] -> [ptr(i32)]
+    expr_builder.local_get(localidx_param);
+    expr_builder.i32_load(wasmgen::MemArg::new4(byte_offset));
+    expr_builder.local_tee(localidx_ptr);
+
+    // net wasm stack: [ptr(i32)] -> [cond(i32)]
+    expr_builder.i32_const(-1);
+    expr_builder.i32_ne();
+    if is_string {
+        expr_builder.local_get(localidx_ptr);
+        expr_builder.i32_const((heap_begin << WASM_PAGE_BITS) as i32);
+        expr_builder.i32_gt_u();
+        expr_builder.i32_and();
+    }
+
+    // net wasm stack: [cond(i32)] -> []
+    expr_builder.if_(&[]);
+    {
+        // net wasm stack: [] -> [param(i32)]
+        expr_builder.local_get(localidx_param);
+
+        // net wasm stack: [] -> [ptr_minus_4(i32)]
+        expr_builder.local_get(localidx_ptr);
+        expr_builder.i32_const(4);
+        expr_builder.i32_sub();
+
+        // net wasm stack: [ptr_minus_4(i32)] -> [val(i32)]
+        expr_builder.i32_load(wasmgen::MemArg::new4(0));
+        expr_builder.local_tee(localidx_val);
+
+        // net wasm stack: [val(i32)] -> [cond(i32)]
+        expr_builder.i32_const(i32::min_value());
+        expr_builder.i32_and();
+
+        // net wasm stack: [cond(i32)] -> [ret(i32)]
+        expr_builder.if_(&[wasmgen::ValType::I32]);
+        expr_builder.local_get(localidx_val);
+        expr_builder.i32_const(1);
+        expr_builder.i32_shl();
+        expr_builder.else_();
+        expr_builder.local_get(localidx_ptr);
+        expr_builder.call(copy_func);
+        expr_builder.end();
+
+        // net wasm stack: [param(i32), ret(i32)] -> []
+        expr_builder.i32_store(wasmgen::MemArg::new4(byte_offset));
+    }
+    expr_builder.end();
+
+    scratch.pop_i32();
+    scratch.pop_i32();
+}
@@ -1,73 +1,186 @@
+use std::collections::hash_map::HashMap;
 use wasmgen::Scratch;
 
+use crate::ARRAY_HEADER_SIZE;
+
 pub fn make_copy_funcs(
     wasm_module: &mut wasmgen::WasmModule,
     struct_sizes: &[u32],
+    memidx: wasmgen::MemIdx,
     free_mem_ptr: wasmgen::GlobalIdx,
+    use_wasm_bulk_memory_feature: bool,
 ) -> Box<[Option<wasmgen::FuncIdx>]> {
-    let funcidx_copy_string: wasmgen::FuncIdx = {
-        let functype = wasmgen::FuncType::new(
-            Box::new([wasmgen::ValType::I32]),
-            Box::new([wasmgen::ValType::I32]),
-        );
-        let (_type_idx, func_idx) = wasm_module.register_func(&functype);
-        let mut code_builder = wasmgen::CodeBuilder::new(functype);
+    let funcidx_copy_string: wasmgen::FuncIdx = make_copy_unsized_func(
+        wasm_module,
+        "gc_copy_string",
+        memidx,
+        free_mem_ptr,
+        use_wasm_bulk_memory_feature,
+        |expr_builder, localidx_param| {
+            // let str_end = ptr + 4 + round_up_to_multiple_of_4(*ptr);
+            // Actually, we do:
+            // let str_end = ptr + ((*ptr + 7) & (~3));
+            // net wasm stack: [] -> [str_end(i32)]
+            expr_builder.local_get(localidx_param);
+            expr_builder.local_get(localidx_param);
+            expr_builder.i32_load(wasmgen::MemArg::new4(0));
+            expr_builder.i32_const(7);
+            expr_builder.i32_add();
+            expr_builder.i32_const(-4);
+            expr_builder.i32_and();
+            expr_builder.i32_add();
+        },
+    );
+    let funcidx_copy_array_storage: wasmgen::FuncIdx = make_copy_unsized_func(
+        wasm_module,
+        "gc_copy_array_storage",
+        memidx,
+        free_mem_ptr,
+        use_wasm_bulk_memory_feature,
+        |expr_builder, localidx_param| {
+            // let storage_end = ptr + 4 + (*ptr) * sizeof(Any);
+            // net wasm stack: [] -> [storage_end(i32)]
+            expr_builder.local_get(localidx_param);
+            expr_builder.local_get(localidx_param);
+            expr_builder.i32_load(wasmgen::MemArg::new4(0));
+            expr_builder.i32_const(12);
+            expr_builder.i32_mul();
+            expr_builder.i32_add();
+            expr_builder.i32_const(4);
+            expr_builder.i32_add();
+        },
+    );
+
+    // Since copy_$i for fixed-size objects only depends on sizeof($i), we can combine all of them with the same size.
+    let mut sized_funcs = HashMap::<u32, wasmgen::FuncIdx>::new();
+    let mut get_sized_func = |wasm_module: &mut wasmgen::WasmModule, size: u32| {
+        *(sized_funcs
+            .entry(size)
+            .or_insert_with(|| make_copy_sized_func(wasm_module, free_mem_ptr, size)))
+    };
+    let funcidx_copy_array: wasmgen::FuncIdx = get_sized_func(wasm_module, ARRAY_HEADER_SIZE);
+
+    // Generate functions for copy_$i
+    std::iter::empty()
+        .chain(std::iter::once(None)) // Unassigned
+        .chain(std::iter::once(None)) // Undefined
+        .chain(std::iter::once(None)) // Number
+        .chain(std::iter::once(None)) // Boolean
+        .chain(std::iter::once(Some(funcidx_copy_string))) // String
+        .chain(std::iter::once(None)) // Func
+        .chain(std::iter::once(Some(funcidx_copy_array))) // Array
+        .chain(std::iter::once(None)) // Null
+        .chain(std::iter::once(Some(funcidx_copy_array_storage))) // ARRAY_STORAGE_TAG
+        .chain(
+            struct_sizes
+                .iter()
+                .map(|size| Some(get_sized_func(wasm_module, *size))),
+        )
+        .collect()
+}
+
+// Makes copy_$i for objects whose size is only known at runtime (i.e. String and the storage of an Array).
+// `encode_obj_end` should have net wasm stack [] -> [i32(obj_end)], where `obj_end` is the ptr past-the-end of the object
+// pointed to by the given local (which excludes the tag).
+fn make_copy_unsized_func<F: Fn(&mut wasmgen::ExprBuilder, wasmgen::LocalIdx)>(
+    wasm_module: &mut wasmgen::WasmModule,
+    name: &str,
+    memidx: wasmgen::MemIdx,
+    free_mem_ptr: wasmgen::GlobalIdx,
+    use_wasm_bulk_memory_feature: bool,
+    encode_obj_end: F,
+) -> wasmgen::FuncIdx {
+    let functype = wasmgen::FuncType::new(
+        Box::new([wasmgen::ValType::I32]),
+        Box::new([wasmgen::ValType::I32]),
+    );
+    let (_type_idx, func_idx) = wasm_module.register_func(&functype);
+    wasm_module.set_func_name(func_idx, name.to_string());
+    let mut code_builder = wasmgen::CodeBuilder::new(functype);
+    {
+        let (locals_builder, expr_builder) = code_builder.split();
+        let mut scratch = Scratch::new(locals_builder);
+        let localidx_param = wasmgen::LocalIdx { idx: 0 };
+
+        // Algorithm
+        /*
+        let new_ptr = free_mem_ptr + 4; // skip the tag
+        let obj_end = encode_obj_end(ptr);
+        ptr -= 4;
+        free_mem_ptr = move(ptr, obj_end, free_mem_ptr); // move everything, including the tag.
+        (*ptr) = I32_MIN | (new_ptr >> 1); // say that we already copied it.
+        return new_ptr;
+        */
         {
-            let (locals_builder, expr_builder) = code_builder.split();
-            let mut scratch = Scratch::new(locals_builder);
-            let localidx_param = wasmgen::LocalIdx { idx: 0 };
+            let localidx_free_mem_ptr = scratch.push_i32();
+            let localidx_new_ptr = scratch.push_i32();
+            let localidx_obj_end = scratch.push_i32();
 
-            // Algorithm
-            /*
-            let new_ptr = free_mem_ptr + 4; // skip the tag
-            let str_end = ptr + 4 + round_up_to_multiple_of_4(*ptr);
-            ptr -= 4;
-            free_mem_ptr = move(ptr, str_end, free_mem_ptr); // move everything, including the tag.
-            (*ptr) = I32_MIN | (new_ptr >> 1); // say that we already copied it.
-            return new_ptr;
-            */
+            // let new_ptr = free_mem_ptr + 4;
+            // net wasm stack: [] -> [new_ptr]
             {
-                let localidx_free_mem_ptr = scratch.push_i32();
-                let localidx_new_ptr = scratch.push_i32();
-                let localidx_str_end = scratch.push_i32();
+                expr_builder.global_get(free_mem_ptr);
+                expr_builder.local_tee(localidx_free_mem_ptr);
+                expr_builder.i32_const(4);
+                expr_builder.i32_add();
+                expr_builder.local_tee(localidx_new_ptr);
+            }
 
-                // let new_ptr = free_mem_ptr + 4;
-                // net wasm stack: [] -> [new_ptr]
-                {
-                    expr_builder.global_get(free_mem_ptr);
-                    expr_builder.local_tee(localidx_free_mem_ptr);
-                    expr_builder.i32_const(4);
-                    expr_builder.i32_add();
-                    expr_builder.local_tee(localidx_new_ptr);
-                }
+            // let obj_end = encode_obj_end(ptr);
+            // net wasm stack: [] -> []
+            {
+                encode_obj_end(expr_builder, localidx_param);
+                expr_builder.local_set(localidx_obj_end);
+            }
 
-                // let str_end = ptr + 4 + round_up_to_multiple_of_4(*ptr);
-                // Actually, we do:
-                // let str_end = ptr + ((*ptr + 7) & (~3));
+            // ptr -= 4;
+            // net wasm stack: [] -> []
+            {
+                expr_builder.local_get(localidx_param);
+                expr_builder.i32_const(4);
+                expr_builder.i32_sub();
+                expr_builder.local_set(localidx_param);
+            }
+
+            // free_mem_ptr = move(ptr, obj_end, free_mem_ptr);
+            if use_wasm_bulk_memory_feature {
+                // we actually do:
+                /*
+                memcpy(free_mem_ptr, ptr, obj_end - ptr);
+                free_mem_ptr += obj_end - ptr;
+                */
                 // net wasm stack: [] -> []
                 {
+                    let localidx_len: wasmgen::LocalIdx = scratch.push_i32();
+
+                    // memcpy(free_mem_ptr, ptr, obj_end - ptr);
+                    // net wasm stack: [] -> []
+                    expr_builder.local_get(localidx_free_mem_ptr);
                     expr_builder.local_get(localidx_param);
+                    expr_builder.local_get(localidx_obj_end);
                     expr_builder.local_get(localidx_param);
-                    expr_builder.i32_load(wasmgen::MemArg::new4(0));
-                    expr_builder.i32_const(7);
-                    expr_builder.i32_add();
-                    expr_builder.i32_const(-4);
-                    expr_builder.i32_and();
+                    expr_builder.i32_sub();
+                    expr_builder.local_tee(localidx_len);
+                    expr_builder.memory_copy(memidx, memidx);
+
+                    // free_mem_ptr += obj_end - ptr;
+                    // net wasm stack: [] -> []
+                    expr_builder.local_get(localidx_free_mem_ptr);
+                    expr_builder.local_get(localidx_len);
                     expr_builder.i32_add();
-                    expr_builder.local_set(localidx_str_end);
-                }
+                    expr_builder.global_set(free_mem_ptr);
 
-                // ptr -= 4;
-                // free_mem_ptr = move(ptr - 4, str_end, free_mem_ptr);
-                // we actually do:
+                    scratch.pop_i32();
+                }
+            } else {
+                // we actually do (obj_end - ptr is a multiple of 4, so we move 4 bytes at a time):
                 /*
-                ptr -= 4;
                 let it = ptr;
                 do {
                     *free_mem_ptr = *it;
-                    ++free_mem_ptr;
-                    ++it;
-                } while (it != str_end);
+                    free_mem_ptr += 4;
+                    it += 4;
+                } while (it != obj_end);
                 // rmb to assign the local free_mem_ptr back to global
                 */
                 // net wasm stack: [] -> []
@@ -77,9 +190,6 @@ pub fn make_copy_funcs(
                     // net wasm stack: [] -> []
                     {
                         expr_builder.local_get(localidx_param);
-                        expr_builder.i32_const(4);
-                        expr_builder.i32_sub();
-                        expr_builder.local_tee(localidx_param);
                         expr_builder.local_set(localidx_it);
                     }
 
@@ -94,160 +204,146 @@ pub fn make_copy_funcs(
                         expr_builder.i32_load(wasmgen::MemArg::new4(0));
                         expr_builder.i32_store(wasmgen::MemArg::new4(0));
 
-                        // ++free_mem_ptr;
+                        // free_mem_ptr += 4;
                         // net wasm stack: [] -> []
                         expr_builder.local_get(localidx_free_mem_ptr);
-                        expr_builder.i32_const(1);
+                        expr_builder.i32_const(4);
                         expr_builder.i32_add();
                         expr_builder.local_set(localidx_free_mem_ptr);
 
-                        // ++it;
+                        // it += 4;
                         // net wasm stack: [] -> [it]
                         expr_builder.local_get(localidx_it);
-                        expr_builder.i32_const(1);
+                        expr_builder.i32_const(4);
                         expr_builder.i32_add();
                         expr_builder.local_tee(localidx_it);
 
-                        // while (it != str_end);
+                        // while (it != obj_end);
                         // net wasm stack: [it] -> []
-                        expr_builder.local_get(localidx_str_end);
+                        expr_builder.local_get(localidx_obj_end);
                         expr_builder.i32_ne();
                         expr_builder.br_if(0); // conditional jump to start of innermost loop
 
                         expr_builder.end();
                     }
 
+                    // assign the local free_mem_ptr back to global
+                    // net wasm stack: [] -> []
+                    expr_builder.local_get(localidx_free_mem_ptr);
+                    expr_builder.global_set(free_mem_ptr);
+
                     scratch.pop_i32();
                 }
+            }
 
-                // (*ptr) = I32_MIN | (new_ptr >> 1);
-                // net wasm stack: [] -> []
-                {
+            // (*ptr) = I32_MIN | (new_ptr >> 1);
+            // net wasm stack: [] -> []
+            {
+                expr_builder.local_get(localidx_param);
+                expr_builder.i32_const(i32::min_value());
+                expr_builder.local_get(localidx_new_ptr);
+                expr_builder.i32_const(1);
+                expr_builder.i32_shr_u();
+                expr_builder.i32_or();
+                expr_builder.i32_store(wasmgen::MemArg::new4(0));
+            }
+
+            // currently stack is [new_ptr], which automatically gets returned
+            expr_builder.end();
+
+            scratch.pop_i32();
+            scratch.pop_i32();
+            scratch.pop_i32();
+        }
+    }
+    wasm_module.commit_func(func_idx, code_builder);
+    func_idx
+}
+
+// Makes copy_$i for objects with a fixed `size` (i.e. StructT and the header of an Array).
+// Since copy_$i only depends on the size, the caller may share it between all types with the same size.
+fn make_copy_sized_func(
+    wasm_module: &mut wasmgen::WasmModule,
+    free_mem_ptr: wasmgen::GlobalIdx,
+    size: u32,
+) -> wasmgen::FuncIdx {
+    let functype = wasmgen::FuncType::new(
+        Box::new([wasmgen::ValType::I32]),
+        Box::new([wasmgen::ValType::I32]),
+    );
+    let (_type_idx, func_idx) = wasm_module.register_func(&functype);
+    wasm_module.set_func_name(func_idx, format!("gc_copy_sized_{}", size));
+    let mut code_builder = wasmgen::CodeBuilder::new(functype);
+    {
+        let (locals_builder, expr_builder) = code_builder.split();
+        let mut scratch = Scratch::new(locals_builder);
+        let localidx_param = wasmgen::LocalIdx { idx: 0 };
+
+        {
+            let localidx_free_mem_ptr = scratch.push_i32();
+            let localidx_new_ptr = scratch.push_i32();
+
+            // let new_ptr = free_mem_ptr + 4;
+            // net wasm stack: [] -> [new_ptr]
+            {
+                expr_builder.global_get(free_mem_ptr);
+                expr_builder.local_tee(localidx_free_mem_ptr);
+                expr_builder.i32_const(4);
+                expr_builder.i32_add();
+                expr_builder.local_tee(localidx_new_ptr);
+            }
+
+            // ptr-=4;
+            // net wasm stack: [] -> []
+            {
+                expr_builder.local_get(localidx_param);
+                expr_builder.i32_const(4);
+                expr_builder.i32_sub();
+                expr_builder.local_set(localidx_param);
+            }
+
+            // free_mem_ptr = move(ptr, ptr + 4 + sizeof($i), free_mem_ptr);
+            // we actually do:
+            /*
+            *free_mem_ptr = *ptr;
+            *(free_mem_ptr+4) = *(ptr+4);
+            *(free_mem_ptr+8) = *(ptr+8);
+            ...
+            */
+            // net wasm stack: [] -> []
+            {
+                assert!(size % 4 == 0);
+                for offset in (0..(4 + size)).step_by(4) {
+                    expr_builder.local_get(localidx_free_mem_ptr);
                     expr_builder.local_get(localidx_param);
-                    expr_builder.i32_const(i32::min_value());
-                    expr_builder.local_get(localidx_new_ptr);
-                    expr_builder.i32_const(1);
-                    expr_builder.i32_shr_u();
-                    expr_builder.i32_or();
-                    expr_builder.i32_store(wasmgen::MemArg::new4(0));
+                    expr_builder.i32_load(wasmgen::MemArg::new4(offset));
+                    expr_builder.i32_store(wasmgen::MemArg::new4(offset));
                 }
+                expr_builder.local_get(localidx_free_mem_ptr);
+                expr_builder.i32_const((4 + size) as i32);
+                expr_builder.i32_add();
+                expr_builder.global_set(free_mem_ptr);
+            }
 
-                // currently stack is [new_ptr], which automatically gets returned
-                expr_builder.end();
-
-                scratch.pop_i32();
-                scratch.pop_i32();
-                scratch.pop_i32();
+            // (*ptr) = I32_MIN | (new_ptr >> 1);
+            // net wasm stack: [] -> []
+            {
+                expr_builder.local_get(localidx_param);
+                expr_builder.i32_const(i32::min_value());
+                expr_builder.local_get(localidx_new_ptr);
+                expr_builder.i32_const(1);
+                expr_builder.i32_shr_u();
+                expr_builder.i32_or();
+                expr_builder.i32_store(wasmgen::MemArg::new4(0));
             }
-        }
-        wasm_module.commit_func(func_idx, code_builder);
-        func_idx
-    };
 
-    // Generate functions for copy_$i
-    // Since copy_$i only depends on sizeof($i), we can combine all structs with the same size.
-    std::iter::empty()
-        .chain(std::iter::once(None)) // Unassigned
-        .chain(std::iter::once(None)) // Undefined
-        .chain(std::iter::once(None)) // Number
-        .chain(std::iter::once(None)) // Boolean
-        .chain(std::iter::once(Some(funcidx_copy_string))) // String
-        .chain(std::iter::once(None)) // Func
-        .chain(
-            struct_sizes
-                .iter()
-                .cloned()
-                .scan(
-                    std::collections::hash_map::HashMap::<u32, wasmgen::FuncIdx>::new(),
-                    |hm, size| {
-                        Some(
-                            *(hm.entry(size).or_insert_with(|| {
-                                // encode copy_$i for StructT with `size`
-                                let functype = wasmgen::FuncType::new(
-                                    Box::new([wasmgen::ValType::I32]),
-                                    Box::new([wasmgen::ValType::I32]),
-                                );
-                                let (_type_idx, func_idx) = wasm_module.register_func(&functype);
-                                let mut code_builder = wasmgen::CodeBuilder::new(functype);
-                                {
-                                    let (locals_builder, expr_builder) = code_builder.split();
-                                    let mut scratch = Scratch::new(locals_builder);
-                                    let localidx_param = wasmgen::LocalIdx { idx: 0 };
-
-                                    {
-                                        let localidx_free_mem_ptr = scratch.push_i32();
-                                        let localidx_new_ptr = scratch.push_i32();
-
-                                        // let new_ptr = free_mem_ptr + 4;
-                                        // net wasm stack: [] -> [new_ptr]
-                                        {
-                                            expr_builder.global_get(free_mem_ptr);
-                                            expr_builder.local_tee(localidx_free_mem_ptr);
-                                            expr_builder.i32_const(4);
-                                            expr_builder.i32_add();
-                                            expr_builder.local_tee(localidx_new_ptr);
-                                        }
-
-                                        // ptr-=4;
-                                        // net wasm stack: [] -> []
-                                        {
-                                            expr_builder.local_get(localidx_param);
-                                            expr_builder.i32_const(4);
-                                            expr_builder.i32_sub();
-                                            expr_builder.local_set(localidx_param);
-                                        }
-
-                                        // free_mem_ptr = move(ptr, ptr + 4 + sizeof($i), free_mem_ptr);
-                                        // we actually do:
-                                        /*
-                                        *free_mem_ptr = *ptr;
-                                        *(free_mem_ptr+4) = *(ptr+4);
-                                        *(free_mem_ptr+8) = *(ptr+8);
-                                        ...
-                                        */
-                                        // net wasm stack: [] -> []
-                                        {
-                                            assert!(size % 4 == 0);
-                                            for offset in (0..(4 + size)).step_by(4) {
-                                                expr_builder.local_get(localidx_free_mem_ptr);
-                                                expr_builder.local_get(localidx_param);
-                                                expr_builder
-                                                    .i32_load(wasmgen::MemArg::new4(offset));
-                                                expr_builder
-                                                    .i32_store(wasmgen::MemArg::new4(offset));
-                                            }
-                                            expr_builder.local_get(localidx_free_mem_ptr);
-                                            expr_builder.i32_const((4 + size) as i32);
-                                            expr_builder.i32_add();
-                                            expr_builder.global_set(free_mem_ptr);
-                                        }
-
-                                        // (*ptr) = I32_MIN | (new_ptr >> 1);
-                                        // net wasm stack: [] -> []
-                                        {
-                                            expr_builder.local_get(localidx_param);
-                                            expr_builder.i32_const(i32::min_value());
-                                            expr_builder.local_get(localidx_new_ptr);
-                                            expr_builder.i32_const(1);
-                                            expr_builder.i32_shr_u();
-                                            expr_builder.i32_or();
-                                            expr_builder.i32_store(wasmgen::MemArg::new4(0));
-                                        }
-
-                                        // currently stack is [new_ptr], which automatically gets returned
-                                        expr_builder.end();
-
-                                        scratch.pop_i32();
-                                        scratch.pop_i32();
-                                    }
-                                }
-                                wasm_module.commit_func(func_idx, code_builder);
-                                func_idx
-                            })),
-                        )
-                    },
-                )
-                .map(|x| Some(x)),
-        )
-        .collect()
+            // currently stack is [new_ptr], which automatically gets returned
+            expr_builder.end();
+
+            scratch.pop_i32();
+            scratch.pop_i32();
+        }
+    }
+    wasm_module.commit_func(func_idx, code_builder);
+    func_idx
 }
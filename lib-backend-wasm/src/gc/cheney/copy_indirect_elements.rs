@@ -23,7 +23,7 @@ pub fn make_copy_indirect_elements(
                     return make_func(f.idx, i32_wrap_i64((*(GC_TABLE_PTR_COPY_INDIRECT_OFFSET + *(f.closure-4)))(i64_extend_i32(f.closure))));
                 }
             }
-        } else if constexpr $i is not a ptr (i.e. not StructT or string) {
+        } else if constexpr $i is not a ptr (i.e. not StructT, Array or String) {
             // NO-OP
         } else {
             if (ptr != -1 && (f is not String || ptr > heap_begin * WASM_PAGE_SIZE)) {
@@ -44,6 +44,7 @@ pub fn make_copy_indirect_elements(
             Box::new([wasmgen::ValType::I64]),
         );
         let (_type_idx, func_idx) = wasm_module.register_func(&functype);
+        wasm_module.set_func_name(func_idx, "gc_copy_indirect_no_op".to_string());
         let mut code_builder = wasmgen::CodeBuilder::new(functype);
         {
             let (_locals_builder, expr_builder) = code_builder.split();
@@ -66,6 +67,7 @@ pub fn make_copy_indirect_elements(
             Box::new([wasmgen::ValType::I64]),
         );
         let (_type_idx, func_idx) = wasm_module.register_func(&functype);
+        wasm_module.set_func_name(func_idx, "gc_copy_indirect_func".to_string());
         let mut code_builder = wasmgen::CodeBuilder::new(functype);
         {
             let (locals_builder, expr_builder) = code_builder.split();
@@ -164,6 +166,7 @@ pub fn make_copy_indirect_elements(
     // `copy_func` is the function copy_$i.
     fn make_struct_function(
         wasm_module: &mut wasmgen::WasmModule,
+        name: String,
         copy_func: wasmgen::FuncIdx,
         heap_begin: u32,
         is_string: bool,
@@ -173,6 +176,7 @@ pub fn make_copy_indirect_elements(
             Box::new([wasmgen::ValType::I64]),
         );
         let (_type_idx, func_idx) = wasm_module.register_func(&functype);
+        wasm_module.set_func_name(func_idx, name);
         let mut code_builder = wasmgen::CodeBuilder::new(functype);
         {
             let (locals_builder, expr_builder) = code_builder.split();
@@ -260,10 +264,18 @@ pub fn make_copy_indirect_elements(
         make_func_function(wasm_module, copy_indirect_table_offset, tableidx);
     let string_funcidx: wasmgen::FuncIdx = make_struct_function(
         wasm_module,
+        "gc_copy_indirect_string".to_string(),
         copy_funcs[ir::VarType::String.tag() as usize].unwrap(),
         heap_begin,
         true,
     );
+    let array_funcidx: wasmgen::FuncIdx = make_struct_function(
+        wasm_module,
+        "gc_copy_indirect_array".to_string(),
+        copy_funcs[ir::VarType::Array.tag() as usize].unwrap(),
+        heap_begin,
+        false,
+    );
 
     let copy_indirect_elements: Box<[wasmgen::FuncIdx]> = std::iter::empty()
         .chain(std::iter::once(no_op_funcidx)) // Unassigned
@@ -272,9 +284,13 @@ pub fn make_copy_indirect_elements(
         .chain(std::iter::once(no_op_funcidx)) // Boolean
         .chain(std::iter::once(string_funcidx)) // String
         .chain(std::iter::once(func_funcidx)) // Func
+        .chain(std::iter::once(array_funcidx)) // Array
+        .chain(std::iter::once(no_op_funcidx)) // Null
+        .chain(std::iter::once(no_op_funcidx)) // ARRAY_STORAGE_TAG (never stored in an Any)
         .chain((0..num_structs).map(|n| {
             make_struct_function(
                 wasm_module,
+                format!("gc_copy_indirect_struct#{}", n),
                 copy_funcs[ir::NUM_PRIMITIVE_TAG_TYPES + n].unwrap(),
                 heap_begin,
                 false,
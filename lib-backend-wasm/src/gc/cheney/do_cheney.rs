@@ -16,6 +16,7 @@ pub fn make_do_cheney<'a>(
     copy_funcs: &[Option<wasmgen::FuncIdx>],
     global_var_manager: GlobalVarManagerRef<'a>,
     heap_begin: u32,
+    use_wasm_bulk_memory_feature: bool,
 ) -> wasmgen::FuncIdx {
     // Guaranteed to synchronise localidx_free_mem_ptr and globalidx_free_mem_ptr before returning.
     // net wasm stack: [] -> []
@@ -69,7 +70,7 @@ pub fn make_do_cheney<'a>(
                         expr_builder.global_set(wasm_globalidxs[1]); // store the `data` of the Any
                     }
                     ir::VarType::Unassigned => {}
-                    ir::VarType::Undefined => {}
+                    ir::VarType::Undefined | ir::VarType::Null => {}
                     ir::VarType::Number => {}
                     ir::VarType::Boolean => {}
                     ir::VarType::String => {
@@ -152,14 +153,14 @@ pub fn make_do_cheney<'a>(
                         scratch.pop_i32();
                         scratch.pop_i32();
                     }
-                    ir::VarType::StructT { typeidx } => {
+                    ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                         // net wasm stack: [] -> []
                         gen(
                             expr_builder,
                             scratch,
                             wasm_globalidxs[0],
                             tableidx,
-                            copy_funcs[ir::VarType::StructT { typeidx }.tag() as usize].unwrap(),
+                            copy_funcs[ir_vartype.tag() as usize].unwrap(),
                             heap_begin,
                             false,
                         );
@@ -377,6 +378,7 @@ pub fn make_do_cheney<'a>(
         Box::new([wasmgen::ValType::I32]),
     );
     let (_type_idx, func_idx) = wasm_module.register_func(&functype);
+    wasm_module.set_func_name(func_idx, "do_cheney".to_string());
     let mut code_builder = wasmgen::CodeBuilder::new(functype);
     {
         let (locals_builder, expr_builder) = code_builder.split();
@@ -601,9 +603,9 @@ pub fn make_do_cheney<'a>(
                         expr_builder.global_set(globalidx_end_mem_ptr);
 
                         // let it = gc_roots_stack_base_ptr;
-                        // net wasm stack: [] -> [it(i32)]
+                        // net wasm stack: [] -> []
                         expr_builder.local_get(localidx_gc_roots_stack_base_ptr);
-                        expr_builder.local_tee(localidx_it);
+                        expr_builder.local_set(localidx_it);
 
                         // gc_roots_stack_base_ptr += request_delta;
                         // let tmp = gc_roots_stack_base_ptr;
@@ -615,69 +617,101 @@ pub fn make_do_cheney<'a>(
                         expr_builder.local_tee(localidx_tmp);
                         expr_builder.global_set(globalidx_gc_roots_stack_base_ptr);
 
-                        /*
-                        while (it != gc_roots_stack_ptr) {
-                            tmp->tag = it->tag;
-                            tmp->data = it->data;
-                            it += 12;
-                            tmp += 12;
-                        }
-                        */
-                        // Actually we encode it as:
-                        /*
-                        if (it != gc_roots_stack_ptr) {
-                            do {
-                                tmp->tag = it->tag;
-                                tmp->data = it->data;
-                                tmp += 12;
-                                it += 12;
-                            } while(it != gc_roots_stack_ptr);
-                        }
-                        */
-                        // net wasm stack: [it(i32)] -> []
-                        expr_builder.local_get(localidx_gc_roots_stack_ptr);
-                        expr_builder.i32_ne();
-                        expr_builder.if_(&[]);
-                        {
-                            expr_builder.loop_(&[]);
+                        // move(it, gc_roots_stack_ptr, tmp);
+                        if use_wasm_bulk_memory_feature {
+                            /*
+                            memmove(tmp, it, gc_roots_stack_ptr - it);
+                            tmp += gc_roots_stack_ptr - it;
+                            */
+                            // net wasm stack: [] -> []
                             {
-                                // tmp->tag = it->tag;
-                                // net wasm stack: [] -> []
-                                expr_builder.local_get(localidx_tmp);
-                                expr_builder.local_get(localidx_it);
-                                expr_builder.i32_load(wasmgen::MemArg::new4(0));
-                                expr_builder.i32_store(wasmgen::MemArg::new4(0));
+                                let localidx_len = scratch.push_i32();
 
-                                // tmp->data = it->data;
+                                // memmove(tmp, it, gc_roots_stack_ptr - it);
                                 // net wasm stack: [] -> []
                                 expr_builder.local_get(localidx_tmp);
                                 expr_builder.local_get(localidx_it);
-                                expr_builder.i64_load(wasmgen::MemArg::new4(4));
-                                expr_builder.i64_store(wasmgen::MemArg::new4(4));
+                                expr_builder.local_get(localidx_gc_roots_stack_ptr);
+                                expr_builder.local_get(localidx_it);
+                                expr_builder.i32_sub();
+                                expr_builder.local_tee(localidx_len);
+                                expr_builder.memory_copy(memidx, memidx);
 
-                                // tmp += 12;
+                                // tmp += gc_roots_stack_ptr - it;
                                 // net wasm stack: [] -> []
                                 expr_builder.local_get(localidx_tmp);
-                                expr_builder.i32_const(12);
+                                expr_builder.local_get(localidx_len);
                                 expr_builder.i32_add();
                                 expr_builder.local_set(localidx_tmp);
 
-                                // it += 12;
-                                // net wasm stack: [] -> [it(i32)]
-                                expr_builder.local_get(localidx_it);
-                                expr_builder.i32_const(12);
-                                expr_builder.i32_add();
-                                expr_builder.local_tee(localidx_it);
-
-                                // ... while(it != gc_roots_stack_ptr);
-                                // net wasm stack: [it(i32)] -> []
-                                expr_builder.local_get(localidx_gc_roots_stack_ptr);
-                                expr_builder.i32_ne();
-                                expr_builder.br_if(0);
+                                scratch.pop_i32();
+                            }
+                        } else {
+                            /*
+                            while (it != gc_roots_stack_ptr) {
+                                tmp->tag = it->tag;
+                                tmp->data = it->data;
+                                it += 12;
+                                tmp += 12;
+                            }
+                            */
+                            // Actually we encode it as:
+                            /*
+                            if (it != gc_roots_stack_ptr) {
+                                do {
+                                    tmp->tag = it->tag;
+                                    tmp->data = it->data;
+                                    tmp += 12;
+                                    it += 12;
+                                } while(it != gc_roots_stack_ptr);
+                            }
+                            */
+                            // net wasm stack: [] -> []
+                            expr_builder.local_get(localidx_it);
+                            expr_builder.local_get(localidx_gc_roots_stack_ptr);
+                            expr_builder.i32_ne();
+                            expr_builder.if_(&[]);
+                            {
+                                expr_builder.loop_(&[]);
+                                {
+                                    // tmp->tag = it->tag;
+                                    // net wasm stack: [] -> []
+                                    expr_builder.local_get(localidx_tmp);
+                                    expr_builder.local_get(localidx_it);
+                                    expr_builder.i32_load(wasmgen::MemArg::new4(0));
+                                    expr_builder.i32_store(wasmgen::MemArg::new4(0));
+
+                                    // tmp->data = it->data;
+                                    // net wasm stack: [] -> []
+                                    expr_builder.local_get(localidx_tmp);
+                                    expr_builder.local_get(localidx_it);
+                                    expr_builder.i64_load(wasmgen::MemArg::new4(4));
+                                    expr_builder.i64_store(wasmgen::MemArg::new4(4));
+
+                                    // tmp += 12;
+                                    // net wasm stack: [] -> []
+                                    expr_builder.local_get(localidx_tmp);
+                                    expr_builder.i32_const(12);
+                                    expr_builder.i32_add();
+                                    expr_builder.local_set(localidx_tmp);
+
+                                    // it += 12;
+                                    // net wasm stack: [] -> [it(i32)]
+                                    expr_builder.local_get(localidx_it);
+                                    expr_builder.i32_const(12);
+                                    expr_builder.i32_add();
+                                    expr_builder.local_tee(localidx_it);
+
+                                    // ... while(it != gc_roots_stack_ptr);
+                                    // net wasm stack: [it(i32)] -> []
+                                    expr_builder.local_get(localidx_gc_roots_stack_ptr);
+                                    expr_builder.i32_ne();
+                                    expr_builder.br_if(0);
+                                }
+                                expr_builder.end();
                             }
                             expr_builder.end();
                         }
-                        expr_builder.end();
 
                         // gc_roots_stack_ptr = tmp;
                         // no need to write to local cache, because it will never be used again.
@@ -1,4 +1,6 @@
+use super::encode_array_init;
 use super::HeapManager;
+use super::ARRAY_HEADER_SIZE;
 use super::WASM_PAGE_BITS;
 use super::WASM_PAGE_SIZE;
 use crate::global_var::GlobalVarManagerRef;
@@ -69,6 +71,7 @@ impl<'a, 'b, 'c> Cheney<'a, 'b, 'c> {
         heap_initial_end: u32,
         global_var_manager: GlobalVarManagerRef<'d>, // stores global vars that are gc roots too
         error_func: wasmgen::FuncIdx,
+        use_wasm_bulk_memory_feature: bool, // Whether we can use memory.copy to move objects
         wasm_module: &mut wasmgen::WasmModule,
     ) -> Self {
         assert!(heap_begin + MEM_INITIAL_HEAP_SIZE == heap_initial_end);
@@ -224,7 +227,7 @@ impl<'a, 'b, 'c> Cheney<'a, 'b, 'c> {
                 return end_mem_ptr - free_mem_ptr >= bytes_required;
             }
         }
-        Note: For type like Unassigned, Undefined, Number, Boolean that are not a ptr, the indirect function is a no-op, and it doesn't have copy_$i or copy_children_$i.
+        Note: For type like Unassigned, Undefined, Number, Boolean, Null that are not a ptr, the indirect function is a no-op, and it doesn't have copy_$i or copy_children_$i.
         For Func, it has copy_indirect_$i (which will simply forward to the closure struct), but not copy_$i (copy_children should invoke the closure directly).  It also doesn't have copy_children_$i.
         Any does not have copy_$i and copy_indirect_$i (since copy_indirect_$i is suppose to indirectly determine the type of the any)
         */
@@ -245,8 +248,13 @@ impl<'a, 'b, 'c> Cheney<'a, 'b, 'c> {
         );
 
         // copy_$i functions, indexed by VarType::tag().
-        let copy_funcs: Box<[Option<wasmgen::FuncIdx>]> =
-            copy_funcs::make_copy_funcs(wasm_module, struct_sizes, free_mem_ptr);
+        let copy_funcs: Box<[Option<wasmgen::FuncIdx>]> = copy_funcs::make_copy_funcs(
+            wasm_module,
+            struct_sizes,
+            memidx,
+            free_mem_ptr,
+            use_wasm_bulk_memory_feature,
+        );
         assert!(copy_funcs.len() == ir::NUM_PRIMITIVE_TAG_TYPES + struct_sizes.len());
 
         let tableidx: wasmgen::TableIdx = wasm_module.get_or_add_table();
@@ -283,6 +291,7 @@ impl<'a, 'b, 'c> Cheney<'a, 'b, 'c> {
             &copy_funcs,
             global_var_manager,
             heap_begin,
+            use_wasm_bulk_memory_feature,
         );
 
         Cheney {
@@ -311,6 +320,7 @@ impl<'a, 'b, 'c> Cheney<'a, 'b, 'c> {
             .filter(|(ir_vartype, _)| match ir_vartype {
                 ir::VarType::Unassigned
                 | ir::VarType::Undefined
+                | ir::VarType::Null
                 | ir::VarType::Number
                 | ir::VarType::Boolean => false,
                 _ => true,
@@ -492,7 +502,9 @@ impl<'a, 'b, 'c> HeapManager for Cheney<'a, 'b, 'c> {
                                 expr_builder.i32_const(ir::VarType::Unassigned.tag());
                                 expr_builder.i32_store(wasmgen::MemArg::new4(*byte_offset));
                             }
-                            ir::VarType::String | ir::VarType::StructT { typeidx: _ } => {
+                            ir::VarType::String
+                            | ir::VarType::Array
+                            | ir::VarType::StructT { typeidx: _ } => {
                                 expr_builder.local_get(localidx_ptr);
                                 expr_builder.i32_const(-1);
                                 expr_builder.i32_store(wasmgen::MemArg::new4(*byte_offset));
@@ -569,7 +581,58 @@ impl<'a, 'b, 'c> HeapManager for Cheney<'a, 'b, 'c> {
                 scratch.pop_i32();
                 scratch.pop_i32();
             }
-            _ => panic!("incorrect VarType, expected String"),
+            ir::VarType::Array => {
+                let localidx_capacity: wasmgen::LocalIdx = scratch.push_i32();
+                let localidx_mem_size: wasmgen::LocalIdx = scratch.push_i32();
+
+                // The header and the storage are allocated together (each with their own tag),
+                // but they are separate objects as far as the GC is concerned.
+                // Algorithm: mem_size = 4 + ARRAY_HEADER_SIZE + 4 + 4 + capacity * sizeof(Any)
+                // net wasm stack: [i32(capacity)] -> []
+                expr_builder.local_tee(localidx_capacity);
+                expr_builder.i32_const(12);
+                expr_builder.i32_mul();
+                expr_builder.i32_const((ARRAY_HEADER_SIZE + 12) as i32);
+                expr_builder.i32_add();
+                expr_builder.local_set(localidx_mem_size);
+
+                // net wasm stack: [] -> [i32(ptr)]
+                self.encode_allocation(
+                    |expr_builder| {
+                        // net wasm stack: [] -> [i32(size)]
+                        expr_builder.local_get(localidx_mem_size);
+                    },
+                    ir_vartype.tag(),
+                    local_types,
+                    local_map,
+                    wasm_local_map,
+                    scratch,
+                    expr_builder,
+                );
+
+                // write the tag of the storage (which is just after the header)
+                // net wasm stack: [i32(ptr)] -> [i32(ptr)]
+                {
+                    let localidx_ret: wasmgen::LocalIdx = scratch.push_i32();
+                    expr_builder.local_tee(localidx_ret);
+                    expr_builder.local_get(localidx_ret);
+                    expr_builder.i32_const(ir::ARRAY_STORAGE_TAG);
+                    expr_builder.i32_store(wasmgen::MemArg::new4(ARRAY_HEADER_SIZE));
+                    scratch.pop_i32();
+                }
+
+                // net wasm stack: [i32(ptr)] -> [i32(ptr)]
+                encode_array_init(
+                    localidx_capacity,
+                    ARRAY_HEADER_SIZE + 4,
+                    scratch,
+                    expr_builder,
+                );
+
+                scratch.pop_i32();
+                scratch.pop_i32();
+            }
+            _ => panic!("incorrect VarType, expected String or Array"),
         }
     }
 
@@ -694,6 +757,61 @@ impl<'a, 'b, 'c> HeapManager for Cheney<'a, 'b, 'c> {
         todo!();
     }
 
+    // Handles are indices into the gc_roots stack (counting from gc_roots_stack_base_ptr, in units of Any),
+    // so they stay valid when do_cheney() moves the gc_roots stack to make more space for the heap.
+    // net wasm stack: [] -> [i32(handle)]
+    fn encode_handle_push(
+        &self,
+        local_root: (ir::VarType, &[wasmgen::LocalIdx]),
+        scratch: &mut Scratch,
+        expr_builder: &mut wasmgen::ExprBuilder,
+    ) {
+        let (ir_vartype, wasm_local_slice) = local_root;
+
+        // *gc_roots_stack_ptr = to_any(local);
+        // net wasm stack: [] -> []
+        expr_builder.global_get(self.gc_roots_stack_ptr);
+        encode_load_local(wasm_local_slice, ir_vartype, ir_vartype, expr_builder);
+        encode_store_memory(0, ir::VarType::Any, ir_vartype, scratch, expr_builder);
+
+        // handle = (gc_roots_stack_ptr - gc_roots_stack_base_ptr) / 12;
+        // net wasm stack: [] -> [i32(handle)]
+        expr_builder.global_get(self.gc_roots_stack_ptr);
+        expr_builder.global_get(self.gc_roots_stack_base_ptr);
+        expr_builder.i32_sub();
+        expr_builder.i32_const(12);
+        expr_builder.i32_div_u();
+
+        // gc_roots_stack_ptr += 12;
+        // net wasm stack: [] -> []
+        expr_builder.global_get(self.gc_roots_stack_ptr);
+        expr_builder.i32_const(12);
+        expr_builder.i32_add();
+        expr_builder.global_set(self.gc_roots_stack_ptr);
+    }
+
+    // net wasm stack: [] -> []
+    fn encode_handles_pop(&self, count: u32, expr_builder: &mut wasmgen::ExprBuilder) {
+        if count > 0 {
+            expr_builder.global_get(self.gc_roots_stack_ptr);
+            expr_builder.i32_const((12 * count) as i32);
+            expr_builder.i32_sub();
+            expr_builder.global_set(self.gc_roots_stack_ptr);
+        }
+    }
+
+    // net wasm stack: [i32(handle)] -> [<Any>]
+    fn encode_handle_read(&self, scratch: &mut Scratch, expr_builder: &mut wasmgen::ExprBuilder) {
+        // net wasm stack: [i32(handle)] -> [i32(ptr)]
+        expr_builder.i32_const(12);
+        expr_builder.i32_mul();
+        expr_builder.global_get(self.gc_roots_stack_base_ptr);
+        expr_builder.i32_add();
+
+        // net wasm stack: [i32(ptr)] -> [<Any>]
+        encode_load_memory(0, ir::VarType::Any, ir::VarType::Any, scratch, expr_builder);
+    }
+
     // We allow Undefined (which is encoded as the nullptr value),
     // and any reference type (i.e. strings and structs)
     // net wasm stack: [<closure_irvartype>] -> [i32(closure)]
@@ -724,7 +842,7 @@ impl<'a, 'b, 'c> HeapManager for Cheney<'a, 'b, 'c> {
             local_types.iter().copied().zip(local_map.iter().copied())
         {
             match ir_vartype {
-                ir::VarType::String | ir::VarType::StructT { typeidx: _ } => {
+                ir::VarType::String | ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                     expr_builder.i32_const(-1);
                     expr_builder.local_set(wasm_local_map[wasm_local_map_index]);
                 }
@@ -1,4 +1,5 @@
 use super::*;
+use crate::global_var::GlobalVarManager;
 use wasm_test_harness::*;
 
 pub fn wasmtest<C: TestContext>(c: &mut C) {
@@ -20,6 +21,7 @@ pub fn wasmtest<C: TestContext>(c: &mut C) {
         let struct_field_byte_offsets: [Box<[u32]>; 1] = [Box::new([0, 12, 24])];
         let struct_sizes: [u32; 1] = [28];
         let mem = wasm_module.add_unbounded_memory(MEM_INITIAL_HEAP_SIZE);
+        let global_var_manager = GlobalVarManager::default(); // no global variables
         let cheney = Cheney::new(
             &struct_types,
             &struct_field_byte_offsets,
@@ -27,7 +29,9 @@ pub fn wasmtest<C: TestContext>(c: &mut C) {
             mem,
             0,
             MEM_INITIAL_HEAP_SIZE,
+            global_var_manager.deref(),
             error_func,
+            false,
             wasm_module,
         );
 
@@ -154,6 +158,7 @@ pub fn wasmtest<C: TestContext>(c: &mut C) {
         let struct_field_byte_offsets: [Box<[u32]>; 1] = [Box::new([0, 12, 24])];
         let struct_sizes: [u32; 1] = [28];
         let mem = wasm_module.add_unbounded_memory(MEM_INITIAL_HEAP_SIZE);
+        let global_var_manager = GlobalVarManager::default(); // no global variables
         let cheney = Cheney::new(
             &struct_types,
             &struct_field_byte_offsets,
@@ -161,7 +166,9 @@ pub fn wasmtest<C: TestContext>(c: &mut C) {
             mem,
             0,
             MEM_INITIAL_HEAP_SIZE,
+            global_var_manager.deref(),
             error_func,
+            false,
             wasm_module,
         );
 
@@ -243,4 +250,133 @@ pub fn wasmtest<C: TestContext>(c: &mut C) {
         expr_builder.i32_const((MEM_INITIAL_USABLE_SIZE * 8 + (1 << 4)) as i32);
         t.i32_assert_eq(&mut scratch, expr_builder);
     });
+
+    for use_wasm_bulk_memory_feature in [false, true] {
+        let name = if use_wasm_bulk_memory_feature {
+            "string root with bulk memory"
+        } else {
+            "string root"
+        };
+        c.add_test(name, |code_builder, wasm_module, error_func, t| {
+            /*
+            In this test we will create a Cheney with the initial size (1MiB usuable size),
+            and allocate a 10-byte string that is kept as a root.
+            Then add 32768*3 copies of 28-byte structs (without holding references to them), so that the string is copied by the GC a few times.
+            Every struct is allocated right after the string (in the to-space) after a collection, so this will overwrite the string if the GC lost track of the end of it.
+            The string should have moved, and its contents should be unchanged.
+            */
+            // 28 bytes struct
+            let struct_types: [Box<[ir::VarType]>; 1] = [Box::new([
+                ir::VarType::Any,
+                ir::VarType::Any,
+                ir::VarType::Boolean,
+            ])];
+            let struct_field_byte_offsets: [Box<[u32]>; 1] = [Box::new([0, 12, 24])];
+            let struct_sizes: [u32; 1] = [28];
+            let mem = wasm_module.add_unbounded_memory(MEM_INITIAL_HEAP_SIZE);
+            let global_var_manager = GlobalVarManager::default(); // no global variables
+            let cheney = Cheney::new(
+                &struct_types,
+                &struct_field_byte_offsets,
+                &struct_sizes,
+                mem,
+                0,
+                MEM_INITIAL_HEAP_SIZE,
+                global_var_manager.deref(),
+                error_func,
+                use_wasm_bulk_memory_feature,
+                wasm_module,
+            );
+
+            let (locals_builder, expr_builder) = code_builder.split();
+
+            let localidx_str = locals_builder.add(wasmgen::ValType::I32);
+            let localidx_old_str = locals_builder.add(wasmgen::ValType::I32);
+
+            let mut scratch = Scratch::new(locals_builder);
+
+            // str = new string(10);
+            // net wasm stack: [] -> []
+            expr_builder.i32_const(10);
+            cheney.encode_dynamic_allocation(
+                ir::VarType::String,
+                &[],
+                &[],
+                &[],
+                &mut scratch,
+                expr_builder,
+            );
+            expr_builder.local_tee(localidx_str);
+            expr_builder.local_set(localidx_old_str);
+
+            // fill the contents of the string (including the padding after it)
+            // net wasm stack: [] -> []
+            for (offset, val) in [(4, 0x11121314), (8, 0x21222324), (12, 0x31323334)].iter() {
+                expr_builder.local_get(localidx_str);
+                expr_builder.i32_const(*val);
+                expr_builder.i32_store(wasmgen::MemArg::new4(*offset));
+            }
+
+            // add 32768*3 structs, keeping only the string alive
+            // net wasm stack: [] -> []
+            {
+                let localidx_i = scratch.push_i32();
+
+                // i = 0;
+                expr_builder.i32_const(0);
+                expr_builder.local_set(localidx_i);
+
+                // do {..} while(..);
+                expr_builder.loop_(&[]);
+                {
+                    // new struct$0();
+                    cheney.encode_fixed_allocation(
+                        ir::VarType::StructT { typeidx: 0 },
+                        &[ir::VarType::String],
+                        &[0],
+                        &[localidx_str],
+                        &mut scratch,
+                        expr_builder,
+                    );
+                    expr_builder.drop();
+
+                    // i = i + 1;
+                    expr_builder.local_get(localidx_i);
+                    expr_builder.i32_const(1);
+                    expr_builder.i32_add();
+                    expr_builder.local_set(localidx_i);
+
+                    // while (i < 32768*3);
+                    expr_builder.local_get(localidx_i);
+                    expr_builder.i32_const(32768 * 3);
+                    expr_builder.i32_lt_u();
+                    expr_builder.br_if(0);
+                }
+                expr_builder.end();
+
+                scratch.pop_i32();
+            }
+
+            // assert(str != old_str);
+            expr_builder.local_get(localidx_str);
+            expr_builder.local_get(localidx_old_str);
+            expr_builder.i32_ne();
+            expr_builder.i32_const(1);
+            t.i32_assert_eq(&mut scratch, expr_builder);
+
+            // assert(str->length == 10);
+            expr_builder.local_get(localidx_str);
+            expr_builder.i32_load(wasmgen::MemArg::new4(0));
+            expr_builder.i32_const(10);
+            t.i32_assert_eq(&mut scratch, expr_builder);
+
+            // check the contents of the string (including the padding after it)
+            for (offset, val) in [(4, 0x11121314), (8, 0x21222324), (12, 0x31323334)].iter() {
+                expr_builder.local_get(localidx_str);
+                expr_builder.i32_load(wasmgen::MemArg::new4(*offset));
+                expr_builder.i32_const(*val);
+                t.i32_assert_eq(&mut scratch, expr_builder);
+            }
+        });
+    }
 }
@@ -1,5 +1,8 @@
+use super::encode_array_init;
+use super::ARRAY_HEADER_SIZE;
 use super::WASM_PAGE_BITS;
 use super::WASM_PAGE_SIZE;
+use crate::var_conv::*;
 use wasmgen::Scratch;
 
 /**
@@ -277,7 +280,38 @@ impl<'a, 'b, 'c> super::HeapManager for Leaky<'a, 'b, 'c> {
                 scratch.pop_i32();
                 scratch.pop_i32();
             }
-            _ => panic!("incorrect VarType, expected String"),
+            ir::VarType::Array => {
+                let localidx_capacity: wasmgen::LocalIdx = scratch.push_i32();
+                let localidx_mem_size: wasmgen::LocalIdx = scratch.push_i32();
+
+                // Algorithm: mem_size = ARRAY_HEADER_SIZE + 4 + capacity * sizeof(Any)
+                // net wasm stack: [i32(capacity)] -> []
+                {
+                    expr_builder.local_tee(localidx_capacity);
+                    expr_builder.i32_const(12);
+                    expr_builder.i32_mul();
+                    expr_builder.i32_const((ARRAY_HEADER_SIZE + 4) as i32);
+                    expr_builder.i32_add();
+                    expr_builder.local_set(localidx_mem_size);
+                }
+
+                // net wasm stack: [] -> [i32(ptr)]
+                self.encode_allocation(
+                    |expr_builder| {
+                        // net wasm stack: [] -> [i32(size)]
+                        expr_builder.local_get(localidx_mem_size);
+                    },
+                    scratch,
+                    expr_builder,
+                );
+
+                // net wasm stack: [i32(ptr)] -> [i32(ptr)]
+                encode_array_init(localidx_capacity, ARRAY_HEADER_SIZE, scratch, expr_builder);
+
+                scratch.pop_i32();
+                scratch.pop_i32();
+            }
+            _ => panic!("incorrect VarType, expected String or Array"),
         }
     }
 
@@ -337,6 +371,45 @@ impl<'a, 'b, 'c> super::HeapManager for Leaky<'a, 'b, 'c> {
         // Do nothing - because our memory manager will never collect garbage.  The garbage will leak.
     }
 
+    // Since nothing ever moves, the handle is just a pointer to a copy of the value (as an Any) on the heap.
+    // The copy is leaked like everything else.
+    // net wasm stack: [] -> [i32(handle)]
+    fn encode_handle_push(
+        &self,
+        local_root: (ir::VarType, &[wasmgen::LocalIdx]),
+        scratch: &mut Scratch,
+        expr_builder: &mut wasmgen::ExprBuilder,
+    ) {
+        let (ir_vartype, wasm_local_slice) = local_root;
+
+        // net wasm stack: [] -> [i32(handle)]
+        self.encode_allocation(
+            |expr_builder| {
+                // net wasm stack: [] -> [i32(size)]
+                expr_builder.i32_const(12);
+            },
+            scratch,
+            expr_builder,
+        );
+
+        // *handle = to_any(local);
+        // net wasm stack: [i32(handle)] -> [i32(handle)]
+        let localidx_handle: wasmgen::LocalIdx = scratch.push_i32();
+        expr_builder.local_tee(localidx_handle);
+        encode_load_local(wasm_local_slice, ir_vartype, ir_vartype, expr_builder);
+        encode_store_memory(0, ir::VarType::Any, ir_vartype, scratch, expr_builder);
+        expr_builder.local_get(localidx_handle);
+        scratch.pop_i32();
+    }
+
+    // The copies are leaked, so there is nothing to do.
+    fn encode_handles_pop(&self, _count: u32, _expr_builder: &mut wasmgen::ExprBuilder) {}
+
+    // net wasm stack: [i32(handle)] -> [<Any>]
+    fn encode_handle_read(&self, scratch: &mut Scratch, expr_builder: &mut wasmgen::ExprBuilder) {
+        encode_load_memory(0, ir::VarType::Any, ir::VarType::Any, scratch, expr_builder);
+    }
+
     // Since there is no GC, we allow any type that fits into an i32.
     fn encode_closure_conversion(
         &self,
@@ -1,6 +1,7 @@
 pub mod cheney;
 pub mod leaky;
 
+use crate::ARRAY_HEADER_SIZE;
 use crate::WASM_PAGE_BITS;
 use crate::WASM_PAGE_SIZE;
 
@@ -62,6 +63,7 @@ pub trait HeapManager {
 
     // Encodes instructions to get a chunk of memory for an string/array of unknown size.  See `encode_fixed_allocation` for more details.
     // The size need not be a multiple of 4.  (But the allocator will round up to nearest 4-byte boundary.)
+    // For Array, `num_bytes` is instead the capacity (in number of elements) of the storage; the array will have length zero.
     //
     // This function generates code equivalent to, but possibly more efficient to doing this:
     // self.encode_local_roots_prologue(local_roots, expr_builder);
@@ -137,6 +139,26 @@ pub trait HeapManager {
         expr_builder: &mut wasmgen::ExprBuilder,
     );
 
+    // Encodes instructions to push the value of a local variable onto the gc_roots stack, so that it stays alive until it is popped by `encode_handles_pop()`.
+    // Unlike `encode_local_roots_prologue()`, the value is never written back to the local.  It is instead referred to by a handle:
+    // the position of the value from the bottom of the gc_roots stack, which does not change even if the GC moves the gc_roots stack or the value itself.
+    // This is used to give the host values that it can use after it calls back into the module (see the note on host imports in lib.rs).
+    // net wasm stack: [] -> [i32(handle)]
+    fn encode_handle_push(
+        &self,
+        local_root: (ir::VarType, &[wasmgen::LocalIdx]),
+        scratch: &mut Scratch,
+        expr_builder: &mut wasmgen::ExprBuilder,
+    );
+
+    // Encodes instructions to pop the last `count` values pushed by `encode_handle_push()`.
+    // net wasm stack: [] -> []
+    fn encode_handles_pop(&self, count: u32, expr_builder: &mut wasmgen::ExprBuilder);
+
+    // Encodes instructions to read the current value referred to by a handle (as an Any).
+    // net wasm stack: [i32(handle)] -> [<Any>]
+    fn encode_handle_read(&self, scratch: &mut Scratch, expr_builder: &mut wasmgen::ExprBuilder);
+
     // Encodes the the conversion of a expr representing a closure to the actual closure i32 value.
     // Typically GCs will want to be able to assume that this is a pointer (or null).
     // net wasm stack: [] -> [i32]
@@ -160,3 +182,99 @@ pub trait HeapManager {
         expr_builder: &mut wasmgen::ExprBuilder,
     );
 }
+
+// Initializes a newly allocated Array with length zero, whose storage is placed `storage_offset` bytes after the header.
+// All `capacity` entries of the storage will be set to Undefined.
+// The storage tag (if the GC needs one) should be written by the caller.
+// net wasm stack: [i32(ptr)] -> [i32(ptr)]
+fn encode_array_init(
+    localidx_capacity: wasmgen::LocalIdx,
+    storage_offset: u32,
+    scratch: &mut Scratch,
+    expr_builder: &mut wasmgen::ExprBuilder,
+) {
+    /*
+    ptr->length = 0;
+    ptr->storage = ptr + storage_offset;
+    *(ptr->storage) = capacity;
+    let it = ptr->storage + 4;
+    let end = it + capacity * sizeof(Any);
+    while (it != end) {
+        it->tag = Undefined;
+        it += 12;
+    }
+    */
+    assert!(storage_offset >= ARRAY_HEADER_SIZE);
+
+    let localidx_ptr: wasmgen::LocalIdx = scratch.push_i32();
+    let localidx_it: wasmgen::LocalIdx = scratch.push_i32();
+    let localidx_end: wasmgen::LocalIdx = scratch.push_i32();
+
+    // ptr->length = 0;
+    // net wasm stack: [i32(ptr)] -> []
+    expr_builder.local_tee(localidx_ptr);
+    expr_builder.i32_const(0);
+    expr_builder.i32_store(wasmgen::MemArg::new4(0));
+
+    // ptr->storage = ptr + storage_offset;
+    // net wasm stack: [] -> []
+    expr_builder.local_get(localidx_ptr);
+    expr_builder.local_get(localidx_ptr);
+    expr_builder.i32_const(storage_offset as i32);
+    expr_builder.i32_add();
+    expr_builder.i32_store(wasmgen::MemArg::new4(4));
+
+    // *(ptr->storage) = capacity;
+    // net wasm stack: [] -> []
+    expr_builder.local_get(localidx_ptr);
+    expr_builder.local_get(localidx_capacity);
+    expr_builder.i32_store(wasmgen::MemArg::new4(storage_offset));
+
+    // let it = ptr->storage + 4;
+    // let end = it + capacity * sizeof(Any);
+    // net wasm stack: [] -> []
+    expr_builder.local_get(localidx_ptr);
+    expr_builder.i32_const((storage_offset + 4) as i32);
+    expr_builder.i32_add();
+    expr_builder.local_tee(localidx_it);
+    expr_builder.local_get(localidx_capacity);
+    expr_builder.i32_const(12);
+    expr_builder.i32_mul();
+    expr_builder.i32_add();
+    expr_builder.local_set(localidx_end);
+
+    // note: this loop cannot be replaced by memory.fill even with bulk memory enabled,
+    // because memory.fill writes the same byte everywhere, but only every 12th i32 is a tag (and Undefined is not zero)
+    // net wasm stack: [] -> []
+    expr_builder.block(&[]);
+    expr_builder.loop_(&[]);
+    {
+        // while (it != end)
+        expr_builder.local_get(localidx_it);
+        expr_builder.local_get(localidx_end);
+        expr_builder.i32_eq();
+        expr_builder.br_if(1);
+
+        // it->tag = Undefined;
+        expr_builder.local_get(localidx_it);
+        expr_builder.i32_const(ir::VarType::Undefined.tag());
+        expr_builder.i32_store(wasmgen::MemArg::new4(0));
+
+        // it += 12;
+        expr_builder.local_get(localidx_it);
+        expr_builder.i32_const(12);
+        expr_builder.i32_add();
+        expr_builder.local_set(localidx_it);
+
+        expr_builder.br(0);
+    }
+    expr_builder.end();
+    expr_builder.end();
+
+    // net wasm stack: [] -> [i32(ptr)]
+    expr_builder.local_get(localidx_ptr);
+
+    scratch.pop_i32();
+    scratch.pop_i32();
+    scratch.pop_i32();
+}
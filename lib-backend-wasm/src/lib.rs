@@ -21,14 +21,23 @@
  * Boolean -> i32 (1: true; 0: false)
  * String -> i32 (ptr to unsized mem)
  * Func -> i32 (index in wasm table) + i32 (closure)
+ * Array -> i32 (ptr to header)
+ * Null -> <nothing>
  * StructT -> i32 (ptr to data)
  * Any -> i32 (tag) + i64 (data, reinterpret as the concrete type specified in the tag)
  *
- * Note on String (and in the future Array should be similar):
+ * Note on String:
  * * The content of a String is: length(4 bytes) followed by the content(length bytes).
  * * The pointer returned points to the `length` field.
  * * The actual size of the memory used is (length+4) bytes rounded up to nearest 4-byte boundary.
  *
+ * Note on Array:
+ * * An Array is a fixed-size header: length(4 bytes) followed by a pointer to the storage(4 bytes).
+ * * The storage is a separate heap object (tagged with ir::ARRAY_STORAGE_TAG): capacity(4 bytes) followed by `capacity` entries of Any(12 bytes each).
+ * * Entries past the length (but within the capacity) are always Undefined.
+ * * When the array grows past its capacity, a new storage is allocated and the header is updated to point to it,
+ *   so all references to the array (which point to the header) will see the new elements.
+ *
  * Most functions have a comment that looks like: net wasm stack: [...] -> [...]
  * This refers to net change to the wasm protected stack (top of stack on the right side, which agrees with the webassembly specification).
  * Stack elements in quotes (e.g. <ir_vartype>) means that that position of the stack contains a value (or values) of the given `ir_vartype` (not necessarily Any).
@@ -52,10 +61,33 @@
  * * * Note: By convention, arguments and return values on the stack go **on top** of the stack pointer.
  * * * So if we have a 12-byte value on the stack that is a return value, it will be at location (global#0 - 12).
  * * The GC might add more globals.  So the funcs should not make any assumption about the starting globalidx that they can use.
+ *
+ * Host imports:
+ * An import is called like a function whose params and result are the VarTypes of its ImportValTypes.
+ * So the wasm params of each import param are in the reverse order of the encoding above (bottom of the stack first),
+ * except that the host gets a handle to each function and any param:
+ * * boolean -> i32
+ * * function -> i32 (handle)
+ * * any -> i64 (data), i32 (tag, see ir::VarType::tag()), i32 (handle)
+ *
+ * A handle refers to a value that is kept on the gc_roots stack until the import returns,
+ * so it stays valid for the whole import call, even if the host calls back into the module (which might run the GC and move the value).
+ * A function or any result is returned in the same way as from any other function:
+ * as multiple values if the multi-value feature is enabled,
+ * otherwise the host writes it to the unprotected stack, i.e. to [stackptr - size, stackptr) in the same layout as in linear memory (the tag of an Any is at the lower address).
+ * If any import takes or returns a function or any, the module also exports:
+ * * "stack_pointer": [] -> [i32(stackptr)]
+ * * "call_function": [i32(handle), i32(num_args)] -> [<Any>], which calls the function referred to by the handle with the uniform calling convention
+ *   (if the handle refers to an any that is not a function, it raises a not callable error).
+ *   Before calling it, the host writes the args as Anys to the unprotected stack, the first arg at [stackptr - 12, stackptr), the next at [stackptr - 24, stackptr - 12), and so on.
+ *   The result is an Any, which (like other results) is written to [stackptr - 12, stackptr) if the multi-value feature is not enabled.
+ * Other values received by the host that contain pointers (strings, the data of an any, and the results of call_function)
+ * are only valid until the host calls back into the module, because the GC might move them during the call.
  */
 use ir;
 use wasmgen;
 
+mod array_prim_inst;
 mod func;
 mod gc;
 mod global_var;
@@ -63,7 +95,11 @@ mod multi_value_polyfill;
 mod mutcontext;
 mod opt_var_conv;
 mod pre_traverse;
+mod source_map;
 mod string_prim_inst;
+#[cfg(test)]
+mod test_support;
+mod trampoline;
 mod var_conv;
 
 use gc::cheney::Cheney;
@@ -73,22 +109,109 @@ use gc::HeapManager;
 use projstd::iter::*;
 use projstd::tuple::*;
 
+use boolinator::*;
+
 use wasmgen::Scratch;
 
+use std::collections::HashMap;
+
+pub use source_map::SourceMap;
+
 const IR_FUNCIDX_TABLE_OFFSET: u32 = 0; // If ir::FuncIdx == x, then wasmgen::TableIdx == IR_FUNCIDX_TABLE_OFFSET + x as u32
 
 const WASM_PAGE_SIZE: u32 = 65536;
 const WASM_PAGE_BITS: u32 = WASM_PAGE_SIZE.trailing_zeros();
 
+const ARRAY_HEADER_SIZE: u32 = 8; // length(i32) + storage ptr(i32), see the note on Array above
+
 // In units of WASM_PAGE_SIZE
 const MEM_STACK_SIZE: u32 = 1 << 4; // 1 MiB of stack space
 
+// Feature flags (as a bitmask), for hosts that pass the set of supported WebAssembly proposals as a single integer
+pub const FEATURE_MULTI_VALUE: u32 = 1 << 0;
+pub const FEATURE_BULK_MEMORY: u32 = 1 << 1;
+pub const FEATURE_TAIL_CALL: u32 = 1 << 2;
+// Not a WebAssembly proposal, but selects TailCallStrategy::Trampoline
+pub const FEATURE_TRAMPOLINE_TAIL_CALL: u32 = 1 << 3;
+
+// Strategy for encoding function calls in tail position, so that tail recursion runs in constant stack space.
+// If the WebAssembly tail call proposal is enabled, `return_call` is always used regardless of the strategy.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TailCallStrategy {
+    Call,       // Encode them like any other call (the stack grows with each tail call)
+    Trampoline, // Return a continuation to the caller, which invokes it in a loop
+}
+impl Default for TailCallStrategy {
+    fn default() -> Self {
+        TailCallStrategy::Call
+    }
+}
+
 // Struct containing compilation options
+// The default options only generate code that uses WebAssembly 1.0 features.
+// Use the builder methods to enable more features, e.g. `Options::new().wasm_multi_value(true)`.
 #[derive(Default, Copy, Clone)]
 pub struct Options {
     wasm_multi_value: bool, // Whether we can generate code that uses the WebAssembly multi-valued returns proposal
     wasm_bulk_memory: bool, // Whether we can generate code that uses the WebAssembly bulk memory proposal
     wasm_tail_call: bool, // Whether we can generate code that uses the WebAssembly tail call proposal
+    tail_call_strategy: TailCallStrategy, // How to encode tail calls if the tail call proposal is not enabled
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /**
+     * Makes the options from a bitmask of FEATURE_* flags.
+     * Unknown bits are ignored.
+     */
+    pub fn from_feature_flags(flags: u32) -> Self {
+        Self::new()
+            .wasm_multi_value(flags & FEATURE_MULTI_VALUE != 0)
+            .wasm_bulk_memory(flags & FEATURE_BULK_MEMORY != 0)
+            .wasm_tail_call(flags & FEATURE_TAIL_CALL != 0)
+            .tail_call_strategy(if flags & FEATURE_TRAMPOLINE_TAIL_CALL != 0 {
+                TailCallStrategy::Trampoline
+            } else {
+                TailCallStrategy::Call
+            })
+    }
+    /**
+     * Note: If multi-value is enabled, the entry point ("main") returns its result on the wasm stack instead of on the unprotected stack.
+     */
+    pub fn wasm_multi_value(mut self, enable: bool) -> Self {
+        self.wasm_multi_value = enable;
+        self
+    }
+    pub fn wasm_bulk_memory(mut self, enable: bool) -> Self {
+        self.wasm_bulk_memory = enable;
+        self
+    }
+    pub fn wasm_tail_call(mut self, enable: bool) -> Self {
+        self.wasm_tail_call = enable;
+        self
+    }
+    pub fn tail_call_strategy(mut self, strategy: TailCallStrategy) -> Self {
+        self.tail_call_strategy = strategy;
+        self
+    }
+    pub fn get_wasm_multi_value(&self) -> bool {
+        self.wasm_multi_value
+    }
+    pub fn get_wasm_bulk_memory(&self) -> bool {
+        self.wasm_bulk_memory
+    }
+    pub fn get_wasm_tail_call(&self) -> bool {
+        self.wasm_tail_call
+    }
+    pub fn get_tail_call_strategy(&self) -> TailCallStrategy {
+        self.tail_call_strategy
+    }
+    // Whether tail calls should be encoded using trampolines
+    fn use_trampoline(&self) -> bool {
+        !self.wasm_tail_call && self.tail_call_strategy == TailCallStrategy::Trampoline
+    }
 }
 
 /**
@@ -96,10 +219,27 @@ pub struct Options {
  * Call it, and everything will work.
  */
 pub fn run_backend(ir_program: &ir::Program, options: Options) -> wasmgen::WasmModule {
-    encode_program(ir_program, options)
+    encode_program(ir_program, options).0
+}
+
+/**
+ * Like run_backend(), but also returns the source map from code offsets in the serialized module
+ * to the locations of the SourceMarkers in the IR.
+ */
+pub fn run_backend_with_source_map(
+    ir_program: &ir::Program,
+    options: Options,
+) -> (wasmgen::WasmModule, SourceMap) {
+    let (wasm_module, marker_locations) = encode_program(ir_program, options);
+    let source_map = SourceMap::new(&wasm_module, &marker_locations);
+    (wasm_module, source_map)
 }
 
-fn encode_program(ir_program: &ir::Program, options: Options) -> wasmgen::WasmModule {
+// Returns the module, and the location of each marker id
+fn encode_program(
+    ir_program: &ir::Program,
+    options: Options,
+) -> (wasmgen::WasmModule, Vec<ir::SourceLocation>) {
     // (note: not the same was the wasm entry point!)
     // By convention, this is a normal function exported as "main")
 
@@ -128,18 +268,61 @@ fn encode_program(ir_program: &ir::Program, options: Options) -> wasmgen::WasmMo
         .imports
         .iter()
         .map(|ir_import| {
-            let import_param_list = encode_import_params(&ir_import.params);
-            let import_result = encode_import_param(ir_import.result);
+            let import_param_list = encode_import_param_list(&ir_import.params);
+            let import_result = func::encode_result(
+                Some(translate_import_param(ir_import.result)),
+                options.wasm_multi_value,
+            );
             wasm_module_builder.import_func(
                 ir_import.module_name.clone(),
                 ir_import.entity_name.clone(),
-                &wasmgen::FuncType::new(import_param_list, import_result.into()),
+                &wasmgen::FuncType::new(import_param_list, import_result),
             )
         })
         .collect();
 
     let mut wasm_module = wasm_module_builder.build();
 
+    // names for the wasm name section (directly maps from ir::FuncIdx)
+    // imports are named after their module and entity names
+    // funcs that share a name (e.g. operator overloads, or specialised copies made by the optimiser)
+    // are told apart by appending their param types
+    let mut name_counts: HashMap<&str, usize> = HashMap::new();
+    for ir_func in ir_program.funcs.iter() {
+        if let Some(name) = &ir_func.name {
+            *name_counts.entry(name.as_str()).or_default() += 1;
+        }
+    }
+    let func_names: Box<[String]> = ir_program
+        .imports
+        .iter()
+        .map(|ir_import| format!("{}.{}", ir_import.module_name, ir_import.entity_name))
+        .chain(
+            ir_program
+                .funcs
+                .iter()
+                .enumerate()
+                .map(|(i, ir_func)| match &ir_func.name {
+                    Some(name) if name_counts[name.as_str()] > 1 => format!(
+                        "{}({})",
+                        name,
+                        ir_func
+                            .params
+                            .iter()
+                            .map(|vartype| vartype.to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    ),
+                    Some(name) => name.clone(),
+                    None => format!("func#{}", ir_program.imports.len() + i),
+                }),
+        )
+        .collect();
+    wasm_module.set_func_name(error_func, "core.error".to_string());
+    for (name, wasm_funcidx) in func_names.iter().zip(imported_funcs.iter()) {
+        wasm_module.set_func_name(*wasm_funcidx, name.clone());
+    }
+
     // build the signature list (directly maps from ir::FuncIdx)
     let signature_list: Box<[func::Signature]> = ir_program
         .imports
@@ -158,6 +341,11 @@ fn encode_program(ir_program: &ir::Program, options: Options) -> wasmgen::WasmMo
     let globalidx_stackptr =
         wasm_module.add_i32_global(wasmgen::Mut::Var, (MEM_STACK_SIZE * WASM_PAGE_SIZE) as i32);
 
+    // add globals to hold the pending continuation (if we use trampolines for tail calls)
+    let trampoline: Option<trampoline::Trampoline> = options
+        .use_trampoline()
+        .as_some_from(|| trampoline::Trampoline::new(&mut wasm_module));
+
     // add ir global vars
     let global_var_manager =
         global_var::GlobalVarManager::make_from_ir_globals(&ir_program.globals, &mut wasm_module);
@@ -189,8 +377,22 @@ fn encode_program(ir_program: &ir::Program, options: Options) -> wasmgen::WasmMo
     let pre_traverse::TraverseResult {
         string_pool,
         thunk_sv,
-        appl_location_sv,
-    } = pre_traverse::pre_traverse_funcs(&ir_program.funcs);
+        mut appl_location_sv,
+        marker_location_sv,
+    } = pre_traverse::pre_traverse_funcs(&ir_program.funcs, options.use_trampoline());
+
+    // the host can only get a Func from an import that takes or returns a Func or Any,
+    // and when it calls the Func there is no source location to report errors at
+    let has_host_callbacks = ir_program.imports.iter().any(|ir_import| {
+        ir_import
+            .params
+            .iter()
+            .chain(std::iter::once(&ir_import.result))
+            .any(|ivt| matches!(ivt, ir::ImportValType::Func | ir::ImportValType::Any))
+    });
+    if has_host_callbacks {
+        appl_location_sv.insert_copy(&ir::SourceLocation::default());
+    }
 
     let (shifted_string_pool, pool_data) =
         string_pool.into_shifted_and_buffer(MEM_STACK_SIZE << WASM_PAGE_BITS);
@@ -205,6 +407,13 @@ fn encode_program(ir_program: &ir::Program, options: Options) -> wasmgen::WasmMo
 
     assert!(appl_data.len() & 3 == 0); // assert that it is at 4-byte boundary
 
+    // marker ids for the source map
+    let (marker_locations, marker_index) = marker_location_sv.into_parts();
+    let marker_encoder: HashMap<ir::SourceLocation, u32> = marker_index
+        .into_iter()
+        .map(|(location, i)| (location, i as u32))
+        .collect();
+
     // in terms of WASM_PAGE_SIZE (rounded up to nearest page boundary)
     let globals_num_pages: u32 =
         ((pool_data.len() + appl_data.len()) as u32 + (WASM_PAGE_SIZE - 1)) >> WASM_PAGE_BITS;
@@ -248,6 +457,7 @@ fn encode_program(ir_program: &ir::Program, options: Options) -> wasmgen::WasmMo
         MEM_STACK_SIZE + globals_num_pages + Cheney::initial_heap_size(),
         global_var_manager.deref(),
         error_func,
+        options.wasm_bulk_memory,
         &mut wasm_module,
     );
     /*let heap = Cheney::new(
@@ -265,8 +475,51 @@ fn encode_program(ir_program: &ir::Program, options: Options) -> wasmgen::WasmMo
     // can call it to allocate a returned string.
     encode_heap_alloc_exports(&heap, &mut wasm_module);
 
+    // Encode the functions that let the host call a Func (see the note on host imports above).
+    if has_host_callbacks {
+        encode_stack_pointer_export(globalidx_stackptr, &mut wasm_module);
+        func::encode_call_function_export(
+            *appl_data_encoder
+                .get(&ir::SourceLocation::default())
+                .unwrap(),
+            &heap,
+            error_func,
+            trampoline,
+            options,
+            &mut wasm_module,
+        );
+    }
+
+    // Imports that take a Func or Any are called through a wrapper that gives the host handles to them
+    // (see the note on host imports above).
+    let imported_funcs: Box<[wasmgen::FuncIdx]> = imported_funcs
+        .iter()
+        .zip(ir_program.imports.iter())
+        .zip(func_names.iter())
+        .map(|((wasm_funcidx, ir_import), name)| {
+            if ir_import
+                .params
+                .iter()
+                .any(|ivt| matches!(ivt, ir::ImportValType::Func | ir::ImportValType::Any))
+            {
+                func::encode_import_wrapper(
+                    *wasm_funcidx,
+                    name,
+                    &translate_import_params(&ir_import.params),
+                    Some(translate_import_param(ir_import.result)),
+                    &heap,
+                    options,
+                    &mut wasm_module,
+                )
+            } else {
+                *wasm_funcidx
+            }
+        })
+        .collect();
+
     func::encode_funcs(
         &signature_list, // for checking types of params and results only
+        &func_names,
         &ir_program.funcs,
         &ir_program.struct_types,
         &struct_field_byte_offsets,
@@ -274,9 +527,11 @@ fn encode_program(ir_program: &ir::Program, options: Options) -> wasmgen::WasmMo
         ir_program.entry_point,
         global_var_manager.deref(),
         globalidx_stackptr,
+        trampoline,
         memidx,
         thunk_sv,
         appl_data_encoder,
+        marker_encoder,
         &heap,
         &shifted_string_pool,
         error_func,
@@ -284,7 +539,7 @@ fn encode_program(ir_program: &ir::Program, options: Options) -> wasmgen::WasmMo
         &mut wasm_module,
     );
 
-    wasm_module
+    (wasm_module, marker_locations)
 }
 
 fn translate_import_params(ivts: &[ir::ImportValType]) -> Box<[ir::VarType]> {
@@ -294,27 +549,36 @@ fn translate_import_params(ivts: &[ir::ImportValType]) -> Box<[ir::VarType]> {
         .collect()
 }
 
-fn translate_import_param(ivt: ir::ImportValType) -> ir::VarType {
-    match ivt {
-        ir::ImportValType::Undefined => ir::VarType::Undefined,
-        ir::ImportValType::Number => ir::VarType::Number,
-        ir::ImportValType::String => ir::VarType::String,
-    }
-}
-
-fn encode_import_params(ivts: &[ir::ImportValType]) -> Box<[wasmgen::ValType]> {
+// The wasm params of an import, which are the same as those of a function with the translated signature,
+// except that a Func is replaced by its handle, and an Any is followed by its handle (see the note on host imports above)
+fn encode_import_param_list(ivts: &[ir::ImportValType]) -> Box<[wasmgen::ValType]> {
     ivts.iter()
         .copied()
-        .flat_map(|ivt| encode_import_param(ivt))
-        .copied()
+        .flat_map(|ivt| {
+            let (wasm_param_valtypes, _, _) =
+                func::encode_param_list(&[translate_import_param(ivt)]);
+            match ivt {
+                ir::ImportValType::Func => vec![wasmgen::ValType::I32],
+                ir::ImportValType::Any => wasm_param_valtypes
+                    .into_vec()
+                    .into_iter()
+                    .chain(std::iter::once(wasmgen::ValType::I32))
+                    .collect(),
+                _ => wasm_param_valtypes.into_vec(),
+            }
+        })
         .collect()
 }
 
-fn encode_import_param(ivt: ir::ImportValType) -> &'static [wasmgen::ValType] {
+// Imports are encoded in the same way as functions with the translated signature (see the note on host imports above)
+fn translate_import_param(ivt: ir::ImportValType) -> ir::VarType {
     match ivt {
-        ir::ImportValType::Undefined => &[],
-        ir::ImportValType::Number => &[wasmgen::ValType::F64],
-        ir::ImportValType::String => &[wasmgen::ValType::I32],
+        ir::ImportValType::Undefined => ir::VarType::Undefined,
+        ir::ImportValType::Number => ir::VarType::Number,
+        ir::ImportValType::String => ir::VarType::String,
+        ir::ImportValType::Boolean => ir::VarType::Boolean,
+        ir::ImportValType::Func => ir::VarType::Func,
+        ir::ImportValType::Any => ir::VarType::Any,
     }
 }
 
@@ -345,6 +609,7 @@ fn encode_heap_alloc_exports<H: HeapManager>(heap: &H, wasm_module: &mut wasmgen
         Box::new([wasmgen::ValType::I32]),
     );
     let (_, string_alloc_funcidx) = wasm_module.register_func(&wasm_functype);
+    wasm_module.set_func_name(string_alloc_funcidx, "allocate_string".to_string());
     let mut code_builder = wasmgen::CodeBuilder::new(wasm_functype);
     {
         let (locals_builder, expr_builder) = code_builder.split();
@@ -367,7 +632,178 @@ fn encode_heap_alloc_exports<H: HeapManager>(heap: &H, wasm_module: &mut wasmgen
     wasm_module.export_func(string_alloc_funcidx, "allocate_string".to_string());
 }
 
+/**
+ * Encodes a function that returns the stack pointer (global#0), and exports it as "stack_pointer".
+ * The host needs it to pass values that use the unprotected stack (see the note on host imports above).
+ */
+fn encode_stack_pointer_export(
+    stackptr: wasmgen::GlobalIdx,
+    wasm_module: &mut wasmgen::WasmModule,
+) {
+    // [] -> [i32(stackptr)]
+    let wasm_functype = wasmgen::FuncType::new(Box::new([]), Box::new([wasmgen::ValType::I32]));
+    let (_, stackptr_funcidx) = wasm_module.register_func(&wasm_functype);
+    wasm_module.set_func_name(stackptr_funcidx, "stack_pointer".to_string());
+    let mut code_builder = wasmgen::CodeBuilder::new(wasm_functype);
+    {
+        let (_, expr_builder) = code_builder.split();
+        expr_builder.global_get(stackptr);
+        expr_builder.end();
+    }
+    wasm_module.commit_func(stackptr_funcidx, code_builder);
+    wasm_module.export_func(stackptr_funcidx, "stack_pointer".to_string());
+}
+
 #[cfg(feature = "wasmtest")]
 pub fn wasmtest<C: wasm_test_harness::TestContext>(c: &mut C) {
     gc::cheney::wasmtest::wasmtest(c);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::*;
+
+    #[test]
+    fn recursive_closure_is_valid() {
+        // function fact(n) { return n === 0 ? 1 : n * fact(n - 1); } display(fact(5));
+        assert_valid_for_all_options(
+            r#"
+            struct ()
+            import "misc" "display" (number) -> undefined
+            global any
+            func (struct#0, any) -> any {
+              (typecast:void number narrow (var:any local 1)
+                (return:void (if:any (prim:boolean number_eq (var:number local 2) (number:number 0.0))
+                  (number:number 1.0)
+                  (typecast:any func narrow (var:any global 0)
+                    (typecast:any number narrow
+                      (appl:any (var:func local 3) [(prim:number number_sub (var:number local 2) (number:number 1.0))])
+                      (prim:number number_mul (var:number local 2) (var:number local 4))
+                      (trap:void 19 @0:1:3-1:4))
+                    (trap:void 22 @0:1:2-1:10))))
+                (trap:void 19 @0:1:3-1:4))
+            }
+            func () -> undefined {
+              (seq:undefined
+                (assign:undefined global 0 (func:func [1 closure] (struct:struct#0 0)))
+                (typecast:undefined func narrow (var:any global 0)
+                  (typecast:undefined number narrow (appl:any (var:func local 0) [(number:number 5.0)])
+                    (direct:undefined 0 (var:number local 1))
+                    (trap:void 17 @0:2:0-2:4))
+                  (trap:void 22 @0:2:0-2:4)))
+            }
+            entry 2
+            "#,
+        );
+    }
+
+    #[test]
+    fn loops_and_arrays_are_valid() {
+        // let a = []; let i = 0; while (i < 3) { a[i] = i * i; i = i + 1; } display(a[2]);
+        assert_valid_for_all_options(
+            r#"
+            import "misc" "display" (number) -> undefined
+            func () -> undefined {
+              (let:undefined array = (prim:array array_new (number:number 0.0))
+                (let:undefined number = (number:number 0.0)
+                  (seq:undefined
+                    (loop:undefined (if:undefined (prim:boolean number_lt (var:number local 1) (number:number 3.0))
+                      (seq:void
+                        (prim:undefined array_set (var:array local 0) (var:number local 1)
+                          (prim:number number_mul (var:number local 1) (var:number local 1)))
+                        (assign:undefined local 1 (prim:number number_add (var:number local 1) (number:number 1.0)))
+                        (break:void 0 (undefined:undefined)))
+                      (undefined:undefined)))
+                    (direct:undefined 0 (typecast:number number narrow
+                      (prim:any array_get (var:array local 0) (number:number 2.0))
+                      (var:number local 2)
+                      (trap:void 19))))))
+            }
+            entry 1
+            "#,
+        );
+    }
+
+    #[test]
+    fn name_section() {
+        let program = parse_and_optimize(
+            r#"
+            struct (any, any)
+            struct ()
+            import "misc" "display" (number) -> undefined
+            func "+" (number, number) -> number {
+              (prim:number number_add (var:number local 0) (var:number local 1))
+            }
+            func "+" (string, string) -> string {
+              (prim:string string_add (var:string local 0) (var:string local 1))
+            }
+            func "main" () -> undefined {
+              (let:undefined any = (number:number 2.0)
+                (let:undefined number = (direct:number 1 (number:number 1.0) (number:number 2.0))
+                  (seq:undefined
+                    (appl:any (func:func [1, 2] (undefined:undefined)) [(number:number 1.0) (var:any local 0)])
+                    (direct:undefined 0 (var:number local 1)))))
+            }
+            entry 3
+            "#,
+            ir::opt::OptLevel::O0,
+        );
+        for flags in all_feature_flags() {
+            let module = encode_and_decode(&program, Options::from_feature_flags(flags));
+            let func_names: HashMap<&str, wasmgen::FuncIdx> = module
+                .func_names()
+                .map(|(funcidx, name)| (name, funcidx))
+                .collect();
+            assert_eq!(
+                func_names.len(),
+                module.func_names().count(),
+                "duplicate function names with feature flags {}",
+                flags
+            );
+            for name in &[
+                "misc.display",
+                "core.error",
+                "+(number, number)",
+                "+(string, string)",
+                "main",
+                "do_cheney",
+                "gc_copy_children_struct#0",
+                "gc_copy_children_struct#1",
+                "gc_copy_indirect_struct#0",
+                "gc_copy_indirect_struct#1",
+                "thunk[+(number, number), +(string, string)]",
+            ] {
+                assert!(
+                    func_names.contains_key(name),
+                    "no function named {} with feature flags {}",
+                    name,
+                    flags
+                );
+            }
+            let local_names = |name: &str| -> Vec<String> {
+                module
+                    .local_names(func_names[name])
+                    .map(|(_, name)| name.to_owned())
+                    .collect()
+            };
+            assert_eq!(local_names("+(number, number)"), vec!["param0", "param1"]);
+            assert_eq!(
+                local_names("thunk[+(number, number), +(string, string)]"),
+                vec!["closure", "num_params", "callerid"]
+            );
+            let main_local_names = local_names("main");
+            for name in &["local0.tag", "local0.data", "local1"] {
+                assert!(
+                    main_local_names
+                        .iter()
+                        .any(|n| n.split('/').any(|n| n == *name)),
+                    "main has no local named {} with feature flags {}: {:?}",
+                    name,
+                    flags,
+                    main_local_names
+                );
+            }
+        }
+    }
+}
@@ -3,6 +3,51 @@ use wasmgen::ExprBuilder;
 use wasmgen::Scratch;
 use wasmgen::ValType;
 
+// Emits the header of an if-stmt, using a type index if there is more than one value (only valid if multi-value is enabled)
+fn encode_if_header(valtypes: &[ValType], mutctx: &mut MutContext, expr_builder: &mut ExprBuilder) {
+    if valtypes.len() <= 1 {
+        expr_builder.if_(valtypes);
+    } else {
+        expr_builder.if_typeidx(block_typeidx(valtypes, mutctx));
+    }
+}
+
+// Emits the header of a block, using a type index if there is more than one value (only valid if multi-value is enabled)
+fn encode_block_header(
+    valtypes: &[ValType],
+    mutctx: &mut MutContext,
+    expr_builder: &mut ExprBuilder,
+) {
+    if valtypes.len() <= 1 {
+        expr_builder.block(valtypes);
+    } else {
+        expr_builder.block_typeidx(block_typeidx(valtypes, mutctx));
+    }
+}
+
+// Emits the header of a loop, using a type index if there is more than one value (only valid if multi-value is enabled)
+fn encode_loop_header(
+    valtypes: &[ValType],
+    mutctx: &mut MutContext,
+    expr_builder: &mut ExprBuilder,
+) {
+    if valtypes.len() <= 1 {
+        expr_builder.loop_(valtypes);
+    } else {
+        expr_builder.loop_typeidx(block_typeidx(valtypes, mutctx));
+    }
+}
+
+// note: `valtypes` lists the top of the stack first, but wasm result types list the bottom of the stack first
+fn block_typeidx(valtypes: &[ValType], mutctx: &mut MutContext) -> wasmgen::TypeIdx {
+    mutctx
+        .module_wrapper()
+        .add_wasm_type(wasmgen::FuncType::new(
+            Box::new([]),
+            valtypes.iter().rev().copied().collect(),
+        ))
+}
+
 // Encodes an if-stmt (with 'else' part), abstracting over the issues relating to lack of multi-value support by spawning new locals if necessary
 // net wasm stack [i32 cond] -> [valtypes...]
 pub fn if_<
@@ -18,7 +63,7 @@ pub fn if_<
 ) {
     if use_multi_value || valtypes.len() <= 1 {
         // net wasm stack [i32 cond] -> [valtypes...]
-        expr_builder.if_(valtypes);
+        encode_if_header(valtypes, mutctx, expr_builder);
         {
             // net wasm stack [] -> [valtypes...]
             true_encoder(mutctx, expr_builder);
@@ -71,7 +116,7 @@ pub fn if_without_else<F: FnOnce(&mut MutContext, &mut ExprBuilder)>(
 ) {
     if use_multi_value || valtypes.len() <= 1 {
         // net wasm stack [i32 cond] -> [valtypes...]
-        expr_builder.if_(valtypes);
+        encode_if_header(valtypes, mutctx, expr_builder);
         {
             // net wasm stack [] -> [valtypes...]
             true_encoder(mutctx, expr_builder);
@@ -140,7 +185,7 @@ pub fn block<F: FnOnce(&mut MutContext, &[wasmgen::LocalIdx], &mut ExprBuilder)>
 ) {
     if use_multi_value || valtypes.len() <= 1 {
         // net wasm stack [i32 cond] -> [valtypes...]
-        expr_builder.block(valtypes);
+        encode_block_header(valtypes, mutctx, expr_builder);
         {
             // net wasm stack [] -> [valtypes...]
             inner_encoder(mutctx, &[], expr_builder);
@@ -170,6 +215,47 @@ pub fn block<F: FnOnce(&mut MutContext, &[wasmgen::LocalIdx], &mut ExprBuilder)>
     }
 }
 
+// Encodes a loop, abstracting over the issues relating to lack of multi-value support by spawning new locals if necessary
+// Branches that target the loop do not carry any values, so no landing context is needed for them.
+// net wasm stack [] -> [valtypes...]
+pub fn loop_<F: FnOnce(&mut MutContext, &mut ExprBuilder)>(
+    valtypes: &[ValType],
+    use_multi_value: bool,
+    mutctx: &mut MutContext,
+    expr_builder: &mut ExprBuilder,
+    inner_encoder: F,
+) {
+    if use_multi_value || valtypes.len() <= 1 {
+        encode_loop_header(valtypes, mutctx, expr_builder);
+        {
+            // net wasm stack [] -> [valtypes...]
+            inner_encoder(mutctx, expr_builder);
+        }
+        expr_builder.end();
+    } else {
+        // we don't have multi-value enabled, but we have more than one value.  The last value (deepest in the stack) is left onto the stack, but everything else goes into locals.
+        let (rest, last) = valtypes.split_at(valtypes.len() - 1);
+
+        // make temporary variables for them
+        mutctx.with_scratches(rest, |mutctx, tmp_locals| {
+            expr_builder.loop_(last);
+            {
+                // net wasm stack [] -> [valtypes...]
+                inner_encoder(mutctx, expr_builder);
+                // net wasm stack [valtypes...] -> [last]
+                tmp_locals.iter().copied().for_each(|localidx| {
+                    expr_builder.local_set(localidx);
+                });
+            }
+            expr_builder.end();
+            // net wasm stack [last] -> [valtypes...]
+            tmp_locals.iter().copied().rev().for_each(|localidx| {
+                expr_builder.local_get(localidx);
+            });
+        });
+    }
+}
+
 pub fn break_(
     landing_idx: usize,
     landing_ctx: &[wasmgen::LocalIdx],
@@ -32,6 +32,8 @@ pub struct MutContext<'a, 'b> {
     // information for calculating and encoding Break exprs
     ir_landings: Vec<(usize, ir::VarType, Box<[wasmgen::LocalIdx]>)>, // first item of the pair is the landing index (1-based), can be equal to (but no more than) wasm_landing_count
     wasm_landing_count: usize,
+    // names of the wasm locals that hold named locals, for the wasm name section (a wasm local may be reused by several named locals)
+    local_names: Vec<(wasmgen::LocalIdx, String)>,
     // Global for whole program
     module_wrapper: ModuleEncodeWrapper<'b>,
     // will also include function indices
@@ -53,6 +55,7 @@ impl<'a, 'b> MutContext<'a, 'b> {
             named_local_map: (0..num_locals).collect(),
             ir_landings: Vec::new(),
             wasm_landing_count: 0,
+            local_names: Vec::new(),
             module_wrapper: module_wrapper,
         }
     }
@@ -108,6 +111,7 @@ impl<'a, 'b> MutContext<'a, 'b> {
             move |mutctx, expr_builder, ir_localidx| {
                 let named_ir_localidx = mutctx.named_local_map.len();
                 mutctx.named_local_map.push(ir_localidx);
+                mutctx.record_local_name(ir_localidx, named_ir_localidx);
                 let ret = f(mutctx, expr_builder, named_ir_localidx);
                 mutctx.named_local_map.pop();
                 ret
@@ -140,6 +144,7 @@ impl<'a, 'b> MutContext<'a, 'b> {
         self.with_uninitialized_shadow_local(ir_vartype, move |mutctx, ir_localidx| {
             let named_ir_localidx = mutctx.named_local_map.len();
             mutctx.named_local_map.push(ir_localidx);
+            mutctx.record_local_name(ir_localidx, named_ir_localidx);
             let ret = f(mutctx, named_ir_localidx);
             mutctx.named_local_map.pop();
             ret
@@ -240,6 +245,25 @@ impl<'a, 'b> MutContext<'a, 'b> {
         self.local_types.pop();
     }
 
+    /**
+     * Records the names of the wasm locals of a named local, which are named after its ir localidx.
+     */
+    fn record_local_name(&mut self, ir_localidx: usize, named_ir_localidx: usize) {
+        let names = encode_local_names(
+            &format!("local{}", named_ir_localidx),
+            self.local_types[ir_localidx],
+        );
+        let wasm_localidxs: Vec<wasmgen::LocalIdx> = self.wasm_local_slice(ir_localidx).to_vec();
+        self.local_names
+            .extend(wasm_localidxs.into_iter().zip(names.into_vec()));
+    }
+    /**
+     * Returns the names of the wasm locals that held named locals (see record_local_name()).
+     */
+    pub fn take_local_names(&mut self) -> Vec<(wasmgen::LocalIdx, String)> {
+        std::mem::take(&mut self.local_names)
+    }
+
     pub fn named_local_types_elem(&self, named_idx: usize) -> ir::VarType {
         self.local_types[self.named_local_map[named_idx]]
     }
@@ -27,6 +27,7 @@ pub struct TraverseResult {
     // (note: we can know the signature from the funcidx)
     pub thunk_sv: SearchableVec<Box<[ir::OverloadEntry]>>,
     pub appl_location_sv: SearchableVec<ir::SourceLocation>,
+    pub marker_location_sv: SearchableVec<ir::SourceLocation>,
 }
 
 /*
@@ -34,39 +35,42 @@ Traverses the IR to:
 - put all string constants in a string pool, and encodes the static data buffer
 - put all overload sets (thunks) in a SearchableVec
 - extract all SourceLocations in Appls into a SearchableVec
+- extract all SourceLocations in SourceMarkers into another SearchableVec
+- if `trampoline` is true, put the thunks for DirectAppls in tail position in the SearchableVec too (because the trampoline calls them indirectly)
 */
-pub fn pre_traverse_funcs(funcs: &[ir::Func]) -> TraverseResult {
+pub fn pre_traverse_funcs(funcs: &[ir::Func], trampoline: bool) -> TraverseResult {
     let mut res = TraverseResult::default();
     for func in funcs {
-        pre_traverse_func(func, &mut res);
+        pre_traverse_func(func, trampoline, &mut res);
     }
     res
 }
 
-fn pre_traverse_func(func: &ir::Func, res: &mut TraverseResult) {
-    pre_traverse_expr(&func.expr, res);
+fn pre_traverse_func(func: &ir::Func, trampoline: bool, res: &mut TraverseResult) {
+    pre_traverse_expr(&func.expr, trampoline, res);
 }
 
-fn pre_traverse_exprs(exprs: &[ir::Expr], res: &mut TraverseResult) {
+fn pre_traverse_exprs(exprs: &[ir::Expr], trampoline: bool, res: &mut TraverseResult) {
     for expr in exprs {
-        pre_traverse_expr(expr, res);
+        pre_traverse_expr(expr, trampoline, res);
     }
 }
 
-fn pre_traverse_expr(expr: &ir::Expr, res: &mut TraverseResult) {
-    pre_traverse_expr_kind(&expr.kind, res);
+fn pre_traverse_expr(expr: &ir::Expr, trampoline: bool, res: &mut TraverseResult) {
+    pre_traverse_expr_kind(&expr.kind, trampoline, res);
 }
 
-fn pre_traverse_expr_kind(expr_kind: &ir::ExprKind, res: &mut TraverseResult) {
+fn pre_traverse_expr_kind(expr_kind: &ir::ExprKind, trampoline: bool, res: &mut TraverseResult) {
     match expr_kind {
         ir::ExprKind::PrimUndefined
+        | ir::ExprKind::PrimNull
         | ir::ExprKind::PrimNumber { val: _ }
         | ir::ExprKind::PrimBoolean { val: _ }
         | ir::ExprKind::PrimStructT { typeidx: _ } => {}
         ir::ExprKind::PrimString { val } => res.string_pool.insert(val),
         ir::ExprKind::PrimFunc { funcidxs, closure } => {
             res.thunk_sv.insert_copy(funcidxs);
-            pre_traverse_expr(closure, res);
+            pre_traverse_expr(closure, trampoline, res);
         }
         ir::ExprKind::TypeCast {
             test,
@@ -75,30 +79,30 @@ fn pre_traverse_expr_kind(expr_kind: &ir::ExprKind, res: &mut TraverseResult) {
             true_expr,
             false_expr,
         } => {
-            pre_traverse_expr(test, res);
-            pre_traverse_expr(true_expr, res);
-            pre_traverse_expr(false_expr, res);
+            pre_traverse_expr(test, trampoline, res);
+            pre_traverse_expr(true_expr, trampoline, res);
+            pre_traverse_expr(false_expr, trampoline, res);
         }
         ir::ExprKind::VarName { source: _ } => {}
-        ir::ExprKind::PrimAppl { prim_inst: _, args } => pre_traverse_exprs(args, res),
+        ir::ExprKind::PrimAppl { prim_inst: _, args } => pre_traverse_exprs(args, trampoline, res),
         ir::ExprKind::Appl {
             func,
             args,
             location,
         } => {
-            pre_traverse_expr(func, res);
-            pre_traverse_exprs(args, res);
+            pre_traverse_expr(func, trampoline, res);
+            pre_traverse_exprs(args, trampoline, res);
             res.appl_location_sv.insert_copy(location);
         }
-        ir::ExprKind::DirectAppl { funcidx: _, args } => pre_traverse_exprs(args, res),
+        ir::ExprKind::DirectAppl { funcidx: _, args } => pre_traverse_exprs(args, trampoline, res),
         ir::ExprKind::Conditional {
             cond,
             true_expr,
             false_expr,
         } => {
-            pre_traverse_expr(cond, res);
-            pre_traverse_expr(true_expr, res);
-            pre_traverse_expr(false_expr, res);
+            pre_traverse_expr(cond, trampoline, res);
+            pre_traverse_expr(true_expr, trampoline, res);
+            pre_traverse_expr(false_expr, trampoline, res);
         }
         ir::ExprKind::Declaration {
             local: _,
@@ -106,27 +110,65 @@ fn pre_traverse_expr_kind(expr_kind: &ir::ExprKind, res: &mut TraverseResult) {
             contained_expr,
         } => {
             if let Some(init_expr) = init {
-                pre_traverse_expr(init_expr, res);
+                pre_traverse_expr(init_expr, trampoline, res);
             }
-            pre_traverse_expr(contained_expr, res);
+            pre_traverse_expr(contained_expr, trampoline, res);
+        }
+        ir::ExprKind::Return { expr } => {
+            if trampoline {
+                pre_traverse_tail_direct_appls(expr, res);
+            }
+            pre_traverse_expr(expr, trampoline, res)
         }
         ir::ExprKind::Assign { target: _, expr }
-        | ir::ExprKind::Return { expr }
         | ir::ExprKind::Break {
             num_frames: _,
             expr,
         }
-        | ir::ExprKind::Block { expr } => pre_traverse_expr(expr, res),
+        | ir::ExprKind::Block { expr }
+        | ir::ExprKind::Loop { expr } => pre_traverse_expr(expr, trampoline, res),
         ir::ExprKind::Sequence { content } => {
-            pre_traverse_exprs(content, res);
+            pre_traverse_exprs(content, trampoline, res);
         }
         ir::ExprKind::Trap {
             code: _,
             location: _,
         } => {}
+        ir::ExprKind::SourceMarker { location } => {
+            res.marker_location_sv.insert_copy(location);
+        }
     };
 }
 
+// Puts the thunks for DirectAppls in tail position of `expr` into the SearchableVec.
+// This must find at least the same DirectAppls as func::is_tail_callable().
+fn pre_traverse_tail_direct_appls(expr: &ir::Expr, res: &mut TraverseResult) {
+    match &expr.kind {
+        ir::ExprKind::DirectAppl { funcidx, args: _ } => {
+            res.thunk_sv.insert(Box::new([ir::OverloadEntry {
+                funcidx: *funcidx,
+                has_closure_param: false,
+            }]));
+        }
+        ir::ExprKind::Conditional {
+            cond: _,
+            true_expr,
+            false_expr,
+        } => {
+            pre_traverse_tail_direct_appls(true_expr, res);
+            pre_traverse_tail_direct_appls(false_expr, res);
+        }
+        ir::ExprKind::Declaration {
+            local: _,
+            init: Some(_),
+            contained_expr,
+        } => {
+            pre_traverse_tail_direct_appls(contained_expr, res);
+        }
+        _ => {}
+    }
+}
+
 impl StringPool {
     /*
     Inserts this string into the string pool if it doesn't already exist.
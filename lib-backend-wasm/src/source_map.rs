@@ -0,0 +1,124 @@
+/**
+ * Generates source maps (version 3, see https://sourcemaps.info/spec.html) for the encoded module.
+ * The frontend puts a SourceMarker before each statement, and the backend records the code offset at each marker.
+ *
+ * For WebAssembly, browser devtools treat the whole module as a single line, so the generated "column" of each mapping
+ * is the byte offset from the start of the serialized module.
+ * The module should point to the source map with the "sourceMappingURL" custom section (see WasmModule::set_source_mapping_url()).
+ */
+pub struct SourceMap {
+    entries: Vec<(u32, ir::SourceLocation)>, // (byte offset in the serialized module, location of the statement), in increasing order of byte offset
+}
+
+impl SourceMap {
+    pub(crate) fn new(
+        wasm_module: &wasmgen::WasmModule,
+        marker_locations: &[ir::SourceLocation],
+    ) -> Self {
+        let mut entries: Vec<(u32, ir::SourceLocation)> = Vec::new();
+        for (offset, id) in wasm_module.marker_offsets() {
+            let location = marker_locations[id as usize];
+            match entries.last_mut() {
+                // statements that did not generate any code share the offset of the next statement, which is the one that we want to keep
+                Some(last) if last.0 == offset => last.1 = location,
+                _ => entries.push((offset, location)),
+            }
+        }
+        SourceMap { entries: entries }
+    }
+
+    pub fn entries(&self) -> &[(u32, ir::SourceLocation)] {
+        &self.entries
+    }
+
+    /**
+     * Serializes the source map as JSON.
+     * `sources` contains the file name for each fileidx used in the SourceLocations.
+     */
+    pub fn to_json(&self, sources: &[&str]) -> String {
+        let mut mappings = String::new();
+        // each field of a segment is relative to the same field in the previous segment
+        let mut prev: [i64; 4] = [0; 4]; // [generated column, source index, original line, original column]
+        for (offset, location) in &self.entries {
+            let curr: [i64; 4] = [
+                *offset as i64,
+                location.file as i64,
+                location.start.line as i64 - 1, // ESTree lines start from 1, but source map lines start from 0
+                location.start.column as i64,
+            ];
+            if !mappings.is_empty() {
+                mappings.push(',');
+            }
+            for (c, p) in curr.iter().zip(prev.iter()) {
+                encode_vlq(c - p, &mut mappings);
+            }
+            prev = curr;
+        }
+        format!(
+            "{{\"version\":3,\"sources\":[{}],\"names\":[],\"mappings\":\"{}\"}}",
+            sources
+                .iter()
+                .map(|source| json_string(source))
+                .collect::<Vec<String>>()
+                .join(","),
+            mappings
+        )
+    }
+}
+
+// Appends the base64 VLQ encoding of the value
+fn encode_vlq(val: i64, out: &mut String) {
+    const BASE64_CHARS: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    // the sign is stored in the least significant bit
+    let mut rest: u64 = if val < 0 {
+        ((-val as u64) << 1) | 1
+    } else {
+        (val as u64) << 1
+    };
+    loop {
+        let digit = (rest & 31) as usize;
+        rest >>= 5;
+        if rest == 0 {
+            out.push(BASE64_CHARS[digit] as char);
+            break;
+        }
+        out.push(BASE64_CHARS[digit | 32] as char);
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut ret = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vlq(val: i64) -> String {
+        let mut out = String::new();
+        encode_vlq(val, &mut out);
+        out
+    }
+
+    #[test]
+    fn encode_vlq_values() {
+        assert_eq!(vlq(0), "A");
+        assert_eq!(vlq(1), "C");
+        assert_eq!(vlq(-1), "D");
+        assert_eq!(vlq(15), "e");
+        assert_eq!(vlq(16), "gB");
+        assert_eq!(vlq(-16), "hB");
+        assert_eq!(vlq(1000), "w+B");
+    }
+}
@@ -789,3 +789,47 @@ pub fn encode_string_add<H: HeapManager>(
         });
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+
+    #[test]
+    fn string_concatenation_and_gc_with_bulk_memory() {
+        // let s = "ab"; for (let i = 0; i < 15; i = i + 1) { s = s + s; }
+        // const t = s + "c";
+        // for (let i = 0; i < 20; i = i + 1) { const garbage = []; garbage[29999] = 0; }
+        // (t === s + "c" ? 1 : 0) + (s < t ? 10 : 0);
+        // (the GC moves s and t while allocating the garbage)
+        let text = format!(
+            r#"
+            func () -> number {{
+              (let:void string = (string:string "ab")
+                (let:void number = (number:number 0.0)
+                  (seq:void
+                    {}
+                    (let:void string = (prim:string string_add (var:string local 0) (string:string "c"))
+                      (seq:void
+                        {}
+                        (return:void (prim:number number_add
+                          (if:number (prim:boolean string_eq (var:string local 2)
+                              (prim:string string_add (var:string local 0) (string:string "c")))
+                            (number:number 1.0)
+                            (number:number 0.0))
+                          (if:number (prim:boolean string_lt (var:string local 0) (var:string local 2))
+                            (number:number 10.0)
+                            (number:number 0.0)))))))))
+            }}
+            entry 0
+            "#,
+            counted_loop(
+                1,
+                15,
+                "(assign:undefined local 0 (prim:string string_add (var:string local 0) (var:string local 0)))"
+            ),
+            allocate_garbage(1, 3)
+        );
+        let program = parse_and_optimize(&text, ir::opt::OptLevel::O0);
+        assert_result_for_all_options(&program, (number(11.0), vec![]));
+    }
+}
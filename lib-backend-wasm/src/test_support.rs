@@ -0,0 +1,317 @@
+/**
+ * Helpers for the tests of the backend.
+ * Test programs are written in the IR text format, and the encoded modules go through the binary format
+ * (so that wasmgen's validator checks them) before they are run with wasmgen's interpreter.
+ */
+use crate::{run_backend, Options, FEATURE_TRAMPOLINE_TAIL_CALL, MEM_STACK_SIZE, WASM_PAGE_SIZE};
+use wasmgen::interp::{Caller, Host, Instance, Trap, Value};
+use wasmgen::WasmSerialize;
+
+/**
+ * Every combination of feature flags.
+ */
+pub fn all_feature_flags() -> std::ops::Range<u32> {
+    0..(FEATURE_TRAMPOLINE_TAIL_CALL << 1)
+}
+
+/**
+ * Parses the program and optimizes it at the given level.
+ */
+pub fn parse_and_optimize(text: &str, level: ir::opt::OptLevel) -> ir::Program {
+    ir::opt::optimize(
+        text.parse().unwrap(),
+        &ir::opt::OptOptions::new().level(level),
+    )
+}
+
+/**
+ * Compiles the program with the given options, and decodes the module from its binary format.
+ */
+pub fn encode_and_decode(program: &ir::Program, options: Options) -> wasmgen::WasmModule {
+    let mut bytes = Vec::<u8>::new();
+    run_backend(program, options).wasm_serialize(&mut bytes);
+    wasmgen::WasmModule::decode(&bytes).unwrap()
+}
+
+/**
+ * Compiles the program with every combination of feature flags,
+ * and checks that each module is valid after going through the binary format.
+ */
+pub fn assert_valid_for_all_options(text: &str) {
+    let program = parse_and_optimize(text, ir::opt::OptLevel::default());
+    for flags in all_feature_flags() {
+        let module = encode_and_decode(&program, Options::from_feature_flags(flags));
+        if let Err(e) = module.validate() {
+            panic!("invalid module with feature flags {}: {}", flags, e);
+        }
+    }
+}
+
+/**
+ * Provides the imports of the test programs: "misc" "display" (number) records the number that was displayed,
+ * and "core" "error" traps with the error code.
+ */
+pub struct TestHost {
+    pub displayed: Vec<f64>,
+}
+
+impl Host for TestHost {
+    fn call_import(
+        &mut self,
+        module_name: &str,
+        entity_name: &str,
+        args: &[Value],
+        _caller: &mut Caller,
+    ) -> Result<Box<[Value]>, Trap> {
+        match (module_name, entity_name, args) {
+            ("misc", "display", [Value::F64(x)]) => {
+                self.displayed.push(*x);
+                Ok(Box::new([]))
+            }
+            _ => unexpected_import(module_name, entity_name, args),
+        }
+    }
+}
+
+/**
+ * Traps with the error code for "core" "error", and with a message naming the import for anything else.
+ * Hosts call this for the imports that they do not provide themselves.
+ */
+pub fn unexpected_import(
+    module_name: &str,
+    entity_name: &str,
+    args: &[Value],
+) -> Result<Box<[Value]>, Trap> {
+    match (module_name, entity_name, args) {
+        ("core", "error", [Value::I32(code), ..]) => Err(Trap {
+            message: format!("error {}", code),
+        }),
+        _ => Err(Trap {
+            message: format!("unexpected import \"{}\" \"{}\"", module_name, entity_name),
+        }),
+    }
+}
+
+/**
+ * The value returned by "main" (as the tag and data of an Any, with the unused bits cleared) or the trap message,
+ * and the numbers that were displayed.
+ */
+pub type RunResult = (Result<(i32, u64), String>, Vec<f64>);
+
+pub fn number(x: f64) -> Result<(i32, u64), String> {
+    Ok((ir::VarType::Number.tag(), x.to_bits()))
+}
+
+/**
+ * Reads the Any at the top of the unprotected stack, i.e. at [stackptr - 12, stackptr), as (tag, data).
+ */
+fn read_stack_any(memory: &[u8], stackptr: usize) -> (i32, u64) {
+    let mut tag_bytes = [0u8; 4];
+    tag_bytes.copy_from_slice(&memory[(stackptr - 12)..(stackptr - 8)]);
+    let mut data_bytes = [0u8; 8];
+    data_bytes.copy_from_slice(&memory[(stackptr - 8)..stackptr]);
+    (
+        i32::from_le_bytes(tag_bytes),
+        u64::from_le_bytes(data_bytes),
+    )
+}
+
+/**
+ * Compiles the (already optimized) program with the given options, checks that the module is valid,
+ * and runs it with wasmgen's interpreter.
+ */
+pub fn run_with_options(program: &ir::Program, options: Options) -> RunResult {
+    let module = encode_and_decode(program, options);
+    if let Err(e) = module.validate() {
+        panic!("invalid module: {}", e);
+    }
+    let mut host = TestHost {
+        displayed: Vec::new(),
+    };
+    let mut instance = Instance::new(&module, &mut host).unwrap();
+    let result_vartype = program.get_func(program.entry_point).result;
+    let result = instance
+        .invoke("main", &[], &mut host)
+        .map_err(|trap| trap.message)
+        .map(|values| {
+            let result_vartype = result_vartype.expect("entry point returned but has void type");
+            let (tag, data) = match (result_vartype, &*values) {
+                (ir::VarType::Any, [Value::I64(data), Value::I32(tag)]) => (*tag, *data as u64),
+                (ir::VarType::Any, []) => {
+                    // the result is on the unprotected stack
+                    read_stack_any(
+                        instance.memory(),
+                        (MEM_STACK_SIZE * WASM_PAGE_SIZE) as usize,
+                    )
+                }
+                (vartype, []) => (vartype.tag(), 0),
+                (vartype, [Value::F64(x)]) => (vartype.tag(), x.to_bits()),
+                (vartype, [Value::I32(x)]) => (vartype.tag(), *x as u32 as u64),
+                (vartype, _) => panic!("main returned the wrong types for {}", vartype),
+            };
+            if tag == ir::VarType::Number.tag() {
+                (tag, data)
+            } else if tag == ir::VarType::Undefined.tag() || tag == ir::VarType::Null.tag() {
+                (tag, 0)
+            } else {
+                (tag, data & 0xffff_ffff)
+            }
+        });
+    (result, host.displayed)
+}
+
+/**
+ * Optimizes the program at the default level, runs it with every combination of feature flags,
+ * and checks that they all give the same result.
+ */
+pub fn run_for_all_options(text: &str) -> RunResult {
+    let program = parse_and_optimize(text, ir::opt::OptLevel::default());
+    let expected = run_with_options(&program, Options::default());
+    for flags in all_feature_flags().skip(1) {
+        assert_eq!(
+            run_with_options(&program, Options::from_feature_flags(flags)),
+            expected,
+            "different result with feature flags {}",
+            flags
+        );
+    }
+    expected
+}
+
+/**
+ * Runs the (already optimized) program with every combination of feature flags,
+ * and checks that each of them gives the expected result.
+ */
+pub fn assert_result_for_all_options(program: &ir::Program, expected: RunResult) {
+    for flags in all_feature_flags() {
+        assert_eq!(
+            run_with_options(program, Options::from_feature_flags(flags)),
+            expected,
+            "wrong result with feature flags {}",
+            flags
+        );
+    }
+}
+
+/**
+ * A loop (of type undefined) that runs the body `count` times, with the number local `counter` going from 0 to count - 1.
+ * The body should have type void or undefined, and must not assign to the counter.
+ */
+pub fn counted_loop(counter: usize, count: u32, body: &str) -> String {
+    format!(
+        "(seq:undefined
+          (assign:undefined local {0} (number:number 0.0))
+          (loop:undefined (if:undefined (prim:boolean number_lt (var:number local {0}) (number:number {1}.0))
+            (seq:void
+              {2}
+              (assign:undefined local {0} (prim:number number_add (var:number local {0}) (number:number 1.0)))
+              (break:void 0 (undefined:undefined)))
+            (undefined:undefined))))",
+        counter, count, body
+    )
+}
+
+/**
+ * A loop that allocates more than the usable heap in garbage arrays, so that the GC moves everything that is still live.
+ * `garbage` is the index that the array local (declared by the loop) gets.
+ */
+pub fn allocate_garbage(counter: usize, garbage: usize) -> String {
+    counted_loop(
+        counter,
+        20,
+        &format!(
+            "(let:undefined array = (prim:array array_new (number:number 0.0))
+              (prim:undefined array_set (var:array local {}) (number:number 29999.0) (number:number 0.0)))",
+            garbage
+        ),
+    )
+}
+
+/**
+ * Calls the function referred to by `handle` with number args, in the way described in the note on host imports,
+ * and returns the data and tag of the result (or the message of the trap).
+ */
+pub fn call_back<H: Host>(
+    caller: &mut Caller,
+    host: &mut H,
+    handle: i32,
+    args: &[f64],
+) -> Result<(u64, i32), String> {
+    fn stack_pointer<H: Host>(caller: &mut Caller, host: &mut H) -> usize {
+        match &*caller.invoke("stack_pointer", &[], host).unwrap() {
+            [Value::I32(stackptr)] => *stackptr as usize,
+            _ => panic!("stack_pointer returned the wrong types"),
+        }
+    }
+    let stackptr = stack_pointer(caller, host);
+    for (i, arg) in args.iter().enumerate() {
+        let memory = caller.memory_mut();
+        let addr = stackptr - 12 * (i + 1);
+        memory[addr..(addr + 4)].copy_from_slice(&ir::VarType::Number.tag().to_le_bytes());
+        memory[(addr + 4)..(addr + 12)].copy_from_slice(&arg.to_bits().to_le_bytes());
+    }
+    let result = caller
+        .invoke(
+            "call_function",
+            &[Value::I32(handle), Value::I32(args.len() as i32)],
+            host,
+        )
+        .map_err(|trap| trap.message)?;
+    match &*result {
+        [Value::I64(data), Value::I32(tag)] => Ok((*data as u64, *tag)),
+        [] => {
+            // the result is on the unprotected stack
+            let stackptr = stack_pointer(caller, host);
+            let (tag, data) = read_stack_any(caller.memory(), stackptr);
+            Ok((data, tag))
+        }
+        _ => panic!("call_function returned the wrong types: {:?}", result),
+    }
+}
+
+// function f(n, acc) { return n === 0 ? acc : f(n - 1, acc + 1); } f(100000, 0);
+// (ten times deeper than the call depth of wasmgen's interpreter)
+pub const DEEP_TAIL_RECURSION_APPL: &str = r#"
+    struct ()
+    global any
+    func "f" (struct#0, any, any) -> any {
+      (typecast:void number narrow (var:any local 1)
+        (if:void (prim:boolean number_eq (var:number local 3) (number:number 0.0))
+          (return:void (var:any local 2))
+          (typecast:void number narrow (var:any local 2)
+            (typecast:void func narrow (var:any global 0)
+              (return:void (appl:any (var:func local 5) [
+                (prim:number number_sub (var:number local 3) (number:number 1.0))
+                (prim:number number_add (var:number local 4) (number:number 1.0))]))
+              (trap:void 22))
+            (trap:void 17)))
+        (trap:void 17))
+    }
+    func () -> any {
+      (seq:any
+        (assign:undefined global 0 (func:func [0 closure] (struct:struct#0 0)))
+        (typecast:void func narrow (var:any global 0)
+          (return:void (appl:any (var:func local 0) [(number:number 100000.0) (number:number 0.0)]))
+          (trap:void 22)))
+    }
+    entry 1
+    "#;
+
+// the same, but f is called directly
+pub const DEEP_TAIL_RECURSION_DIRECT: &str = r#"
+    func "f" (any, any) -> any {
+      (typecast:void number narrow (var:any local 0)
+        (if:void (prim:boolean number_eq (var:number local 2) (number:number 0.0))
+          (return:void (var:any local 1))
+          (typecast:void number narrow (var:any local 1)
+            (return:void (direct:any 0
+              (prim:number number_sub (var:number local 2) (number:number 1.0))
+              (prim:number number_add (var:number local 3) (number:number 1.0))))
+            (trap:void 17)))
+        (trap:void 17))
+    }
+    func () -> any {
+      (return:void (direct:any 0 (number:number 100000.0) (number:number 0.0)))
+    }
+    entry 1
+    "#;
@@ -0,0 +1,169 @@
+/**
+ * Contains stuff related to trampolines, which are used to encode proper tail calls when the WebAssembly tail call proposal is not available.
+ *
+ * A function (that returns Any) does not make a call in tail position itself.
+ * Instead, it puts the arguments on the unprotected stack (exactly like an indirect call using the uniform calling convention),
+ * saves the rest of the call (the thunk, closure, number of arguments and caller id) into some globals, and then returns immediately.
+ * This saved call is the "continuation".
+ * Every caller of a function that might return a continuation then invokes the continuation in a loop (the "trampoline"),
+ * until some function returns normally.
+ *
+ * The continuation is invoked with the uniform calling convention, so it always returns Any.
+ * This is why only functions that return Any are allowed to return a continuation.
+ * The entry point never returns a continuation either, because the host that calls it does not run the trampoline.
+ */
+use crate::mutcontext::MutContext;
+use crate::var_conv::*;
+
+use wasmgen::ExprBuilder;
+
+// Value of the tableidx global when there is no pending continuation
+const NO_CONTINUATION: i32 = -1;
+
+#[derive(Copy, Clone)]
+pub struct Trampoline {
+    tableidx: wasmgen::GlobalIdx, // the thunk to call, or NO_CONTINUATION if there is no pending continuation
+    closure: wasmgen::GlobalIdx,
+    num_args: wasmgen::GlobalIdx,
+    callerid: wasmgen::GlobalIdx,
+}
+
+impl Trampoline {
+    pub fn new(wasm_module: &mut wasmgen::WasmModule) -> Self {
+        Trampoline {
+            tableidx: wasm_module.add_i32_global(wasmgen::Mut::Var, NO_CONTINUATION),
+            closure: wasm_module.add_i32_global(wasmgen::Mut::Var, 0),
+            num_args: wasm_module.add_i32_global(wasmgen::Mut::Var, 0),
+            callerid: wasm_module.add_i32_global(wasmgen::Mut::Var, 0),
+        }
+    }
+
+    // Saves the continuation, and returns from the current function (which must return Any).
+    // The args must already be on the unprotected stack.
+    // net wasm stack: [i32(closure), i32(num_args), i32(callerid), i32(tableidx)] -> [stack-polymorphic]
+    pub fn encode_return_continuation(
+        &self,
+        use_wasm_multi_value_feature: bool,
+        expr_builder: &mut ExprBuilder,
+    ) {
+        expr_builder.global_set(self.tableidx);
+        expr_builder.global_set(self.callerid);
+        expr_builder.global_set(self.num_args);
+        expr_builder.global_set(self.closure);
+        if use_wasm_multi_value_feature {
+            // the caller will ignore the returned value, so we just return a dummy Any
+            // net wasm stack: [] -> [<Any>]
+            expr_builder.i64_const(0);
+            expr_builder.i32_const(ir::VarType::Unassigned.tag());
+        }
+        // note: if Any is returned on the unprotected stack, we must not write to it because it overlaps with the args
+        expr_builder.return_();
+    }
+
+    // Invokes the pending continuation (if any) repeatedly, until a function returns normally.
+    // This should be placed immediately after calling a function that returns Any.
+    // `uniform_typeidx` is the wasm type of functions that use the uniform calling convention.
+    // net wasm stack: [return_calling_conv(Any)] -> [return_calling_conv(Any)]
+    pub fn encode_loop(
+        &self,
+        uniform_typeidx: wasmgen::TypeIdx,
+        use_wasm_multi_value_feature: bool,
+        mutctx: &mut MutContext,
+        expr_builder: &mut ExprBuilder,
+    ) {
+        if use_wasm_multi_value_feature {
+            // The returned value is on the wasm stack, so we keep it in locals while looping
+            mutctx.with_scratches(
+                encode_vartype(ir::VarType::Any),
+                |_mutctx, result_locals| {
+                    let store_result = |expr_builder: &mut ExprBuilder| {
+                        encode_store_local(
+                            result_locals,
+                            ir::VarType::Any,
+                            ir::VarType::Any,
+                            expr_builder,
+                        );
+                    };
+                    store_result(expr_builder);
+                    self.encode_loop_impl(uniform_typeidx, store_result, expr_builder);
+                    encode_load_local(
+                        result_locals,
+                        ir::VarType::Any,
+                        ir::VarType::Any,
+                        expr_builder,
+                    );
+                },
+            );
+        } else {
+            // The returned value is on the unprotected stack, so each call just overwrites it
+            self.encode_loop_impl(uniform_typeidx, |_| {}, expr_builder);
+        }
+    }
+
+    // net wasm stack: [] -> []
+    fn encode_loop_impl<F: Fn(&mut ExprBuilder)>(
+        &self,
+        uniform_typeidx: wasmgen::TypeIdx,
+        store_result: F,
+        expr_builder: &mut ExprBuilder,
+    ) {
+        // loop {
+        //   if (tableidx != NO_CONTINUATION) {
+        //     <clear and invoke the continuation>
+        //     <store the result>
+        //     continue;
+        //   }
+        // }
+        expr_builder.loop_(&[]);
+        {
+            expr_builder.global_get(self.tableidx);
+            expr_builder.i32_const(NO_CONTINUATION);
+            expr_builder.i32_ne();
+            expr_builder.if_(&[]);
+            {
+                // net wasm stack: [] -> [i32(closure), i32(num_args), i32(callerid), i32(tableidx)]
+                expr_builder.global_get(self.closure);
+                expr_builder.global_get(self.num_args);
+                expr_builder.global_get(self.callerid);
+                expr_builder.global_get(self.tableidx);
+
+                // clear the continuation first, because the callee might save another one
+                expr_builder.i32_const(NO_CONTINUATION);
+                expr_builder.global_set(self.tableidx);
+
+                // net wasm stack: [i32(closure), i32(num_args), i32(callerid), i32(tableidx)] -> [return_calling_conv(Any)]
+                expr_builder.call_indirect(uniform_typeidx, wasmgen::TableIdx { idx: 0 });
+
+                // net wasm stack: [return_calling_conv(Any)] -> []
+                store_result(expr_builder);
+
+                expr_builder.br(1);
+            }
+            expr_builder.end();
+        }
+        expr_builder.end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+    use crate::{Options, FEATURE_TAIL_CALL, FEATURE_TRAMPOLINE_TAIL_CALL};
+
+    #[test]
+    fn deep_tail_recursion_with_trampoline() {
+        for text in &[DEEP_TAIL_RECURSION_APPL, DEEP_TAIL_RECURSION_DIRECT] {
+            let program = parse_and_optimize(text, ir::opt::OptLevel::default());
+            for flags in all_feature_flags().filter(|flags| {
+                flags & FEATURE_TRAMPOLINE_TAIL_CALL != 0 && flags & FEATURE_TAIL_CALL == 0
+            }) {
+                assert_eq!(
+                    run_with_options(&program, Options::from_feature_flags(flags)),
+                    (number(100000.0), vec![]),
+                    "wrong result with feature flags {}",
+                    flags
+                );
+            }
+        }
+    }
+}
@@ -4,12 +4,24 @@ pub fn encode_vartype(ir_vartype: ir::VarType) -> &'static [wasmgen::ValType] {
     match ir_vartype {
         ir::VarType::Any => &[wasmgen::ValType::I32, wasmgen::ValType::I64],
         ir::VarType::Unassigned => panic!("ICE: IR->Wasm: Unassigned type may not be encoded"),
-        ir::VarType::Undefined => &[],
+        ir::VarType::Undefined | ir::VarType::Null => &[],
         ir::VarType::Number => &[wasmgen::ValType::F64],
         ir::VarType::Boolean => &[wasmgen::ValType::I32],
         ir::VarType::String => &[wasmgen::ValType::I32],
         ir::VarType::Func => &[wasmgen::ValType::I32, wasmgen::ValType::I32],
-        ir::VarType::StructT { typeidx: _ } => &[wasmgen::ValType::I32],
+        ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => &[wasmgen::ValType::I32],
+    }
+}
+
+// names of the wasm locals that encode_vartype() makes for an ir local with the given name (for the wasm name section)
+pub fn encode_local_names(name: &str, ir_vartype: ir::VarType) -> Box<[String]> {
+    match ir_vartype {
+        ir::VarType::Any => Box::new([format!("{}.tag", name), format!("{}.data", name)]),
+        ir::VarType::Func => Box::new([format!("{}.tableidx", name), format!("{}.closure", name)]),
+        _ => encode_vartype(ir_vartype)
+            .iter()
+            .map(|_| name.to_owned())
+            .collect(),
     }
 }
 
@@ -32,11 +44,11 @@ pub fn encode_store_local(
                 assert!(wasm_localidx.len() == 1);
                 expr_builder.local_set(wasm_localidx[0]);
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 assert!(wasm_localidx.len() == 1);
                 expr_builder.local_set(wasm_localidx[0]);
             }
-            ir::VarType::Undefined => {
+            ir::VarType::Undefined | ir::VarType::Null => {
                 assert!(wasm_localidx.len() == 0);
             }
             ir::VarType::Unassigned => {
@@ -50,7 +62,7 @@ pub fn encode_store_local(
             ir::VarType::Any => {
                 panic!("ICE");
             }
-            ir::VarType::Undefined => {
+            ir::VarType::Undefined | ir::VarType::Null => {
                 expr_builder.i32_const(ir_source_vartype.tag());
                 expr_builder.local_set(wasm_localidx[0]);
             }
@@ -69,7 +81,7 @@ pub fn encode_store_local(
                 expr_builder.i64_extend_i32_u(); // convert i32 to i64
                 expr_builder.local_set(wasm_localidx[1]);
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 expr_builder.i32_const(ir_source_vartype.tag());
                 expr_builder.local_set(wasm_localidx[0]);
                 expr_builder.i64_extend_i32_u(); // convert i32 to i64
@@ -114,11 +126,11 @@ pub fn encode_store_global(
                 assert!(wasm_globalidx.len() == 1);
                 expr_builder.global_set(wasm_globalidx[0]);
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 assert!(wasm_globalidx.len() == 1);
                 expr_builder.global_set(wasm_globalidx[0]);
             }
-            ir::VarType::Undefined => {
+            ir::VarType::Undefined | ir::VarType::Null => {
                 assert!(wasm_globalidx.len() == 0);
             }
             ir::VarType::Unassigned => {
@@ -132,7 +144,7 @@ pub fn encode_store_global(
             ir::VarType::Any => {
                 panic!("ICE");
             }
-            ir::VarType::Undefined => {
+            ir::VarType::Undefined | ir::VarType::Null => {
                 expr_builder.i32_const(ir_source_vartype.tag());
                 expr_builder.global_set(wasm_globalidx[0]);
             }
@@ -151,7 +163,7 @@ pub fn encode_store_global(
                 expr_builder.i64_extend_i32_u(); // convert i32 to i64
                 expr_builder.global_set(wasm_globalidx[1]);
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 expr_builder.i32_const(ir_source_vartype.tag());
                 expr_builder.global_set(wasm_globalidx[0]);
                 expr_builder.i64_extend_i32_u(); // convert i32 to i64
@@ -206,7 +218,7 @@ pub fn encode_store_memory(
             ir::VarType::Unassigned => {
                 panic!("ICE: IR->Wasm: Cannot assign from unassigned value");
             }
-            ir::VarType::Undefined => {}
+            ir::VarType::Undefined | ir::VarType::Null => {}
             ir::VarType::Number => {
                 expr_builder.f64_store(wasmgen::MemArg::new4(wasm_struct_offset));
             }
@@ -232,7 +244,7 @@ pub fn encode_store_memory(
                 scratch.pop_i32();
                 scratch.pop_i32();
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 expr_builder.i32_store(wasmgen::MemArg::new4(wasm_struct_offset));
             }
         }
@@ -245,7 +257,7 @@ pub fn encode_store_memory(
             ir::VarType::Unassigned => {
                 panic!("ICE: IR->Wasm: Cannot assign from unassigned value");
             }
-            ir::VarType::Undefined => {
+            ir::VarType::Undefined | ir::VarType::Null => {
                 expr_builder.i32_const(ir_source_vartype.tag());
                 expr_builder.i32_store(wasmgen::MemArg::new4(wasm_struct_offset));
             }
@@ -275,7 +287,7 @@ pub fn encode_store_memory(
                 scratch.pop_i32();
                 scratch.pop_i32();
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 let localidx_val: wasmgen::LocalIdx = scratch.push_i32();
                 let localidx_ptr: wasmgen::LocalIdx = scratch.push_i32();
                 expr_builder.local_set(localidx_val);
@@ -332,11 +344,11 @@ pub fn encode_load_local(
                 assert!(wasm_localidx.len() == 1);
                 expr_builder.local_get(wasm_localidx[0]);
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 assert!(wasm_localidx.len() == 1);
                 expr_builder.local_get(wasm_localidx[0]);
             }
-            ir::VarType::Undefined => {
+            ir::VarType::Undefined | ir::VarType::Null => {
                 assert!(wasm_localidx.len() == 0);
             }
             ir::VarType::Unassigned => {
@@ -350,7 +362,7 @@ pub fn encode_load_local(
             ir::VarType::Any => {
                 panic!("ICE");
             }
-            ir::VarType::Undefined => {}
+            ir::VarType::Undefined | ir::VarType::Null => {}
             ir::VarType::Unassigned => {
                 panic!("ICE: IR->Wasm: Cannot load from unassigned local");
             }
@@ -362,7 +374,7 @@ pub fn encode_load_local(
                 expr_builder.local_get(wasm_localidx[1]);
                 expr_builder.i32_wrap_i64(); // convert i64 to i32
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 expr_builder.local_get(wasm_localidx[1]);
                 expr_builder.i32_wrap_i64(); // convert i64 to i32
             }
@@ -402,11 +414,11 @@ pub fn encode_load_global(
                 assert!(wasm_globalidx.len() == 1);
                 expr_builder.global_get(wasm_globalidx[0]);
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 assert!(wasm_globalidx.len() == 1);
                 expr_builder.global_get(wasm_globalidx[0]);
             }
-            ir::VarType::Undefined => {
+            ir::VarType::Undefined | ir::VarType::Null => {
                 assert!(wasm_globalidx.len() == 0);
             }
             ir::VarType::Unassigned => {
@@ -420,7 +432,7 @@ pub fn encode_load_global(
             ir::VarType::Any => {
                 panic!("ICE");
             }
-            ir::VarType::Undefined => {}
+            ir::VarType::Undefined | ir::VarType::Null => {}
             ir::VarType::Unassigned => {
                 panic!("ICE: IR->Wasm: Cannot load from unassigned global");
             }
@@ -432,7 +444,7 @@ pub fn encode_load_global(
                 expr_builder.global_get(wasm_globalidx[1]);
                 expr_builder.i32_wrap_i64(); // convert i64 to i32
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 expr_builder.global_get(wasm_globalidx[1]);
                 expr_builder.i32_wrap_i64(); // convert i64 to i32
             }
@@ -470,7 +482,7 @@ pub fn encode_load_memory(
                 expr_builder.i32_load(wasmgen::MemArg::new4(wasm_struct_offset));
                 scratch.pop_i32();
             }
-            ir::VarType::Undefined => {}
+            ir::VarType::Undefined | ir::VarType::Null => {}
             ir::VarType::Unassigned => {
                 panic!("ICE: IR->Wasm: Cannot load from unassigned memory");
             }
@@ -491,7 +503,7 @@ pub fn encode_load_memory(
                 expr_builder.i32_load(wasmgen::MemArg::new4(wasm_struct_offset));
                 scratch.pop_i32();
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 expr_builder.i32_load(wasmgen::MemArg::new4(wasm_struct_offset));
             }
         }
@@ -503,7 +515,7 @@ pub fn encode_load_memory(
             ir::VarType::Unassigned => {
                 panic!("ICE: IR->Wasm: Cannot load from unassigned memory");
             }
-            ir::VarType::Undefined => {}
+            ir::VarType::Undefined | ir::VarType::Null => {}
             ir::VarType::Number => {
                 expr_builder.f64_load(wasmgen::MemArg::new4(wasm_struct_offset + 4));
             }
@@ -511,7 +523,7 @@ pub fn encode_load_memory(
                 expr_builder.i32_load(wasmgen::MemArg::new4(wasm_struct_offset + 4));
                 // note: high bytes of memory not used
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 expr_builder.i32_load(wasmgen::MemArg::new4(wasm_struct_offset + 4));
                 // note: high bytes of memory not used
             }
@@ -544,7 +556,7 @@ pub fn encode_widening_operation(
             ir::VarType::Any => {
                 panic!("ICE");
             }
-            ir::VarType::Undefined => {
+            ir::VarType::Undefined | ir::VarType::Null => {
                 expr_builder.i64_const(0); // unused data
                 expr_builder.i32_const(source_type.tag());
             }
@@ -559,7 +571,7 @@ pub fn encode_widening_operation(
                 expr_builder.i64_extend_i32_u(); // convert i32 to i64
                 expr_builder.i32_const(source_type.tag());
             }
-            ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => {
                 expr_builder.i64_extend_i32_u(); // convert i32 to i64
                 expr_builder.i32_const(source_type.tag());
             }
@@ -608,7 +620,7 @@ pub fn encode_narrowing_operation<F: FnOnce(&mut wasmgen::ExprBuilder)>(
             ir::VarType::Any => {
                 panic!("ICE");
             }
-            ir::VarType::Undefined => {
+            ir::VarType::Undefined | ir::VarType::Null => {
                 expr_builder.drop(); // i64(data) unused
             }
             ir::VarType::Unassigned => {
@@ -617,7 +629,10 @@ pub fn encode_narrowing_operation<F: FnOnce(&mut wasmgen::ExprBuilder)>(
             ir::VarType::Number => {
                 expr_builder.f64_reinterpret_i64(); // convert i64 to f64
             }
-            ir::VarType::Boolean | ir::VarType::String | ir::VarType::StructT { typeidx: _ } => {
+            ir::VarType::Boolean
+            | ir::VarType::String
+            | ir::VarType::Array
+            | ir::VarType::StructT { typeidx: _ } => {
                 expr_builder.i32_wrap_i64(); // convert i64 to i32
             }
             ir::VarType::Func => {
@@ -650,7 +665,7 @@ pub fn encode_unchecked_local_conv_any_narrowing(
         ir::VarType::Any => {
             panic!("ICE: IR->Wasm: Cannot TypeCast from Any to Any");
         }
-        ir::VarType::Undefined => {}
+        ir::VarType::Undefined | ir::VarType::Null => {}
         ir::VarType::Unassigned => {
             panic!("ICE: IR->Wasm: Cannot TypeCast from Any to Unassigned");
         }
@@ -660,7 +675,10 @@ pub fn encode_unchecked_local_conv_any_narrowing(
             expr_builder.f64_reinterpret_i64(); // convert i64 to f64
             expr_builder.local_set(wasm_dest_localidx[0]);
         }
-        ir::VarType::Boolean | ir::VarType::String | ir::VarType::StructT { typeidx: _ } => {
+        ir::VarType::Boolean
+        | ir::VarType::String
+        | ir::VarType::Array
+        | ir::VarType::StructT { typeidx: _ } => {
             assert!(wasm_dest_localidx.len() == 1);
             expr_builder.local_get(wasm_source_localidx);
             expr_builder.i32_wrap_i64(); // convert i64 to i32
@@ -684,11 +702,94 @@ pub fn size_in_memory(ir_vartype: ir::VarType) -> u32 {
     match ir_vartype {
         ir::VarType::Any => 4 + 8,
         ir::VarType::Unassigned => 0,
-        ir::VarType::Undefined => 0,
+        ir::VarType::Undefined | ir::VarType::Null => 0,
         ir::VarType::Number => 8,
         ir::VarType::Boolean => 4,
         ir::VarType::String => 4,
         ir::VarType::Func => 4 + 4,
-        ir::VarType::StructT { typeidx: _ } => 4,
+        ir::VarType::Array | ir::VarType::StructT { typeidx: _ } => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::*;
+    use crate::Options;
+
+    #[test]
+    fn list_length_with_null_comparison() {
+        // let xs = pair(1, pair(2, pair(3, null))); let n = 0;
+        // while (!(xs === null)) { n = n + 1; xs = tail(xs); }
+        // n * 100 + (5 === null ? 10 : 0) + (null === null ? 1 : 0);
+        // (the overloads of === are registered in the same order as the frontend does)
+        let pair = |localidx: usize, head: &str, tail: &str| {
+            format!(
+                "(let:struct#0 struct#0 = (struct:struct#0 0)
+                  (seq:struct#0
+                    (assign:undefined local {0} .0.0 {1})
+                    (assign:undefined local {0} .0.1 {2})
+                    (var:struct#0 local {0})))",
+                localidx, head, tail
+            )
+        };
+        let is_null = |arg: &str| {
+            format!(
+                "(appl:any (func:func [0, 1, 2, 3] (undefined:undefined)) [{} (null:null)])",
+                arg
+            )
+        };
+        let text = format!(
+            r#"
+            struct (any, any)
+            func "===" (null, any) -> boolean {{ (boolean:boolean false) }}
+            func "===" (any, null) -> boolean {{ (boolean:boolean false) }}
+            func "===" (null, null) -> boolean {{ (boolean:boolean true) }}
+            func "===" (number, number) -> boolean {{
+              (prim:boolean number_eq (var:number local 0) (var:number local 1))
+            }}
+            func "main" () -> number {{
+              (let:void any = {}
+                (let:void number = (number:number 0.0)
+                  (seq:void
+                    (loop:undefined
+                      (if:undefined
+                        (typecast:boolean boolean narrow {} (var:boolean local 2) (trap:void 23))
+                        (undefined:undefined)
+                        (seq:void
+                          (assign:undefined local 1 (prim:number number_add (var:number local 1) (number:number 1.0)))
+                          (assign:undefined local 0
+                            (typecast:any struct#0 narrow (var:any local 0) (var:any local 2 .0.1) (trap:void 17)))
+                          (break:void 0 (undefined:undefined)))))
+                    (return:void (prim:number number_add
+                      (prim:number number_add
+                        (prim:number number_mul (var:number local 1) (number:number 100.0))
+                        (typecast:number boolean narrow {}
+                          (if:number (var:boolean local 2) (number:number 10.0) (number:number 0.0))
+                          (trap:void 23)))
+                      (typecast:number boolean narrow {}
+                        (if:number (var:boolean local 2) (number:number 1.0) (number:number 0.0))
+                        (trap:void 23)))))))
+            }}
+            entry 4
+            "#,
+            pair(
+                0,
+                "(number:number 1.0)",
+                &pair(
+                    1,
+                    "(number:number 2.0)",
+                    &pair(2, "(number:number 3.0)", "(null:null)")
+                )
+            ),
+            is_null("(var:any local 0)"),
+            is_null("(number:number 5.0)"),
+            is_null("(null:null)")
+        );
+        assert_eq!(run_for_all_options(&text), (number(301.0), vec![]));
+        let program = parse_and_optimize(&text, ir::opt::OptLevel::O0);
+        assert_eq!(
+            run_with_options(&program, Options::default()),
+            (number(301.0), vec![])
+        );
     }
 }
@@ -21,6 +21,15 @@ const MUL: &str = "*";
 const DIV: &str = "/";
 const MOD: &str = "%";
 
+// Pair functions (Source §2)
+const PAIR: &str = "pair";
+const HEAD: &str = "head";
+const TAIL: &str = "tail";
+const SET_HEAD: &str = "set_head";
+const SET_TAIL: &str = "set_tail";
+const IS_PAIR: &str = "is_pair";
+const IS_NULL: &str = "is_null";
+
 pub fn resolve_unary_operator(es_op: &str) -> Option<&'static str> {
     match es_op {
         "-" => Some(UNARY_MINUS),
@@ -79,6 +88,16 @@ pub fn state_with_builtins(
     register_equality_op(EQ, true, ir::PrimInst::NumberEq, ir::PrimInst::BooleanEq, ir::PrimInst::StringEq, &mut name_ctx, &mut parse_ctx, ir_program);
     register_equality_op(NE, false, ir::PrimInst::NumberNeq, ir::PrimInst::BooleanNeq, ir::PrimInst::StringNeq, &mut name_ctx, &mut parse_ctx, ir_program);
 
+    let pair_vartype = ir::VarType::StructT { typeidx: ir_program.struct_types.len() };
+    ir_program.struct_types.push(Box::new([ir::VarType::Any, ir::VarType::Any]));
+    register_pair_constructor(PAIR, pair_vartype, &mut name_ctx, &mut parse_ctx, ir_program);
+    register_pair_getter(HEAD, pair_vartype, 0, &mut name_ctx, &mut parse_ctx, ir_program);
+    register_pair_getter(TAIL, pair_vartype, 1, &mut name_ctx, &mut parse_ctx, ir_program);
+    register_pair_setter(SET_HEAD, pair_vartype, 0, &mut name_ctx, &mut parse_ctx, ir_program);
+    register_pair_setter(SET_TAIL, pair_vartype, 1, &mut name_ctx, &mut parse_ctx, ir_program);
+    register_type_predicate(IS_PAIR, pair_vartype, &mut name_ctx, &mut parse_ctx, ir_program);
+    register_type_predicate(IS_NULL, ir::VarType::Null, &mut name_ctx, &mut parse_ctx, ir_program);
+
     (name_ctx, parse_ctx)
 }
 
@@ -108,7 +127,9 @@ fn register_unary_op(
     };
 
     let funcidx = ir_program.add_func(ir::Func {
+        name: Some(name.to_owned()),
         params: Box::new([ir_vartype]),
+        param_names: Default::default(),
         result: Some(ir_vartype),
         expr: ir_expr,
         signature_filter: Default::default(),
@@ -124,6 +145,7 @@ fn register_unary_op(
 
 // write the actual function (we hope it gets inlined by the ir optimizer later)
 fn make_binary_op_impl(
+    name: &str,
     ir_priminst: ir::PrimInst,
     ir_param_vartype: ir::VarType,
     ir_result_vartype: ir::VarType,
@@ -157,7 +179,9 @@ fn make_binary_op_impl(
     };
 
     let funcidx = ir_program.add_func(ir::Func {
+        name: Some(name.to_owned()),
         params: Box::new([ir_param_vartype, ir_param_vartype]),
+        param_names: Default::default(),
         result: Some(ir_result_vartype),
         expr: ir_expr,
         signature_filter: Default::default(),
@@ -167,14 +191,21 @@ fn make_binary_op_impl(
 }
 
 // write the actual function (we hope it gets inlined by the ir optimizer later)
-fn make_trivial_func_undefined_impl(ret: bool, ir_program: &mut ir::Program) -> ir::FuncIdx {
+fn make_trivial_func_impl(
+    name: &str,
+    ir_param_vartypes: [ir::VarType; 2],
+    ret: bool,
+    ir_program: &mut ir::Program,
+) -> ir::FuncIdx {
     let ir_expr = ir::Expr {
         vartype: Some(ir::VarType::Boolean),
         kind: ir::ExprKind::PrimBoolean { val: ret },
     };
 
     let funcidx = ir_program.add_func(ir::Func {
-        params: Box::new([ir::VarType::Undefined, ir::VarType::Undefined]),
+        name: Some(name.to_owned()),
+        params: Box::new(ir_param_vartypes),
+        param_names: Default::default(),
         result: Some(ir::VarType::Boolean),
         expr: ir_expr,
         signature_filter: Default::default(),
@@ -191,7 +222,7 @@ fn register_binary_op(
     parse_ctx: &mut ParseState,
     ir_program: &mut ir::Program,
 ) {
-    let funcidx = make_binary_op_impl(ir_priminst, ir_vartype, ir_vartype, ir_program);
+    let funcidx = make_binary_op_impl(name, ir_priminst, ir_vartype, ir_vartype, ir_program);
 
     // insert the necessary things into name_ctx and parse_ctx
     name_ctx.insert(name.to_owned(), PreVar::Direct);
@@ -211,12 +242,14 @@ fn register_addition_op(
     ir_program: &mut ir::Program,
 ) {
     let funcidx_number = make_binary_op_impl(
+        name,
         ir_priminst_number,
         ir::VarType::Number,
         ir::VarType::Number,
         ir_program,
     );
     let funcidx_string = make_binary_op_impl(
+        name,
         ir_priminst_string,
         ir::VarType::String,
         ir::VarType::String,
@@ -247,12 +280,14 @@ fn register_comparison_op(
     ir_program: &mut ir::Program,
 ) {
     let funcidx_number = make_binary_op_impl(
+        name,
         ir_priminst_number,
         ir::VarType::Number,
         ir::VarType::Boolean,
         ir_program,
     );
     let funcidx_string = make_binary_op_impl(
+        name,
         ir_priminst_string,
         ir::VarType::String,
         ir::VarType::Boolean,
@@ -286,26 +321,53 @@ fn register_equality_op(
     parse_ctx: &mut ParseState,
     ir_program: &mut ir::Program,
 ) {
-    let funcidx_undefined = make_trivial_func_undefined_impl(undefined_ret_val, ir_program);
+    let funcidx_undefined = make_trivial_func_impl(
+        name,
+        [ir::VarType::Undefined, ir::VarType::Undefined],
+        undefined_ret_val,
+        ir_program,
+    );
+    // null is compared with a value of any type, so that `xs === null` works for lists
+    let funcidx_null = make_trivial_func_impl(
+        name,
+        [ir::VarType::Null, ir::VarType::Null],
+        undefined_ret_val,
+        ir_program,
+    );
+    let funcidx_null_any = make_trivial_func_impl(
+        name,
+        [ir::VarType::Null, ir::VarType::Any],
+        !undefined_ret_val,
+        ir_program,
+    );
+    let funcidx_any_null = make_trivial_func_impl(
+        name,
+        [ir::VarType::Any, ir::VarType::Null],
+        !undefined_ret_val,
+        ir_program,
+    );
     let funcidx_number = make_binary_op_impl(
+        name,
         ir_priminst_number,
         ir::VarType::Number,
         ir::VarType::Boolean,
         ir_program,
     );
     let funcidx_boolean = make_binary_op_impl(
+        name,
         ir_priminst_boolean,
         ir::VarType::Boolean,
         ir::VarType::Boolean,
         ir_program,
     );
     let funcidx_string = make_binary_op_impl(
+        name,
         ir_priminst_string,
         ir::VarType::String,
         ir::VarType::Boolean,
         ir_program,
     );
-    //let funcidx_func = make_binary_op_impl(ir_priminst_func, ir::VarType::Func, ir::VarType::Boolean, ir_program);
+    //let funcidx_func = make_binary_op_impl(name, ir_priminst_func, ir::VarType::Func, ir::VarType::Boolean, ir_program);
 
     // insert the necessary things into name_ctx and parse_ctx
     name_ctx.insert(name.to_owned(), PreVar::Direct);
@@ -314,6 +376,20 @@ fn register_equality_op(
         Box::new([ir::VarType::Undefined, ir::VarType::Undefined]) as Box<[ir::VarType]>,
         funcidx_undefined,
     ));
+    // the (null, null) overload must come after the (null, any) and (any, null) overloads,
+    // otherwise it will get eliminated because it is a subset of them
+    overload_set.append((
+        Box::new([ir::VarType::Null, ir::VarType::Any]) as Box<[ir::VarType]>,
+        funcidx_null_any,
+    ));
+    overload_set.append((
+        Box::new([ir::VarType::Any, ir::VarType::Null]) as Box<[ir::VarType]>,
+        funcidx_any_null,
+    ));
+    overload_set.append((
+        Box::new([ir::VarType::Null, ir::VarType::Null]) as Box<[ir::VarType]>,
+        funcidx_null,
+    ));
     overload_set.append((
         Box::new([ir::VarType::Number, ir::VarType::Number]) as Box<[ir::VarType]>,
         funcidx_number,
@@ -329,3 +405,196 @@ fn register_equality_op(
     //overload_set.append((Box::new([ir::VarType::Func, ir::VarType::Func]), funcidx_func));
     parse_ctx.add_direct(name.to_owned(), overload_set);
 }
+
+fn make_local(localidx: usize, ir_vartype: ir::VarType) -> ir::Expr {
+    ir::Expr {
+        vartype: Some(ir_vartype),
+        kind: ir::ExprKind::VarName {
+            source: ir::TargetExpr::Local {
+                localidx: localidx,
+                next: None,
+            },
+        },
+    }
+}
+
+fn as_typeidx(pair_vartype: ir::VarType) -> usize {
+    match pair_vartype {
+        ir::VarType::StructT { typeidx } => typeidx,
+        _ => panic!("ICE: pair must be a struct"),
+    }
+}
+
+fn make_pair_field_target(
+    localidx: usize,
+    pair_vartype: ir::VarType,
+    fieldidx: usize,
+) -> ir::TargetExpr {
+    ir::TargetExpr::Local {
+        localidx: localidx,
+        next: Some(Box::new(ir::StructField {
+            typeidx: as_typeidx(pair_vartype),
+            fieldidx: fieldidx,
+            next: None,
+        })),
+    }
+}
+
+// pair(x, y) creates a new pair with head x and tail y
+fn register_pair_constructor(
+    name: &str,
+    pair_vartype: ir::VarType,
+    name_ctx: &mut HashMap<String, PreVar>,
+    parse_ctx: &mut ParseState,
+    ir_program: &mut ir::Program,
+) {
+    // the new pair is stored in local #2 (after the two params)
+    let ir_expr = ir::Expr {
+        vartype: Some(pair_vartype),
+        kind: ir::ExprKind::Declaration {
+            local: pair_vartype,
+            init: Some(Box::new(ir::Expr {
+                vartype: Some(pair_vartype),
+                kind: ir::ExprKind::PrimStructT {
+                    typeidx: as_typeidx(pair_vartype),
+                },
+            })),
+            contained_expr: Box::new(ir::Expr {
+                vartype: Some(pair_vartype),
+                kind: ir::ExprKind::Sequence {
+                    content: (0..2)
+                        .map(|fieldidx| ir::Expr {
+                            vartype: Some(ir::VarType::Undefined),
+                            kind: ir::ExprKind::Assign {
+                                target: make_pair_field_target(2, pair_vartype, fieldidx),
+                                expr: Box::new(make_local(fieldidx, ir::VarType::Any)),
+                            },
+                        })
+                        .chain(std::iter::once(make_local(2, pair_vartype)))
+                        .collect(),
+                },
+            }),
+        },
+    };
+
+    let funcidx = ir_program.add_func(ir::Func {
+        name: Some(name.to_owned()),
+        params: Box::new([ir::VarType::Any, ir::VarType::Any]),
+        param_names: Default::default(),
+        result: Some(pair_vartype),
+        expr: ir_expr,
+        signature_filter: Default::default(),
+    });
+
+    name_ctx.insert(name.to_owned(), PreVar::Direct);
+    parse_ctx.add_direct(
+        name.to_owned(),
+        OverloadSet::from_single((Box::new([ir::VarType::Any, ir::VarType::Any]), funcidx)),
+    );
+}
+
+// head(p) and tail(p)
+fn register_pair_getter(
+    name: &str,
+    pair_vartype: ir::VarType,
+    fieldidx: usize,
+    name_ctx: &mut HashMap<String, PreVar>,
+    parse_ctx: &mut ParseState,
+    ir_program: &mut ir::Program,
+) {
+    let ir_expr = ir::Expr {
+        vartype: Some(ir::VarType::Any),
+        kind: ir::ExprKind::VarName {
+            source: make_pair_field_target(0, pair_vartype, fieldidx),
+        },
+    };
+
+    let funcidx = ir_program.add_func(ir::Func {
+        name: Some(name.to_owned()),
+        params: Box::new([pair_vartype]),
+        param_names: Default::default(),
+        result: Some(ir::VarType::Any),
+        expr: ir_expr,
+        signature_filter: Default::default(),
+    });
+
+    name_ctx.insert(name.to_owned(), PreVar::Direct);
+    parse_ctx.add_direct(
+        name.to_owned(),
+        OverloadSet::from_single((Box::new([pair_vartype]), funcidx)),
+    );
+}
+
+// set_head(p, x) and set_tail(p, x)
+fn register_pair_setter(
+    name: &str,
+    pair_vartype: ir::VarType,
+    fieldidx: usize,
+    name_ctx: &mut HashMap<String, PreVar>,
+    parse_ctx: &mut ParseState,
+    ir_program: &mut ir::Program,
+) {
+    let ir_expr = ir::Expr {
+        vartype: Some(ir::VarType::Undefined),
+        kind: ir::ExprKind::Assign {
+            target: make_pair_field_target(0, pair_vartype, fieldidx),
+            expr: Box::new(make_local(1, ir::VarType::Any)),
+        },
+    };
+
+    let funcidx = ir_program.add_func(ir::Func {
+        name: Some(name.to_owned()),
+        params: Box::new([pair_vartype, ir::VarType::Any]),
+        param_names: Default::default(),
+        result: Some(ir::VarType::Undefined),
+        expr: ir_expr,
+        signature_filter: Default::default(),
+    });
+
+    name_ctx.insert(name.to_owned(), PreVar::Direct);
+    parse_ctx.add_direct(
+        name.to_owned(),
+        OverloadSet::from_single((Box::new([pair_vartype, ir::VarType::Any]), funcidx)),
+    );
+}
+
+// is_pair(x) and is_null(x), which return true if the value has the given type
+fn register_type_predicate(
+    name: &str,
+    ir_vartype: ir::VarType,
+    name_ctx: &mut HashMap<String, PreVar>,
+    parse_ctx: &mut ParseState,
+    ir_program: &mut ir::Program,
+) {
+    let ir_expr = ir::Expr {
+        vartype: Some(ir::VarType::Boolean),
+        kind: ir::ExprKind::TypeCast {
+            test: Box::new(make_local(0, ir::VarType::Any)),
+            expected: ir_vartype,
+            create_narrow_local: false,
+            true_expr: Box::new(ir::Expr {
+                vartype: Some(ir::VarType::Boolean),
+                kind: ir::ExprKind::PrimBoolean { val: true },
+            }),
+            false_expr: Box::new(ir::Expr {
+                vartype: Some(ir::VarType::Boolean),
+                kind: ir::ExprKind::PrimBoolean { val: false },
+            }),
+        },
+    };
+
+    let funcidx = ir_program.add_func(ir::Func {
+        name: Some(name.to_owned()),
+        params: Box::new([ir::VarType::Any]),
+        param_names: Default::default(),
+        result: Some(ir::VarType::Boolean),
+        expr: ir_expr,
+        signature_filter: Default::default(),
+    });
+
+    name_ctx.insert(name.to_owned(), PreVar::Direct);
+    parse_ctx.add_direct(
+        name.to_owned(),
+        OverloadSet::from_single((Box::new([ir::VarType::Any]), funcidx)),
+    );
+}
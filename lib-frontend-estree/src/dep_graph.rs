@@ -1,9 +1,12 @@
 use crate::error::DepError;
+use crate::error::FetchError;
 use crate::error::FetcherError;
 use crate::error::GraphError;
-use crate::import_name_resolver;
+use crate::error::ImportEdge;
+use crate::import_name_resolver::ModuleResolver;
 use async_trait::async_trait;
 use projstd::log::CompileMessage;
+use projstd::log::SourceLocation;
 use projstd::log::SourceLocationRef as plSLRef;
 use std::boxed::Box;
 use std::collections::HashMap;
@@ -14,19 +17,25 @@ use std::iter::FusedIterator;
 use std::pin::Pin;
 use std::result::Result;
 
+/**
+ * Fetches a module by its resolved name.
+ * The implementations of `Fetcher<String>` (which return the contents of the module) are in the `fetcher` module.
+ * If the module does not exist, the error should be a FetchError, so that the next candidate name can be tried.
+ */
 //#[async_trait(?Send)]
-pub trait Fetcher<T>: Copy {
+pub trait Fetcher<T> {
     fn fetch<'a>(
-        self,
+        &'a self,
         name: &'a str,
         sl: plSLRef<'a>,
-    ) -> std::pin::Pin<Box<dyn 'a + Future<Output = Result<T, CompileMessage<FetcherError>>>>>;
+    ) -> Pin<Box<dyn 'a + Future<Output = Result<T, CompileMessage<FetcherError>>>>>;
 }
 
 pub trait ExtractDeps<'a> {
-    type Iter: Iterator<Item = (import_name_resolver::ResolveIter, plSLRef<'a>)>;
-    // Note: If we find a relative import path, then this function should resolve it to a (unique) absolute path before returning.
-    // This is because the caller might do caching - to avoid making multiple web requests for the same file,
+    type Iter: Iterator<Item = (&'a str, plSLRef<'a>)>;
+    // Returns the names of the imports as written in the file.
+    // They are resolved by the ModuleResolver given to the graph, which should resolve a relative import path to a (unique) absolute path.
+    // This is because we do caching - to avoid making multiple web requests for the same file,
     // and we will still want to fetch a second file with the same relative path, if the absolute path is different.
     fn extract_deps(&'a self, filename: Option<&'a str>) -> Self::Iter;
 }
@@ -41,30 +50,97 @@ pub struct Graph<T> {
     nodes: Vec<GraphNode<T>>,
 }
 
+// An import declaration, and the file it refers to
+struct Import {
+    candidates: Box<[String]>, // candidate names from the ModuleResolver
+    location: SourceLocation,
+    next_candidate: usize,
+    err: Option<CompileMessage<DepError>>, // the error from the last candidate that failed
+    // None: still trying the candidates
+    // Some(Ok(name)): the first candidate that was fetched successfully
+    // Some(Err(e)): no candidate could be fetched (or one of them had a fatal error)
+    result: Option<Result<String, CompileMessage<DepError>>>,
+}
+
+struct FetchedFile<T> {
+    content: Result<T, CompileMessage<FetcherError>>,
+    imports: Vec<usize>, // indices into the list of imports
+}
+
 impl<T> Graph<T>
 where
     for<'a> T: ExtractDeps<'a>,
 {
     // Will ensure that nodes with larger index will only depend on nodes with smaller index
     // So the largest index will be the given `t` (root)
-    pub async fn try_async_build_from_root<'c, F: Fetcher<T>>(
+    // The nodes (and any error) are the same as if the files were fetched one at a time in depth-first order,
+    // but all the files that are needed at the same time are fetched concurrently.
+    pub async fn try_async_build_from_root<F: Fetcher<T>, R: ModuleResolver>(
         t: T,
-        f: F,
+        f: &F,
+        resolver: &R,
     ) -> Result<Self, CompileMessage<DepError>> {
+        let mut imports: Vec<Import> = Vec::new();
+        let root_imports: Vec<usize> = add_imports(&t, None, resolver, &mut imports);
+        // every file that has been fetched (whether successfully or not), by resolved name
+        let mut files = HashMap::<String, FetchedFile<T>>::new();
+        loop {
+            // settle the imports whose current candidate has already been fetched
+            for import in imports.iter_mut() {
+                settle_import(import, &files);
+            }
+            // the next round consists of the current candidate of every import that is still unsettled
+            // (each name is fetched once, with the location of the first import that needs it)
+            let mut round: Vec<(String, SourceLocation)> = Vec::new();
+            for import in imports.iter() {
+                if import.result.is_none() {
+                    let name = &import.candidates[import.next_candidate];
+                    if !round.iter().any(|(n, _)| n == name) {
+                        round.push((name.clone(), import.location.clone()));
+                    }
+                }
+            }
+            if round.is_empty() {
+                break;
+            }
+            let contents = futures::future::join_all(
+                round.iter().map(|(name, sl)| f.fetch(name, sl.as_ref())),
+            )
+            .await;
+            // the new imports are added in the order of the round, so that everything stays deterministic
+            for ((name, _), content) in round.into_iter().zip(contents) {
+                let file_imports = match &content {
+                    Ok(t) => add_imports(t, Some(name.as_str()), resolver, &mut imports),
+                    Err(_) => Vec::new(),
+                };
+                files.insert(
+                    name,
+                    FetchedFile {
+                        content: content,
+                        imports: file_imports,
+                    },
+                );
+            }
+        }
+
+        // now build the graph in depth-first order
         let mut graph = Graph::<T> { nodes: Vec::new() };
         // cache.get(name) == None: never seen this file before
         // cache.get(name) == Some(None): seen this file on the ancestor chain
         // cache.get(name) == Some(Some(idx)): seen this file on an unrelated chain, so it would have already gotten an index
         let mut cache = HashMap::<String, Option<usize>>::new();
+        // the files on the ancestor chain, each with the location of the import declaration (in its parent) that imported it
+        let mut ancestors = Vec::<(String, SourceLocation)>::new();
         let mut deps = Vec::new();
-        for (dep, sl) in t.extract_deps(None) {
-            deps.push(
-                graph
-                    .get_or_fetch_node_recursive(dep, sl, &mut cache, f)
-                    .await?,
-            );
+        for import_idx in root_imports {
+            deps.push(graph.add_node_recursive(
+                import_idx,
+                &mut imports,
+                &mut files,
+                &mut cache,
+                &mut ancestors,
+            )?);
         }
-        let idx = graph.nodes.len();
         graph.nodes.push(GraphNode {
             deps: deps,
             content: t,
@@ -72,56 +148,135 @@ where
         });
         Ok(graph)
     }
-    fn get_or_fetch_node_recursive<'b, F: 'b + Fetcher<T>>(
-        &'b mut self,
-        mut candidate_resolved_names: impl Iterator<Item = String> + 'static,
-        sl: plSLRef<'b>,
-        cache: &'b mut HashMap<String, Option<usize>>,
-        f: F,
-    ) -> Pin<Box<dyn 'b + Future<Output = Result<usize, CompileMessage<DepError>>>>> {
-        Box::pin(async move {
-            let mut err: Option<CompileMessage<DepError>> = None;
-            while let Some(name) = candidate_resolved_names.next() {
-                if let Some(opt_idx) = cache.get(name.as_str()) {
-                    if let Some(idx) = opt_idx {
-                        return Ok(*idx);
+    fn add_node_recursive(
+        &mut self,
+        import_idx: usize,
+        imports: &mut Vec<Import>,
+        files: &mut HashMap<String, FetchedFile<T>>,
+        cache: &mut HashMap<String, Option<usize>>,
+        ancestors: &mut Vec<(String, SourceLocation)>,
+    ) -> Result<usize, CompileMessage<DepError>> {
+        // every import is visited once, since every file is visited once
+        let name: String = imports[import_idx].result.take().unwrap()?;
+        if let Some(opt_idx) = cache.get(name.as_str()) {
+            if let Some(idx) = opt_idx {
+                return Ok(*idx);
+            }
+            // this file is on the ancestor chain, so the import creates a cycle
+            let cycle_start = ancestors
+                .iter()
+                .position(|(ancestor, _)| *ancestor == name)
+                .unwrap();
+            let mut cycle: Vec<ImportEdge> = ancestors[cycle_start..]
+                .windows(2)
+                .map(|edge| ImportEdge {
+                    importer: edge[0].0.clone(),
+                    imported: edge[1].0.clone(),
+                    location: edge[1].1.clone(),
+                })
+                .collect();
+            cycle.push(ImportEdge {
+                importer: ancestors.last().unwrap().0.clone(),
+                imported: name.clone(),
+                location: imports[import_idx].location.clone(),
+            });
+            return Err(CompileMessage::new_error(
+                imports[import_idx].location.clone(),
+                GraphError::new(cycle.into_boxed_slice()),
+            )
+            .into_cm());
+        }
+        cache.insert(name.clone(), None);
+        ancestors.push((name.clone(), imports[import_idx].location.clone()));
+        let file_imports: Vec<usize> = files[name.as_str()].imports.clone();
+        let mut deps = Vec::new();
+        for dep_import_idx in file_imports {
+            deps.push(self.add_node_recursive(dep_import_idx, imports, files, cache, ancestors)?);
+        }
+        ancestors.pop();
+        // the import was settled with this name, so the file was fetched successfully
+        let content: T = match files.remove(name.as_str()).unwrap().content {
+            Ok(t) => t,
+            Err(_) => unreachable!(),
+        };
+        let idx = self.nodes.len();
+        self.nodes.push(GraphNode {
+            deps: deps,
+            content: content,
+            name: Some(name.clone()),
+        });
+        *cache.get_mut(name.as_str()).unwrap() = Some(idx);
+        Ok(idx)
+    }
+}
+
+// Adds the imports of a file to the list of imports, and returns their indices
+fn add_imports<T, R: ModuleResolver>(
+    t: &T,
+    filename: Option<&str>,
+    resolver: &R,
+    imports: &mut Vec<Import>,
+) -> Vec<usize>
+where
+    for<'a> T: ExtractDeps<'a>,
+{
+    t.extract_deps(filename)
+        .map(|(dep, sl)| {
+            imports.push(Import {
+                candidates: resolver.resolve(dep, filename).collect(),
+                location: sl.to_owned(),
+                next_candidate: 0,
+                err: None,
+                result: None,
+            });
+            imports.len() - 1
+        })
+        .collect()
+}
+
+// Tries the candidates of an unsettled import, until one of them has not been fetched yet
+fn settle_import<T>(import: &mut Import, files: &HashMap<String, FetchedFile<T>>) {
+    while import.result.is_none() {
+        let name = &import.candidates[import.next_candidate];
+        match files.get(name.as_str()) {
+            None => return,
+            Some(FetchedFile {
+                content: Ok(_),
+                imports: _,
+            }) => import.result = Some(Ok(name.clone())),
+            Some(FetchedFile {
+                content: Err(e),
+                imports: _,
+            }) => {
+                // If we get an error, it could be that the file does not exist (in which case we might get served a custom 404 page)
+                // if that happens, we will get a ESTreeParseError.
+                // So we only continue if we get FetchError or ESTreeParseError (but not ImportsParseError).
+                match e.message() {
+                    FetcherError::FetchError(_) => {
+                        // the file might have been fetched for another import, so we make our own error at this import
+                        import.err = Some(
+                            CompileMessage::new_error(
+                                import.location.clone(),
+                                FetchError::new(name.clone()),
+                            )
+                            .into_cm(),
+                        );
                     }
-                    return Err(CompileMessage::new_error(sl.to_owned(), GraphError {}).into_cm());
-                }
-                match f.fetch(name.as_str(), sl).await {
-                    Err(e) => {
-                        // If we get an error, it could be that the file does not exist (in which case we might get served a custom 404 page)
-                        // if that happens, we will get a ESTreeParseError.
-                        // So we only continue if we get FetchError or ESTreeParseError (but not ImportsParseError).
-                        match e.message() {
-                            FetcherError::FetchError(_) | FetcherError::ESTreeParseError(_) => {
-                                err = Some(e.into_cm());
-                            }
-                            _ => {
-                                return Err(e.into_cm());
-                            }
-                        }
+                    FetcherError::ESTreeParseError(_) => {
+                        import.err = Some(e.clone().into_cm());
                     }
-                    Ok(t) => {
-                        cache.insert(name.to_owned(), None);
-                        let mut deps = Vec::new();
-                        for (dep, sl) in t.extract_deps(Some(name.as_str())) {
-                            deps.push(self.get_or_fetch_node_recursive(dep, sl, cache, f).await?);
-                        }
-                        let idx = self.nodes.len();
-                        self.nodes.push(GraphNode {
-                            deps: deps,
-                            content: t,
-                            name: Some(name.to_owned()),
-                        });
-                        *cache.get_mut(name.as_str()).unwrap() = Some(idx);
-                        return Ok(idx);
+                    FetcherError::ImportsParseError(_) => {
+                        import.result = Some(Err(e.clone().into_cm()));
+                        return;
                     }
                 }
+                import.next_candidate += 1;
+                if import.next_candidate == import.candidates.len() {
+                    // should not panic, because we will definitely have at least one candidate
+                    import.result = Some(Err(import.err.take().unwrap()));
+                }
             }
-            // should not panic, because we will definitely have at least one candidate
-            Err(err.unwrap())
-        })
+        }
     }
 }
 
@@ -177,3 +332,210 @@ impl<T> Graph<T> {
     }
     */
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import_name_resolver::ResolveIter;
+    use projstd::log::Loggable;
+    use std::cell::Cell;
+    use std::cell::RefCell;
+    use std::task::Context;
+    use std::task::Poll;
+
+    // A module that only consists of its imports (one on each line)
+    struct Module {
+        imports: Vec<&'static str>,
+    }
+
+    impl<'a> ExtractDeps<'a> for Module {
+        type Iter = Box<dyn Iterator<Item = (&'a str, plSLRef<'a>)> + 'a>;
+        fn extract_deps(&'a self, filename: Option<&'a str>) -> Self::Iter {
+            Box::new(
+                self.imports
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, name)| (*name, plSLRef::entire_line(i as i32 + 1, filename))),
+            )
+        }
+    }
+
+    struct ModuleFetcher {
+        modules: HashMap<&'static str, Vec<&'static str>>,
+        log: RefCell<Vec<String>>, // the names that were fetched, in order
+        in_flight: Cell<usize>,
+        max_in_flight: Cell<usize>,
+    }
+
+    // A future that is pending when it is first polled, so that fetches take some time
+    struct YieldOnce {
+        yielded: bool,
+    }
+
+    impl Future for YieldOnce {
+        type Output = ();
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.yielded {
+                Poll::Ready(())
+            } else {
+                self.yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    impl Fetcher<Module> for ModuleFetcher {
+        fn fetch<'a>(
+            &'a self,
+            name: &'a str,
+            sl: plSLRef<'a>,
+        ) -> Pin<Box<dyn 'a + Future<Output = Result<Module, CompileMessage<FetcherError>>>>>
+        {
+            self.log.borrow_mut().push(name.to_owned());
+            self.in_flight.set(self.in_flight.get() + 1);
+            self.max_in_flight.set(std::cmp::max(
+                self.max_in_flight.get(),
+                self.in_flight.get(),
+            ));
+            Box::pin(async move {
+                YieldOnce { yielded: false }.await;
+                self.in_flight.set(self.in_flight.get() - 1);
+                match self.modules.get(name) {
+                    Some(imports) => Ok(Module {
+                        imports: imports.clone(),
+                    }),
+                    None => Err(CompileMessage::new_error(
+                        sl.to_owned(),
+                        crate::error::FetchError::new(name.to_owned()),
+                    )
+                    .into_cm()),
+                }
+            })
+        }
+    }
+
+    // Resolves every name to itself
+    struct IdentityResolver;
+
+    impl ModuleResolver for IdentityResolver {
+        fn resolve(&self, name: &str, _current_filename: Option<&str>) -> ResolveIter {
+            Box::new(std::iter::once(name.to_owned()))
+        }
+    }
+
+    fn build_with_fetcher(
+        root: Vec<&'static str>,
+        modules: &[(&'static str, Vec<&'static str>)],
+    ) -> (
+        Result<Graph<Module>, CompileMessage<DepError>>,
+        ModuleFetcher,
+    ) {
+        let fetcher = ModuleFetcher {
+            modules: modules.iter().cloned().collect(),
+            log: RefCell::new(Vec::new()),
+            in_flight: Cell::new(0),
+            max_in_flight: Cell::new(0),
+        };
+        let result = futures::executor::block_on(Graph::try_async_build_from_root(
+            Module { imports: root },
+            &fetcher,
+            &IdentityResolver,
+        ));
+        (result, fetcher)
+    }
+
+    fn build(
+        root: Vec<&'static str>,
+        modules: &[(&'static str, Vec<&'static str>)],
+    ) -> Result<Graph<Module>, CompileMessage<DepError>> {
+        build_with_fetcher(root, modules).0
+    }
+
+    #[test]
+    fn topological_order() {
+        let graph = build(
+            vec!["a", "b"],
+            &[("a", vec!["c"]), ("b", vec!["c"]), ("c", vec![])],
+        )
+        .ok()
+        .unwrap();
+        let names: Vec<Option<&str>> = graph.topological_traverse().map(|(_, name)| name).collect();
+        assert_eq!(names, vec![Some("c"), Some("a"), Some("b"), None]);
+    }
+
+    #[test]
+    fn concurrent_fetches() {
+        let (result, fetcher) = build_with_fetcher(
+            vec!["a", "b", "c"],
+            &[
+                ("a", vec!["d"]),
+                ("b", vec!["d", "e"]),
+                ("c", vec![]),
+                ("d", vec!["e"]),
+                ("e", vec![]),
+            ],
+        );
+        let graph = result.ok().unwrap();
+        let names: Vec<Option<&str>> = graph.topological_traverse().map(|(_, name)| name).collect();
+        // the nodes are in depth-first order, regardless of the order that the files were fetched in
+        assert_eq!(
+            names,
+            vec![Some("e"), Some("d"), Some("a"), Some("b"), Some("c"), None]
+        );
+        // every file is fetched once, and the imports of the root are fetched at the same time
+        assert_eq!(*fetcher.log.borrow(), vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(fetcher.max_in_flight.get(), 3);
+    }
+
+    #[test]
+    fn first_error_in_depth_first_order() {
+        let err = build(vec!["a", "b"], &[("a", vec!["x", "y"]), ("b", vec!["z"])])
+            .err()
+            .unwrap();
+        assert_eq!(err.message().to_string(), "Cannot find source file \"x\"");
+        assert_eq!(err.location(), plSLRef::entire_line(1, Some("a")));
+    }
+
+    #[test]
+    fn cycle_path() {
+        let err = build(
+            vec!["x", "a"],
+            &[
+                ("x", vec![]),
+                ("a", vec!["x", "b"]),
+                ("b", vec!["c"]),
+                ("c", vec!["a"]),
+            ],
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.message().to_string(),
+            "Cycle detected in import graph: \"a\" -> \"b\" -> \"c\" -> \"a\""
+        );
+        // the error is at the import that closes the cycle, and there is a note at each of the other imports
+        assert_eq!(err.location(), plSLRef::entire_line(1, Some("c")));
+        let notes = match err.message() {
+            DepError::GraphError(e) => e.notes(),
+            _ => panic!("expected a GraphError"),
+        };
+        let notes: Vec<(String, plSLRef)> = notes
+            .iter()
+            .map(|note| (Loggable::message(note), note.location()))
+            .collect();
+        assert_eq!(
+            notes,
+            vec![
+                (
+                    "\"a\" imports \"b\" here (part of the cycle)".to_owned(),
+                    plSLRef::entire_line(2, Some("a"))
+                ),
+                (
+                    "\"b\" imports \"c\" here (part of the cycle)".to_owned(),
+                    plSLRef::entire_line(1, Some("b"))
+                ),
+            ]
+        );
+    }
+}
@@ -1,7 +1,10 @@
+use projstd::log::CompileMessage;
+use projstd::log::Severity;
+use projstd::log::SourceLocation;
 use std::error::Error;
 use std::fmt;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FetchError {
     name: String,
 }
@@ -17,7 +20,7 @@ impl fmt::Display for FetchError {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ESTreeParseError {}
 impl Error for ESTreeParseError {}
 impl fmt::Display for ESTreeParseError {
@@ -26,7 +29,7 @@ impl fmt::Display for ESTreeParseError {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ImportsParseError {
     InvalidHeader,
     MissingHostModuleName,
@@ -55,12 +58,66 @@ impl fmt::Display for ImportsParseError {
     }
 }
 
+/**
+ * An import declaration in `importer` (at `location`) that imports the file `imported`.
+ */
+#[derive(Debug)]
+pub struct ImportEdge {
+    pub importer: String,
+    pub imported: String,
+    pub location: SourceLocation,
+}
+
 #[derive(Debug)]
-pub struct GraphError {}
+pub struct GraphError {
+    cycle: Box<[ImportEdge]>, // in order, and the last edge is the import that closes the cycle
+}
+impl GraphError {
+    pub fn new(cycle: Box<[ImportEdge]>) -> Self {
+        Self { cycle: cycle }
+    }
+    /**
+     * Notes that point to the import declarations of the other edges of the cycle (the error itself is at the last edge).
+     */
+    pub fn notes(&self) -> Vec<CompileMessage<ImportCycleNote>> {
+        self.cycle[..(self.cycle.len() - 1)]
+            .iter()
+            .map(|edge| {
+                CompileMessage::new(
+                    edge.location.clone(),
+                    Severity::Note,
+                    ImportCycleNote {
+                        importer: edge.importer.clone(),
+                        imported: edge.imported.clone(),
+                    },
+                )
+            })
+            .collect()
+    }
+}
 impl Error for GraphError {}
 impl fmt::Display for GraphError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Cycle detected in import graph")
+        write!(f, "Cycle detected in import graph: ")?;
+        for edge in self.cycle.iter() {
+            write!(f, "\"{}\" -> ", edge.importer)?;
+        }
+        write!(f, "\"{}\"", self.cycle.last().unwrap().imported)
+    }
+}
+
+#[derive(Debug)]
+pub struct ImportCycleNote {
+    importer: String,
+    imported: String,
+}
+impl fmt::Display for ImportCycleNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "\"{}\" imports \"{}\" here (part of the cycle)",
+            self.importer, self.imported
+        )
     }
 }
 
@@ -103,7 +160,7 @@ impl From<GraphError> for DepError {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum FetcherError {
     FetchError(FetchError),
     ImportsParseError(ImportsParseError),
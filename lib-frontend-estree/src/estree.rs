@@ -38,6 +38,8 @@ pub enum NodeKind {
     BreakStatement(BreakStatement),
     ContinueStatement(ContinueStatement),
     IfStatement(IfStatement),
+    WhileStatement(WhileStatement),
+    ForStatement(ForStatement),
     FunctionDeclaration(FunctionDeclaration),
     VariableDeclaration(VariableDeclaration),
     VariableDeclarator(VariableDeclarator),
@@ -50,6 +52,8 @@ pub enum NodeKind {
     LogicalExpression(LogicalExpression),
     ConditionalExpression(ConditionalExpression),
     CallExpression(CallExpression),
+    ArrayExpression(ArrayExpression),
+    MemberExpression(MemberExpression),
     ImportDeclaration(ImportDeclaration),
     ImportSpecifier(ImportSpecifier),
     ImportDefaultSpecifier(ImportDefaultSpecifier),
@@ -162,6 +166,22 @@ pub struct IfStatement {
     pub alternate: Option<Box<Node>>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct WhileStatement {
+    pub test: Box<Node>,
+    pub body: Box<Node>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ForStatement {
+    pub init: Option<Box<Node>>,
+    pub test: Option<Box<Node>>,
+    pub update: Option<Box<Node>>,
+    pub body: Box<Node>,
+    #[serde(skip)]
+    pub address_taken_vars: Vec<usize>, // list of address-taken vars declared in `init`, populated by pre_parse()
+}
+
 #[derive(Deserialize, Debug)]
 pub struct FunctionDeclaration {
     pub id: Box<Node>,
@@ -256,6 +276,18 @@ pub struct CallExpression {
     pub arguments: Vec<Node>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ArrayExpression {
+    pub elements: Vec<Option<Node>>, // `None` for holes (e.g. `[1, , 2]`)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MemberExpression {
+    pub object: Box<Node>,
+    pub property: Box<Node>,
+    pub computed: bool,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct ImportDeclaration {
     pub specifiers: Vec<Node>,
@@ -296,6 +328,7 @@ pub trait Function: Scope {
     fn params_body_mut(&mut self) -> (&[Node], &mut Node);
     fn captured_vars_mut(&mut self) -> &mut Vec<VarLocId>; // captured variables, except globals
     fn params_mut(&mut self) -> &mut Vec<Node>;
+    fn name(&self) -> Option<&str>; // the declared name of the function, or None if it is anonymous
 }
 
 impl Function for FunctionDeclaration {
@@ -311,6 +344,13 @@ impl Function for FunctionDeclaration {
     fn params_mut(&mut self) -> &mut Vec<Node> {
         &mut self.params
     }
+    fn name(&self) -> Option<&str> {
+        if let NodeKind::Identifier(id) = &self.id.kind {
+            Some(&id.name)
+        } else {
+            None
+        }
+    }
 }
 
 /*impl Function for FunctionExpression {
@@ -332,6 +372,9 @@ impl Function for ArrowFunctionExpression {
     fn params_mut(&mut self) -> &mut Vec<Node> {
         &mut self.params
     }
+    fn name(&self) -> Option<&str> {
+        None
+    }
 }
 
 pub trait Scope {
@@ -0,0 +1,228 @@
+/**
+ * Ready-made fetchers that return the contents of a module (as a string), for use with `run_frontend()`.
+ * They can be chained, e.g. to use an in-memory copy of some modules and read the rest from disk.
+ */
+use crate::dep_graph::Fetcher;
+use crate::error::FetchError;
+use crate::error::FetcherError;
+use projstd::log::CompileMessage;
+use projstd::log::SourceLocationRef as plSLRef;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::pin::Pin;
+
+type FetchFuture<'a> =
+    Pin<Box<dyn 'a + Future<Output = Result<String, CompileMessage<FetcherError>>>>>;
+
+fn fetch_error(name: &str, sl: plSLRef) -> CompileMessage<FetcherError> {
+    CompileMessage::new_error(sl.to_owned(), FetchError::new(name.to_owned())).into_cm()
+}
+
+/**
+ * Fetches modules with an async function (e.g. a callback into the JavaScript host), which returns None if the module cannot be fetched.
+ */
+pub struct FnFetcher<F> {
+    raw_fetch: F,
+}
+
+impl<F> FnFetcher<F> {
+    pub fn new(raw_fetch: F) -> Self {
+        Self {
+            raw_fetch: raw_fetch,
+        }
+    }
+}
+
+impl<Fut: 'static + Future<Output = Option<String>>, F: Fn(String) -> Fut> Fetcher<String>
+    for FnFetcher<F>
+{
+    fn fetch<'a>(&'a self, name: &'a str, sl: plSLRef<'a>) -> FetchFuture<'a> {
+        Box::pin(async move {
+            (self.raw_fetch)(name.to_owned())
+                .await
+                .ok_or_else(|| fetch_error(name, sl))
+        })
+    }
+}
+
+/**
+ * Reads modules from a directory tree on disk.
+ * The scheme and domain of a URL are removed, and the rest of the name is used as a path relative to the root directory.
+ * Names that would be outside the root directory (i.e. starting with `..`) are never found.
+ */
+pub struct FileSystemFetcher {
+    root: PathBuf,
+    prefix: Option<String>,
+}
+
+impl FileSystemFetcher {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            root: root,
+            prefix: None,
+        }
+    }
+    /**
+     * Only finds names that start with `prefix` (e.g. the location of the standard library), which is replaced by the root directory.
+     */
+    pub fn with_prefix(root: PathBuf, prefix: String) -> Self {
+        Self {
+            root: root,
+            prefix: Some(prefix),
+        }
+    }
+    /**
+     * Returns the path that the given name is read from, if any.
+     */
+    pub fn path(&self, name: &str) -> Option<PathBuf> {
+        let relative: &str = match &self.prefix {
+            Some(prefix) => name.strip_prefix(prefix.as_str())?,
+            None => match name.find("//") {
+                Some(scheme_end) => {
+                    let after_scheme = &name[(scheme_end + "//".len())..];
+                    after_scheme
+                        .find('/')
+                        .map_or("", |i| &after_scheme[(i + 1)..])
+                }
+                None => name,
+            },
+        };
+        let relative = Path::new(relative.trim_start_matches('/'));
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            return None;
+        }
+        Some(self.root.join(relative))
+    }
+}
+
+impl Fetcher<String> for FileSystemFetcher {
+    // Note: the file is read synchronously, which is fine since the native compiler does nothing else in the meantime
+    fn fetch<'a>(&'a self, name: &'a str, sl: plSLRef<'a>) -> FetchFuture<'a> {
+        let content = self
+            .path(name)
+            .and_then(|path| std::fs::read_to_string(path).ok());
+        Box::pin(async move { content.ok_or_else(|| fetch_error(name, sl)) })
+    }
+}
+
+/**
+ * Returns modules from a map from resolved names to their contents.
+ */
+pub struct MemoryFetcher {
+    modules: HashMap<String, String>,
+}
+
+impl MemoryFetcher {
+    pub fn new(modules: HashMap<String, String>) -> Self {
+        Self { modules: modules }
+    }
+}
+
+impl Fetcher<String> for MemoryFetcher {
+    fn fetch<'a>(&'a self, name: &'a str, sl: plSLRef<'a>) -> FetchFuture<'a> {
+        let content = self.modules.get(name).cloned();
+        Box::pin(async move { content.ok_or_else(|| fetch_error(name, sl)) })
+    }
+}
+
+/**
+ * Tries each fetcher in order, and returns the module from the first one that finds it.
+ * Errors other than FetchError (e.g. parse errors) are returned immediately.
+ */
+pub struct ChainFetcher<T> {
+    fetchers: Vec<Box<dyn Fetcher<T>>>,
+}
+
+impl<T> ChainFetcher<T> {
+    pub fn new(fetchers: Vec<Box<dyn Fetcher<T>>>) -> Self {
+        Self { fetchers: fetchers }
+    }
+}
+
+impl<T> Fetcher<T> for ChainFetcher<T> {
+    fn fetch<'a>(
+        &'a self,
+        name: &'a str,
+        sl: plSLRef<'a>,
+    ) -> Pin<Box<dyn 'a + Future<Output = Result<T, CompileMessage<FetcherError>>>>> {
+        Box::pin(async move {
+            for fetcher in &self.fetchers {
+                match fetcher.fetch(name, sl).await {
+                    Err(e) => match e.message() {
+                        FetcherError::FetchError(_) => {}
+                        _ => return Err(e),
+                    },
+                    Ok(t) => return Ok(t),
+                }
+            }
+            Err(fetch_error(name, sl))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetch_now<F: Fetcher<String>>(fetcher: &F, name: &str) -> Option<String> {
+        futures::executor::block_on(fetcher.fetch(name, plSLRef::entire_file(None))).ok()
+    }
+
+    #[test]
+    fn file_system_paths() {
+        let fetcher = FileSystemFetcher::new(PathBuf::from("lib"));
+        assert_eq!(
+            fetcher.path("https://example.com/a/b.source"),
+            Some(PathBuf::from("lib/a/b.source"))
+        );
+        assert_eq!(
+            fetcher.path("/x.source"),
+            Some(PathBuf::from("lib/x.source"))
+        );
+        assert_eq!(
+            fetcher.path("x.source"),
+            Some(PathBuf::from("lib/x.source"))
+        );
+        assert_eq!(fetcher.path("../x.source"), None);
+        let stdlib_fetcher = FileSystemFetcher::with_prefix(
+            PathBuf::from("lib"),
+            "https://btzy.github.io/libsourceror/".to_owned(),
+        );
+        assert_eq!(
+            stdlib_fetcher.path("https://btzy.github.io/libsourceror/std/misc.source"),
+            Some(PathBuf::from("lib/std/misc.source"))
+        );
+        assert_eq!(stdlib_fetcher.path("std/misc.source"), None);
+    }
+
+    #[test]
+    fn chain_memory_and_file_system() {
+        let dir =
+            std::env::temp_dir().join(format!("sourceror-fetcher-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("std")).unwrap();
+        std::fs::write(dir.join("std/misc.source"), "from disk").unwrap();
+        std::fs::write(dir.join("std/math.source"), "from disk").unwrap();
+        let mut modules = HashMap::new();
+        modules.insert("std/math.source".to_owned(), "from memory".to_owned());
+        let fetcher: ChainFetcher<String> = ChainFetcher::new(vec![
+            Box::new(MemoryFetcher::new(modules)),
+            Box::new(FileSystemFetcher::new(dir.clone())),
+        ]);
+        assert_eq!(
+            fetch_now(&fetcher, "std/math.source").as_deref(),
+            Some("from memory")
+        );
+        assert_eq!(
+            fetch_now(&fetcher, "std/misc.source").as_deref(),
+            Some("from disk")
+        );
+        assert_eq!(fetch_now(&fetcher, "std/list.source"), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -26,6 +26,9 @@ impl<K, V> VarCtx<K, V> {
     pub fn len(&self) -> usize {
         self.map.len()
     }
+    pub fn iter(&self) -> std::collections::hash_map::Iter<'_, K, V> {
+        self.map.iter()
+    }
 }
 
 impl<K: Hash + Eq, V: Append<V>> VarCtx<K, V> {
@@ -36,6 +36,7 @@ pub fn parse_constraint(
 fn convert_vartype(s: &str) -> Option<ir::VarType> {
     match s {
         "undefined" => Some(ir::VarType::Undefined),
+        "null" => Some(ir::VarType::Null),
         "number" => Some(ir::VarType::Number),
         "boolean" => Some(ir::VarType::Boolean),
         "string" => Some(ir::VarType::String),
@@ -186,6 +186,7 @@ pub fn post_parse_program(
 
     // emit the body
     body.each_with_attributes_into(filename, |es_node, attr| {
+        push_source_marker(ir_toplevel_seq, &es_node.loc, filename);
         let ir_expr: ir::Expr = post_parse_toplevel_statement(
             es_node,
             attr,
@@ -341,6 +342,7 @@ fn post_parse_scope<S: Scope, PE: ScopePrefixEmitter>(
 
         // process all the body statements
         while let Some((es_stmt, attr)) = stmt_iter.next() {
+            push_source_marker(&mut sequence, &es_stmt.loc, filename);
             let (ir_expr, new_stmt_iter) = post_parse_statement(
                 es_stmt,
                 attr,
@@ -408,6 +410,7 @@ fn add_remaining_stmts<I: Iterator<Item = (Node, HashMap<String, Option<String>>
 ) -> Result<(Vec<ir::Expr>, I), CompileMessage<ParseProgramError>> {
     let mut ret: Vec<ir::Expr> = Vec::new();
     while let Some((es_stmt, attr)) = stmt_iter.next() {
+        push_source_marker(&mut ret, &es_stmt.loc, filename);
         let (ir_expr, new_stmt_iter) = post_parse_statement(
             es_stmt, attr, parse_ctx, stmt_iter, depth, num_locals, filename, ir_program,
         )?;
@@ -624,6 +627,12 @@ fn post_parse_direct_function(
     let (ir_params, ir_funcidx) = std::mem::take(&mut es_func.direct_props).unwrap();
     let num_params = es_func.params.len();
     assert!(num_params == ir_params.len());
+    let name: String = as_id_ref(&*es_func.id).name.clone();
+    let param_names: Box<[String]> = es_func
+        .params
+        .iter()
+        .map(|es_param| as_id_ref(es_param).name.clone())
+        .collect();
     //let es_params = std::mem::take(&mut es_func.params);
 
     let undo_ctx = parse_ctx.enter_closure(Box::new([])); // new closure with no non-global Target entries in the parse_ctx
@@ -643,7 +652,9 @@ fn post_parse_direct_function(
 
     assert!(ir_funcidx >= ir_program.imports.len());
     let curr_func: &mut ir::Func = ir_program.get_func_mut(ir_funcidx);
+    curr_func.name = Some(name);
     curr_func.params = ir_params;
+    curr_func.param_names = param_names;
     curr_func.result = Some(ir::VarType::Any);
     curr_func.expr = ir_func_body;
     Ok(())
@@ -658,6 +669,8 @@ fn post_parse_function<Func: Function>(
     filename: Option<&str>,
     ir_program: &mut ir::Program,
 ) -> Result<ir::Expr, CompileMessage<ParseProgramError>> {
+    let name: String = func_name(&es_func, &loc, filename);
+
     // firstly, prep the closure
 
     // todo! only generate the closure if there is at least one captured variable (otherwise, the function can have closure_count==0)
@@ -681,6 +694,14 @@ fn post_parse_function<Func: Function>(
     })
     .chain(es_func.params_mut().iter().map(|_| ir::VarType::Any))
     .collect();
+    let param_names: Box<[String]> = std::iter::once("closure".to_owned())
+        .chain(
+            es_func
+                .params_mut()
+                .iter()
+                .map(|es_param| as_id_ref(es_param).name.clone()),
+        )
+        .collect();
 
     // create the struct allocation
     let init_expr = ir::Expr {
@@ -845,7 +866,9 @@ fn post_parse_function<Func: Function>(
 
     // add the function to the ir_program
     let ir_funcidx = ir_program.add_func(ir::Func {
+        name: Some(name),
         params: ir_params_with_closure,
+        param_names: param_names,
         result: Some(ir::VarType::Any),
         expr: ir_func_body,
         signature_filter: Default::default(),
@@ -952,6 +975,50 @@ fn post_parse_statement<I: Iterator<Item = (Node, HashMap<String, Option<String>
             )?,
             more_stmt_attr_iter,
         )),
+        NodeKind::WhileStatement(stmt) => Ok((
+            post_parse_while_statement(
+                stmt,
+                es_node.loc,
+                parse_ctx,
+                depth,
+                num_locals,
+                filename,
+                ir_program,
+            )?,
+            more_stmt_attr_iter,
+        )),
+        NodeKind::ForStatement(stmt) => Ok((
+            post_parse_for_statement(
+                stmt,
+                es_node.loc,
+                parse_ctx,
+                depth,
+                num_locals,
+                filename,
+                ir_program,
+            )?,
+            more_stmt_attr_iter,
+        )),
+        NodeKind::BreakStatement(_) => Ok((
+            ir::Expr {
+                vartype: None,
+                kind: ir::ExprKind::Break {
+                    num_frames: parse_ctx.get_break_frames().unwrap(), // pre_parse() would have ensured that we are in a loop
+                    expr: Box::new(make_prim_undefined()),
+                },
+            },
+            more_stmt_attr_iter,
+        )),
+        NodeKind::ContinueStatement(_) => Ok((
+            ir::Expr {
+                vartype: None,
+                kind: ir::ExprKind::Break {
+                    num_frames: parse_ctx.get_continue_frames().unwrap(), // pre_parse() would have ensured that we are in a loop
+                    expr: Box::new(make_prim_undefined()),
+                },
+            },
+            more_stmt_attr_iter,
+        )),
         NodeKind::FunctionDeclaration(func_decl) => {
             if attributes.get("direct").is_some() {
                 // direct func declarations do not generate any ir::Expr in the current context
@@ -1022,6 +1089,12 @@ fn post_parse_toplevel_statement(
         NodeKind::IfStatement(stmt) => {
             post_parse_if_statement(stmt, es_node.loc, parse_ctx, 0, 0, filename, ir_program)
         }
+        NodeKind::WhileStatement(stmt) => {
+            post_parse_while_statement(stmt, es_node.loc, parse_ctx, 0, 0, filename, ir_program)
+        }
+        NodeKind::ForStatement(stmt) => {
+            post_parse_for_statement(stmt, es_node.loc, parse_ctx, 0, 0, filename, ir_program)
+        }
         NodeKind::FunctionDeclaration(func_decl) => {
             if attributes.get("direct").is_some() {
                 // direct func declarations do not generate any ir::Expr in the current context
@@ -1235,6 +1308,366 @@ fn post_parse_if_statement(
     })
 }
 
+fn post_parse_while_statement(
+    es_while: WhileStatement,
+    loc: Option<esSL>,
+    parse_ctx: &mut ParseState,
+    depth: usize,
+    num_locals: usize, // current number of IR locals
+    filename: Option<&str>,
+    ir_program: &mut ir::Program,
+) -> Result<ir::Expr, CompileMessage<ParseProgramError>> {
+    // Emits the loop structure (see post_parse_loop()).
+
+    let es_test = *es_while.test;
+    let es_body = *es_while.body;
+    post_parse_loop(parse_ctx, |parse_ctx| {
+        let cond_expr =
+            post_parse_loop_cond(es_test, parse_ctx, depth, num_locals, filename, ir_program)?;
+        let (block_stmt, loc) = as_block_statement_with_loc(es_body);
+        let body_expr = post_parse_block_statement(
+            block_stmt, loc, parse_ctx, depth, num_locals, filename, ir_program,
+        )?;
+        Ok((cond_expr, body_expr, Vec::new()))
+    })
+}
+
+fn post_parse_for_statement(
+    es_for: ForStatement,
+    loc: Option<esSL>,
+    parse_ctx: &mut ParseState,
+    depth: usize,
+    num_locals: usize, // current number of IR locals
+    filename: Option<&str>,
+    ir_program: &mut ir::Program,
+) -> Result<ir::Expr, CompileMessage<ParseProgramError>> {
+    // Emits the loop structure (see post_parse_loop()), wrapped in the declaration of the loop variable if there is one.
+    // If the loop variable is address-taken, a fresh copy of its struct is made at the end of every iteration (before the update),
+    // so that closures created in different iterations see different variables (as mandated by JavaScript).
+
+    let new_depth = depth + 1;
+
+    let ForStatement {
+        init: es_init,
+        test: es_test,
+        update: es_update,
+        body: es_body,
+        address_taken_vars,
+    } = es_for;
+
+    // the part of the loop that is executed repeatedly
+    // `copy_expr` is emitted at the end of every iteration, before the update
+    let emit_loop = move |parse_ctx: &mut ParseState,
+                          num_locals: usize,
+                          copy_expr: Option<ir::Expr>,
+                          ir_program: &mut ir::Program| {
+        post_parse_loop(parse_ctx, |parse_ctx| {
+            let cond_expr = match es_test {
+                Some(es_test_node) => post_parse_loop_cond(
+                    *es_test_node,
+                    parse_ctx,
+                    new_depth,
+                    num_locals,
+                    filename,
+                    ir_program,
+                )?,
+                None => ir::Expr {
+                    vartype: Some(ir::VarType::Boolean),
+                    kind: ir::ExprKind::PrimBoolean { val: true },
+                },
+            };
+            let (block_stmt, loc) = as_block_statement_with_loc(*es_body);
+            let body_expr = post_parse_block_statement(
+                block_stmt, loc, parse_ctx, new_depth, num_locals, filename, ir_program,
+            )?;
+            let mut step_exprs: Vec<ir::Expr> = copy_expr.into_iter().collect();
+            if let Some(es_update_node) = es_update {
+                step_exprs.push(post_parse_expr(
+                    *es_update_node,
+                    parse_ctx,
+                    new_depth,
+                    num_locals,
+                    filename,
+                    ir_program,
+                )?);
+            }
+            Ok((cond_expr, body_expr, step_exprs))
+        })
+    };
+
+    match es_init {
+        Some(es_init_node) => match es_init_node.kind {
+            NodeKind::VariableDeclaration(var_decl) => {
+                // pre_parse() would have ensured that there is exactly one declarator
+                let es_var_decr = as_var_decr(var_decl.declarations.into_iter().next().unwrap());
+                let varlocid = as_varlocid(as_id(*es_var_decr.id).prevar.unwrap());
+                let es_init_expr = *es_var_decr.init.unwrap();
+
+                if address_taken_vars.is_empty() {
+                    // not address-taken, so we just declare a local (like post_parse_decl_helper())
+                    let init_expr = post_parse_expr(
+                        es_init_expr,
+                        parse_ctx,
+                        new_depth,
+                        num_locals,
+                        filename,
+                        ir_program,
+                    )?;
+                    let undo_ctx = parse_ctx.add_target(
+                        varlocid,
+                        ir::TargetExpr::Local {
+                            localidx: num_locals,
+                            next: None,
+                        },
+                    );
+                    let loop_expr = emit_loop(parse_ctx, num_locals + 1, None, ir_program)?;
+                    parse_ctx.remove_target(undo_ctx);
+                    Ok(ir::Expr {
+                        vartype: Some(ir::VarType::Undefined),
+                        kind: ir::ExprKind::Declaration {
+                            local: ir::VarType::Any,
+                            init: Some(Box::new(init_expr)),
+                            contained_expr: Box::new(loop_expr),
+                        },
+                    })
+                } else {
+                    // address-taken, so we synthesise a struct for it (like post_parse_scope())
+                    let struct_idx = ir_program.struct_types.len();
+                    ir_program.struct_types.push(Box::new([ir::VarType::Any]));
+                    let struct_vartype = ir::VarType::StructT {
+                        typeidx: struct_idx,
+                    };
+                    let make_field_target = |localidx: usize| ir::TargetExpr::Local {
+                        localidx: localidx,
+                        next: Some(Box::new(ir::StructField {
+                            typeidx: struct_idx,
+                            fieldidx: 0,
+                            next: None,
+                        })),
+                    };
+                    let make_struct_varname = |localidx: usize| ir::Expr {
+                        vartype: Some(struct_vartype),
+                        kind: ir::ExprKind::VarName {
+                            source: ir::TargetExpr::Local {
+                                localidx: localidx,
+                                next: None,
+                            },
+                        },
+                    };
+                    let make_struct_alloc = || ir::Expr {
+                        vartype: Some(struct_vartype),
+                        kind: ir::ExprKind::PrimStructT {
+                            typeidx: struct_idx,
+                        },
+                    };
+
+                    let undo_ctx = parse_ctx.add_target(varlocid, make_field_target(num_locals));
+                    let init_expr = post_parse_expr(
+                        es_init_expr,
+                        parse_ctx,
+                        new_depth,
+                        num_locals + 1,
+                        filename,
+                        ir_program,
+                    )?;
+
+                    // let new_struct = <new struct>; new_struct.field = old_struct.field; old_struct = new_struct;
+                    let copy_expr = ir::Expr {
+                        vartype: Some(ir::VarType::Undefined),
+                        kind: ir::ExprKind::Declaration {
+                            local: struct_vartype,
+                            init: Some(Box::new(make_struct_alloc())),
+                            contained_expr: Box::new(ir::Expr {
+                                vartype: Some(ir::VarType::Undefined),
+                                kind: ir::ExprKind::Sequence {
+                                    content: vec![
+                                        ir::Expr {
+                                            vartype: Some(ir::VarType::Undefined),
+                                            kind: ir::ExprKind::Assign {
+                                                target: make_field_target(num_locals + 1),
+                                                expr: Box::new(ir::Expr {
+                                                    vartype: Some(ir::VarType::Any),
+                                                    kind: ir::ExprKind::VarName {
+                                                        source: make_field_target(num_locals),
+                                                    },
+                                                }),
+                                            },
+                                        },
+                                        ir::Expr {
+                                            vartype: Some(ir::VarType::Undefined),
+                                            kind: ir::ExprKind::Assign {
+                                                target: ir::TargetExpr::Local {
+                                                    localidx: num_locals,
+                                                    next: None,
+                                                },
+                                                expr: Box::new(make_struct_varname(num_locals + 1)),
+                                            },
+                                        },
+                                    ],
+                                },
+                            }),
+                        },
+                    };
+
+                    let loop_expr =
+                        emit_loop(parse_ctx, num_locals + 1, Some(copy_expr), ir_program)?;
+                    parse_ctx.remove_target(undo_ctx);
+                    Ok(ir::Expr {
+                        vartype: Some(ir::VarType::Undefined),
+                        kind: ir::ExprKind::Declaration {
+                            local: struct_vartype,
+                            init: Some(Box::new(make_struct_alloc())),
+                            contained_expr: Box::new(ir::Expr {
+                                vartype: Some(ir::VarType::Undefined),
+                                kind: ir::ExprKind::Sequence {
+                                    content: vec![
+                                        ir::Expr {
+                                            vartype: Some(ir::VarType::Undefined),
+                                            kind: ir::ExprKind::Assign {
+                                                target: make_field_target(num_locals),
+                                                expr: Box::new(init_expr),
+                                            },
+                                        },
+                                        loop_expr,
+                                    ],
+                                },
+                            }),
+                        },
+                    })
+                }
+            }
+            es_init_kind => {
+                // the init part is just an expression (usually an assignment)
+                let init_expr = post_parse_expr(
+                    Node {
+                        loc: es_init_node.loc,
+                        kind: es_init_kind,
+                    },
+                    parse_ctx,
+                    new_depth,
+                    num_locals,
+                    filename,
+                    ir_program,
+                )?;
+                let loop_expr = emit_loop(parse_ctx, num_locals, None, ir_program)?;
+                Ok(ir::Expr {
+                    vartype: Some(ir::VarType::Undefined),
+                    kind: ir::ExprKind::Sequence {
+                        content: vec![init_expr, loop_expr],
+                    },
+                })
+            }
+        },
+        None => emit_loop(parse_ctx, num_locals, None, ir_program),
+    }
+}
+
+/**
+ * Emits the loop structure shared by while and for loops:
+ * Block { Loop { if (<cond>) { Block { <body> }; <step>; <jump to start of Loop> } } }
+ * A break statement jumps to the end of the outer Block, and a continue statement jumps to the end of the inner Block.
+ * `f` should return the condition, body, and step exprs (in that order); the landings are registered in the parse_ctx while `f` runs.
+ */
+fn post_parse_loop<
+    F: FnOnce(
+        &mut ParseState,
+    ) -> Result<(ir::Expr, ir::Expr, Vec<ir::Expr>), CompileMessage<ParseProgramError>>,
+>(
+    parse_ctx: &mut ParseState,
+    f: F,
+) -> Result<ir::Expr, CompileMessage<ParseProgramError>> {
+    let break_landing = parse_ctx.add_landing(); // outer Block
+    parse_ctx.add_landing(); // Loop
+    let continue_landing = parse_ctx.add_landing(); // inner Block
+    parse_ctx.add_loop(break_landing, continue_landing);
+    let res = f(parse_ctx);
+    parse_ctx.remove_loop();
+    parse_ctx.remove_landing();
+    parse_ctx.remove_landing();
+    parse_ctx.remove_landing();
+    let (cond_expr, body_expr, mut step_exprs) = res?;
+
+    let mut iteration_exprs: Vec<ir::Expr> = vec![ir::Expr {
+        vartype: Some(ir::VarType::Undefined),
+        kind: ir::ExprKind::Block {
+            expr: Box::new(body_expr),
+        },
+    }];
+    iteration_exprs.append(&mut step_exprs);
+    iteration_exprs.push(ir::Expr {
+        vartype: None,
+        kind: ir::ExprKind::Break {
+            num_frames: 0, // jump to the start of the Loop
+            expr: Box::new(make_prim_undefined()),
+        },
+    });
+
+    Ok(ir::Expr {
+        vartype: Some(ir::VarType::Undefined),
+        kind: ir::ExprKind::Block {
+            expr: Box::new(ir::Expr {
+                vartype: Some(ir::VarType::Undefined),
+                kind: ir::ExprKind::Loop {
+                    expr: Box::new(ir::Expr {
+                        vartype: Some(ir::VarType::Undefined),
+                        kind: ir::ExprKind::Conditional {
+                            cond: Box::new(cond_expr),
+                            true_expr: Box::new(ir::Expr {
+                                vartype: None,
+                                kind: ir::ExprKind::Sequence {
+                                    content: iteration_exprs,
+                                },
+                            }),
+                            false_expr: Box::new(make_prim_undefined()),
+                        },
+                    }),
+                },
+            }),
+        },
+    })
+}
+
+/**
+ * Emits the condition of a loop, with a type check to ensure that it is boolean type (like post_parse_if_statement()).
+ */
+fn post_parse_loop_cond(
+    es_test: Node,
+    parse_ctx: &mut ParseState,
+    depth: usize,
+    num_locals: usize, // current number of IR locals
+    filename: Option<&str>,
+    ir_program: &mut ir::Program,
+) -> Result<ir::Expr, CompileMessage<ParseProgramError>> {
+    let cond_loc: ir::SourceLocation = as_ir_sl(&es_test.loc, 0 /*FILE*/);
+
+    Ok(ir::Expr {
+        vartype: Some(ir::VarType::Boolean),
+        kind: ir::ExprKind::TypeCast {
+            test: Box::new(post_parse_expr(
+                es_test, parse_ctx, depth, num_locals, filename, ir_program,
+            )?),
+            expected: ir::VarType::Boolean,
+            create_narrow_local: true,
+            true_expr: Box::new(ir::Expr {
+                vartype: Some(ir::VarType::Boolean),
+                kind: ir::ExprKind::VarName {
+                    source: ir::TargetExpr::Local {
+                        localidx: num_locals,
+                        next: None,
+                    },
+                },
+            }),
+            false_expr: Box::new(ir::Expr {
+                vartype: None,
+                kind: ir::ExprKind::Trap {
+                    code: ir::error::ERROR_CODE_IF_STATEMENT_CONDITION_TYPE,
+                    location: cond_loc,
+                },
+            }),
+        },
+    })
+}
+
 fn post_parse_direct_func_decl(
     es_func_decl: FunctionDeclaration,
     loc: Option<esSL>,
@@ -1659,6 +2092,25 @@ fn post_parse_expr(
             filename,
             ir_program,
         ),
+        NodeKind::ArrayExpression(array_expr) => post_parse_array_expr(
+            array_expr,
+            es_expr.loc,
+            parse_ctx,
+            depth,
+            num_locals,
+            filename,
+            ir_program,
+        ),
+        NodeKind::MemberExpression(member_expr) => post_parse_member_expr(
+            member_expr,
+            None,
+            es_expr.loc,
+            parse_ctx,
+            depth,
+            num_locals,
+            filename,
+            ir_program,
+        ),
         _ => pppanic(),
     }
 }
@@ -1755,6 +2207,10 @@ fn post_parse_literal(
             vartype: Some(ir::VarType::Number),
             kind: ir::ExprKind::PrimNumber { val: number_val },
         }),
+        LiteralValue::Null => Ok(ir::Expr {
+            vartype: Some(ir::VarType::Null),
+            kind: ir::ExprKind::PrimNull,
+        }),
         _ => pppanic(),
     }
 }
@@ -1870,23 +2326,303 @@ fn post_parse_assign_expr(
         ));
     }
     // an assignment expr, that returns undefined
-    let varlocid = as_varlocid(as_id(*es_assign_expr.left).prevar.unwrap());
+    match es_assign_expr.left.kind {
+        NodeKind::Identifier(es_id) => {
+            let varlocid = as_varlocid(es_id.prevar.unwrap());
+            Ok(ir::Expr {
+                vartype: Some(ir::VarType::Undefined),
+                kind: ir::ExprKind::Assign {
+                    target: parse_ctx.get_target(&varlocid).unwrap().clone(),
+                    expr: Box::new(post_parse_expr(
+                        *es_assign_expr.right,
+                        parse_ctx,
+                        depth,
+                        num_locals,
+                        filename,
+                        ir_program,
+                    )?),
+                },
+            })
+        }
+        NodeKind::MemberExpression(member_expr) => post_parse_member_expr(
+            member_expr,
+            Some(*es_assign_expr.right),
+            loc,
+            parse_ctx,
+            depth,
+            num_locals,
+            filename,
+            ir_program,
+        ),
+        _ => pppanic(),
+    }
+}
+
+fn post_parse_array_expr(
+    es_array_expr: ArrayExpression,
+    _loc: Option<esSL>,
+    parse_ctx: &mut ParseState,
+    depth: usize,
+    num_locals: usize, // current number of IR locals
+    filename: Option<&str>,
+    ir_program: &mut ir::Program,
+) -> Result<ir::Expr, CompileMessage<ParseProgramError>> {
+    // Emits something like this:
+    // {
+    //     let arr: Array = ArrayNew(n);
+    //     ArraySet(arr, 0, elem_0);
+    //     ...
+    //     ArraySet(arr, n-1, elem_n-1);
+    //     arr
+    // }
+    // The array lives in a local so that it stays alive (and gets updated by the GC) while the elements are evaluated.
+
+    let make_array_local = || ir::Expr {
+        vartype: Some(ir::VarType::Array),
+        kind: ir::ExprKind::VarName {
+            source: ir::TargetExpr::Local {
+                localidx: num_locals,
+                next: None,
+            },
+        },
+    };
+
+    let init_expr = ir::Expr {
+        vartype: Some(ir::VarType::Array),
+        kind: ir::ExprKind::PrimAppl {
+            prim_inst: ir::PrimInst::ArrayNew,
+            args: Box::new([ir::Expr {
+                vartype: Some(ir::VarType::Number),
+                kind: ir::ExprKind::PrimNumber {
+                    val: es_array_expr.elements.len() as f64,
+                },
+            }]),
+        },
+    };
+
+    let mut sequence: Vec<ir::Expr> = Vec::new();
+    for (i, opt_elem) in es_array_expr.elements.into_iter().enumerate() {
+        // pre_parse() would have already ensured that there are no holes
+        let es_elem = opt_elem.unwrap_or_else(|| pppanic());
+        sequence.push(ir::Expr {
+            vartype: Some(ir::VarType::Undefined),
+            kind: ir::ExprKind::PrimAppl {
+                prim_inst: ir::PrimInst::ArraySet,
+                args: Box::new([
+                    make_array_local(),
+                    ir::Expr {
+                        vartype: Some(ir::VarType::Number),
+                        kind: ir::ExprKind::PrimNumber { val: i as f64 },
+                    },
+                    post_parse_expr(
+                        es_elem,
+                        parse_ctx,
+                        depth,
+                        num_locals + 1,
+                        filename,
+                        ir_program,
+                    )?,
+                ]),
+            },
+        });
+    }
+    sequence.push(make_array_local());
+
     Ok(ir::Expr {
-        vartype: Some(ir::VarType::Undefined),
-        kind: ir::ExprKind::Assign {
-            target: parse_ctx.get_target(&varlocid).unwrap().clone(),
-            expr: Box::new(post_parse_expr(
-                *es_assign_expr.right,
-                parse_ctx,
-                depth,
-                num_locals,
-                filename,
-                ir_program,
-            )?),
+        vartype: Some(ir::VarType::Array),
+        kind: ir::ExprKind::Declaration {
+            local: ir::VarType::Array,
+            init: Some(Box::new(init_expr)),
+            contained_expr: Box::new(ir::Expr {
+                vartype: Some(ir::VarType::Array),
+                kind: ir::ExprKind::Sequence { content: sequence },
+            }),
         },
     })
 }
 
+// Handles both reading from an array element (if `es_value` is None)
+// and writing to an array element (if `es_value` is Some).
+fn post_parse_member_expr(
+    es_member_expr: MemberExpression,
+    es_value: Option<Node>,
+    _loc: Option<esSL>,
+    parse_ctx: &mut ParseState,
+    depth: usize,
+    num_locals: usize, // current number of IR locals
+    filename: Option<&str>,
+    ir_program: &mut ir::Program,
+) -> Result<ir::Expr, CompileMessage<ParseProgramError>> {
+    // Emits something like this:
+    // {
+    //     let obj: Any = <object>;
+    //     let idx: Any = <property>;
+    //     let val: Any = <value>; // only when writing
+    //     ArrayGet(cast<Array>(obj), check_index(cast<Number>(idx))) // or ArraySet(..., val) when writing
+    // }
+    // The operands are stored in locals before doing the type checks,
+    // so that the array pointer is not left on the wasm stack while evaluating
+    // the other operands (which might trigger a GC).
+    // check_index() traps unless idx % 1 === 0 && idx >= 0 (this also rejects NaN and the infinities).
+    // The primitives check the index too, but they don't know the source location, so we check it here first.
+
+    let obj_loc: ir::SourceLocation = as_ir_sl(&es_member_expr.object.loc, 0 /*FILE*/);
+    let idx_loc: ir::SourceLocation = as_ir_sl(&es_member_expr.property.loc, 0 /*FILE*/);
+
+    let mut inits: Vec<ir::Expr> = Vec::new();
+    inits.push(post_parse_expr(
+        *es_member_expr.object,
+        parse_ctx,
+        depth,
+        num_locals,
+        filename,
+        ir_program,
+    )?);
+    inits.push(post_parse_expr(
+        *es_member_expr.property,
+        parse_ctx,
+        depth,
+        num_locals + 1,
+        filename,
+        ir_program,
+    )?);
+    if let Some(es_value) = es_value {
+        inits.push(post_parse_expr(
+            es_value,
+            parse_ctx,
+            depth,
+            num_locals + 2,
+            filename,
+            ir_program,
+        )?);
+    }
+
+    let make_local = |localidx: usize, vartype: ir::VarType| ir::Expr {
+        vartype: Some(vartype),
+        kind: ir::ExprKind::VarName {
+            source: ir::TargetExpr::Local {
+                localidx: localidx,
+                next: None,
+            },
+        },
+    };
+
+    // the narrow locals of the type casts go after all the temporaries
+    let narrow_localidx = num_locals + inits.len();
+    let make_trap = |code: u32, location| ir::Expr {
+        vartype: None,
+        kind: ir::ExprKind::Trap {
+            code: code,
+            location: location,
+        },
+    };
+    let make_number_prim = |prim_inst: ir::PrimInst, lhs: ir::Expr, rhs: f64| {
+        let vartype = prim_inst.signature().1;
+        ir::Expr {
+            vartype: vartype,
+            kind: ir::ExprKind::PrimAppl {
+                prim_inst: prim_inst,
+                args: Box::new([
+                    lhs,
+                    ir::Expr {
+                        vartype: Some(ir::VarType::Number),
+                        kind: ir::ExprKind::PrimNumber { val: rhs },
+                    },
+                ]),
+            },
+        }
+    };
+    let make_cast =
+        |localidx: usize, expected: ir::VarType, true_expr: ir::Expr, code: u32, location| {
+            ir::Expr {
+                vartype: Some(expected),
+                kind: ir::ExprKind::TypeCast {
+                    test: Box::new(make_local(localidx, ir::VarType::Any)),
+                    expected: expected,
+                    create_narrow_local: true,
+                    true_expr: Box::new(true_expr),
+                    false_expr: Box::new(make_trap(code, location)),
+                },
+            }
+        };
+
+    // if (idx % 1 === 0 && idx >= 0) idx else error
+    let checked_idx = ir::Expr {
+        vartype: Some(ir::VarType::Number),
+        kind: ir::ExprKind::Conditional {
+            cond: Box::new(ir::Expr {
+                vartype: Some(ir::VarType::Boolean),
+                kind: ir::ExprKind::PrimAppl {
+                    prim_inst: ir::PrimInst::BooleanAnd,
+                    args: Box::new([
+                        make_number_prim(
+                            ir::PrimInst::NumberEq,
+                            make_number_prim(
+                                ir::PrimInst::NumberRem,
+                                make_local(narrow_localidx, ir::VarType::Number),
+                                1.0,
+                            ),
+                            0.0,
+                        ),
+                        make_number_prim(
+                            ir::PrimInst::NumberGe,
+                            make_local(narrow_localidx, ir::VarType::Number),
+                            0.0,
+                        ),
+                    ]),
+                },
+            }),
+            true_expr: Box::new(make_local(narrow_localidx, ir::VarType::Number)),
+            false_expr: Box::new(make_trap(
+                ir::error::ERROR_CODE_ARRAY_INDEX_NOT_NONNEGATIVE_INTEGER,
+                idx_loc,
+            )),
+        },
+    };
+
+    let mut args: Vec<ir::Expr> = vec![
+        make_cast(
+            num_locals,
+            ir::VarType::Array,
+            make_local(narrow_localidx, ir::VarType::Array),
+            ir::error::ERROR_CODE_ARRAY_ACCESS_NOT_ARRAY,
+            obj_loc,
+        ),
+        make_cast(
+            num_locals + 1,
+            ir::VarType::Number,
+            checked_idx,
+            ir::error::ERROR_CODE_ARRAY_INDEX_NOT_NONNEGATIVE_INTEGER,
+            idx_loc,
+        ),
+    ];
+    let (prim_inst, result_vartype) = if inits.len() == 3 {
+        args.push(make_local(num_locals + 2, ir::VarType::Any));
+        (ir::PrimInst::ArraySet, ir::VarType::Undefined)
+    } else {
+        (ir::PrimInst::ArrayGet, ir::VarType::Any)
+    };
+
+    // wrap the declarations around the primitive, innermost first
+    Ok(inits.into_iter().rev().fold(
+        ir::Expr {
+            vartype: Some(result_vartype),
+            kind: ir::ExprKind::PrimAppl {
+                prim_inst: prim_inst,
+                args: args.into_boxed_slice(),
+            },
+        },
+        |contained_expr, init_expr| ir::Expr {
+            vartype: Some(result_vartype),
+            kind: ir::ExprKind::Declaration {
+                local: ir::VarType::Any,
+                init: Some(Box::new(init_expr)),
+                contained_expr: Box::new(contained_expr),
+            },
+        },
+    ))
+}
+
 fn post_parse_cond_expr(
     es_cond_expr: ConditionalExpression,
     loc: Option<esSL>,
@@ -2079,6 +2815,21 @@ fn make_prim_undefined() -> ir::Expr {
     }
 }
 
+/**
+ * Appends a SourceMarker for the statement at the given location, which the backend uses to generate source maps.
+ * Markers are only emitted for the main program (i.e. not the prelude or imported files), because the fileidx is always zero.
+ */
+fn push_source_marker(sequence: &mut Vec<ir::Expr>, loc: &Option<esSL>, filename: Option<&str>) {
+    if loc.is_some() && filename.is_none() {
+        sequence.push(ir::Expr {
+            vartype: Some(ir::VarType::Undefined),
+            kind: ir::ExprKind::SourceMarker {
+                location: as_ir_sl(loc, 0 /*FILE*/),
+            },
+        });
+    }
+}
+
 fn make_trap_for_accessing_var_before_init(ir_sl: ir::SourceLocation) -> ir::Expr {
     ir::Expr {
         vartype: None,
@@ -2161,6 +2912,23 @@ fn as_id_ref(es_node: &Node) -> &Identifier {
     }
 }
 
+/**
+ * Returns the name of the function for debugging purposes.
+ * Anonymous functions are named by their location (with 1-based columns, like the locations in compiler messages).
+ */
+fn func_name<Func: Function>(es_func: &Func, loc: &Option<esSL>, filename: Option<&str>) -> String {
+    match (es_func.name(), loc) {
+        (Some(name), _) => name.to_owned(),
+        (None, Some(es_sl)) => format!(
+            "anonymous@{}{}:{}",
+            filename.map_or_else(String::new, |f| format!("{}:", f)),
+            es_sl.start.line,
+            es_sl.start.column + 1
+        ),
+        (None, None) => "anonymous".to_owned(),
+    }
+}
+
 // TODO: store both line and column, and make fileidx work.
 fn as_ir_sl(opt_es_sl: &Option<SourceLocation>, fileidx: u32) -> ir::SourceLocation {
     let (start, end) = match opt_es_sl {
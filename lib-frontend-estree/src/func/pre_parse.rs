@@ -54,8 +54,15 @@ pub fn pre_parse_program(
     es_program
         .body
         .each_with_attributes_mut(filename, |es_node, attr| {
-            let usages =
-                pre_parse_statement(es_node, attr, name_ctx, &mut direct_funcs, 0, filename)?;
+            let usages = pre_parse_statement(
+                es_node,
+                attr,
+                name_ctx,
+                &mut direct_funcs,
+                0,
+                false,
+                filename,
+            )?;
             assert!(
                 usages.is_empty(),
                 "Global variable got returned as a Usage, this is a bug"
@@ -85,6 +92,7 @@ fn pre_parse_block_statement(
     /*deps: &[&HashMap<String, PreVar>],*/
     /*order: usize,*/
     depth: usize,
+    in_loop: bool, // whether break and continue statements are allowed here
     filename: Option<&str>,
 ) -> Result<BTreeMap<VarLocId, Usage>, CompileMessage<ParseProgramError>> {
     let new_depth = depth + 1;
@@ -109,6 +117,7 @@ fn pre_parse_block_statement(
                 name_ctx,
                 &mut direct_funcs,
                 new_depth,
+                in_loop,
                 filename,
             )?;
             let tmp = std::mem::take(&mut ret_usages); // necessary because of weird borrow rules in Rust
@@ -185,6 +194,7 @@ fn pre_parse_function<F: Function + Scope>(
                     name_ctx,
                     &mut direct_funcs,
                     new_depth,
+                    false, // break and continue cannot jump out of a function
                     filename,
                 )?;
                 let tmp = std::mem::take(&mut ret_usages); // necessary because of weird borrow rules in Rust
@@ -251,6 +261,7 @@ fn pre_parse_statement(
     /*deps: &[&HashMap<String, PreVar>],*/
     /*order: usize,*/
     depth: usize,
+    in_loop: bool, // whether break and continue statements are allowed here
     filename: Option<&str>,
 ) -> Result<BTreeMap<VarLocId, Usage>, CompileMessage<ParseProgramError>> {
     let mut is_direct = false;
@@ -323,13 +334,39 @@ fn pre_parse_statement(
             pre_parse_expr_statement(stmt, &es_node.loc, name_ctx, depth, filename)
         }
         NodeKind::BlockStatement(block) => {
-            pre_parse_block_statement(block, &es_node.loc, name_ctx, depth, filename)
+            pre_parse_block_statement(block, &es_node.loc, name_ctx, depth, in_loop, filename)
         }
         NodeKind::ReturnStatement(stmt) => {
             pre_parse_return_statement(stmt, &es_node.loc, name_ctx, depth, filename)
         }
         NodeKind::IfStatement(stmt) => {
-            pre_parse_if_statement(stmt, &es_node.loc, name_ctx, depth, filename)
+            pre_parse_if_statement(stmt, &es_node.loc, name_ctx, depth, in_loop, filename)
+        }
+        NodeKind::WhileStatement(stmt) => {
+            pre_parse_while_statement(stmt, &es_node.loc, name_ctx, depth, filename)
+        }
+        NodeKind::ForStatement(stmt) => {
+            pre_parse_for_statement(stmt, &es_node.loc, name_ctx, depth, filename)
+        }
+        NodeKind::BreakStatement(BreakStatement { label })
+        | NodeKind::ContinueStatement(ContinueStatement { label }) => {
+            if label.is_some() {
+                Err(CompileMessage::new_error(
+                    es_node.loc.into_sl(filename).to_owned(),
+                    ParseProgramError::SourceRestrictionError(
+                        "Labelled break and continue statements are not allowed",
+                    ),
+                ))
+            } else if !in_loop {
+                Err(CompileMessage::new_error(
+                    es_node.loc.into_sl(filename).to_owned(),
+                    ParseProgramError::ESTreeError(
+                        "Break and continue statements must be inside a loop",
+                    ),
+                ))
+            } else {
+                Ok(BTreeMap::new()) // break and continue do not use any variables
+            }
         }
         NodeKind::FunctionDeclaration(func_decl) => {
             pre_parse_func_decl(func_decl, &es_node.loc, name_ctx, depth, filename)
@@ -340,9 +377,7 @@ fn pre_parse_statement(
         NodeKind::EmptyStatement(_) => Ok(BTreeMap::new()), // EmptyStatement does not use any variables
         NodeKind::DebuggerStatement(_)
         | NodeKind::WithStatement(_)
-        | NodeKind::LabeledStatement(_)
-        | NodeKind::BreakStatement(_)
-        | NodeKind::ContinueStatement(_) => Err(CompileMessage::new_error(
+        | NodeKind::LabeledStatement(_) => Err(CompileMessage::new_error(
             es_node.loc.into_sl(filename).to_owned(),
             ParseProgramError::ESTreeError("This statement type is not allowed"),
         )),
@@ -382,9 +417,21 @@ fn pre_parse_expr_statement(
     name_ctx: &mut HashMap<String, PreVar>, // contains all names referenceable from outside the current sequence
     depth: usize,
     filename: Option<&str>,
+) -> Result<BTreeMap<VarLocId, Usage>, CompileMessage<ParseProgramError>> {
+    pre_parse_expr_or_assign(&mut *es_expr_stmt.expression, name_ctx, depth, filename)
+}
+
+/**
+ * Like pre_parse_expr(), but also accepts a (non-nested) AssignmentExpression.
+ * Used for expression statements and the init and update parts of a for-loop.
+ */
+fn pre_parse_expr_or_assign(
+    es_expr_node: &mut Node,
+    name_ctx: &mut HashMap<String, PreVar>, // contains all names referenceable from outside the current sequence
+    depth: usize,
+    filename: Option<&str>,
 ) -> Result<BTreeMap<VarLocId, Usage>, CompileMessage<ParseProgramError>> {
     // we have to detect the AssignmentExpression here, since in Source AssignmentExpression is not allowed to be nested.
-    let es_expr_node: &mut Node = &mut *es_expr_stmt.expression;
     if let NodeKind::AssignmentExpression(AssignmentExpression {
         operator,
         left,
@@ -399,8 +446,7 @@ fn pre_parse_expr_statement(
                 } => {
                     let rhs_expr = pre_parse_expr(&mut **right, name_ctx, depth, filename)?;
                     let resvar = *name_ctx.get(name.as_str()).unwrap();
-                    assert!(*prevar == Some(resvar)); // they should already have a prevar attached
-                                                      // note: this is probably a bug, they would not have prevar attached yet...
+                    *prevar = Some(resvar);
                     let varlocid = match resvar {
                         PreVar::Target(varlocid) => varlocid,
                         PreVar::Direct => panic!("ICE: Should be VarLocId"),
@@ -418,10 +464,20 @@ fn pre_parse_expr_statement(
                         ))
                     }
                 }
+                Node {
+                    loc,
+                    kind: NodeKind::MemberExpression(member_expr),
+                } => {
+                    // the array and index are evaluated before the RHS
+                    let lhs_expr =
+                        pre_parse_member_expr(member_expr, loc, name_ctx, depth, filename)?;
+                    let rhs_expr = pre_parse_expr(&mut **right, name_ctx, depth, filename)?;
+                    Ok(varusage::merge_series(lhs_expr, rhs_expr))
+                }
                 Node { loc, kind: _ } => Err(CompileMessage::new_error(
                     loc.into_sl(filename).to_owned(),
                     ParseProgramError::ESTreeError(
-                        "Expected ESTree Identifier at LHS of AssignmentExpression",
+                        "Expected ESTree Identifier or MemberExpression at LHS of AssignmentExpression",
                     ),
                 )),
             },
@@ -459,6 +515,7 @@ fn pre_parse_if_statement(
     loc: &Option<esSL>,
     name_ctx: &mut HashMap<String, PreVar>, // contains all names referenceable from outside the current sequence
     depth: usize,
+    in_loop: bool,
     filename: Option<&str>,
 ) -> Result<BTreeMap<VarLocId, Usage>, CompileMessage<ParseProgramError>> {
     if let NodeKind::BlockStatement(es_true_block) = &mut es_if.consequent.kind {
@@ -472,6 +529,7 @@ fn pre_parse_if_statement(
                             &es_if.consequent.loc,
                             name_ctx,
                             depth,
+                            in_loop,
                             filename,
                         )?,
                         pre_parse_block_statement(
@@ -479,6 +537,7 @@ fn pre_parse_if_statement(
                             &es_false_node.loc,
                             name_ctx,
                             depth,
+                            in_loop,
                             filename,
                         )?,
                     ),
@@ -507,6 +566,126 @@ fn pre_parse_if_statement(
     }
 }
 
+fn pre_parse_while_statement(
+    es_while: &mut WhileStatement,
+    loc: &Option<esSL>,
+    name_ctx: &mut HashMap<String, PreVar>, // contains all names referenceable from outside the current sequence
+    depth: usize,
+    filename: Option<&str>,
+) -> Result<BTreeMap<VarLocId, Usage>, CompileMessage<ParseProgramError>> {
+    if let NodeKind::BlockStatement(es_body_block) = &mut es_while.body.kind {
+        // the test and body are executed repeatedly
+        Ok(varusage::wrap_loop(varusage::merge_series(
+            pre_parse_expr(&mut *es_while.test, name_ctx, depth, filename)?,
+            pre_parse_block_statement(
+                es_body_block,
+                &es_while.body.loc,
+                name_ctx,
+                depth,
+                true,
+                filename,
+            )?,
+        )))
+    } else {
+        Err(CompileMessage::new_error(
+            loc.into_sl(filename).to_owned(),
+            ParseProgramError::SourceRestrictionError("Body of while loop must be a block"),
+        ))
+    }
+}
+
+/**
+ * The variable declared in the init part of the for-loop lives in its own scope (that encloses the test, update, and body),
+ * so this is handled similarly to pre_parse_block_statement().
+ */
+fn pre_parse_for_statement(
+    es_for: &mut ForStatement,
+    loc: &Option<esSL>,
+    name_ctx: &mut HashMap<String, PreVar>, // contains all names referenceable from outside the current sequence
+    depth: usize,
+    filename: Option<&str>,
+) -> Result<BTreeMap<VarLocId, Usage>, CompileMessage<ParseProgramError>> {
+    let new_depth = depth + 1;
+
+    let curr_decls: Vec<(String, PreVar)> = match &es_for.init {
+        Some(es_init_node) => {
+            if let NodeKind::VariableDeclaration(var_decl) = &es_init_node.kind {
+                if var_decl.kind != "let" || var_decl.declarations.len() != 1 {
+                    return Err(CompileMessage::new_error(
+                        es_init_node.loc.into_sl(filename).to_owned(),
+                        ParseProgramError::SourceRestrictionError(
+                            "Initializer of for loop must declare exactly one variable using 'let'",
+                        ),
+                    ));
+                }
+            }
+            validate_and_extract_decls(
+                std::slice::from_ref(&**es_init_node),
+                new_depth,
+                &mut 0,
+                filename,
+            )?
+        }
+        None => Vec::new(),
+    };
+
+    let undo_ctx = name_ctx.add_scope(curr_decls);
+
+    let init_usages = match &mut es_for.init {
+        Some(es_init_node) => {
+            if let NodeKind::VariableDeclaration(var_decl) = &mut es_init_node.kind {
+                pre_parse_var_decl(var_decl, &es_init_node.loc, name_ctx, new_depth, filename)?
+            } else {
+                pre_parse_expr_or_assign(&mut **es_init_node, name_ctx, new_depth, filename)?
+            }
+        }
+        None => BTreeMap::new(),
+    };
+
+    let test_usages = match &mut es_for.test {
+        Some(es_test_node) => pre_parse_expr(&mut **es_test_node, name_ctx, new_depth, filename)?,
+        None => BTreeMap::new(),
+    };
+
+    let body_usages = if let NodeKind::BlockStatement(es_body_block) = &mut es_for.body.kind {
+        pre_parse_block_statement(
+            es_body_block,
+            &es_for.body.loc,
+            name_ctx,
+            new_depth,
+            true,
+            filename,
+        )?
+    } else {
+        return Err(CompileMessage::new_error(
+            loc.into_sl(filename).to_owned(),
+            ParseProgramError::SourceRestrictionError("Body of for loop must be a block"),
+        ));
+    };
+
+    let update_usages = match &mut es_for.update {
+        Some(es_update_node) => {
+            pre_parse_expr_or_assign(&mut **es_update_node, name_ctx, new_depth, filename)?
+        }
+        None => BTreeMap::new(),
+    };
+
+    // the test, body, and update are executed repeatedly (in that order)
+    let mut ret_usages = varusage::merge_series(
+        init_usages,
+        varusage::wrap_loop(varusage::merge_series(
+            varusage::merge_series(test_usages, body_usages),
+            update_usages,
+        )),
+    );
+
+    es_for.address_taken_vars = split_off_address_taken_vars(&mut ret_usages, new_depth);
+
+    name_ctx.remove_scope(undo_ctx);
+
+    Ok(ret_usages)
+}
+
 /**
  * This is a normal function declaration, not the direct kind.  So it is equivalent to a const declaration.
  */
@@ -781,13 +960,10 @@ fn pre_parse_expr(
             pre_parse_identifier_use(identifier, &es_expr.loc, name_ctx, depth, filename)
         }
         NodeKind::Literal(literal) => match literal.value {
-            LiteralValue::String(_) | LiteralValue::Boolean(_) | LiteralValue::Number(_) => {
-                Ok(BTreeMap::new())
-            }
-            LiteralValue::Null => Err(CompileMessage::new_error(
-                es_expr.loc.into_sl(filename).to_owned(),
-                ParseProgramError::SourceRestrictionError("Null literal not allowed"),
-            )),
+            LiteralValue::String(_)
+            | LiteralValue::Boolean(_)
+            | LiteralValue::Null
+            | LiteralValue::Number(_) => Ok(BTreeMap::new()),
             LiteralValue::RegExp => Err(CompileMessage::new_error(
                 es_expr.loc.into_sl(filename).to_owned(),
                 ParseProgramError::SourceRestrictionError("Regular expression not allowed"),
@@ -858,6 +1034,31 @@ fn pre_parse_expr(
                     })
                 })
         }
+        NodeKind::ArrayExpression(array_expr) => {
+            // array literal, i.e. [a, b, ...]
+            // the elements are evaluated left-to-right
+            let loc = &es_expr.loc;
+            array_expr
+                .elements
+                .iter_mut()
+                .fold(Ok(BTreeMap::new()), |r_prev, opt_elem| {
+                    r_prev.and_then(|prev| match opt_elem {
+                        Some(elem) => Ok(varusage::merge_series(
+                            prev,
+                            pre_parse_expr(elem, name_ctx, depth, filename)?,
+                        )),
+                        None => Err(CompileMessage::new_error(
+                            loc.into_sl(filename).to_owned(),
+                            ParseProgramError::SourceRestrictionError(
+                                "Holes in array literals not allowed",
+                            ),
+                        )),
+                    })
+                })
+        }
+        NodeKind::MemberExpression(member_expr) => {
+            pre_parse_member_expr(member_expr, &es_expr.loc, name_ctx, depth, filename)
+        }
         _ => Err(CompileMessage::new_error(
             es_expr.loc.into_sl(filename).to_owned(),
             ParseProgramError::ESTreeError("Expression node expected"),
@@ -865,6 +1066,28 @@ fn pre_parse_expr(
     }
 }
 
+fn pre_parse_member_expr(
+    es_member_expr: &mut MemberExpression,
+    loc: &Option<esSL>,
+    name_ctx: &mut HashMap<String, PreVar>,
+    depth: usize,
+    filename: Option<&str>,
+) -> Result<BTreeMap<VarLocId, Usage>, CompileMessage<ParseProgramError>> {
+    // array access, i.e. a[i]
+    // JS requires 'a' to be evaluated before 'i'
+    if !es_member_expr.computed {
+        return Err(CompileMessage::new_error(
+            loc.into_sl(filename).to_owned(),
+            ParseProgramError::SourceRestrictionError(
+                "Property access not allowed, use array index syntax instead",
+            ),
+        ));
+    }
+    let obj = pre_parse_expr(&mut *es_member_expr.object, name_ctx, depth, filename)?;
+    let prop = pre_parse_expr(&mut *es_member_expr.property, name_ctx, depth, filename)?;
+    Ok(varusage::merge_series(obj, prop))
+}
+
 fn pre_parse_identifier_use(
     es_id: &mut Identifier,
     loc: &Option<esSL>,
@@ -1448,3 +1671,92 @@ fn validate_and_extract_params(
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Collects the prevar of every declared name and every assignment target, in order.
+     */
+    fn collect_prevars(
+        node: &Node,
+        decls: &mut Vec<(String, Option<PreVar>)>,
+        assigns: &mut Vec<(String, Option<PreVar>)>,
+    ) {
+        match &node.kind {
+            NodeKind::Program(Program { body, .. })
+            | NodeKind::BlockStatement(BlockStatement { body, .. }) => {
+                for stmt in body {
+                    collect_prevars(stmt, decls, assigns);
+                }
+            }
+            NodeKind::FunctionDeclaration(FunctionDeclaration { body, .. }) => {
+                collect_prevars(body, decls, assigns);
+            }
+            NodeKind::VariableDeclaration(VariableDeclaration { declarations, .. }) => {
+                for decl in declarations {
+                    if let NodeKind::VariableDeclarator(VariableDeclarator { id, .. }) = &decl.kind
+                    {
+                        if let NodeKind::Identifier(Identifier { name, prevar }) = &id.kind {
+                            decls.push((name.clone(), *prevar));
+                        }
+                    }
+                }
+            }
+            NodeKind::ExpressionStatement(ExpressionStatement { expression }) => {
+                if let NodeKind::AssignmentExpression(AssignmentExpression { left, .. }) =
+                    &expression.kind
+                {
+                    if let NodeKind::Identifier(Identifier { name, prevar }) = &left.kind {
+                        assigns.push((name.clone(), *prevar));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn assignment_targets_get_prevars() {
+        // the target of an assignment statement gets its prevar here (there is no earlier pass that attaches it),
+        // so this must work for assignments that are not in a loop, at the top level and in a function
+        // function f() {
+        //     let x = 1;
+        //     x = 2;
+        //     return x;
+        // }
+        // let y = f();
+        // y = 3;
+        let estree = r#"{"type":"Program","body":[{"type":"FunctionDeclaration","id":{"type":"Identifier","name":"f"},"params":[],"body":{"type":"BlockStatement","body":[{"type":"VariableDeclaration","declarations":[{"type":"VariableDeclarator","id":{"type":"Identifier","name":"x"},"init":{"type":"Literal","value":1}}],"kind":"let"},{"type":"ExpressionStatement","expression":{"type":"AssignmentExpression","operator":"=","left":{"type":"Identifier","name":"x"},"right":{"type":"Literal","value":2}}},{"type":"ReturnStatement","argument":{"type":"Identifier","name":"x"}}]}},{"type":"VariableDeclaration","declarations":[{"type":"VariableDeclarator","id":{"type":"Identifier","name":"y"},"init":{"type":"CallExpression","callee":{"type":"Identifier","name":"f"},"arguments":[]}}],"kind":"let"},{"type":"ExpressionStatement","expression":{"type":"AssignmentExpression","operator":"=","left":{"type":"Identifier","name":"y"},"right":{"type":"Literal","value":3}}}]}"#;
+        let mut es_program: Node = serde_json::from_str(estree).unwrap();
+        let mut name_ctx: HashMap<String, PreVar> = HashMap::new();
+        let mut start_idx = 0;
+        if let NodeKind::Program(program) = &mut es_program.kind {
+            pre_parse_program(
+                program,
+                &es_program.loc,
+                &mut name_ctx,
+                &[],
+                &mut start_idx,
+                None,
+            )
+            .ok()
+            .unwrap();
+        } else {
+            panic!("expected a Program");
+        }
+        let mut decls = Vec::new();
+        let mut assigns = Vec::new();
+        collect_prevars(&es_program, &mut decls, &mut assigns);
+        assert_eq!(
+            decls
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["x", "y"]
+        );
+        assert!(decls.iter().all(|(_, prevar)| prevar.is_some()));
+        assert_eq!(assigns, decls);
+    }
+}
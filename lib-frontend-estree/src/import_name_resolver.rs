@@ -47,49 +47,226 @@ fn possibly_append_ext(name: String) -> PossiblyExtendExtIterator {
 
 pub type ResolveIter = Box<dyn Iterator<Item = String>>;
 
-pub fn resolve(name: &str, current_filename: Option<&str>) -> ResolveIter {
-    if name.contains("//") {
-        // it is an absolute URL (maybe protocol-relative), so we don't prepend anything
-        Box::new(possibly_append_ext(name.to_owned()))
-    } else if name.starts_with('/') {
-        // it is a domain-relative URL
-        // firstly, try prepending the current domain name
-        let domain: Option<&str> = current_filename.map(|curr| {
-            let domain_start = curr.find("//").map_or(0, |l| l + "//".len());
-            let domain = curr[domain_start..]
-                .find('/')
-                .map_or(curr, |domain_end_offset| {
-                    &curr[..(domain_start + domain_end_offset)]
-                });
-            domain
-        });
-        // secondly, don't prepend anything
-        if let Some(dom) = domain {
-            Box::new(
-                possibly_append_ext(String::from(dom) + name)
-                    .chain(possibly_append_ext(name.to_owned())),
-            )
-        } else {
-            Box::new(possibly_append_ext(name.to_owned()))
+/**
+ * The location of the standard library that is searched by default.
+ */
+pub const DEFAULT_STDLIB_ROOT: &str = "https://btzy.github.io/libsourceror/";
+
+/**
+ * Decides which files an import declaration may refer to.
+ */
+pub trait ModuleResolver {
+    /**
+     * Returns the candidate names for the import `name` in the file `current_filename` (None for the main program), in order of preference.
+     * The first candidate that can be fetched is used.
+     * Fetched files are cached by name, so the same file should always resolve to the same name (e.g. without any `./` or `../` segments).
+     */
+    fn resolve(&self, name: &str, current_filename: Option<&str>) -> ResolveIter;
+}
+
+/**
+ * The default resolver, which treats names as URLs (or paths).
+ * Bare names (e.g. "std/misc") are looked up relative to the current file, then in each of the search roots in order,
+ * and names starting with "./" or "../" are only looked up relative to the current file.
+ * A search root may be a URL or a local directory, e.g. "https://example.com/lib/" or "/usr/share/sourceror/lib".
+ * A ".source" extension is tried after each candidate name.
+ */
+pub struct StandardResolver {
+    search_roots: Vec<String>,
+}
+
+impl StandardResolver {
+    /**
+     * Creates a resolver that searches the default standard library location.
+     */
+    pub fn new() -> Self {
+        Self::with_search_roots(vec![DEFAULT_STDLIB_ROOT.to_owned()])
+    }
+    pub fn with_search_roots(search_roots: Vec<String>) -> Self {
+        Self {
+            search_roots: search_roots,
         }
-    } else {
-        // it is a relative URL
-        // firstly, we resolve this URL in the scope of the current name
-        let current_path_prefix: Option<&str> =
-            current_filename.and_then(|curr| curr.rfind("/").map(|idx| &curr[..(idx + "/".len())]));
-        let first_candidate: Option<String> =
-            current_path_prefix.map(|pref| String::from(pref) + name);
-        // secondly, resolve this as a stdlib name in the hardcoded standard library path
-        let stdlib_name: String = String::from("https://btzy.github.io/libsourceror/") + name;
-        // thirdly, don't prepend anything
-        if let Some(fc) = first_candidate {
-            Box::new(
-                possibly_append_ext(fc)
-                    .chain(possibly_append_ext(stdlib_name))
-                    .chain(possibly_append_ext(name.to_owned())),
-            )
+    }
+}
+
+impl Default for StandardResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ModuleResolver for StandardResolver {
+    fn resolve(&self, name: &str, current_filename: Option<&str>) -> ResolveIter {
+        let mut candidates: Vec<String> = Vec::new();
+        if name.contains("//") {
+            // it is an absolute URL (maybe protocol-relative), so we don't prepend anything
+            candidates.push(normalize(name));
+        } else if name.starts_with('/') {
+            // it is a domain-relative URL
+            // firstly, try prepending the current domain name
+            if let Some(curr) = current_filename {
+                let (domain, _) = split_domain(curr);
+                if !domain.is_empty() {
+                    candidates.push(normalize(&(domain.to_owned() + name)));
+                }
+            }
+            // secondly, don't prepend anything
+            candidates.push(normalize(name));
         } else {
-            Box::new(possibly_append_ext(stdlib_name).chain(possibly_append_ext(name.to_owned())))
+            // it is a relative URL
+            // firstly, we resolve this URL in the scope of the current name
+            if let Some(curr) = current_filename {
+                let current_path_prefix: &str =
+                    curr.rfind('/').map_or("", |idx| &curr[..(idx + "/".len())]);
+                candidates.push(normalize(&(current_path_prefix.to_owned() + name)));
+            }
+            // secondly, resolve bare names in each of the search roots
+            if !name.starts_with("./") && !name.starts_with("../") {
+                for root in &self.search_roots {
+                    let root_prefix: String = if root.ends_with('/') {
+                        root.clone()
+                    } else {
+                        root.clone() + "/"
+                    };
+                    candidates.push(normalize(&(root_prefix + name)));
+                }
+            }
+            // thirdly, don't prepend anything
+            candidates.push(normalize(name));
+        }
+        // different candidates might have been normalized to the same name, so we keep only the first of each
+        let mut deduped: Vec<String> = Vec::new();
+        for candidate in candidates {
+            if !deduped.contains(&candidate) {
+                deduped.push(candidate);
+            }
         }
+        Box::new(deduped.into_iter().flat_map(possibly_append_ext))
+    }
+}
+
+// Splits a URL into the scheme and domain (e.g. "https://example.com", empty if there is none) and the rest of it
+fn split_domain(name: &str) -> (&str, &str) {
+    match name.find("//") {
+        Some(idx) => {
+            let domain_start = idx + "//".len();
+            match name[domain_start..].find('/') {
+                Some(domain_end_offset) => name.split_at(domain_start + domain_end_offset),
+                None => (name, ""),
+            }
+        }
+        None => ("", name),
+    }
+}
+
+/**
+ * Removes empty, `.` and `..` segments from the path of a URL (or a path).
+ * `..` segments that go above the root are dropped, but leading `..` segments of a relative path are kept.
+ */
+pub fn normalize(name: &str) -> String {
+    let (domain, path) = split_domain(name);
+    let rooted = path.starts_with('/');
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => match segments.last() {
+                Some(last) if *last != ".." => {
+                    segments.pop();
+                }
+                _ => {
+                    if !rooted {
+                        segments.push(segment);
+                    }
+                }
+            },
+            _ => segments.push(segment),
+        }
+    }
+    let mut ret = domain.to_owned();
+    if rooted {
+        ret.push('/');
+    }
+    ret += segments.join("/").as_str();
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(
+        resolver: &StandardResolver,
+        name: &str,
+        current_filename: Option<&str>,
+    ) -> Vec<String> {
+        resolver.resolve(name, current_filename).collect()
+    }
+
+    #[test]
+    fn normalize_segments() {
+        assert_eq!(
+            normalize("https://example.com/a/./b/../c"),
+            "https://example.com/a/c"
+        );
+        assert_eq!(
+            normalize("https://example.com/../a"),
+            "https://example.com/a"
+        );
+        assert_eq!(normalize("//example.com//a"), "//example.com/a");
+        assert_eq!(normalize("/a/b/../../../c"), "/c");
+        assert_eq!(normalize("./a/../../b"), "../b");
+        assert_eq!(normalize("lib/./std/misc"), "lib/std/misc");
+    }
+
+    #[test]
+    fn resolve_candidates() {
+        let resolver = StandardResolver::with_search_roots(vec!["/usr/lib/sourceror".to_owned()]);
+        assert_eq!(
+            candidates(
+                &resolver,
+                "std/misc",
+                Some("https://example.com/x/main.source")
+            ),
+            vec![
+                "https://example.com/x/std/misc",
+                "https://example.com/x/std/misc.source",
+                "/usr/lib/sourceror/std/misc",
+                "/usr/lib/sourceror/std/misc.source",
+                "std/misc",
+                "std/misc.source",
+            ]
+        );
+        // the same file is reached under a single name, and explicitly relative names are not searched in the roots
+        assert_eq!(
+            candidates(
+                &resolver,
+                "../y/./z.source",
+                Some("https://example.com/x/main.source")
+            ),
+            vec!["https://example.com/y/z.source", "../y/z.source"]
+        );
+        assert_eq!(
+            candidates(
+                &resolver,
+                "/a/../b",
+                Some("https://example.com/x/main.source")
+            ),
+            vec![
+                "https://example.com/b",
+                "https://example.com/b.source",
+                "/b",
+                "/b.source"
+            ]
+        );
+        assert_eq!(
+            candidates(&StandardResolver::new(), "std/misc", None),
+            vec![
+                "https://btzy.github.io/libsourceror/std/misc",
+                "https://btzy.github.io/libsourceror/std/misc.source",
+                "std/misc",
+                "std/misc.source",
+            ]
+        );
     }
 }
@@ -13,6 +13,7 @@ use std::result::Result;
 // The import file has "@SourceImports" on the first line,
 // and subsequent lines are either empty or are of the following syntax:
 // <local name> <imported namespace> <imported name> <return type> <param types...>
+// where the types are "undefined", "number", "string", "boolean", "function" or "any".
 
 pub struct ImportSpec {
     pub content: Vec<(String, Import)>,
@@ -147,6 +148,9 @@ fn make_vartype(type_name: &str) -> Option<ImportValType> {
         "undefined" => Some(ImportValType::Undefined),
         "number" => Some(ImportValType::Number),
         "string" => Some(ImportValType::String),
+        "boolean" => Some(ImportValType::Boolean),
+        "function" => Some(ImportValType::Func),
+        "any" => Some(ImportValType::Any),
         _ => None,
     }
 }
@@ -1,20 +1,25 @@
-mod dep_graph;
+pub mod dep_graph;
 // mod dep_extract;
 mod attributes;
 mod builtins;
 mod error;
 mod estree;
 mod extensions;
+pub mod fetcher;
 mod frontendvar;
 mod func;
-mod import_name_resolver;
+pub mod import_name_resolver;
 mod importer;
 mod parse_state;
+mod prelude;
 
 use async_trait::async_trait;
+pub use error::FetchError;
+pub use error::FetcherError;
 use error::*;
 use extensions::IntoSourceLocation;
 use frontendvar::*;
+use import_name_resolver::ModuleResolver;
 use ir;
 use projstd::log::CompileMessage;
 use projstd::log::LogErr;
@@ -41,55 +46,44 @@ enum SourceItem {
     ImportSpec(importer::ImportSpec),
 }
 
-#[derive(Copy, Clone)]
+// Parses the modules returned by a raw fetcher
 struct SourceFetcher<F> {
-    raw_fetch: F,
+    raw_fetcher: F,
 }
 //#[async_trait(?Send)]
-impl<Fut: Future<Output = Option<String>>, F: 'static + Copy + FnOnce(String) -> Fut>
-    dep_graph::Fetcher<SourceItem> for SourceFetcher<F>
-{
+impl<F: dep_graph::Fetcher<String>> dep_graph::Fetcher<SourceItem> for SourceFetcher<F> {
     fn fetch<'a>(
-        self,
+        &'a self,
         name: &'a str,
         sl: plSLRef<'a>,
     ) -> std::pin::Pin<
         Box<dyn 'a + Future<Output = Result<SourceItem, CompileMessage<FetcherError>>>>,
     > {
-        Box::pin((|| async move {
-            (self.raw_fetch)(name.to_owned()).await.map_or_else(
-                || {
-                    Err(
-                        CompileMessage::new_error(sl.to_owned(), FetchError::new(name.to_owned()))
-                            .into_cm(),
-                    )
-                },
-                |estree_str: String| {
-                    if importer::has_imports_header(estree_str.as_str()) {
-                        // this is an imports file
-                        importer::parse_imports(name, estree_str.as_str())
-                            .map(|import_spec| SourceItem::ImportSpec(import_spec))
-                            .map_err(|e| e.into_cm())
-                    } else {
-                        serde_json::from_str(estree_str.as_str())
-                            .map(|estree_node| SourceItem::ESTree(estree_node))
-                            .map_err(|_| {
-                                CompileMessage::new_error(
-                                    plSLRef::entire_file(Some(name)).to_owned(),
-                                    ESTreeParseError {},
-                                )
-                                .into_cm()
-                            })
-                    }
-                },
-            )
-        })())
+        Box::pin(async move {
+            let estree_str: String = self.raw_fetcher.fetch(name, sl).await?;
+            if importer::has_imports_header(estree_str.as_str()) {
+                // this is an imports file
+                importer::parse_imports(name, estree_str.as_str())
+                    .map(|import_spec| SourceItem::ImportSpec(import_spec))
+                    .map_err(|e| e.into_cm())
+            } else {
+                serde_json::from_str(estree_str.as_str())
+                    .map(|estree_node| SourceItem::ESTree(estree_node))
+                    .map_err(|_| {
+                        CompileMessage::new_error(
+                            plSLRef::entire_file(Some(name)).to_owned(),
+                            ESTreeParseError {},
+                        )
+                        .into_cm()
+                    })
+            }
+        })
     }
 }
 
 impl<'a> dep_graph::ExtractDeps<'a> for SourceItem {
     // todo! Change `dyn Iterator` to some compile-time thing when Rust gets impl Traits support for traits.
-    type Iter = Box<dyn Iterator<Item = (import_name_resolver::ResolveIter, plSLRef<'a>)> + 'a>;
+    type Iter = Box<dyn Iterator<Item = (&'a str, plSLRef<'a>)> + 'a>;
     fn extract_deps(&'a self, filename: Option<&'a str>) -> Self::Iter {
         match self {
             SourceItem::ESTree(es_node) => Box::new(
@@ -108,10 +102,7 @@ impl<'a> dep_graph::ExtractDeps<'a> for SourceItem {
                             value: LiteralValue::String(s),
                         }) = &source.kind
                         {
-                            return Some((
-                                import_name_resolver::resolve(s.as_str(), filename),
-                                source.loc.into_sl(filename),
-                            ));
+                            return Some((s.as_str(), source.loc.into_sl(filename)));
                         }
                     }
                     None
@@ -122,13 +113,13 @@ impl<'a> dep_graph::ExtractDeps<'a> for SourceItem {
     }
 }
 
-pub async fn run_frontend<
-    L: Logger,
-    F: 'static + Copy + FnOnce(String) -> Fut,
-    Fut: Future<Output = Option<String>>,
->(
+/**
+ * Parses the given program, and all the modules it imports (which are fetched with `fetcher`, see the `fetcher` module), into an ir::Program.
+ */
+pub async fn run_frontend<L: Logger, F: dep_graph::Fetcher<String>, R: ModuleResolver>(
     estree_str: String,
-    raw_fetch: F,
+    fetcher: F,
+    resolver: R,
     logger: L,
 ) -> Result<ir::Program, ()> {
     // parse the given string as estree
@@ -141,12 +132,23 @@ pub async fn run_frontend<
     // fetch and parse all the import files
     let dep_graph = dep_graph::Graph::try_async_build_from_root(
         SourceItem::ESTree(es_program),
-        SourceFetcher::<F> {
-            raw_fetch: raw_fetch,
+        &SourceFetcher::<F> {
+            raw_fetcher: fetcher,
         },
+        &resolver,
     )
     .await
-    .log_err(&logger)?;
+    .map_err(|cm| {
+        // an import cycle is reported along with a note for each import that forms it
+        let notes = match cm.message() {
+            DepError::GraphError(e) => e.notes(),
+            _ => Vec::new(),
+        };
+        logger.log(cm);
+        for note in notes {
+            logger.log(note);
+        }
+    })?;
 
     // find all the FFI imports first
     // (because ir imports must come before all other functions in the ir_program)
@@ -185,10 +187,20 @@ pub async fn run_frontend<
     // contains builtins, e.g. __string_to_number(), and __undefined.
     // The builtins are encoded as string, e.g. "+", "-", etc, and are all Direct
     // the mapping is in builtins module, there is a special transformation for unary minus to avoid name clash
-    // todo: also add the automatic imports
+    // the automatic imports (i.e. the prelude) are added after the builtins, because the prelude uses the builtins
     let mut start_idx = 0;
-    let (name_ctx, parse_state): (HashMap<String, PreVar>, ParseState) =
+    let (mut name_ctx, mut parse_state): (HashMap<String, PreVar>, ParseState) =
         builtins::state_with_builtins(&mut start_idx, &mut ir_program);
+    prelude::add_prelude(
+        &mut name_ctx,
+        &mut parse_state,
+        &mut start_idx,
+        &mut ir_program,
+        &mut ir_toplevel_sequence,
+    )
+    .map_err(|cm| {
+        logger.log(cm);
+    })?;
     dep_graph.topological_traverse_state_into(
         |i, deps, source_item, filename| match source_item {
             SourceItem::ESTree(es_program) => func::parse_program(
@@ -219,7 +231,9 @@ pub async fn run_frontend<
     // put the toplevel sequence into the program
     // and set it as the entry_point function
     let ir_toplevel_func = ir::Func {
+        name: Some("main".to_owned()),
         params: Box::new([]),
+        param_names: Default::default(),
         result: Some(ir::VarType::Any),
         expr: ir::Expr {
             vartype: ir_toplevel_sequence
@@ -7,6 +7,8 @@ use std::collections::HashMap;
 pub struct ParseState {
     targets: HashMap<VarLocId, ir::TargetExpr>, // for the Targets
     directs: VarCtx<String, OverloadSet<(Box<[ir::VarType]>, ir::FuncIdx)>>, // for the Directs
+    num_landings: usize, // number of ir Blocks and Loops enclosing the current position (in the current function)
+    loops: Vec<(usize, usize)>, // landing indices for break and continue of each enclosing loop (in the current function)
 }
 
 // Undoable multiple targets
@@ -134,7 +136,45 @@ impl ParseState {
     }
 }
 
-type ClosureUndoCtx = HashMap<VarLocId, ir::TargetExpr>;
+// Landings for break and continue (not undoable, must be removed in reverse order)
+impl ParseState {
+    /**
+     * Registers a new ir Block or Loop, and returns its landing index.
+     */
+    pub fn add_landing(&mut self) -> usize {
+        self.num_landings += 1;
+        self.num_landings - 1
+    }
+    pub fn remove_landing(&mut self) {
+        self.num_landings -= 1;
+    }
+    pub fn add_loop(&mut self, break_landing: usize, continue_landing: usize) {
+        self.loops.push((break_landing, continue_landing));
+    }
+    pub fn remove_loop(&mut self) {
+        self.loops.pop();
+    }
+}
+
+// Get num_frames of the ir Break for break and continue
+impl ParseState {
+    pub fn get_break_frames(&self) -> Option<usize> {
+        self.loops
+            .last()
+            .map(|(break_landing, _)| self.num_landings - 1 - break_landing)
+    }
+    pub fn get_continue_frames(&self) -> Option<usize> {
+        self.loops
+            .last()
+            .map(|(_, continue_landing)| self.num_landings - 1 - continue_landing)
+    }
+}
+
+type ClosureUndoCtx = (
+    HashMap<VarLocId, ir::TargetExpr>,
+    usize,
+    Vec<(usize, usize)>,
+);
 impl ParseState {
     pub fn enter_closure(
         &mut self,
@@ -149,9 +189,17 @@ impl ParseState {
         for (varlocid, target_expr) in Vec::from(closed_targets) {
             new_targets.insert(varlocid, target_expr);
         }
-        std::mem::replace(&mut self.targets, new_targets)
+        // break and continue cannot jump out of a function
+        (
+            std::mem::replace(&mut self.targets, new_targets),
+            std::mem::take(&mut self.num_landings),
+            std::mem::take(&mut self.loops),
+        )
     }
     pub fn leave_closure(&mut self, undo_ctx: ClosureUndoCtx) {
-        self.targets = undo_ctx;
+        let (targets, num_landings, loops) = undo_ctx;
+        self.targets = targets;
+        self.num_landings = num_landings;
+        self.loops = loops;
     }
 }
@@ -0,0 +1,121 @@
+use crate::estree::*;
+use crate::frontendvar::*;
+use crate::func;
+use crate::func::ParseProgramError;
+use crate::ParseState;
+use crate::ProgramPreExports;
+use projstd::log::CompileMessage;
+use std::collections::HashMap;
+
+/**
+ * The prelude is a Source program that is automatically imported into every program.
+ * It is stored as ESTree (prelude.json) so that the frontend does not need to parse Source by itself;
+ * prelude.json is generated from prelude.source by sourceror-driver/gen-prelude.js.
+ */
+const PRELUDE_ESTREE: &str = include_str!("prelude.json");
+const PRELUDE_FILENAME: &str = "prelude";
+
+/**
+ * Parses the prelude and adds all its exports to the pre-declared Source names,
+ * so that they are visible to all programs (but can be shadowed by them, like the builtins).
+ * The prelude's top-level code is appended to ir_toplevel_seq, so it must be called before parsing any program.
+ */
+pub fn add_prelude(
+    name_ctx: &mut HashMap<String, PreVar>,
+    parse_state: &mut ParseState,
+    start_idx: &mut usize,
+    ir_program: &mut ir::Program,
+    ir_toplevel_seq: &mut Vec<ir::Expr>,
+) -> Result<(), CompileMessage<ParseProgramError>> {
+    let es_program: Node =
+        serde_json::from_str(PRELUDE_ESTREE).expect("ICE: prelude is not valid ESTree");
+
+    let (pre_exports, exports): (ProgramPreExports, ParseState) = func::parse_program(
+        name_ctx,
+        parse_state,
+        es_program,
+        Box::new([]),
+        start_idx,
+        Some(PRELUDE_FILENAME.to_owned()),
+        0,
+        ir_program,
+        ir_toplevel_seq,
+    )?;
+
+    for (name, var_value) in pre_exports.iter() {
+        match var_value {
+            VarValue::Target(varlocid) => {
+                name_ctx.insert(name.clone(), PreVar::Target(*varlocid));
+                parse_state.add_target(*varlocid, exports.get_target(varlocid).unwrap().clone());
+            }
+            VarValue::Direct(_) => {
+                name_ctx.insert(name.clone(), PreVar::Direct);
+                parse_state.add_direct(name.clone(), exports.get_direct(name).unwrap().clone());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Calls `f` on every object in the JSON tree.
+     */
+    fn walk_objects<F: FnMut(&serde_json::Map<String, serde_json::Value>)>(
+        value: &serde_json::Value,
+        f: &mut F,
+    ) {
+        match value {
+            serde_json::Value::Object(object) => {
+                f(object);
+                for child in object.values() {
+                    walk_objects(child, f);
+                }
+            }
+            serde_json::Value::Array(array) => {
+                for child in array {
+                    walk_objects(child, f);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn prelude_json_matches_source() {
+        // prelude.json must be regenerated whenever prelude.source changes,
+        // so check that its offsets, identifiers and literals all still line up with the source
+        // (the offsets count characters, not bytes)
+        let source: Vec<char> = include_str!("prelude.source").chars().collect();
+        let estree: serde_json::Value = serde_json::from_str(PRELUDE_ESTREE).unwrap();
+        assert_eq!(estree["end"].as_u64(), Some(source.len() as u64));
+        let mut num_checked = 0;
+        walk_objects(&estree, &mut |node| {
+            // (the start and end of a `loc` are positions, not offsets)
+            let start = node.get("start").and_then(|start| start.as_u64());
+            let end = node.get("end").and_then(|end| end.as_u64());
+            let text = match (start, end) {
+                (Some(start), Some(end)) => source[start as usize..end as usize]
+                    .iter()
+                    .collect::<String>(),
+                _ => return,
+            };
+            match node["type"].as_str() {
+                Some("Identifier") => {
+                    assert_eq!(Some(text.as_str()), node["name"].as_str());
+                    num_checked += 1;
+                }
+                Some("Literal") => {
+                    assert_eq!(Some(text.as_str()), node["raw"].as_str());
+                    num_checked += 1;
+                }
+                _ => {}
+            }
+        });
+        assert!(num_checked > 0);
+    }
+}
@@ -5,3 +5,5 @@ pub const ERROR_CODE_BINARY_OPERATOR_PARAM_TYPE: u32 = 0x13;
 pub const ERROR_CODE_FUNCTION_APPLICATION_NOT_CALLABLE_TYPE: u32 = 0x16;
 pub const ERROR_CODE_IF_STATEMENT_CONDITION_TYPE: u32 = 0x17;
 pub const ERROR_CODE_ACCESS_VAR_BEFORE_INIT: u32 = 0x1A;
+pub const ERROR_CODE_ARRAY_ACCESS_NOT_ARRAY: u32 = 0x1B;
+pub const ERROR_CODE_ARRAY_INDEX_NOT_NONNEGATIVE_INTEGER: u32 = 0x1C;
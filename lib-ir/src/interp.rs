@@ -0,0 +1,692 @@
+/**
+ * A reference interpreter that runs a Program directly, without going through the backend.
+ * It follows the semantics that the wasm backend implements (including the overload resolution of indirect calls and the trap codes),
+ * so that a program can be run before and after optimisation to check that the optimiser did not change its behaviour.
+ *
+ * Values are dynamically typed, so the static vartypes in the IR are not needed (and not checked) here;
+ * use `verify()` to check them.
+ */
+use super::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Largest number of elements that an array may hold (same as the wasm backend).
+const MAX_ARRAY_CAPACITY: usize = 1 << 27;
+
+/**
+ * The number of nested calls that `run()` allows before trapping.
+ * Every call also uses some native stack (a few kilobytes per nested expr in debug builds),
+ * so a thread that runs deep recursion needs a large enough stack even with this limit.
+ */
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/**
+ * A runtime value.  Arrays and structs are reference types, so they are shared between copies of the value.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unassigned,
+    Undefined,
+    Number(f64),
+    Boolean(bool),
+    String(Rc<str>),
+    Func {
+        funcidxs: Rc<[OverloadEntry]>,
+        closure: Rc<Value>,
+    },
+    Array(Rc<RefCell<Vec<Value>>>),
+    Null,
+    Struct {
+        typeidx: usize,
+        fields: Rc<RefCell<Box<[Value]>>>,
+    },
+}
+
+impl Value {
+    /**
+     * Returns the VarType that a TypeCast would match this value with.
+     */
+    pub fn vartype(&self) -> VarType {
+        match self {
+            Value::Unassigned => VarType::Unassigned,
+            Value::Undefined => VarType::Undefined,
+            Value::Number(_) => VarType::Number,
+            Value::Boolean(_) => VarType::Boolean,
+            Value::String(_) => VarType::String,
+            Value::Func {
+                funcidxs: _,
+                closure: _,
+            } => VarType::Func,
+            Value::Array(_) => VarType::Array,
+            Value::Null => VarType::Null,
+            Value::Struct { typeidx, fields: _ } => VarType::StructT { typeidx: *typeidx },
+        }
+    }
+
+    /**
+     * Returns the value that a variable of the given type holds before it is assigned.
+     */
+    fn default_for(vartype: VarType) -> Value {
+        match vartype {
+            VarType::Undefined => Value::Undefined,
+            VarType::Null => Value::Null,
+            _ => Value::Unassigned,
+        }
+    }
+}
+
+/**
+ * A trap raised by the program, with the same code and location that the wasm backend would pass to the error function.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Trap {
+    pub code: u32,
+    pub location: SourceLocation,
+}
+
+/**
+ * The environment that provides the imported functions.
+ * `args` have the vartypes of the ImportValTypes of the import (an `Any` param may hold a value of any type),
+ * and the returned value should match the result ImportValType.
+ */
+pub trait Host {
+    fn call_import(&mut self, import: &Import, args: &[Value]) -> Result<Value, Trap>;
+}
+
+/**
+ * Runs the entry point of the program, and returns the value it returns or the trap it raises.
+ */
+pub fn run<H: Host>(program: &Program, host: &mut H) -> Result<Value, Trap> {
+    run_with_max_call_depth(program, host, DEFAULT_MAX_CALL_DEPTH)
+}
+
+/**
+ * Like `run()`, but traps with ERROR_CODE_OUT_OF_MEMORY when a call would nest more than `max_call_depth` calls (including the entry point).
+ * The wasm backend has no error code for this (the engine traps by itself when its stack runs out), so this uses the closest one.
+ */
+pub fn run_with_max_call_depth<H: Host>(
+    program: &Program,
+    host: &mut H,
+    max_call_depth: usize,
+) -> Result<Value, Trap> {
+    let mut interp = Interpreter {
+        program: program,
+        host: host,
+        globals: program
+            .globals
+            .iter()
+            .map(|vartype| Value::default_for(*vartype))
+            .collect(),
+        call_depth: 0,
+        max_call_depth: max_call_depth,
+    };
+    interp.call(program.entry_point, Vec::new())
+}
+
+/**
+ * The reasons that the evaluation of an expr might stop before producing a value.
+ */
+enum Unwind {
+    Break { num_frames: usize, value: Value },
+    Return(Value),
+    Trap(Trap),
+}
+
+impl From<Trap> for Unwind {
+    fn from(trap: Trap) -> Self {
+        Unwind::Trap(trap)
+    }
+}
+
+struct Interpreter<'a, H: Host> {
+    program: &'a Program,
+    host: &'a mut H,
+    globals: Vec<Value>,
+    call_depth: usize, // number of calls to non-imported functions that have not returned yet
+    max_call_depth: usize,
+}
+
+fn trap(code: u32) -> Trap {
+    Trap {
+        code: code,
+        location: Default::default(),
+    }
+}
+
+impl<'a, H: Host> Interpreter<'a, H> {
+    /**
+     * Calls the function (which may be an import) with the given args (including the closure, if any).
+     */
+    fn call(&mut self, funcidx: FuncIdx, args: Vec<Value>) -> Result<Value, Trap> {
+        if funcidx < self.program.imports.len() {
+            let program = self.program;
+            return self.host.call_import(&program.imports[funcidx], &args);
+        }
+        if self.call_depth >= self.max_call_depth {
+            return Err(trap(error::ERROR_CODE_OUT_OF_MEMORY));
+        }
+        let func = self.program.get_func(funcidx);
+        let mut locals = args;
+        self.call_depth += 1;
+        let ret = self.eval(&func.expr, &mut locals);
+        self.call_depth -= 1;
+        match ret {
+            Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Trap(t)) => Err(t),
+            Err(Unwind::Break {
+                num_frames: _,
+                value: _,
+            }) => panic!("ICE: IR interpreter: Break out of function"),
+        }
+    }
+
+    /**
+     * Calls a function value with the uniform calling convention, picking the overload in the same way as the thunks in the wasm backend:
+     * the overloads are matched from back to front, and an overload matches if it has the same number of params
+     * and every param is either Any or the type of the corresponding arg.
+     */
+    fn call_indirect(
+        &mut self,
+        func: Value,
+        args: Vec<Value>,
+        location: SourceLocation,
+    ) -> Result<Value, Trap> {
+        let (funcidxs, closure) = match func {
+            Value::Func { funcidxs, closure } => (funcidxs, closure),
+            _ => panic!("ICE: IR interpreter: Appl of a value that is not a Func"),
+        };
+        for oe in funcidxs.iter().rev() {
+            // imports may be overloads too (they never have a closure param)
+            let params: Vec<VarType> = if oe.funcidx < self.program.imports.len() {
+                self.program.imports[oe.funcidx]
+                    .params
+                    .iter()
+                    .map(|param| (*param).into())
+                    .collect()
+            } else {
+                let params: &[VarType] = &self.program.get_func(oe.funcidx).params;
+                if oe.has_closure_param {
+                    params[1..].to_vec()
+                } else {
+                    params.to_vec()
+                }
+            };
+            if params.len() == args.len()
+                && params
+                    .iter()
+                    .zip(args.iter())
+                    .all(|(param, arg)| *param == VarType::Any || *param == arg.vartype())
+            {
+                let mut actual_args: Vec<Value> = Vec::with_capacity(args.len() + 1);
+                if oe.has_closure_param {
+                    actual_args.push((*closure).clone());
+                }
+                actual_args.extend(args);
+                return self.call(oe.funcidx, actual_args);
+            }
+        }
+        Err(Trap {
+            code: error::ERROR_CODE_FUNCTION_PARAM_TYPE,
+            location: location,
+        })
+    }
+
+    fn eval_args(&mut self, args: &[Expr], locals: &mut Vec<Value>) -> Result<Vec<Value>, Unwind> {
+        args.iter().map(|arg| self.eval(arg, locals)).collect()
+    }
+
+    fn read_target(&self, target: &TargetExpr, locals: &[Value]) -> Value {
+        let (value, next) = match target {
+            TargetExpr::Global { globalidx, next } => (&self.globals[*globalidx], next),
+            TargetExpr::Local { localidx, next } => (&locals[*localidx], next),
+        };
+        let mut value: Value = value.clone();
+        let mut curr: &Option<Box<StructField>> = next;
+        while let Some(field) = curr {
+            let inner_value = struct_fields(&value).borrow()[field.fieldidx].clone();
+            value = inner_value;
+            curr = &field.next;
+        }
+        value
+    }
+
+    fn write_target(&mut self, target: &TargetExpr, locals: &mut [Value], new_value: Value) {
+        let (var, next) = match target {
+            TargetExpr::Global { globalidx, next } => (&mut self.globals[*globalidx], next),
+            TargetExpr::Local { localidx, next } => (&mut locals[*localidx], next),
+        };
+        let mut field: &StructField = match next {
+            None => {
+                *var = new_value;
+                return;
+            }
+            Some(field) => field,
+        };
+        let mut fields: Rc<RefCell<Box<[Value]>>> = struct_fields(var).clone();
+        while let Some(inner_field) = &field.next {
+            let inner_fields = struct_fields(&fields.borrow()[field.fieldidx]).clone();
+            fields = inner_fields;
+            field = inner_field;
+        }
+        fields.borrow_mut()[field.fieldidx] = new_value;
+    }
+
+    fn eval(&mut self, expr: &Expr, locals: &mut Vec<Value>) -> Result<Value, Unwind> {
+        // Note: we explicitly list out all possibilities so we will get a compile error if a new exprkind is added.
+        match &expr.kind {
+            ExprKind::PrimUndefined => Ok(Value::Undefined),
+            ExprKind::PrimNull => Ok(Value::Null),
+            ExprKind::PrimNumber { val } => Ok(Value::Number(*val)),
+            ExprKind::PrimBoolean { val } => Ok(Value::Boolean(*val)),
+            ExprKind::PrimString { val } => Ok(Value::String(val.as_str().into())),
+            ExprKind::PrimStructT { typeidx } => Ok(Value::Struct {
+                typeidx: *typeidx,
+                fields: Rc::new(RefCell::new(
+                    self.program.struct_types[*typeidx]
+                        .iter()
+                        .map(|vartype| Value::default_for(*vartype))
+                        .collect(),
+                )),
+            }),
+            ExprKind::PrimFunc { funcidxs, closure } => {
+                let closure_value = self.eval(closure, locals)?;
+                Ok(Value::Func {
+                    funcidxs: funcidxs.iter().copied().collect(),
+                    closure: Rc::new(closure_value),
+                })
+            }
+            ExprKind::TypeCast {
+                test,
+                expected,
+                create_narrow_local,
+                true_expr,
+                false_expr,
+            } => {
+                let value = self.eval(test, locals)?;
+                if value.vartype() == *expected {
+                    if *create_narrow_local {
+                        locals.push(value);
+                        let ret = self.eval(true_expr, locals);
+                        locals.pop();
+                        ret
+                    } else {
+                        self.eval(true_expr, locals)
+                    }
+                } else {
+                    self.eval(false_expr, locals)
+                }
+            }
+            ExprKind::VarName { source } => Ok(self.read_target(source, locals)),
+            ExprKind::PrimAppl { prim_inst, args } => {
+                let arg_values = self.eval_args(args, locals)?;
+                Ok(eval_prim_inst(*prim_inst, arg_values)?)
+            }
+            ExprKind::Appl {
+                func,
+                args,
+                location,
+            } => {
+                let func_value = self.eval(func, locals)?;
+                let arg_values = self.eval_args(args, locals)?;
+                Ok(self.call_indirect(func_value, arg_values, *location)?)
+            }
+            ExprKind::DirectAppl { funcidx, args } => {
+                let arg_values = self.eval_args(args, locals)?;
+                Ok(self.call(*funcidx, arg_values)?)
+            }
+            ExprKind::Conditional {
+                cond,
+                true_expr,
+                false_expr,
+            } => match self.eval(cond, locals)? {
+                Value::Boolean(true) => self.eval(true_expr, locals),
+                Value::Boolean(false) => self.eval(false_expr, locals),
+                _ => panic!("ICE: IR interpreter: condition of Conditional is not a boolean"),
+            },
+            ExprKind::Declaration {
+                local,
+                init,
+                contained_expr,
+            } => {
+                let value = match init {
+                    Some(init_expr) => self.eval(init_expr, locals)?,
+                    None => Value::default_for(*local),
+                };
+                locals.push(value);
+                let ret = self.eval(contained_expr, locals);
+                locals.pop();
+                ret
+            }
+            ExprKind::Assign {
+                target,
+                expr: expr2,
+            } => {
+                let value = self.eval(expr2, locals)?;
+                self.write_target(target, locals, value);
+                Ok(Value::Undefined)
+            }
+            ExprKind::Return { expr: expr2 } => {
+                let value = self.eval(expr2, locals)?;
+                Err(Unwind::Return(value))
+            }
+            ExprKind::Break {
+                num_frames,
+                expr: expr2,
+            } => {
+                let value = self.eval(expr2, locals)?;
+                Err(Unwind::Break {
+                    num_frames: *num_frames,
+                    value: value,
+                })
+            }
+            ExprKind::Block { expr: expr2 } => match self.eval(expr2, locals) {
+                Err(Unwind::Break {
+                    num_frames: 0,
+                    value,
+                }) => Ok(value),
+                ret => outer_frame(ret),
+            },
+            ExprKind::Loop { expr: expr2 } => loop {
+                match self.eval(expr2, locals) {
+                    Err(Unwind::Break {
+                        num_frames: 0,
+                        value: _,
+                    }) => {}
+                    ret => return outer_frame(ret),
+                }
+            },
+            ExprKind::Sequence { content } => {
+                let mut value = Value::Undefined;
+                for expr2 in content {
+                    value = self.eval(expr2, locals)?;
+                }
+                Ok(value)
+            }
+            ExprKind::Trap { code, location } => Err(Unwind::Trap(Trap {
+                code: *code,
+                location: *location,
+            })),
+            ExprKind::SourceMarker { location: _ } => Ok(Value::Undefined),
+        }
+    }
+}
+
+/**
+ * Adjusts a Break that passes through a Block or Loop, so that it refers to the frames relative to the outer expr.
+ */
+fn outer_frame(ret: Result<Value, Unwind>) -> Result<Value, Unwind> {
+    match ret {
+        Err(Unwind::Break { num_frames, value }) => Err(Unwind::Break {
+            num_frames: num_frames - 1,
+            value: value,
+        }),
+        ret => ret,
+    }
+}
+
+fn struct_fields(value: &Value) -> &Rc<RefCell<Box<[Value]>>> {
+    match value {
+        Value::Struct { typeidx: _, fields } => fields,
+        _ => panic!("ICE: IR interpreter: field access on a value that is not a struct"),
+    }
+}
+
+/**
+ * Converts an array index to a usize, trapping if it is not a non-negative integer.
+ */
+fn to_index(val: f64) -> Result<usize, Trap> {
+    if val.trunc() != val || val < 0.0 {
+        Err(trap(error::ERROR_CODE_ARRAY_INDEX_NOT_NONNEGATIVE_INTEGER))
+    } else if val >= MAX_ARRAY_CAPACITY as f64 {
+        Ok(MAX_ARRAY_CAPACITY)
+    } else {
+        Ok(val as usize)
+    }
+}
+
+fn eval_prim_inst(prim_inst: PrimInst, args: Vec<Value>) -> Result<Value, Trap> {
+    use Value::*;
+    Ok(match (prim_inst, args.as_slice()) {
+        (PrimInst::NumberAdd, [Number(a), Number(b)]) => Number(a + b),
+        (PrimInst::NumberSub, [Number(a), Number(b)]) => Number(a - b),
+        (PrimInst::NumberMul, [Number(a), Number(b)]) => Number(a * b),
+        (PrimInst::NumberDiv, [Number(a), Number(b)]) => Number(a / b),
+        (PrimInst::NumberRem, [Number(a), Number(b)]) => Number(a % b),
+        (PrimInst::NumberEq, [Number(a), Number(b)]) => Boolean(a == b),
+        (PrimInst::NumberNeq, [Number(a), Number(b)]) => Boolean(a != b),
+        (PrimInst::NumberGt, [Number(a), Number(b)]) => Boolean(a > b),
+        (PrimInst::NumberLt, [Number(a), Number(b)]) => Boolean(a < b),
+        (PrimInst::NumberGe, [Number(a), Number(b)]) => Boolean(a >= b),
+        (PrimInst::NumberLe, [Number(a), Number(b)]) => Boolean(a <= b),
+        (PrimInst::BooleanEq, [Boolean(a), Boolean(b)]) => Boolean(a == b),
+        (PrimInst::BooleanNeq, [Boolean(a), Boolean(b)]) => Boolean(a != b),
+        (PrimInst::BooleanAnd, [Boolean(a), Boolean(b)]) => Boolean(*a && *b),
+        (PrimInst::BooleanOr, [Boolean(a), Boolean(b)]) => Boolean(*a || *b),
+        (PrimInst::BooleanNot, [Boolean(a)]) => Boolean(!a),
+        (PrimInst::NumberNegate, [Number(a)]) => Number(-a),
+        (PrimInst::StringAdd, [String(a), String(b)]) => String((a.to_string() + b).into()),
+        (PrimInst::StringEq, [String(a), String(b)]) => Boolean(a == b),
+        (PrimInst::StringNeq, [String(a), String(b)]) => Boolean(a != b),
+        (PrimInst::StringGt, [String(a), String(b)]) => Boolean(a > b),
+        (PrimInst::StringLt, [String(a), String(b)]) => Boolean(a < b),
+        (PrimInst::StringGe, [String(a), String(b)]) => Boolean(a >= b),
+        (PrimInst::StringLe, [String(a), String(b)]) => Boolean(a <= b),
+        (PrimInst::ArrayNew, [Number(len)]) => {
+            let len = to_index(*len)?;
+            if len >= MAX_ARRAY_CAPACITY {
+                return Err(trap(error::ERROR_CODE_OUT_OF_MEMORY));
+            }
+            Array(Rc::new(RefCell::new(vec![Undefined; len])))
+        }
+        (PrimInst::ArrayGet, [Array(arr), Number(idx)]) => {
+            let idx = to_index(*idx)?;
+            arr.borrow().get(idx).cloned().unwrap_or(Undefined)
+        }
+        (PrimInst::ArraySet, [Array(arr), Number(idx), value]) => {
+            let idx = to_index(*idx)?;
+            if idx >= MAX_ARRAY_CAPACITY {
+                return Err(trap(error::ERROR_CODE_OUT_OF_MEMORY));
+            }
+            let mut elements = arr.borrow_mut();
+            if idx >= elements.len() {
+                elements.resize(idx + 1, Undefined);
+            }
+            elements[idx] = value.clone();
+            Undefined
+        }
+        (prim_inst, args) => panic!(
+            "ICE: IR interpreter: invalid args to {:?}: {:?}",
+            prim_inst, args
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Host that records the values passed to `display`.
+     */
+    #[derive(Default)]
+    struct RecordingHost {
+        output: Vec<Value>,
+    }
+
+    impl Host for RecordingHost {
+        fn call_import(&mut self, import: &Import, args: &[Value]) -> Result<Value, Trap> {
+            assert_eq!(import.entity_name, "display");
+            self.output.extend(args.iter().cloned());
+            Ok(Value::Undefined)
+        }
+    }
+
+    /**
+     * Runs the program before and after optimisation, and checks that both runs have the same output.
+     */
+    fn run_both(text: &str) -> (Vec<Value>, Result<Value, Trap>) {
+        let mut host = RecordingHost::default();
+        let result = run(&text.parse().unwrap(), &mut host);
+        let mut host_opt = RecordingHost::default();
+        let result_opt = run(
+            &opt::optimize_all(opt::optimize_mandatory(text.parse().unwrap())),
+            &mut host_opt,
+        );
+        assert_eq!(host.output, host_opt.output);
+        assert_eq!(result.is_ok(), result_opt.is_ok());
+        if let (Err(t), Err(t_opt)) = (&result, &result_opt) {
+            assert_eq!(t, t_opt);
+        }
+        (host.output, result)
+    }
+
+    // function fact(n) { return n === 0 ? 1 : n * fact(n - 1); }
+    // display(fact(5));
+    // fact(1, 2);
+    const FACT: &str = r#"
+        struct ()
+        import "misc" "display" (number) -> undefined
+        global any
+        func (struct#0, any) -> any {
+          (typecast:void number narrow (var:any local 1)
+            (return:void (if:any (prim:boolean number_eq (var:number local 2) (number:number 0.0))
+              (number:number 1.0)
+              (typecast:any func narrow (var:any global 0)
+                (typecast:any number narrow
+                  (appl:any (var:func local 3) [(prim:number number_sub (var:number local 2) (number:number 1.0))])
+                  (prim:number number_mul (var:number local 2) (var:number local 4))
+                  (trap:void 19 @0:1:3-1:4))
+                (trap:void 22 @0:1:2-1:10))))
+            (trap:void 19 @0:1:3-1:4))
+        }
+        func () -> undefined {
+          (seq:undefined
+            (assign:undefined global 0 (func:func [1 closure] (struct:struct#0 0)))
+            (typecast:undefined func narrow (var:any global 0)
+              (typecast:undefined number narrow (appl:any (var:func local 0) [(number:number 5.0)])
+                (direct:undefined 0 (var:number local 1))
+                (trap:void 17 @0:2:0-2:4))
+              (trap:void 22 @0:2:0-2:4))
+            (typecast:undefined func narrow (var:any global 0)
+              (seq:undefined (appl:any (var:func local 0) [(number:number 1.0) (number:number 2.0)] @0:3:8-3:16) (undefined:undefined))
+              (trap:void 22 @0:3:0-3:4)))
+        }
+        entry 2
+        "#;
+
+    #[test]
+    fn recursive_function() {
+        let (output, result) = run_both(FACT);
+        assert_eq!(output, vec![Value::Number(120.0)]);
+        assert_eq!(
+            result.unwrap_err().code,
+            error::ERROR_CODE_FUNCTION_PARAM_TYPE
+        );
+    }
+
+    #[test]
+    fn loops_and_arrays() {
+        // let a = []; let i = 0; while (i < 3) { a[i] = i * i; i = i + 1; } display(a[2]); display(a[-1]);
+        let (output, result) = run_both(
+            r#"
+            import "misc" "display" (number) -> undefined
+            func () -> undefined {
+              (let:undefined array = (prim:array array_new (number:number 0.0))
+                (let:undefined number = (number:number 0.0)
+                  (seq:undefined
+                    (loop:undefined (if:undefined (prim:boolean number_lt (var:number local 1) (number:number 3.0))
+                      (seq:void
+                        (prim:undefined array_set (var:array local 0) (var:number local 1)
+                          (prim:number number_mul (var:number local 1) (var:number local 1)))
+                        (assign:undefined local 1 (prim:number number_add (var:number local 1) (number:number 1.0)))
+                        (break:void 0 (undefined:undefined)))
+                      (undefined:undefined)))
+                    (block:undefined (typecast:void number narrow (prim:any array_get (var:array local 0) (number:number 2.0))
+                      (break:void 0 (direct:undefined 0 (var:number local 2)))
+                      (trap:void 19)))
+                    (direct:undefined 0 (typecast:number number narrow
+                      (prim:any array_get (var:array local 0) (number:number -1.0))
+                      (var:number local 2)
+                      (trap:void 19))))))
+            }
+            entry 1
+            "#,
+        );
+        assert_eq!(output, vec![Value::Number(4.0)]);
+        assert_eq!(
+            result.unwrap_err().code,
+            error::ERROR_CODE_ARRAY_INDEX_NOT_NONNEGATIVE_INTEGER
+        );
+    }
+
+    #[test]
+    fn deep_recursion_traps() {
+        // function f(n) { return n === 0 ? 0 : f(n - 1) + 1; }
+        let program = |n: usize| -> Program {
+            format!(
+                r#"
+                func (number) -> number {{
+                  (if:number (prim:boolean number_eq (var:number local 0) (number:number 0.0))
+                    (number:number 0.0)
+                    (prim:number number_add
+                      (direct:number 0 (prim:number number_sub (var:number local 0) (number:number 1.0)))
+                      (number:number 1.0)))
+                }}
+                func () -> number {{
+                  (direct:number 0 (number:number {}))
+                }}
+                entry 1
+                "#,
+                n
+            )
+            .parse()
+            .unwrap()
+        };
+        let mut host = RecordingHost::default();
+        // f(99) makes 100 nested calls, plus the entry point
+        assert_eq!(
+            run_with_max_call_depth(&program(99), &mut host, 101),
+            Ok(Value::Number(99.0))
+        );
+        assert_eq!(
+            run_with_max_call_depth(&program(100), &mut host, 101)
+                .unwrap_err()
+                .code,
+            error::ERROR_CODE_OUT_OF_MEMORY
+        );
+        // without an explicit limit, unbounded recursion traps instead of overflowing the native stack
+        let result = std::thread::Builder::new()
+            .stack_size(256 << 20)
+            .spawn(move || {
+                let mut host = RecordingHost::default();
+                run(&program(1_000_000), &mut host).err().map(|t| t.code)
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(result, Some(error::ERROR_CODE_OUT_OF_MEMORY));
+    }
+
+    #[test]
+    fn import_overload() {
+        // an import used directly as an overload of a func value
+        let (output, result) = run_both(
+            r#"
+            import "misc" "display" (number) -> undefined
+            func () -> undefined {
+              (let:undefined func = (func:func [0] (undefined:undefined))
+                (seq:undefined
+                  (appl:any (var:func local 0) [(number:number 7.0)])
+                  (appl:any (var:func local 0) [(boolean:boolean true)] @0:2:0-2:4)
+                  (undefined:undefined)))
+            }
+            entry 1
+            "#,
+        );
+        assert_eq!(output, vec![Value::Number(7.0)]);
+        assert_eq!(
+            result.unwrap_err().code,
+            error::ERROR_CODE_FUNCTION_PARAM_TYPE
+        );
+    }
+}
@@ -26,10 +26,15 @@ use std::vec::Vec;
  * * todo!: Also, functions should be annotated with a flag whether they might do heap allocations.
  */
 pub mod error;
+pub mod interp;
 pub mod opt;
 pub mod superset;
+pub mod text;
+mod verify;
 // mod primfunc;
 
+pub use verify::*;
+
 // If it stores value `func_idx`, then it refers to imports[func_idx] if (func_idx < imports.len())
 // or funcs[func_idx - imports.len()] otherwise.
 pub type FuncIdx = usize;
@@ -52,6 +57,8 @@ pub enum VarType {
     Boolean,
     String,                     // reference type
     Func,                       // holds a function ptr and a closure
+    Array,                      // reference type; growable, elements are Any
+    Null,                       // the empty list (Source §2); like Undefined, it has only one value
     StructT { typeidx: usize }, // reference type; typeid starts from zero and should be in range [0, object_types.len()).
 }
 impl Default for VarType {
@@ -71,11 +78,14 @@ impl VarType {
             VarType::Boolean => 3,
             VarType::String => 4,
             VarType::Func => 5,
+            VarType::Array => 6,
+            VarType::Null => 7,
             VarType::StructT { typeidx } => (NUM_PRIMITIVE_TAG_TYPES + typeidx) as i32,
         }
     }
 }
-pub const NUM_PRIMITIVE_TAG_TYPES: usize = 6; // does not include Any
+pub const NUM_PRIMITIVE_TAG_TYPES: usize = 9; // does not include Any, but includes ARRAY_STORAGE_TAG
+pub const ARRAY_STORAGE_TAG: i32 = 8; // tag of the heap object holding the elements of an Array; it is never stored in an Any
 
 #[derive(Eq, PartialEq, Ord, PartialOrd, Clone, Hash, Debug)]
 pub struct Import {
@@ -91,11 +101,16 @@ pub enum ImportValType {
     Undefined, // compiles into nothing
     Number,    // compiles into f64 parameter
     String, // compiles into i32(ptr) parameter, the host should look into our linear memory to figure out the length and the actual string content.
+    Boolean, // compiles into i32 parameter (1: true; 0: false)
+    Func, // compiles into an i32(handle) parameter, the host can call it with the exported "call_function" until the import returns
+    Any, // compiles into i64(data) + i32(tag) + i32(handle) parameters, the data is interpreted according to the tag (see VarType::tag())
 }
 
 #[derive(Debug)]
 pub struct Func {
+    pub name: Option<String>, // name of the function in the source code (for debugging only, e.g. in the wasm name section), or None if it has no meaningful name
     pub params: Box<[VarType]>, // list of function parameters (including closure)
+    pub param_names: Box<[String]>, // names of the params in the source code (for debugging only), either one per param or empty if they have no meaningful names
     pub result: Option<VarType>, // if `None`, it means that this function never returns (e.g. it guarantees to trap or infinite loop, see the generated runtime error function)
     pub expr: Expr, // body of the function, must either return Void or return the correct result type
     pub signature_filter: Vec<(Box<[VarType]>, VarType, FuncIdx)>, // list of possibly acceptable signatures (param_types, return_type, constrained_func).
@@ -146,6 +161,7 @@ pub struct OverloadEntry {
 #[derive(Debug, Clone)]
 pub enum ExprKind {
     PrimUndefined, // also functions as a "no-op"
+    PrimNull,      // e.g. `null`
     PrimNumber {
         val: f64,
     }, // e.g. `2`
@@ -221,6 +237,9 @@ pub enum ExprKind {
     Block {
         expr: Box<Expr>,
     }, // Jump landing for Break; type must be at least as wide as expr.vartype and all Breaks that target this block
+    Loop {
+        expr: Box<Expr>,
+    }, // Jump landing for Break, which re-enters the loop from the top; Breaks that target this loop must have Undefined expr (the value is discarded).  Falling off the end of expr exits the loop, so the type is the type of expr
     Sequence {
         content: Vec<Expr>,
     }, // returns the value of the last expression, or `undefined` if there are zero expressions
@@ -228,6 +247,9 @@ pub enum ExprKind {
         code: u32,
         location: SourceLocation, // will be displayed in the error message
     }, // has Void type
+    SourceMarker {
+        location: SourceLocation, // location of the statement that follows this marker in the enclosing Sequence
+    }, // no-op that is placed by the frontend before each statement, for generating source maps; has Undefined type
 }
 
 // enum of possible primitive functions, used by pre-declared operators, or added during type-checking optimisation
@@ -261,8 +283,11 @@ pub enum PrimInst {
     StringLt,
     StringGe,
     StringLe,
+    ArrayNew, // creates an array of the given length, with all elements set to undefined
+    ArrayGet, // returns undefined if the index is past the end of the array
+    ArraySet, // grows the array if the index is past the end of the array
 }
-pub const NUM_PRIM_INST: u8 = PrimInst::StringLe as u8 + 1;
+pub const NUM_PRIM_INST: u8 = PrimInst::ArraySet as u8 + 1;
 
 // enum of pre-declared operators
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
@@ -362,8 +387,10 @@ impl Func {
      */
     pub fn new() -> Func {
         Func {
+            name: None,
             params: Box::new([]),
-            result: None,
+            param_names: Default::default(),
+            result: Some(VarType::Undefined),
             expr: Expr {
                 vartype: Some(VarType::Undefined),
                 kind: ExprKind::PrimUndefined,
@@ -373,7 +400,9 @@ impl Func {
     }
     pub fn new_with_params_and_result(params: &[VarType], result: VarType) -> Func {
         Func {
+            name: None,
             params: params.into(),
+            param_names: Default::default(),
             result: Some(result),
             expr: Expr {
                 vartype: Some(VarType::Undefined),
@@ -416,6 +445,12 @@ impl PrimInst {
             | Self::StringLt
             | Self::StringGe
             | Self::StringLe => (&[VarType::String, VarType::String], Some(VarType::Boolean)),
+            Self::ArrayNew => (&[VarType::Number], Some(VarType::Array)),
+            Self::ArrayGet => (&[VarType::Array, VarType::Number], Some(VarType::Any)),
+            Self::ArraySet => (
+                &[VarType::Array, VarType::Number, VarType::Any],
+                Some(VarType::Undefined),
+            ),
         }
     }
 }
@@ -426,6 +461,9 @@ impl From<ImportValType> for VarType {
             ImportValType::Undefined => VarType::Undefined,
             ImportValType::Number => VarType::Number,
             ImportValType::String => VarType::String,
+            ImportValType::Boolean => VarType::Boolean,
+            ImportValType::Func => VarType::Func,
+            ImportValType::Any => VarType::Any,
         }
     }
 }
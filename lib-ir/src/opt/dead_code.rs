@@ -0,0 +1,431 @@
+use super::walk::*;
+use super::*;
+
+/**
+ * Discretionary optimisation to remove functions and globals that are never used.
+ * The second return value is true if the program got changed, or false otherwise.
+ *
+ * The algorithm works as follows:
+ * 1. Find the live functions and globals, starting from the entry point:
+ *    1a. A function is live if it is referenced (by DirectAppl, PrimFunc, or signature_filter) from a live function.
+ *    1b. A global is live if it is read from a live function.  Assigning to a global does not make it live,
+ *    so the value assigned to a dead global (e.g. the PrimFunc of a function declaration that is never used) is not visited,
+ *    as long as evaluating it has no side effects.
+ *
+ *    Since globals become live during the traversal, we repeat the traversal until the live globals are stable.
+ * 2. Remove the assignments to dead globals (keeping the assigned expr if it might have side effects).
+ * 3. Remove the dead functions and globals, and renumber all the FuncIdxs and globalidxs.
+ *
+ * Imports are never removed, because the host provides all of them anyway.
+ */
+pub fn optimize(mut program: Program) -> (Program, bool) {
+    let (live_funcs, live_globals) = find_live(&program);
+    if live_funcs.iter().all(|x| *x) && live_globals.iter().all(|x| *x) {
+        return (program, false);
+    }
+
+    // remove the assignments to dead globals in the live functions
+    for (i, func) in program.funcs.iter_mut().enumerate() {
+        if live_funcs[program.imports.len() + i] {
+            walk_expr_mut(&mut func.expr, 0, &mut |expr, _| {
+                remove_dead_assign(expr, &live_globals)
+            });
+        }
+    }
+
+    // compute the new indices
+    let func_map: Box<[Option<FuncIdx>]> = make_index_map(&live_funcs);
+    let global_map: Box<[Option<usize>]> = make_index_map(&live_globals);
+
+    // remove the dead functions and globals
+    let imports_len = program.imports.len();
+    program.funcs = std::mem::take(&mut program.funcs)
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| live_funcs[imports_len + i])
+        .map(|(_, func)| func)
+        .collect();
+    program.globals = std::mem::take(&mut program.globals)
+        .into_iter()
+        .enumerate()
+        .filter(|(globalidx, _)| live_globals[*globalidx])
+        .map(|(_, vartype)| vartype)
+        .collect();
+
+    // renumber everything
+    program.entry_point = func_map[program.entry_point].unwrap();
+    for func in &mut program.funcs {
+        for (_, _, funcidx) in &mut func.signature_filter {
+            *funcidx = func_map[*funcidx].unwrap();
+        }
+        walk_expr_mut(&mut func.expr, 0, &mut |expr, _| {
+            renumber_expr(expr, &func_map, &global_map)
+        });
+    }
+
+    (program, true)
+}
+
+/**
+ * Returns whether each FuncIdx (including imports) and each global is live.
+ */
+fn find_live(program: &Program) -> (Box<[bool]>, Box<[bool]>) {
+    let mut live_globals: Box<[bool]> = vec![false; program.globals.len()].into();
+    loop {
+        let mut live_funcs: Box<[bool]> =
+            vec![false; program.imports.len() + program.funcs.len()].into();
+        let mut read_globals: Box<[bool]> = vec![false; program.globals.len()].into();
+        for i in 0..program.imports.len() {
+            live_funcs[i] = true;
+        }
+        live_funcs[program.entry_point] = true;
+        let mut worklist: Vec<FuncIdx> = vec![program.entry_point];
+        while let Some(funcidx) = worklist.pop() {
+            let func = program.get_func(funcidx);
+            let mut refs: Vec<FuncIdx> = func
+                .signature_filter
+                .iter()
+                .map(|(_, _, target)| *target)
+                .collect();
+            visit_expr(&func.expr, &live_globals, &mut refs, &mut read_globals);
+            for target in refs {
+                if !live_funcs[target] {
+                    live_funcs[target] = true;
+                    worklist.push(target);
+                }
+            }
+        }
+        if read_globals == live_globals {
+            return (live_funcs, live_globals);
+        }
+        // reading is monotonic in the live globals, so this terminates
+        live_globals = read_globals;
+    }
+}
+
+/**
+ * Collects the functions referenced and globals read by the expr.
+ * The value assigned to a global that is not in `live_globals` is skipped if it has no side effects,
+ * because it will be removed later.
+ */
+fn visit_expr(
+    expr: &Expr,
+    live_globals: &[bool],
+    refs: &mut Vec<FuncIdx>,
+    read_globals: &mut [bool],
+) {
+    // Note: we explicitly list out all possibilities so we will get a compile error if a new exprkind is added.
+    match &expr.kind {
+        ExprKind::PrimUndefined
+        | ExprKind::PrimNull
+        | ExprKind::PrimNumber { val: _ }
+        | ExprKind::PrimBoolean { val: _ }
+        | ExprKind::PrimStructT { typeidx: _ }
+        | ExprKind::PrimString { val: _ }
+        | ExprKind::Trap {
+            code: _,
+            location: _,
+        }
+        | ExprKind::SourceMarker { location: _ } => {}
+        ExprKind::PrimFunc { funcidxs, closure } => {
+            refs.extend(funcidxs.iter().map(|oe| oe.funcidx));
+            visit_expr(closure, live_globals, refs, read_globals);
+        }
+        ExprKind::TypeCast {
+            test,
+            expected: _,
+            create_narrow_local: _,
+            true_expr,
+            false_expr,
+        } => {
+            visit_expr(test, live_globals, refs, read_globals);
+            visit_expr(true_expr, live_globals, refs, read_globals);
+            visit_expr(false_expr, live_globals, refs, read_globals);
+        }
+        ExprKind::VarName { source } => {
+            if let TargetExpr::Global { globalidx, next: _ } = source {
+                read_globals[*globalidx] = true;
+            }
+        }
+        ExprKind::PrimAppl { prim_inst: _, args } => {
+            for arg in args.iter() {
+                visit_expr(arg, live_globals, refs, read_globals);
+            }
+        }
+        ExprKind::Appl {
+            func,
+            args,
+            location: _,
+        } => {
+            visit_expr(func, live_globals, refs, read_globals);
+            for arg in args.iter() {
+                visit_expr(arg, live_globals, refs, read_globals);
+            }
+        }
+        ExprKind::DirectAppl { funcidx, args } => {
+            refs.push(*funcidx);
+            for arg in args.iter() {
+                visit_expr(arg, live_globals, refs, read_globals);
+            }
+        }
+        ExprKind::Conditional {
+            cond,
+            true_expr,
+            false_expr,
+        } => {
+            visit_expr(cond, live_globals, refs, read_globals);
+            visit_expr(true_expr, live_globals, refs, read_globals);
+            visit_expr(false_expr, live_globals, refs, read_globals);
+        }
+        ExprKind::Declaration {
+            local: _,
+            init,
+            contained_expr,
+        } => {
+            if let Some(init_expr) = init {
+                visit_expr(init_expr, live_globals, refs, read_globals);
+            }
+            visit_expr(contained_expr, live_globals, refs, read_globals);
+        }
+        ExprKind::Assign {
+            target,
+            expr: expr2,
+        } => {
+            match target {
+                TargetExpr::Global {
+                    globalidx,
+                    next: None,
+                } => {
+                    if !live_globals[*globalidx] && is_pure(expr2) {
+                        // this assignment will be removed
+                        return;
+                    }
+                }
+                TargetExpr::Global {
+                    globalidx,
+                    next: Some(_),
+                } => {
+                    // we need to read the struct in the global to assign to its field
+                    read_globals[*globalidx] = true;
+                }
+                TargetExpr::Local {
+                    localidx: _,
+                    next: _,
+                } => {}
+            }
+            visit_expr(expr2, live_globals, refs, read_globals);
+        }
+        ExprKind::Return { expr: expr2 }
+        | ExprKind::Break {
+            num_frames: _,
+            expr: expr2,
+        }
+        | ExprKind::Block { expr: expr2 }
+        | ExprKind::Loop { expr: expr2 } => visit_expr(expr2, live_globals, refs, read_globals),
+        ExprKind::Sequence { content } => {
+            for expr2 in content {
+                visit_expr(expr2, live_globals, refs, read_globals);
+            }
+        }
+    }
+}
+
+/**
+ * Returns true if evaluating the expr has no side effects (and cannot trap), so it may be removed if its value is not used.
+ * This only needs to recognise the exprs that the frontend assigns to the globals for function declarations.
+ */
+fn is_pure(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::PrimUndefined
+        | ExprKind::PrimNull
+        | ExprKind::PrimNumber { val: _ }
+        | ExprKind::PrimBoolean { val: _ }
+        | ExprKind::PrimStructT { typeidx: _ }
+        | ExprKind::PrimString { val: _ }
+        | ExprKind::VarName { source: _ }
+        | ExprKind::SourceMarker { location: _ } => true,
+        ExprKind::PrimFunc {
+            funcidxs: _,
+            closure,
+        } => is_pure(closure),
+        ExprKind::Declaration {
+            local: _,
+            init,
+            contained_expr,
+        } => {
+            (if let Some(init_expr) = init {
+                is_pure(init_expr)
+            } else {
+                true
+            }) && is_pure(contained_expr)
+        }
+        ExprKind::Sequence { content } => content.iter().all(is_pure),
+        _ => false,
+    }
+}
+
+/**
+ * If the expr is an assignment to a dead global, replaces it with just the assigned expr (or nothing, if it has no side effects).
+ */
+fn remove_dead_assign(expr: &mut Expr, live_globals: &[bool]) {
+    if let ExprKind::Assign {
+        target: TargetExpr::Global {
+            globalidx,
+            next: None,
+        },
+        expr: expr2,
+    } = &mut expr.kind
+    {
+        if !live_globals[*globalidx] {
+            let undefined_expr = Expr {
+                vartype: Some(VarType::Undefined),
+                kind: ExprKind::PrimUndefined,
+            };
+            let new_kind = if is_pure(expr2) {
+                ExprKind::PrimUndefined
+            } else {
+                ExprKind::Sequence {
+                    content: vec![
+                        std::mem::replace(&mut **expr2, undefined_expr.clone()),
+                        undefined_expr,
+                    ],
+                }
+            };
+            expr.kind = new_kind;
+        }
+    }
+}
+
+fn renumber_expr(expr: &mut Expr, func_map: &[Option<FuncIdx>], global_map: &[Option<usize>]) {
+    match &mut expr.kind {
+        ExprKind::PrimFunc {
+            funcidxs,
+            closure: _,
+        } => {
+            for oe in funcidxs.iter_mut() {
+                oe.funcidx = func_map[oe.funcidx].unwrap();
+            }
+        }
+        ExprKind::DirectAppl { funcidx, args: _ } => {
+            *funcidx = func_map[*funcidx].unwrap();
+        }
+        ExprKind::VarName {
+            source: TargetExpr::Global { globalidx, next: _ },
+        }
+        | ExprKind::Assign {
+            target: TargetExpr::Global { globalidx, next: _ },
+            expr: _,
+        } => {
+            *globalidx = global_map[*globalidx].unwrap();
+        }
+        _ => {}
+    }
+}
+
+/**
+ * Returns the new index of each old index, where only the live ones are kept (in the same order).
+ */
+fn make_index_map(live: &[bool]) -> Box<[Option<usize>]> {
+    let mut next: usize = 0;
+    live.iter()
+        .map(|is_live| {
+            if *is_live {
+                next += 1;
+                Some(next - 1)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp;
+
+    /**
+     * Host that records the values passed to `display`.
+     */
+    #[derive(Default)]
+    struct RecordingHost {
+        output: Vec<interp::Value>,
+    }
+
+    impl interp::Host for RecordingHost {
+        fn call_import(
+            &mut self,
+            import: &Import,
+            args: &[interp::Value],
+        ) -> Result<interp::Value, interp::Trap> {
+            assert_eq!(import.entity_name, "display");
+            self.output.extend(args.iter().cloned());
+            Ok(interp::Value::Undefined)
+        }
+    }
+
+    #[test]
+    fn removes_and_renumbers() {
+        // "dead" is only stored in a global that is never read, and "h (number)" is only used as h's specialised version
+        let text = r#"
+            import "misc" "display" (number) -> undefined
+            global any
+            global number
+            func "dead" () -> number {
+              (number:number 1.0)
+            }
+            func "h" (any) -> any filter (number) -> number = 3 {
+              (var:any local 0)
+            }
+            func "h" (number) -> number {
+              (var:number local 0)
+            }
+            func "main" () -> undefined {
+              (seq:undefined
+                (assign:undefined global 0 (func:func [1] (undefined:undefined)))
+                (assign:undefined global 1 (number:number 5.0))
+                (typecast:undefined number narrow (direct:any 2 (var:number global 1))
+                  (direct:undefined 0 (var:number local 0))
+                  (trap:void 17)))
+            }
+            entry 4
+            "#;
+        let (program, changed) = optimize(text.parse().unwrap());
+        assert!(changed);
+        assert_eq!(
+            program
+                .funcs
+                .iter()
+                .map(|func| func.name.as_deref().unwrap())
+                .collect::<Vec<&str>>(),
+            vec!["h", "h", "main"]
+        );
+        assert_eq!(program.entry_point, 3);
+        assert_eq!(program.globals, vec![VarType::Number]);
+        assert_eq!(program.funcs[0].signature_filter[0].2, 2);
+        let main = &program.funcs[2].expr;
+        let mut direct_funcidxs: Vec<FuncIdx> = Vec::new();
+        let mut globalidxs: Vec<usize> = Vec::new();
+        walk_expr(main, 0, &mut |expr, _| match &expr.kind {
+            ExprKind::DirectAppl { funcidx, args: _ } => direct_funcidxs.push(*funcidx),
+            ExprKind::VarName {
+                source: TargetExpr::Global { globalidx, next: _ },
+            }
+            | ExprKind::Assign {
+                target: TargetExpr::Global { globalidx, next: _ },
+                expr: _,
+            } => globalidxs.push(*globalidx),
+            _ => {}
+        });
+        direct_funcidxs.sort_unstable();
+        assert_eq!(direct_funcidxs, vec![0, 1]);
+        assert!(globalidxs.iter().all(|globalidx| *globalidx == 0));
+
+        let mut host = RecordingHost::default();
+        interp::run(&program, &mut host).unwrap();
+        assert_eq!(host.output, vec![interp::Value::Number(5.0)]);
+
+        // running it again changes nothing
+        assert!(!optimize(program).1);
+    }
+}
@@ -9,11 +9,9 @@ use relabel_site::relabel_inline_func;
 mod populate_properties;
 use populate_properties::populate_properties_func;
 
-const INLINING_MAX_ALLOWABLE_COST: usize = 40;
-
 /**
  * Discretionary optimisation to inline function calls where beneficial.
- * The second return value is true if the program got changed, or false otherwise.
+ * The second return value is the number of call sites that got inlined (so the program got changed iff it is nonzero).
  * The algorithm is designed to be stable - repeatedly running the algorithm will eventually reach a stable state, even with future devirtualisation.
  *
  * The algorithm works as follows:
@@ -22,7 +20,7 @@ const INLINING_MAX_ALLOWABLE_COST: usize = 40;
  * 3. If there is at least one function in G that has zero outgoing edges:
  *   3a. Take any such function f, remove it from G.
  *     3ai. If there is only one direct call and no indirect calls to f, inline the call (at this point we can also remove f from the program).
- *     3aii. Otherwise, if f has a cost that is less than C, then inline all direct calls to f.
+ *     3aii. Otherwise, if f has a cost that is at most C (`max_allowable_cost`), then inline all direct calls to f.
  *     3aiii. Otherwise, do nothing.
  *   3b. Go to step 3.
  * 4. If G is non empty (i.e. there is a cycle):
@@ -32,8 +30,8 @@ const INLINING_MAX_ALLOWABLE_COST: usize = 40;
  *
  * Only these Expr nodes count to the cost: PrimNumber, PrimBoolean, PrimStructT, PrimString, PrimFunc, VarName, Trap
  */
-pub fn optimize(mut program: Program) -> (Program, bool) {
-    let mut changed = false;
+pub fn optimize(mut program: Program, max_allowable_cost: usize) -> (Program, usize) {
+    let mut num_inlined: usize = 0;
 
     // TODO: collect the FunctionProperties (including imports, which will never be inlining candidates)
     // those with None are not currently inlining candidates (i.e. not in G), those with Some are currently candidates
@@ -113,7 +111,7 @@ pub fn optimize(mut program: Program) -> (Program, bool) {
                         site,
                         std::mem::replace(program.get_func_mut(og_item.funcidx), Func::new()),
                     );
-                    changed = true;
+                    num_inlined += 1;
                     update_caller_func(
                         &mut og_calls_pq,
                         &mut cost_pq,
@@ -121,7 +119,7 @@ pub fn optimize(mut program: Program) -> (Program, bool) {
                         caller_funcidx,
                         fp.cost,
                     );
-                } else if fp.cost <= INLINING_MAX_ALLOWABLE_COST {
+                } else if fp.cost <= max_allowable_cost {
                     // 3aii succeeds, we inline by usual copy
                     for (caller_funcidx, mut direct_call_expr, site) in fp.parents {
                         inline_by_copy(
@@ -129,7 +127,7 @@ pub fn optimize(mut program: Program) -> (Program, bool) {
                             site,
                             program.get_func(og_item.funcidx),
                         );
-                        changed = true;
+                        num_inlined += 1;
                         update_caller_func(
                             &mut og_calls_pq,
                             &mut cost_pq,
@@ -156,7 +154,7 @@ pub fn optimize(mut program: Program) -> (Program, bool) {
         // if it's None, then we should continue to get the next og_item
     }
 
-    (program, changed)
+    (program, num_inlined)
 }
 
 struct FunctionProperties {
@@ -262,11 +260,12 @@ impl PartialEq for CostPQItem {
 fn inline_by_destructive_move(direct_call_expr: &mut Expr, site: SiteProperties, func: Func) {
     let actual_args = std::mem::take(as_direct_appl_args(direct_call_expr));
     let tmp_expr = func.expr;
+    let result = func.result;
     *direct_call_expr = wrap_declarations(
         Vec::from(actual_args).into_iter(),
         &func.params,
         site,
-        |site| relabel_inline_func(tmp_expr, site).0,
+        |site| relabel_inline_func(tmp_expr, result, site).0,
     );
 }
 
@@ -278,7 +277,7 @@ fn inline_by_copy(direct_call_expr: &mut Expr, site: SiteProperties, func: &Func
         site,
         |site| {
             let expr = func.expr.clone();
-            relabel_inline_func(expr, site).0
+            relabel_inline_func(expr, func.result, site).0
         },
     );
 }
@@ -25,7 +25,7 @@ fn populate_properties(
 ) {
     // Note: we explicitly list out all possibilities so we will get a compile error if a new exprkind is added.
     match &mut expr.kind {
-        ExprKind::PrimUndefined => {}
+        ExprKind::PrimUndefined | ExprKind::PrimNull => {}
         ExprKind::PrimNumber { val: _ }
         | ExprKind::PrimBoolean { val: _ }
         | ExprKind::PrimStructT { typeidx: _ }
@@ -121,6 +121,9 @@ fn populate_properties(
         ExprKind::Block { expr } => {
             populate_properties(funcidx, expr, func_props, site);
         }
+        ExprKind::Loop { expr } => {
+            populate_properties(funcidx, expr, func_props, site);
+        }
         ExprKind::Sequence { content } => {
             for expr in content {
                 populate_properties(funcidx, expr, func_props, site);
@@ -132,6 +135,9 @@ fn populate_properties(
         } => {
             inc_cost(&mut func_props[funcidx]);
         }
+        ExprKind::SourceMarker { location: _ } => {
+            // markers do not generate any code, so they do not add to the cost
+        }
     }
 }
 
@@ -1,3 +1,4 @@
+use super::union_type;
 use super::SiteProperties;
 use crate::*;
 
@@ -6,12 +7,17 @@ use crate::*;
  * Note that we only need to rewrite Returns but not Breaks, because Breaks are relative.
  * site.num_landings()
  * Also wraps everything in a block so that returns can jump here.
+ * The block has the result type of the function (`result`), because the rewritten Returns break out of it.
  */
-pub(super) fn relabel_inline_func(mut expr: Expr, site: SiteProperties) -> (Expr, bool) {
+pub(super) fn relabel_inline_func(
+    mut expr: Expr,
+    result: Option<VarType>,
+    site: SiteProperties,
+) -> (Expr, bool) {
     let ret = relabel_site(&mut expr, site, 0);
     (
         Expr {
-            vartype: expr.vartype,
+            vartype: union_type(expr.vartype, result),
             kind: ExprKind::Block {
                 expr: Box::new(expr),
             },
@@ -24,6 +30,7 @@ fn relabel_site(expr: &mut Expr, site: SiteProperties, num_landings: usize) -> b
     // Note: we explicitly list out all possibilities so we will get a compile error if a new exprkind is added.
     match &mut expr.kind {
         ExprKind::PrimUndefined
+        | ExprKind::PrimNull
         | ExprKind::PrimNumber { val: _ }
         | ExprKind::PrimBoolean { val: _ }
         | ExprKind::PrimStructT { typeidx: _ }
@@ -100,6 +107,7 @@ fn relabel_site(expr: &mut Expr, site: SiteProperties, num_landings: usize) -> b
             expr,
         } => relabel_site(&mut **expr, site, num_landings),
         ExprKind::Block { expr } => relabel_site(&mut **expr, site, num_landings + 1),
+        ExprKind::Loop { expr } => relabel_site(&mut **expr, site, num_landings + 1),
         ExprKind::Sequence { content } => content.iter_mut().fold(false, |prev, expr| {
             prev | relabel_site(expr, site, num_landings)
         }),
@@ -107,6 +115,7 @@ fn relabel_site(expr: &mut Expr, site: SiteProperties, num_landings: usize) -> b
             code: _,
             location: _,
         } => false,
+        ExprKind::SourceMarker { location: _ } => false,
     }
 }
 
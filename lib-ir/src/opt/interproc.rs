@@ -0,0 +1,1167 @@
+use super::propagate;
+use super::propagate::intersect_type;
+use super::relabeller::relabel;
+use super::relabeller::Relabeller;
+use super::union_type;
+use super::walk::*;
+use super::*;
+use crate::superset::*;
+use std::collections::BTreeMap;
+
+// maximum number of specialised versions that may be cloned from a single function
+const MAX_SPECIALISATIONS: usize = 4;
+
+/**
+ * Discretionary optimisation to narrow function signatures using the arg types at their call sites (interprocedural type inference).
+ * The second return value is true if the program got changed, or false otherwise.
+ *
+ * Only call sites with a statically known callee are used: DirectAppl, and Appl on a PrimFunc (whose overloads are all statically known).
+ * A function whose PrimFunc is also used as a value might get called with anything, so its params are never narrowed in place.
+ *
+ * The algorithm works as follows:
+ * 1. Expose more statically known callees:
+ *    1a. An Appl on a global that is assigned a function without a (non-empty) closure exactly once, before any other code runs, becomes a DirectAppl.
+ *    1b. An Appl on a Declaration that just returns a PrimFunc (left behind after the TypeCast of the callee is removed) becomes an Appl on that PrimFunc,
+ *    so that propagate.rs can devirtualize it.
+ * 2. Collect the arg types at the static call sites of every function.
+ * 3. If a function is only called statically, and all call sites agree on a narrower type for some param, narrow that param.
+ * 4. Otherwise, clone a specialised version for each narrower signature at its DirectAppl call sites (at most MAX_SPECIALISATIONS),
+ *    and register the clones in signature_filter.
+ * 5. Redirect every DirectAppl to a specialised version of the callee that accepts its arg types, if there is one.
+ * 6. Infer result types optimistically: assume that functions that make direct calls never return, propagate them under that assumption,
+ *    and widen the assumed result types until they are stable.
+ *    propagate.rs cannot find these by itself for recursive functions, because it assumes that a recursive call returns the current result type.
+ *    Functions with direct calls in tail position are left alone, because backends that implement tail calls with trampolines
+ *    can only do so in functions that return Any.
+ */
+pub fn optimize(mut program: Program) -> (Program, bool) {
+    let mut changed = false;
+    changed |= update_signature_filters(&mut program);
+    changed |= resolve_constant_globals(&mut program);
+    changed |= expose_prim_func_callees(&mut program);
+    let sites = collect_call_sites(&program);
+    let narrowed = narrow_params(&mut program, &sites);
+    changed |= narrowed.iter().any(|x| *x);
+    changed |= specialise(&mut program, &sites, &narrowed);
+    changed |= redirect_to_specialisations(&mut program);
+    changed |= infer_results(&mut program);
+    (program, changed)
+}
+
+/**
+ * Static call sites of a function.
+ */
+#[derive(Default)]
+struct CallSites {
+    direct: Vec<Box<[VarType]>>,     // arg types of each DirectAppl
+    overloaded: Vec<Box<[VarType]>>, // arg types (restricted to the params) of each Appl on a PrimFunc that has this function as an overload
+    num_static_refs: usize, // number of references by PrimFuncs that are the callee of an Appl
+    num_refs: usize,        // total number of references by PrimFuncs
+}
+
+impl CallSites {
+    /**
+     * Returns true if the function might be called from somewhere that we don't know about.
+     */
+    fn escapes(&self) -> bool {
+        self.num_refs > self.num_static_refs
+    }
+}
+
+/**
+ * Removes entries of signature_filter whose specialised version no longer has the expected params
+ * (e.g. because it was inlined into its only caller), and updates the result type of the rest.
+ * The return value is true if the program got changed, or false otherwise.
+ */
+fn update_signature_filters(program: &mut Program) -> bool {
+    let (param_types, result_types) = propagate::signatures(program);
+    let mut changed = false;
+    for func in &mut program.funcs {
+        let old_len = func.signature_filter.len();
+        func.signature_filter
+            .retain(|(params, _, funcidx)| param_types[*funcidx] == *params);
+        changed |= func.signature_filter.len() != old_len;
+        for (_, result, funcidx) in &mut func.signature_filter {
+            if let Some(vartype) = result_types[*funcidx] {
+                changed |= useful_update(result, vartype);
+            }
+        }
+    }
+    changed
+}
+
+/**
+ * Converts each Appl on a global that always holds the same function into a DirectAppl (step 1a).
+ * Functions that take a closure are only called directly if the closure struct is empty and never used,
+ * in which case we call a clone that takes Undefined in place of the closure.
+ * The clone is only added if some call site can be converted, so that this pass does not keep adding clones that dead_code removes.
+ * The return value is true if the program got changed, or false otherwise.
+ */
+fn resolve_constant_globals(program: &mut Program) -> bool {
+    // map from globalidx to (funcidx of the function in the global, whether to pass Undefined as the closure)
+    let mut candidates: BTreeMap<usize, (FuncIdx, bool)> = BTreeMap::new();
+    for (globalidx, (funcidx, has_closure_param)) in find_constant_globals(program) {
+        if has_closure_param {
+            let func = program.get_func(funcidx);
+            if func.params.is_empty() || uses_local(&func.expr, 0) {
+                continue;
+            }
+        }
+        candidates.insert(globalidx, (funcidx, has_closure_param));
+    }
+    if candidates.is_empty() {
+        return false;
+    }
+
+    // only keep the globals that have some call site that can become a DirectAppl,
+    // so that we don't add a clone that nobody calls (dead_code would remove it, and we would add it again in the next iteration)
+    let (param_types, result_types) = propagate::signatures(program);
+    let mut used: BTreeMap<usize, (FuncIdx, bool)> = BTreeMap::new();
+    for func in &program.funcs {
+        walk_expr(&func.expr, func.params.len(), &mut |expr, num_locals| {
+            if let ExprKind::Appl {
+                func: callee,
+                args,
+                location: _,
+            } = &expr.kind
+            {
+                if let Some(globalidx) = callee_global(callee, num_locals) {
+                    if let Some((funcidx, pass_closure)) = candidates.get(&globalidx).copied() {
+                        if accepts_args(&param_types[funcidx], args, pass_closure) {
+                            used.insert(globalidx, (funcidx, pass_closure));
+                        }
+                    }
+                }
+            }
+        });
+    }
+    if used.is_empty() {
+        return false;
+    }
+
+    // map from globalidx to (funcidx to call, funcidx of the function in the global, whether to pass Undefined as the closure)
+    let targets: BTreeMap<usize, (FuncIdx, FuncIdx, bool)> = used
+        .into_iter()
+        .map(|(globalidx, (funcidx, pass_closure))| {
+            if !pass_closure {
+                return (globalidx, (funcidx, funcidx, false));
+            }
+            let func = program.get_func(funcidx);
+            let mut params = func.params.clone();
+            params[0] = VarType::Undefined;
+            let new_func = Func {
+                name: func.name.clone(),
+                params: params,
+                param_names: func.param_names.clone(),
+                result: func.result,
+                expr: func.expr.clone(),
+                signature_filter: Vec::new(),
+            };
+            (globalidx, (program.add_func(new_func), funcidx, true))
+        })
+        .collect();
+
+    for func in &mut program.funcs {
+        walk_expr_mut(
+            &mut func.expr,
+            func.params.len(),
+            &mut |expr, num_locals| {
+                if let ExprKind::Appl {
+                    func: callee,
+                    args,
+                    location: _,
+                } = &mut expr.kind
+                {
+                    if let Some((funcidx, original_funcidx, pass_closure)) =
+                        callee_global(callee, num_locals)
+                            .and_then(|globalidx| targets.get(&globalidx).copied())
+                    {
+                        // the clone has the same params as the original function (other than the closure)
+                        if accepts_args(&param_types[original_funcidx], args, pass_closure) {
+                            // the callee has no side effects, and it must be a function since the global has been assigned
+                            let new_args: Vec<Expr> = if pass_closure {
+                                Some(make_prim_undefined())
+                            } else {
+                                None
+                            }
+                            .into_iter()
+                            .chain(Vec::from(std::mem::take(args)))
+                            .collect();
+                            *expr = make_direct_appl(
+                                funcidx,
+                                result_types[original_funcidx],
+                                new_args,
+                                num_locals,
+                            );
+                        }
+                    }
+                }
+            },
+        );
+    }
+    true
+}
+
+/**
+ * Returns true if each arg is known to be acceptable to the function with the given params
+ * (after the closure, if `skip_closure` is true), since a DirectAppl does not typecheck its args.
+ */
+fn accepts_args(params: &[VarType], args: &[Expr], skip_closure: bool) -> bool {
+    params.len() == args.len() + (skip_closure as usize)
+        && args
+            .iter()
+            .zip(params[skip_closure as usize..].iter())
+            .all(|(arg, param)| arg.vartype.is_some_and(|vartype| param.superset(&vartype)))
+}
+
+/**
+ * Makes a DirectAppl that calls the given function with the given args (which must all have a vartype).
+ * Like devirtualization in propagate.rs, the args are first saved into new locals, and the DirectAppl only reads those locals,
+ * because the inliner expects that the args of a DirectAppl contain no other calls.
+ */
+fn make_direct_appl(
+    funcidx: FuncIdx,
+    result: Option<VarType>,
+    args: Vec<Expr>,
+    num_locals: usize,
+) -> Expr {
+    let mut relabeller = Relabeller::new_with_identities(0..num_locals);
+    let decls: Vec<(VarType, Expr)> = args
+        .into_iter()
+        .enumerate()
+        .map(|(i, mut arg)| {
+            // the i-th arg is evaluated after declaring the locals of the previous args
+            relabeller.with_skipped_news(i, |relabeller| relabel(&mut arg, relabeller));
+            (arg.vartype.unwrap(), arg)
+        })
+        .collect();
+    let direct_appl = Expr {
+        vartype: result,
+        kind: ExprKind::DirectAppl {
+            funcidx: funcidx,
+            args: decls
+                .iter()
+                .enumerate()
+                .map(|(i, (vartype, _))| Expr {
+                    vartype: Some(*vartype),
+                    kind: ExprKind::VarName {
+                        source: TargetExpr::Local {
+                            localidx: num_locals + i,
+                            next: None,
+                        },
+                    },
+                })
+                .collect(),
+        },
+    };
+    decls
+        .into_iter()
+        .rev()
+        .fold(direct_appl, |contained_expr, (vartype, init_expr)| Expr {
+            vartype: contained_expr.vartype,
+            kind: ExprKind::Declaration {
+                local: vartype,
+                init: Some(Box::new(init_expr)),
+                contained_expr: Box::new(contained_expr),
+            },
+        })
+}
+
+/**
+ * Returns the globals that always contain the same function, if the function is called through the global.
+ * The value is the funcidx and whether the function takes an (empty) closure.
+ * Such a global must be assigned exactly once, in the part of the entry point that runs before any code that could read the global.
+ * (This is where the frontend puts function declarations, because they are hoisted.)
+ */
+fn find_constant_globals(program: &Program) -> BTreeMap<usize, (FuncIdx, bool)> {
+    let mut num_assigns: Vec<usize> = vec![0; program.globals.len()];
+    for func in &program.funcs {
+        walk_expr(&func.expr, func.params.len(), &mut |expr, _| {
+            if let ExprKind::Assign {
+                target:
+                    TargetExpr::Global {
+                        globalidx,
+                        next: None,
+                    },
+                expr: _,
+            } = &expr.kind
+            {
+                num_assigns[*globalidx] += 1;
+            }
+        });
+    }
+
+    let mut ret = BTreeMap::new();
+    let entry_func = program.get_func(program.entry_point);
+    scan_inert_prefix(
+        &entry_func.expr,
+        entry_func.params.len(),
+        &program.struct_types,
+        &mut ret,
+    );
+    ret.retain(|globalidx, _| num_assigns[*globalidx] == 1);
+    ret
+}
+
+/**
+ * Records the function assigned to each global, in the prefix of the expr that cannot run any code that reads globals.
+ * The return value is false if the prefix ended in this expr, or true otherwise.
+ */
+fn scan_inert_prefix(
+    expr: &Expr,
+    num_locals: usize,
+    struct_types: &[Box<[VarType]>],
+    out: &mut BTreeMap<usize, (FuncIdx, bool)>,
+) -> bool {
+    match &expr.kind {
+        ExprKind::PrimUndefined | ExprKind::SourceMarker { location: _ } => true,
+        ExprKind::Sequence { content } => content
+            .iter()
+            .all(|expr2| scan_inert_prefix(expr2, num_locals, struct_types, out)),
+        ExprKind::Assign {
+            target:
+                TargetExpr::Global {
+                    globalidx,
+                    next: None,
+                },
+            expr: expr2,
+        } => {
+            if let Some(func) = constant_func_value(expr2, num_locals, struct_types) {
+                out.insert(*globalidx, func);
+                true
+            } else {
+                false
+            }
+        }
+        _ => false,
+    }
+}
+
+/**
+ * Returns the funcidx of the function created by the expr, and whether it takes a closure,
+ * if the expr creates a function that has a single overload and no state (i.e. its closure is undefined or an empty struct).
+ */
+fn constant_func_value(
+    expr: &Expr,
+    num_locals: usize,
+    struct_types: &[Box<[VarType]>],
+) -> Option<(FuncIdx, bool)> {
+    match &expr.kind {
+        ExprKind::PrimFunc { funcidxs, closure } => {
+            if let [oe] = &**funcidxs {
+                if !oe.has_closure_param && closure.is_prim_undefined() {
+                    return Some((oe.funcidx, false));
+                }
+            }
+            None
+        }
+        ExprKind::Declaration {
+            local: VarType::StructT { typeidx },
+            init: Some(init_expr),
+            contained_expr,
+        } => {
+            if !struct_types[*typeidx].is_empty() {
+                return None;
+            }
+            if let (
+                ExprKind::PrimStructT { typeidx: _ },
+                ExprKind::PrimFunc { funcidxs, closure },
+            ) = (&init_expr.kind, &contained_expr.kind)
+            {
+                if let (
+                    [oe],
+                    ExprKind::VarName {
+                        source:
+                            TargetExpr::Local {
+                                localidx,
+                                next: None,
+                            },
+                    },
+                ) = (&**funcidxs, &closure.kind)
+                {
+                    if oe.has_closure_param && *localidx == num_locals {
+                        return Some((oe.funcidx, true));
+                    }
+                }
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+/**
+ * Returns the global read by the callee of an Appl, if the callee does nothing else
+ * (other than the TypeCast to Func that the frontend emits).
+ */
+fn callee_global(callee: &Expr, num_locals: usize) -> Option<usize> {
+    match &callee.kind {
+        ExprKind::VarName {
+            source:
+                TargetExpr::Global {
+                    globalidx,
+                    next: None,
+                },
+        } => Some(*globalidx),
+        ExprKind::TypeCast {
+            test,
+            expected: VarType::Func,
+            create_narrow_local: true,
+            true_expr,
+            false_expr: _,
+        } => {
+            if let (
+                ExprKind::VarName {
+                    source:
+                        TargetExpr::Global {
+                            globalidx,
+                            next: None,
+                        },
+                },
+                ExprKind::VarName {
+                    source:
+                        TargetExpr::Local {
+                            localidx,
+                            next: None,
+                        },
+                },
+            ) = (&test.kind, &true_expr.kind)
+            {
+                if *localidx == num_locals {
+                    return Some(*globalidx);
+                }
+            }
+            None
+        }
+        _ => None,
+    }
+}
+
+/**
+ * Returns true if the local is read or assigned anywhere in the expr.
+ */
+fn uses_local(expr: &Expr, localidx: usize) -> bool {
+    let mut ret = false;
+    walk_expr(expr, 0, &mut |expr2, _| match &expr2.kind {
+        ExprKind::VarName {
+            source:
+                TargetExpr::Local {
+                    localidx: localidx2,
+                    next: _,
+                },
+        }
+        | ExprKind::Assign {
+            target:
+                TargetExpr::Local {
+                    localidx: localidx2,
+                    next: _,
+                },
+            expr: _,
+        } => ret |= *localidx2 == localidx,
+        _ => {}
+    });
+    ret
+}
+
+/**
+ * Replaces each callee of the form `Declaration { init: PrimFunc, contained_expr: <the declared local> }` by the PrimFunc itself (step 1b).
+ * This is not done in propagate.rs, because devirtualization there assumes that it happens in the first iteration that sees the PrimFunc.
+ * The return value is true if the program got changed, or false otherwise.
+ */
+fn expose_prim_func_callees(program: &mut Program) -> bool {
+    let mut changed = false;
+    for func in &mut program.funcs {
+        walk_expr_mut(
+            &mut func.expr,
+            func.params.len(),
+            &mut |expr, num_locals| {
+                if let ExprKind::Appl {
+                    func: callee,
+                    args: _,
+                    location: _,
+                } = &mut expr.kind
+                {
+                    let mut exposed: Option<Expr> = None;
+                    if let ExprKind::Declaration {
+                        local: _,
+                        init: Some(init_expr),
+                        contained_expr,
+                    } = &mut callee.kind
+                    {
+                        if let (
+                            ExprKind::PrimFunc {
+                                funcidxs: _,
+                                closure: _,
+                            },
+                            ExprKind::VarName {
+                                source:
+                                    TargetExpr::Local {
+                                        localidx,
+                                        next: None,
+                                    },
+                            },
+                        ) = (&init_expr.kind, &contained_expr.kind)
+                        {
+                            if *localidx == num_locals
+                                && init_expr.vartype == contained_expr.vartype
+                            {
+                                exposed = Some(std::mem::replace(
+                                    &mut **init_expr,
+                                    make_prim_undefined(),
+                                ));
+                            }
+                        }
+                    }
+                    if let Some(prim_func) = exposed {
+                        **callee = prim_func;
+                        changed = true;
+                    }
+                }
+            },
+        );
+    }
+    changed
+}
+
+/**
+ * Collects the static call sites of each FuncIdx (including imports) (step 2).
+ */
+fn collect_call_sites(program: &Program) -> Box<[CallSites]> {
+    let (param_types, _) = propagate::signatures(program);
+    let mut sites: Box<[CallSites]> = param_types.iter().map(|_| CallSites::default()).collect();
+    for func in &program.funcs {
+        // since the walk is pre-order, an Appl is visited before the PrimFunc that is its callee
+        walk_expr(
+            &func.expr,
+            func.params.len(),
+            &mut |expr, _| match &expr.kind {
+                ExprKind::DirectAppl { funcidx, args } => {
+                    if let Some(sig) = args
+                        .iter()
+                        .map(|arg| arg.vartype)
+                        .collect::<Option<Box<[VarType]>>>()
+                    {
+                        sites[*funcidx].direct.push(sig);
+                    }
+                }
+                ExprKind::Appl {
+                    func: callee,
+                    args,
+                    location: _,
+                } => {
+                    if let ExprKind::PrimFunc { funcidxs, closure } = &callee.kind {
+                        for oe in funcidxs.iter() {
+                            sites[oe.funcidx].num_static_refs += 1;
+                            let params: &[VarType] = &param_types[oe.funcidx];
+                            if params.len() != args.len() + (oe.has_closure_param as usize) {
+                                // this overload will never be called from here
+                                continue;
+                            }
+                            // if any arg can't match the param, then this overload will never be called from here either
+                            if let Some(sig) = if oe.has_closure_param {
+                                Some(closure.vartype)
+                            } else {
+                                None
+                            }
+                            .into_iter()
+                            .chain(args.iter().map(|arg| arg.vartype))
+                            .zip(params.iter().copied())
+                            .map(|(arg_vartype, param)| {
+                                arg_vartype.and_then(|vartype| intersect_type(vartype, param))
+                            })
+                            .collect::<Option<Box<[VarType]>>>()
+                            {
+                                sites[oe.funcidx].overloaded.push(sig);
+                            }
+                        }
+                    }
+                }
+                ExprKind::PrimFunc {
+                    funcidxs,
+                    closure: _,
+                } => {
+                    for oe in funcidxs.iter() {
+                        sites[oe.funcidx].num_refs += 1;
+                    }
+                }
+                _ => {}
+            },
+        );
+    }
+    sites
+}
+
+/**
+ * Returns, for each FuncIdx, whether it takes part in some signature_filter (either as the original or a specialised version).
+ * The params of such functions must not be narrowed in place, otherwise the entries might not be subsets of the params any more.
+ */
+fn in_signature_filters(program: &Program) -> Box<[bool]> {
+    let mut ret: Box<[bool]> = vec![false; program.imports.len() + program.funcs.len()].into();
+    for (i, func) in program.funcs.iter().enumerate() {
+        for (_, _, funcidx) in &func.signature_filter {
+            ret[program.imports.len() + i] = true;
+            ret[*funcidx] = true;
+        }
+    }
+    ret
+}
+
+/**
+ * Narrows the params of functions that are only called statically, if all call sites agree (step 3).
+ * Returns, for each FuncIdx, whether it got narrowed.
+ */
+fn narrow_params(program: &mut Program, sites: &[CallSites]) -> Box<[bool]> {
+    let in_filters = in_signature_filters(program);
+    let imports_len = program.imports.len();
+    let entry_point = program.entry_point;
+    let mut narrowed: Box<[bool]> = vec![false; sites.len()].into();
+    for (i, func) in program.funcs.iter_mut().enumerate() {
+        let funcidx = imports_len + i;
+        let func_sites = &sites[funcidx];
+        if funcidx == entry_point || in_filters[funcidx] || func_sites.escapes() {
+            continue;
+        }
+        for paramidx in 0..func.params.len() {
+            if func.params[paramidx] != VarType::Any {
+                continue;
+            }
+            let unioned_type = func_sites
+                .direct
+                .iter()
+                .chain(func_sites.overloaded.iter())
+                .filter(|sig| sig.len() == func.params.len())
+                .fold(None, |acc, sig| union_type(acc, Some(sig[paramidx])));
+            if let Some(vartype) = unioned_type {
+                if vartype != VarType::Any && !assigns_local(&func.expr, paramidx) {
+                    func.params[paramidx] = vartype;
+                    retype_local(&mut func.expr, paramidx, vartype);
+                    narrowed[funcidx] = true;
+                }
+            }
+        }
+    }
+    narrowed
+}
+
+/**
+ * Clones specialised versions of functions for the narrower signatures at their DirectAppl call sites (step 4).
+ * Specialised versions are never specialised further, so the number of functions stays bounded.
+ * The return value is true if the program got changed, or false otherwise.
+ */
+fn specialise(program: &mut Program, sites: &[CallSites], narrowed: &[bool]) -> bool {
+    let mut is_specialisation: Box<[bool]> =
+        vec![false; program.imports.len() + program.funcs.len()].into();
+    for func in &program.funcs {
+        for (_, _, funcidx) in &func.signature_filter {
+            is_specialisation[*funcidx] = true;
+        }
+    }
+
+    let mut changed = false;
+    for funcidx in program.imports.len()..(program.imports.len() + program.funcs.len()) {
+        if funcidx == program.entry_point || is_specialisation[funcidx] || narrowed[funcidx] {
+            continue;
+        }
+        let func = program.get_func(funcidx);
+        let result = match func.result {
+            Some(vartype) => vartype,
+            None => continue, // there is nothing to gain if the function never returns
+        };
+        // params that are assigned to can't be narrowed, because they might be assigned something else
+        let narrowable: Box<[bool]> = (0..func.params.len())
+            .map(|paramidx| {
+                func.params[paramidx] == VarType::Any && !assigns_local(&func.expr, paramidx)
+            })
+            .collect();
+        let mut new_funcs: Vec<(Box<[VarType]>, Func)> = Vec::new();
+        for sig in &sites[funcidx].direct {
+            if func.signature_filter.len() + new_funcs.len() >= MAX_SPECIALISATIONS {
+                break;
+            }
+            if sig.len() != func.params.len() {
+                continue;
+            }
+            let restricted_sig: Box<[VarType]> = sig
+                .iter()
+                .zip(func.params.iter())
+                .zip(narrowable.iter())
+                .map(|((arg, param), narrowable)| if *narrowable { *arg } else { *param })
+                .collect();
+            if restricted_sig == func.params
+                || func
+                    .signature_filter
+                    .iter()
+                    .map(|(params, _, _)| params)
+                    .chain(new_funcs.iter().map(|(params, _)| params))
+                    .any(|params| params.superset(&restricted_sig))
+            {
+                // this call site can already use the function or one of its specialised versions
+                continue;
+            }
+            let mut expr = func.expr.clone();
+            for (paramidx, (old, new)) in func.params.iter().zip(restricted_sig.iter()).enumerate()
+            {
+                if old != new {
+                    retype_local(&mut expr, paramidx, *new);
+                }
+            }
+            new_funcs.push((
+                restricted_sig.clone(),
+                Func {
+                    name: func.name.clone(),
+                    params: restricted_sig,
+                    param_names: func.param_names.clone(),
+                    result: func.result,
+                    expr: expr,
+                    signature_filter: Vec::new(),
+                },
+            ));
+        }
+        for (sig, new_func) in new_funcs {
+            let new_funcidx = program.add_func(new_func);
+            program
+                .get_func_mut(funcidx)
+                .signature_filter
+                .push((sig, result, new_funcidx));
+            changed = true;
+        }
+    }
+    changed
+}
+
+/**
+ * Redirects each DirectAppl to a specialised version of the callee (from its signature_filter) that accepts the arg types (step 5).
+ * The return value is true if the program got changed, or false otherwise.
+ */
+fn redirect_to_specialisations(program: &mut Program) -> bool {
+    let (_, result_types) = propagate::signatures(program);
+    let filters: Box<[Vec<_>]> = program
+        .imports
+        .iter()
+        .map(|_| Vec::new())
+        .chain(program.funcs.iter().map(|func| {
+            func.signature_filter
+                .iter()
+                .map(|(params, _, funcidx)| (params.clone(), *funcidx))
+                .collect()
+        }))
+        .collect();
+    let mut changed = false;
+    for func in &mut program.funcs {
+        walk_expr_mut(&mut func.expr, func.params.len(), &mut |expr, _| {
+            let new_funcidx: Option<FuncIdx> =
+                if let ExprKind::DirectAppl { funcidx, args } = &expr.kind {
+                    args.iter()
+                        .map(|arg| arg.vartype)
+                        .collect::<Option<Box<[VarType]>>>()
+                        .and_then(|sig| {
+                            filters[*funcidx]
+                                .iter()
+                                .find(|(params, _)| params.superset(&sig))
+                                .map(|(_, new_funcidx)| *new_funcidx)
+                        })
+                } else {
+                    None
+                };
+            if let Some(new_funcidx) = new_funcidx {
+                if let ExprKind::DirectAppl { funcidx, args: _ } = &mut expr.kind {
+                    *funcidx = new_funcidx;
+                }
+                expr.vartype = result_types[new_funcidx];
+                changed = true;
+            }
+        });
+    }
+    changed
+}
+
+/**
+ * Infers result types by assuming that all functions that make direct calls never return,
+ * and then widening the assumptions until propagation agrees with them (i.e. finding the least fixed point) (step 6).
+ * The functions whose result type got narrower are replaced by their propagated versions.
+ * The return value is true if the program got changed, or false otherwise.
+ */
+fn infer_results(program: &mut Program) -> bool {
+    // the entry point must keep returning Any, because that is what the host expects
+    let candidates: Vec<FuncIdx> = program
+        .funcs
+        .iter()
+        .enumerate()
+        .map(|(i, func)| (program.imports.len() + i, func))
+        .filter(|(funcidx, func)| {
+            *funcidx != program.entry_point
+                && func.result == Some(VarType::Any)
+                && has_direct_appl(&func.expr)
+                && !has_tail_direct_appl(&func.expr)
+        })
+        .map(|(funcidx, _)| funcidx)
+        .collect();
+    if candidates.is_empty() {
+        return false;
+    }
+
+    let (param_types, mut result_types) = propagate::signatures(program);
+    for funcidx in &candidates {
+        result_types[*funcidx] = None;
+    }
+    // each assumption can only be widened twice (from None to some type, then to Any), so this terminates
+    let new_funcs: Vec<Func> = loop {
+        let new_funcs: Vec<Func> = candidates
+            .iter()
+            .map(|funcidx| {
+                propagate::optimize_func_copy(
+                    program.get_func(*funcidx),
+                    &param_types,
+                    &result_types,
+                )
+            })
+            .collect();
+        let mut stable = true;
+        for (funcidx, new_func) in candidates.iter().zip(new_funcs.iter()) {
+            let widened = union_type(result_types[*funcidx], new_func.result);
+            stable &= !useful_update(&mut result_types[*funcidx], widened);
+        }
+        if stable {
+            break new_funcs;
+        }
+    };
+
+    let mut changed = false;
+    for (funcidx, new_func) in candidates.into_iter().zip(new_funcs) {
+        if let Some(vartype) = result_types[funcidx] {
+            if vartype != VarType::Any {
+                *program.get_func_mut(funcidx) = new_func;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+fn has_direct_appl(expr: &Expr) -> bool {
+    let mut ret = false;
+    walk_expr(expr, 0, &mut |expr2, _| {
+        if let ExprKind::DirectAppl {
+            funcidx: _,
+            args: _,
+        } = &expr2.kind
+        {
+            ret = true;
+        }
+    });
+    ret
+}
+
+/**
+ * Returns true if there is a DirectAppl in tail position (i.e. whose result is returned immediately) anywhere in the expr.
+ */
+fn has_tail_direct_appl(expr: &Expr) -> bool {
+    fn is_tail_direct_appl(expr: &Expr) -> bool {
+        match &expr.kind {
+            ExprKind::DirectAppl {
+                funcidx: _,
+                args: _,
+            } => true,
+            ExprKind::Conditional {
+                cond: _,
+                true_expr,
+                false_expr,
+            } => is_tail_direct_appl(true_expr) || is_tail_direct_appl(false_expr),
+            ExprKind::Declaration {
+                local: _,
+                init: _,
+                contained_expr,
+            } => is_tail_direct_appl(contained_expr),
+            _ => false,
+        }
+    }
+    let mut ret = false;
+    walk_expr(expr, 0, &mut |expr2, _| {
+        if let ExprKind::Return { expr: expr3 } = &expr2.kind {
+            ret |= is_tail_direct_appl(expr3);
+        }
+    });
+    ret
+}
+
+fn make_prim_undefined() -> Expr {
+    Expr {
+        vartype: Some(VarType::Undefined),
+        kind: ExprKind::PrimUndefined,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interp;
+
+    /**
+     * Host that rejects every import (the test programs have none).
+     */
+    struct NoHost;
+
+    impl interp::Host for NoHost {
+        fn call_import(
+            &mut self,
+            import: &Import,
+            _args: &[interp::Value],
+        ) -> Result<interp::Value, interp::Trap> {
+            panic!("unexpected import {}", import.entity_name);
+        }
+    }
+
+    // function f(x) { return x <= 0 ? 1 : f(x - 1) + f(x - 1); }
+    // f(10);
+    const DOUBLE_RECURSION: &str = r#"
+        struct ()
+        global any
+        func "f" (struct#0, any) -> any {
+          (typecast:void number narrow (var:any local 1)
+            (return:void (if:any (prim:boolean number_le (var:number local 2) (number:number 0.0))
+              (number:number 1.0)
+              (typecast:any number narrow
+                (appl:any (typecast:func func narrow (var:any global 0) (var:func local 3) (trap:void 22))
+                  [(prim:number number_sub (var:number local 2) (number:number 1.0))])
+                (typecast:any number narrow
+                  (appl:any (typecast:func func narrow (var:any global 0) (var:func local 4) (trap:void 22))
+                    [(prim:number number_sub (var:number local 2) (number:number 1.0))])
+                  (prim:number number_add (var:number local 3) (var:number local 4))
+                  (trap:void 17))
+                (trap:void 17))))
+            (trap:void 17))
+        }
+        func () -> any {
+          (seq:any
+            (assign:undefined global 0 (let:func struct#0 = (struct:struct#0 0)
+              (func:func [0 closure] (var:struct#0 local 0))))
+            (appl:any (typecast:func func narrow (var:any global 0) (var:func local 0) (trap:void 22))
+              [(number:number 10.0)]))
+        }
+        entry 1
+        "#;
+
+    /**
+     * Returns the signature of every function with the given name.
+     */
+    fn signatures_named(program: &Program, name: &str) -> Vec<(Box<[VarType]>, Option<VarType>)> {
+        program
+            .funcs
+            .iter()
+            .filter(|func| func.name.as_deref() == Some(name))
+            .map(|func| (func.params.clone(), func.result))
+            .collect()
+    }
+
+    /**
+     * Returns the number of Appls in the program.
+     */
+    fn count_appls(program: &Program) -> usize {
+        let mut ret = 0;
+        for func in &program.funcs {
+            walk_expr(&func.expr, func.params.len(), &mut |expr, _| {
+                if let ExprKind::Appl { .. } = expr.kind {
+                    ret += 1;
+                }
+            });
+        }
+        ret
+    }
+
+    #[test]
+    fn narrows_recursive_function() {
+        let program: Program = DOUBLE_RECURSION.parse().unwrap();
+        // without inlining, so that f is still there afterwards
+        let optimized = opt::optimize(
+            DOUBLE_RECURSION.parse().unwrap(),
+            &OptOptions::new().pass(Pass::Inline, false),
+        );
+        // f is called directly, without its closure, and only with numbers
+        assert_eq!(
+            signatures_named(&optimized, "f"),
+            vec![(
+                Box::new([VarType::Undefined, VarType::Number]) as Box<[VarType]>,
+                Some(VarType::Number)
+            )]
+        );
+        assert_eq!(count_appls(&optimized), 0);
+        assert_eq!(
+            interp::run(&optimized, &mut NoHost),
+            Ok(interp::Value::Number(1024.0))
+        );
+        assert_eq!(
+            interp::run(&program, &mut NoHost),
+            Ok(interp::Value::Number(1024.0))
+        );
+        // at O1, the signature is left alone
+        let optimized = opt::optimize(
+            DOUBLE_RECURSION.parse().unwrap(),
+            &OptOptions::new()
+                .level(OptLevel::O1)
+                .pass(Pass::Inline, false),
+        );
+        assert_eq!(
+            signatures_named(&optimized, "f"),
+            vec![(
+                Box::new([VarType::StructT { typeidx: 0 }, VarType::Any]) as Box<[VarType]>,
+                Some(VarType::Number)
+            )]
+        );
+    }
+
+    #[test]
+    fn specialises_when_call_sites_disagree() {
+        // function g(x) { return is_number(x) ? x + 1 : x; }
+        // g(1) + (is_string(g("a")) ? 10 : 0);
+        let text = r#"
+            struct ()
+            global any
+            func "g" (struct#0, any) -> any {
+              (return:void (typecast:any number narrow (var:any local 1)
+                (prim:number number_add (var:number local 2) (number:number 1.0))
+                (var:any local 1)))
+            }
+            func () -> any {
+              (seq:any
+                (assign:undefined global 0 (let:func struct#0 = (struct:struct#0 0)
+                  (func:func [0 closure] (var:struct#0 local 0))))
+                (typecast:any number narrow
+                  (appl:any (typecast:func func narrow (var:any global 0) (var:func local 0) (trap:void 22))
+                    [(number:number 1.0)])
+                  (typecast:any string narrow
+                    (appl:any (typecast:func func narrow (var:any global 0) (var:func local 1) (trap:void 22))
+                      [(string:string "a")])
+                    (prim:number number_add (var:number local 0) (number:number 10.0))
+                    (number:number 0.0))
+                  (trap:void 17)))
+            }
+            entry 1
+            "#;
+        let optimized = opt::optimize(
+            text.parse().unwrap(),
+            &OptOptions::new().pass(Pass::Inline, false),
+        );
+        // each call site gets its own version of g, and the original one is no longer used
+        assert_eq!(
+            signatures_named(&optimized, "g"),
+            vec![
+                (
+                    Box::new([VarType::Undefined, VarType::Number]) as Box<[VarType]>,
+                    Some(VarType::Number)
+                ),
+                (
+                    Box::new([VarType::Undefined, VarType::String]) as Box<[VarType]>,
+                    Some(VarType::String)
+                )
+            ]
+        );
+        assert_eq!(count_appls(&optimized), 0);
+        assert_eq!(
+            interp::run(&optimized, &mut NoHost),
+            Ok(interp::Value::Number(12.0))
+        );
+        assert_eq!(
+            interp::run(&text.parse().unwrap(), &mut NoHost),
+            Ok(interp::Value::Number(12.0))
+        );
+    }
+
+    #[test]
+    fn devirtualizes_declared_prim_func_callee() {
+        // the callee is a PrimFunc that is only reachable through a Declaration, as left behind by the typecast pass
+        let text = r#"
+            func "inc" (number) -> number {
+              (prim:number number_add (var:number local 0) (number:number 1.0))
+            }
+            func "inc" (string) -> string {
+              (var:string local 0)
+            }
+            func () -> any {
+              (appl:any (let:func func = (func:func [0, 1] (undefined:undefined)) (var:func local 0))
+                [(number:number 1.0)])
+            }
+            entry 2
+            "#;
+        let optimized = opt::optimize(
+            text.parse().unwrap(),
+            &OptOptions::new().pass(Pass::Inline, false),
+        );
+        assert_eq!(count_appls(&optimized), 0);
+        assert_eq!(
+            interp::run(&optimized, &mut NoHost),
+            Ok(interp::Value::Number(2.0))
+        );
+        // without interproc, the Appl stays
+        let optimized = opt::optimize(
+            text.parse().unwrap(),
+            &OptOptions::new()
+                .pass(Pass::Inline, false)
+                .pass(Pass::Interproc, false),
+        );
+        assert_eq!(count_appls(&optimized), 1);
+    }
+
+    #[test]
+    fn infers_result_of_recursive_function() {
+        // function down(n) { if (n <= 0) { return 0; } else { const r = down(n - 1); return r; } }
+        // down(5);
+        // the recursive call is not in tail position, so the result type depends on itself
+        let text = r#"
+            func "down" (number) -> any {
+              (return:void (if:any (prim:boolean number_le (var:number local 0) (number:number 0.0))
+                (number:number 0.0)
+                (let:any any = (direct:any 0 (prim:number number_sub (var:number local 0) (number:number 1.0)))
+                  (var:any local 1))))
+            }
+            func () -> any {
+              (direct:any 0 (number:number 5.0))
+            }
+            entry 1
+            "#;
+        let optimized = opt::optimize(
+            text.parse().unwrap(),
+            &OptOptions::new().pass(Pass::Inline, false),
+        );
+        assert_eq!(
+            signatures_named(&optimized, "down"),
+            vec![(
+                Box::new([VarType::Number]) as Box<[VarType]>,
+                Some(VarType::Number)
+            )]
+        );
+        assert_eq!(
+            interp::run(&optimized, &mut NoHost),
+            Ok(interp::Value::Number(0.0))
+        );
+    }
+
+    #[test]
+    fn no_clone_for_unconvertible_call_site() {
+        // the only call site passes an any to a number param, so it must stay an Appl (which typechecks the arg),
+        // and the closure-less clone of f must not be added (otherwise the pipeline would never reach a fixpoint)
+        let text = r#"
+            struct ()
+            import "misc" "get" () -> any
+            global any
+            func "f" (struct#0, number) -> number {
+              (var:number local 1)
+            }
+            func () -> any {
+              (seq:any
+                (assign:undefined global 0 (let:func struct#0 = (struct:struct#0 0)
+                  (func:func [1 closure] (var:struct#0 local 0))))
+                (appl:any (typecast:func func narrow (var:any global 0) (var:func local 0) (trap:void 22))
+                  [(direct:any 0)]))
+            }
+            entry 2
+            "#;
+        let (optimized, _) = opt::optimize_with_stats(text.parse().unwrap(), &OptOptions::new());
+        assert_eq!(optimized.funcs.len(), 2);
+        assert_eq!(count_appls(&optimized), 1);
+        let (optimized, _) = opt::optimize_with_stats(
+            text.parse().unwrap(),
+            &OptOptions::new().pass(Pass::DeadCode, false),
+        );
+        assert_eq!(optimized.funcs.len(), 2);
+    }
+}
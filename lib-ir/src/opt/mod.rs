@@ -1,22 +1,64 @@
+mod dead_code;
 mod inline;
+mod interproc;
 mod landing_context;
+mod options;
 mod propagate;
 mod relabeller;
+mod stats;
 mod typecast;
 mod unreachable;
+mod walk;
 
 use super::*;
+pub use options::*;
+use stats::run_pass;
+pub use stats::{OptStats, PassStats};
 
 /**
  * Main function to do mandatory optimizations for a program.
  * Mandatory optimizations are those that are required for the IR to function correctly.
  */
-pub fn optimize_mandatory(mut program: Program) -> Program {
+pub fn optimize_mandatory(program: Program) -> Program {
+    run_mandatory(program, &mut None)
+}
+
+/**
+ * Main function to do discretionary optimizations for a program, with the default options (O2).
+ * Like `optimize_mandatory()`, this does not run the other kind of optimizations; use `optimize()` to run both.
+ */
+pub fn optimize_all(program: Program) -> Program {
+    debug_verify(&program, "frontend", verify_structure);
+    run_discretionary(program, &OptOptions::new(), &mut None)
+}
+
+/**
+ * Runs the mandatory optimizations, and then the discretionary passes enabled in `options` until none of them changes the program.
+ */
+pub fn optimize(program: Program, options: &OptOptions) -> Program {
+    run_pipeline(program, options, &mut None)
+}
+
+/**
+ * Like `optimize()`, but also returns the statistics of every pass that was run.
+ * Note: This uses `std::time::Instant`, so it cannot be used on wasm32-unknown-unknown.
+ */
+pub fn optimize_with_stats(program: Program, options: &OptOptions) -> (Program, OptStats) {
+    let mut stats = OptStats::default();
+    let program = run_pipeline(program, options, &mut Some(&mut stats));
+    (program, stats)
+}
+
+fn run_mandatory(mut program: Program, stats: &mut Option<&mut OptStats>) -> Program {
     let mut n: usize = 0;
     const TOTAL: usize = 2;
+    let mut iteration: usize = 0;
     loop {
         {
-            let (new_program, changed) = unreachable::optimize(program);
+            let (new_program, changed) =
+                run_pass(program, "unreachable", iteration, stats, |program| {
+                    (unreachable::optimize(program), 0)
+                });
             program = new_program;
             if changed {
                 n = 1;
@@ -28,7 +70,10 @@ pub fn optimize_mandatory(mut program: Program) -> Program {
             }
         }
         {
-            let (new_program, changed) = typecast::optimize(program);
+            let (new_program, changed) =
+                run_pass(program, "typecast", iteration, stats, |program| {
+                    (typecast::optimize(program), 0)
+                });
             program = new_program;
             if changed {
                 n = 1;
@@ -39,47 +84,98 @@ pub fn optimize_mandatory(mut program: Program) -> Program {
                 break;
             }
         }
+        iteration += 1;
     }
 
     program
 }
 
+fn run_pipeline(
+    mut program: Program,
+    options: &OptOptions,
+    stats: &mut Option<&mut OptStats>,
+) -> Program {
+    debug_verify(&program, "frontend", verify_structure);
+    program = run_mandatory(program, stats);
+    debug_verify(&program, "mandatory passes", verify_between_passes);
+    program = run_discretionary(program, options, stats);
+    debug_verify(&program, "optimize", verify);
+    program
+}
+
 /**
- * Main function to do discretionary optimizations for a program.
+ * Runs the discretionary passes enabled in `options` until none of them changes the program.
  */
-pub fn optimize_all(mut program: Program) -> Program {
+fn run_discretionary(
+    mut program: Program,
+    options: &OptOptions,
+    stats: &mut Option<&mut OptStats>,
+) -> Program {
+    let passes: Vec<Pass> = Pass::ALL
+        .iter()
+        .copied()
+        .filter(|pass| options.get_pass(*pass))
+        // interproc narrows the types of params, and relies on propagate to update the exprs that use them
+        .filter(|pass| *pass != Pass::Interproc || options.get_pass(Pass::Propagate))
+        .collect();
     let mut n: usize = 0;
-    const TOTAL: usize = 2;
-    loop {
-        {
-            let (new_program, changed) = propagate::optimize(program);
+    let mut iteration: usize = 0;
+    while n < passes.len() {
+        for pass in passes.iter().copied() {
+            let (new_program, changed) = run_pass(
+                program,
+                pass.name(),
+                iteration,
+                stats,
+                |program| match pass {
+                    Pass::DeadCode => (dead_code::optimize(program), 0),
+                    Pass::Propagate => (propagate::optimize(program), 0),
+                    Pass::Inline => {
+                        let (program, num_inlined) =
+                            inline::optimize(program, options.get_inlining_budget());
+                        ((program, num_inlined > 0), num_inlined)
+                    }
+                    Pass::Interproc => (interproc::optimize(program), 0),
+                },
+            );
             program = new_program;
+            debug_verify(&program, pass.name(), verify_between_passes);
             if changed {
                 n = 0;
             } else {
                 n += 1;
             }
-            if n == TOTAL {
-                break;
-            }
-        }
-        {
-            let (new_program, changed) = inline::optimize(program);
-            program = new_program;
-            if changed {
-                n = 0;
-            } else {
-                n += 1;
-            }
-            if n == TOTAL {
+            if n == passes.len() {
                 break;
             }
         }
+        iteration += 1;
     }
 
     program
 }
 
+/**
+ * In debug builds, checks that the program is still valid after the given pass (using `verifier`, which is `verify`, `verify_between_passes` or `verify_structure`),
+ * so that a pass that breaks an invariant is caught immediately instead of causing an ICE in the backend.
+ */
+fn debug_verify(
+    program: &Program,
+    pass_name: &str,
+    verifier: fn(&Program) -> Result<(), Vec<VerifyError>>,
+) {
+    if cfg!(debug_assertions) {
+        if let Err(errors) = verifier(program) {
+            let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            panic!(
+                "ICE: IR verification failed after {}:\n{}",
+                pass_name,
+                messages.join("\n")
+            );
+        }
+    }
+}
+
 /**
  * Returns the type wide enough to contain both the given two types.
  */
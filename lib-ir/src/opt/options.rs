@@ -0,0 +1,163 @@
+use std::fmt;
+use std::str::FromStr;
+
+/**
+ * The default cost limit for inlining a function that is called from more than one place.
+ */
+pub const DEFAULT_INLINING_BUDGET: usize = 40;
+
+/**
+ * Predefined sets of discretionary passes:
+ * * O0: no discretionary passes (only the mandatory ones), useful to check if a miscompile is caused by the optimiser
+ * * O1: the passes that are cheap and local to each function (dead_code, propagate, inline)
+ * * O2: all passes, including the interprocedural ones that may clone functions
+ */
+#[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Debug, Default)]
+pub enum OptLevel {
+    O0,
+    O1,
+    #[default]
+    O2,
+}
+
+impl OptLevel {
+    /**
+     * Makes the level from its number (0, 1 or 2).  Numbers larger than 2 are treated as O2.
+     */
+    pub fn from_number(level: u32) -> Self {
+        match level {
+            0 => OptLevel::O0,
+            1 => OptLevel::O1,
+            _ => OptLevel::O2,
+        }
+    }
+}
+
+/**
+ * The discretionary passes, in the order that they are run in each iteration of the pipeline.
+ */
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Pass {
+    DeadCode,
+    Propagate,
+    Inline,
+    Interproc, // only run if Propagate is also enabled
+}
+pub const NUM_PASSES: usize = Pass::Interproc as usize + 1;
+
+impl Pass {
+    pub const ALL: [Pass; NUM_PASSES] = [
+        Pass::DeadCode,
+        Pass::Propagate,
+        Pass::Inline,
+        Pass::Interproc,
+    ];
+    /**
+     * The name used to refer to this pass, e.g. on the command line.
+     */
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::DeadCode => "dead_code",
+            Pass::Propagate => "propagate",
+            Pass::Inline => "inline",
+            Pass::Interproc => "interproc",
+        }
+    }
+    fn min_level(self) -> OptLevel {
+        match self {
+            Pass::DeadCode | Pass::Propagate | Pass::Inline => OptLevel::O1,
+            Pass::Interproc => OptLevel::O2,
+        }
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Pass {
+    type Err = String;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Pass::ALL
+            .iter()
+            .copied()
+            .find(|pass| pass.name() == name)
+            .ok_or_else(|| format!("Unknown optimization pass \"{}\"", name))
+    }
+}
+
+// Struct containing optimization options
+// The default options are O2 with the default inlining budget.
+// Use the builder methods to change them, e.g. `OptOptions::new().level(OptLevel::O1).pass(Pass::Inline, false)`.
+// The mandatory passes are always run, regardless of these options.
+#[derive(Copy, Clone, Debug)]
+pub struct OptOptions {
+    enabled_passes: [bool; NUM_PASSES], // indexed by `Pass as usize`
+    inlining_budget: usize, // Largest cost of a function that may be copied into all its callers
+}
+
+impl Default for OptOptions {
+    fn default() -> Self {
+        Self {
+            enabled_passes: [true; NUM_PASSES],
+            inlining_budget: DEFAULT_INLINING_BUDGET,
+        }
+    }
+}
+
+impl OptOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /**
+     * Enables exactly the passes of the given level.
+     * Note: This overrides any earlier calls to `pass()`, so it should be called first.
+     */
+    pub fn level(mut self, level: OptLevel) -> Self {
+        for pass in Pass::ALL.iter().copied() {
+            self.enabled_passes[pass as usize] = level >= pass.min_level();
+        }
+        self
+    }
+    pub fn pass(mut self, pass: Pass, enable: bool) -> Self {
+        self.enabled_passes[pass as usize] = enable;
+        self
+    }
+    pub fn inlining_budget(mut self, budget: usize) -> Self {
+        self.inlining_budget = budget;
+        self
+    }
+    pub fn get_pass(&self, pass: Pass) -> bool {
+        self.enabled_passes[pass as usize]
+    }
+    pub fn get_inlining_budget(&self) -> usize {
+        self.inlining_budget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_and_pass_names() {
+        let o1 = OptOptions::new().level(OptLevel::O1);
+        assert!(o1.get_pass(Pass::Inline));
+        assert!(!o1.get_pass(Pass::Interproc));
+        let o0 = OptOptions::new()
+            .level(OptLevel::O0)
+            .pass(Pass::DeadCode, true);
+        let enabled: Vec<Pass> = Pass::ALL
+            .iter()
+            .copied()
+            .filter(|p| o0.get_pass(*p))
+            .collect();
+        assert_eq!(enabled, vec![Pass::DeadCode]);
+        for pass in Pass::ALL.iter().copied() {
+            assert_eq!(pass.name().parse::<Pass>(), Ok(pass));
+        }
+        assert!("constant_folding".parse::<Pass>().is_err());
+    }
+}
@@ -3,6 +3,7 @@ use super::relabeller::Relabeller;
 use super::superset::*;
 use super::union_type;
 use super::useful_update;
+use super::walk::*;
 use super::*;
 use itertools::Itertools;
 use projstd::iter::*;
@@ -14,6 +15,23 @@ use projstd::iter::*;
  */
 pub fn optimize(mut program: Program) -> (Program, bool) {
     let mut changed = false;
+    let (param_types, result_types) = signatures(&program);
+    for func in &mut program.funcs {
+        changed |= optimize_func(
+            func,
+            Context {
+                param_types: &param_types,
+                result_types: &result_types,
+            },
+        );
+    }
+    (program, changed)
+}
+
+/**
+ * Returns the param types and result type of each FuncIdx (including imports).
+ */
+pub(super) fn signatures(program: &Program) -> (Box<[Box<[VarType]>]>, Box<[Option<VarType>]>) {
     let param_types: Box<[Box<[VarType]>]> = program
         .imports
         .iter()
@@ -26,16 +44,33 @@ pub fn optimize(mut program: Program) -> (Program, bool) {
         .map(|import| Some(import.result.into()))
         .chain(program.funcs.iter().map(|func| func.result))
         .collect();
-    for func in &mut program.funcs {
-        changed |= optimize_func(
-            func,
-            Context {
-                param_types: &param_types,
-                result_types: &result_types,
-            },
-        );
-    }
-    (program, changed)
+    (param_types, result_types)
+}
+
+/**
+ * Returns a copy of the function that has been propagated until it is stable,
+ * assuming that each FuncIdx has the given signature (which need not be the same as in the program).
+ * This lets other optimisations try out assumptions without modifying the function itself.
+ */
+pub(super) fn optimize_func_copy(
+    func: &Func,
+    param_types: &[Box<[VarType]>],
+    result_types: &[Option<VarType>],
+) -> Func {
+    let mut ret = Func {
+        name: func.name.clone(),
+        params: func.params.clone(),
+        param_names: func.param_names.clone(),
+        result: func.result,
+        expr: func.expr.clone(),
+        signature_filter: func.signature_filter.clone(),
+    };
+    let ctx = Context {
+        param_types: param_types,
+        result_types: result_types,
+    };
+    while optimize_func(&mut ret, ctx) {}
+    ret
 }
 
 #[derive(Copy, Clone)]
@@ -93,6 +128,10 @@ fn optimize_expr(
             assert!(expr.vartype == Some(VarType::Undefined));
             false
         }
+        ExprKind::PrimNull => {
+            assert!(expr.vartype == Some(VarType::Null));
+            false
+        }
         ExprKind::PrimNumber { val: _ } => {
             assert!(expr.vartype == Some(VarType::Number));
             false
@@ -301,7 +340,7 @@ fn optimize_expr(
             }
         }
         ExprKind::Declaration {
-            local: _,
+            local,
             init,
             contained_expr,
         } => {
@@ -317,8 +356,9 @@ fn optimize_expr(
                 true
             } else {
                 let real_res = init_res
-                    | local_map.with_entry(|local_map, _, _| {
+                    | local_map.with_entry(|local_map, _, new_localidx| {
                         optimize_expr(&mut **contained_expr, local_map, ctx, landing_ctx)
+                            | narrow_declared_local(local, init, contained_expr, new_localidx)
                     });
                 real_res | useful_update(&mut expr.vartype, contained_expr.vartype)
             }
@@ -395,6 +435,13 @@ fn optimize_expr(
                 )
             }
         }
+        ExprKind::Loop { expr: expr2 } => {
+            // Breaks that target this loop re-enter it, so they do not contribute to the result type
+            let (ret, _) = landing_ctx.with_landing(|landing_ctx| {
+                optimize_expr(&mut **expr2, local_map, ctx, landing_ctx)
+            });
+            ret | useful_update(&mut expr.vartype, expr2.vartype)
+        }
         ExprKind::Sequence { content } => {
             let tmp_content = std::mem::take(content);
             let mut changed = false;
@@ -432,6 +479,7 @@ fn optimize_expr(
             assert!(expr.vartype == None);
             false
         }
+        ExprKind::SourceMarker { location: _ } => false,
     }
 }
 
@@ -732,6 +780,10 @@ fn try_const_eval(expr: &mut Expr) -> bool {
                     set_vartype(&mut expr.vartype, VarType::Boolean)
                 }
             }
+            // arrays live on the heap, so they can never be evaluated at compile time
+            PrimInst::ArrayNew => set_vartype(&mut expr.vartype, VarType::Array),
+            PrimInst::ArrayGet => set_vartype(&mut expr.vartype, VarType::Any),
+            PrimInst::ArraySet => set_vartype(&mut expr.vartype, VarType::Undefined),
         }
     } else {
         panic!("Expected PrimAppl");
@@ -765,7 +817,7 @@ fn try_devirtualize_appl(
             let overloads = std::mem::take(funcidxs);
             let mut allowable_overloads: Vec<OverloadEntry> = Vec::new();
             // iterate in the reverse direction, since we match them from back to front
-            'outer: for overload in Vec::from(overloads).into_iter() {
+            'outer: for overload in Vec::from(overloads).into_iter().rev() {
                 let sig: &[VarType] = &ctx.param_types[overload.funcidx];
                 if sig.len() != args.len() {
                     // wrong number of params, will never be matched
@@ -954,7 +1006,8 @@ fn try_devirtualize_appl(
                                 arg_localidxs[idx] = orig_arg_localidx;
                                 let tmp_seq = make_sequence_from_exprs(new_out);
                                 out.push(Expr {
-                                    vartype: tmp_seq.vartype,
+                                    // the false branch falls through to the next check
+                                    vartype: union_type(tmp_seq.vartype, Some(VarType::Undefined)),
                                     kind: ExprKind::TypeCast {
                                         test: Box::new(Expr {
                                             vartype: args[idx].vartype,
@@ -1034,6 +1087,10 @@ fn try_devirtualize_appl(
                                 )
                             });
 
+                            // the reads of this local in inner_expr were made with the current type of arg,
+                            // so the local should keep that type even if reoptimizing makes arg narrower
+                            let arg_vartype = arg.vartype.unwrap();
+
                             // reoptimize arg, to get the locals re-numbered
                             // hopefully this is not too slow (since each call can only be converted to direct once)
                             optimize_expr(&mut arg, local_map, ctx, landing_ctx);
@@ -1041,7 +1098,7 @@ fn try_devirtualize_appl(
                             Expr {
                                 vartype: inner_expr.vartype,
                                 kind: ExprKind::Declaration {
-                                    local: arg.vartype.unwrap(),
+                                    local: arg_vartype,
                                     init: Some(Box::new(arg)),
                                     contained_expr: Box::new(inner_expr),
                                 },
@@ -1095,6 +1152,30 @@ fn try_devirtualize_appl(
     }
 }
 
+/**
+ * Narrows a local declared as Any to the type of its initializer, if the local is never assigned afterwards.
+ * Reads of the local in the contained expr are narrowed too, so that later iterations can propagate the type further.
+ * The return value is true if the local got narrowed.
+ */
+fn narrow_declared_local(
+    local: &mut VarType,
+    init: &Option<Box<Expr>>,
+    contained_expr: &mut Expr,
+    localidx: usize,
+) -> bool {
+    if *local != VarType::Any {
+        return false;
+    }
+    match init.as_ref().and_then(|init_expr| init_expr.vartype) {
+        Some(vartype) if vartype != VarType::Any && !assigns_local(contained_expr, localidx) => {
+            *local = vartype;
+            retype_local(contained_expr, localidx, vartype);
+            true
+        }
+        _ => false,
+    }
+}
+
 fn make_sequence_from_exprs(exprs: Vec<Expr>) -> Expr {
     match exprs.len() {
         0 => make_prim_undefined(),
@@ -1121,7 +1202,7 @@ fn intersect_signature(
         .ok()
 }
 
-fn intersect_type(first: VarType, second: VarType) -> Option<VarType> {
+pub(super) fn intersect_type(first: VarType, second: VarType) -> Option<VarType> {
     if first == VarType::Any {
         return Some(second);
     }
@@ -1170,3 +1251,81 @@ fn make_prim_string(val: String) -> Expr {
         kind: ExprKind::PrimString { val: val },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_overload(params: &[VarType], val: f64) -> Func {
+        let mut func = Func::new_with_params_and_result(params, VarType::Number);
+        func.expr = Expr {
+            vartype: Some(VarType::Number),
+            kind: ExprKind::PrimNumber { val: val },
+        };
+        func
+    }
+
+    /**
+     * Finds the DirectAppl that devirtualizing a single Appl leaves behind (inside a Block and the Declarations of its args).
+     */
+    fn find_direct_appl(expr: &Expr) -> Option<FuncIdx> {
+        match &expr.kind {
+            ExprKind::DirectAppl { funcidx, args: _ } => Some(*funcidx),
+            ExprKind::Block { expr: inner }
+            | ExprKind::Break {
+                num_frames: _,
+                expr: inner,
+            } => find_direct_appl(inner),
+            ExprKind::Declaration {
+                local: _,
+                init: _,
+                contained_expr,
+            } => find_direct_appl(contained_expr),
+            _ => None,
+        }
+    }
+
+    fn make_prim_null() -> Expr {
+        Expr {
+            vartype: Some(VarType::Null),
+            kind: ExprKind::PrimNull,
+        }
+    }
+
+    #[test]
+    fn devirtualized_appl_prefers_later_overloads() {
+        // overloads are matched from back to front, so the (null, null) overload must win over the (any, any) one
+        // (the frontend relies on this by registering the more specific overloads last, e.g. for `===` on nulls)
+        let mut program = Program::new_with_imports(Box::new([]));
+        program.add_func(make_overload(&[VarType::Any, VarType::Any], 0.0));
+        program.add_func(make_overload(&[VarType::Null, VarType::Null], 1.0));
+        let mut main = Func::new_with_params_and_result(&[], VarType::Any);
+        main.expr = Expr {
+            vartype: Some(VarType::Any),
+            kind: ExprKind::Appl {
+                func: Box::new(Expr {
+                    vartype: Some(VarType::Func),
+                    kind: ExprKind::PrimFunc {
+                        funcidxs: Box::new([
+                            OverloadEntry {
+                                funcidx: 0,
+                                has_closure_param: false,
+                            },
+                            OverloadEntry {
+                                funcidx: 1,
+                                has_closure_param: false,
+                            },
+                        ]),
+                        closure: Box::new(make_prim_undefined()),
+                    },
+                }),
+                args: Box::new([make_prim_null(), make_prim_null()]),
+                location: Default::default(),
+            },
+        };
+        program.entry_point = program.add_func(main);
+        let (optimized, changed) = optimize(program);
+        assert!(changed);
+        assert_eq!(find_direct_appl(&optimized.funcs[2].expr), Some(1));
+    }
+}
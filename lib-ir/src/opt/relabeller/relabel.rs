@@ -9,6 +9,7 @@ pub fn relabel(expr: &mut Expr, relabeller: &mut Relabeller) -> bool {
     // Note: we explicitly list out all possibilities so we will get a compile error if a new exprkind is added.
     match &mut expr.kind {
         ExprKind::PrimUndefined
+        | ExprKind::PrimNull
         | ExprKind::PrimNumber { val: _ }
         | ExprKind::PrimBoolean { val: _ }
         | ExprKind::PrimStructT { typeidx: _ }
@@ -79,6 +80,7 @@ pub fn relabel(expr: &mut Expr, relabeller: &mut Relabeller) -> bool {
             expr,
         } => relabel(&mut **expr, relabeller),
         ExprKind::Block { expr } => relabel(&mut **expr, relabeller),
+        ExprKind::Loop { expr } => relabel(&mut **expr, relabeller),
         ExprKind::Sequence { content } => content
             .iter_mut()
             .fold(false, |prev, expr| prev | relabel(expr, relabeller)),
@@ -86,6 +88,7 @@ pub fn relabel(expr: &mut Expr, relabeller: &mut Relabeller) -> bool {
             code: _,
             location: _,
         } => false,
+        ExprKind::SourceMarker { location: _ } => false,
     }
 }
 
@@ -0,0 +1,192 @@
+use super::walk::walk_expr;
+use super::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use std::time::Instant;
+
+/**
+ * Statistics for one run of one pass.
+ */
+#[derive(Clone, Debug)]
+pub struct PassStats {
+    pub pass: &'static str, // name of the pass, e.g. "typecast" or "inline"
+    pub iteration: usize, // the round of the fixpoint loop (starting from 0) in which the pass was run
+    pub duration: Duration,
+    pub changed: bool, // whether the pass reported that it changed the program
+    pub funcs_changed: usize, // number of funcs after the pass that did not exist (with identical content) before the pass
+    pub nodes_before: usize,
+    pub nodes_after: usize,
+    pub inlined_call_sites: usize,
+    pub typecasts_eliminated: usize, // net decrease in the number of TypeCast exprs
+}
+
+/**
+ * Statistics collected by `optimize_with_stats()`, with one entry for each pass run (including the mandatory passes), in order.
+ * The Display impl prints it as a table, followed by the totals for each pass.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct OptStats {
+    pub passes: Vec<PassStats>,
+}
+
+impl OptStats {
+    pub fn total_duration(&self) -> Duration {
+        self.passes.iter().map(|ps| ps.duration).sum()
+    }
+}
+
+impl fmt::Display for OptStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:>5} {:>10} {:>7} {:>8} {:>8} {:>8} {:>10}",
+            "pass", "iter", "time(us)", "funcs", "before", "after", "inlined", "typecasts"
+        )?;
+        for ps in &self.passes {
+            writeln!(
+                f,
+                "{:<12} {:>5} {:>10} {:>7} {:>8} {:>8} {:>8} {:>10}",
+                ps.pass,
+                ps.iteration,
+                ps.duration.as_micros(),
+                ps.funcs_changed,
+                ps.nodes_before,
+                ps.nodes_after,
+                ps.inlined_call_sites,
+                ps.typecasts_eliminated
+            )?;
+        }
+        // totals for each pass (with the number of runs in the iter column), in the order in which the passes were first run
+        writeln!(f, "totals:")?;
+        let mut names: Vec<&'static str> = Vec::new();
+        for ps in &self.passes {
+            if !names.contains(&ps.pass) {
+                names.push(ps.pass);
+            }
+        }
+        for name in names {
+            let runs = self.passes.iter().filter(|ps| ps.pass == name);
+            let duration: Duration = runs.clone().map(|ps| ps.duration).sum();
+            writeln!(
+                f,
+                "{:<12} {:>5} {:>10} {:>7} {:>8} {:>8} {:>8} {:>10}",
+                name,
+                runs.clone().count(),
+                duration.as_micros(),
+                runs.clone().map(|ps| ps.funcs_changed).sum::<usize>(),
+                "",
+                "",
+                runs.clone().map(|ps| ps.inlined_call_sites).sum::<usize>(),
+                runs.map(|ps| ps.typecasts_eliminated).sum::<usize>()
+            )?;
+        }
+        write!(f, "total time: {}us", self.total_duration().as_micros())
+    }
+}
+
+/**
+ * Runs a pass, recording its statistics in `stats` if it is Some.
+ * `pass_fn` returns the new program, whether it got changed, and the number of call sites that were inlined.
+ */
+pub(super) fn run_pass<F: FnOnce(Program) -> ((Program, bool), usize)>(
+    program: Program,
+    pass: &'static str,
+    iteration: usize,
+    stats: &mut Option<&mut OptStats>,
+    pass_fn: F,
+) -> (Program, bool) {
+    match stats {
+        None => pass_fn(program).0,
+        Some(stats) => {
+            let before = Snapshot::new(&program);
+            let start = Instant::now();
+            let ((program, changed), inlined_call_sites) = pass_fn(program);
+            let duration = start.elapsed();
+            let after = Snapshot::new(&program);
+            stats.passes.push(PassStats {
+                pass: pass,
+                iteration: iteration,
+                duration: duration,
+                changed: changed,
+                funcs_changed: after.funcs_not_in(&before),
+                nodes_before: before.num_nodes,
+                nodes_after: after.num_nodes,
+                inlined_call_sites: inlined_call_sites,
+                typecasts_eliminated: before.num_typecasts.saturating_sub(after.num_typecasts),
+            });
+            (program, changed)
+        }
+    }
+}
+
+/**
+ * The parts of a program that we need to compare to compute the statistics of a pass.
+ */
+struct Snapshot {
+    num_nodes: usize,
+    num_typecasts: usize,
+    funcs: HashMap<String, usize>, // textual representation of each func -> number of funcs with that representation
+}
+
+impl Snapshot {
+    fn new(program: &Program) -> Self {
+        let mut num_nodes = 0;
+        let mut num_typecasts = 0;
+        let mut funcs: HashMap<String, usize> = HashMap::new();
+        for func in &program.funcs {
+            walk_expr(&func.expr, func.params.len(), &mut |expr, _| {
+                num_nodes += 1;
+                if let ExprKind::TypeCast { .. } = expr.kind {
+                    num_typecasts += 1;
+                }
+            });
+            *funcs.entry(func.to_string()).or_insert(0) += 1;
+        }
+        Snapshot {
+            num_nodes: num_nodes,
+            num_typecasts: num_typecasts,
+            funcs: funcs,
+        }
+    }
+
+    /**
+     * Returns the number of funcs in self that do not have an identical func in `other`.
+     */
+    fn funcs_not_in(&self, other: &Snapshot) -> usize {
+        self.funcs
+            .iter()
+            .map(|(text, count)| count.saturating_sub(*other.funcs.get(text).unwrap_or(&0)))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_inlined_call_sites() {
+        let program: Program = r#"
+            import "misc" "display" (number) -> undefined
+            func (number) -> number {
+              (prim:number number_add (var:number local 0) (number:number 1.0))
+            }
+            func () -> undefined {
+              (seq:undefined
+                (direct:undefined 0 (direct:number 1 (number:number 1.0)))
+                (direct:undefined 0 (direct:number 1 (number:number 2.0))))
+            }
+            entry 2
+            "#
+        .parse()
+        .unwrap();
+        let (_, stats) = optimize_with_stats(program, &OptOptions::new().level(OptLevel::O1));
+        let inlined: usize = stats.passes.iter().map(|ps| ps.inlined_call_sites).sum();
+        assert_eq!(inlined, 2);
+        for (ps, next) in stats.passes.iter().zip(stats.passes.iter().skip(1)) {
+            assert_eq!(ps.nodes_after, next.nodes_before);
+        }
+        assert!(stats.passes.iter().any(|ps| ps.pass == "typecast"));
+    }
+}
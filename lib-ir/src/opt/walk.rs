@@ -0,0 +1,219 @@
+use super::*;
+
+/**
+ * Calls `f` on the expr and all its sub-exprs in pre-order, together with the number of locals (including params) in scope at that expr.
+ * Since `f` is called on an expr before its sub-exprs, `f` may replace the expr, and the walk continues into the replacement.
+ */
+pub fn walk_expr_mut<F: FnMut(&mut Expr, usize)>(expr: &mut Expr, num_locals: usize, f: &mut F) {
+    f(expr, num_locals);
+    // Note: we explicitly list out all possibilities so we will get a compile error if a new exprkind is added.
+    match &mut expr.kind {
+        ExprKind::PrimUndefined
+        | ExprKind::PrimNull
+        | ExprKind::PrimNumber { val: _ }
+        | ExprKind::PrimBoolean { val: _ }
+        | ExprKind::PrimStructT { typeidx: _ }
+        | ExprKind::PrimString { val: _ }
+        | ExprKind::VarName { source: _ }
+        | ExprKind::Trap {
+            code: _,
+            location: _,
+        }
+        | ExprKind::SourceMarker { location: _ } => {}
+        ExprKind::PrimFunc {
+            funcidxs: _,
+            closure,
+        } => walk_expr_mut(closure, num_locals, f),
+        ExprKind::TypeCast {
+            test,
+            expected: _,
+            create_narrow_local,
+            true_expr,
+            false_expr,
+        } => {
+            walk_expr_mut(test, num_locals, f);
+            walk_expr_mut(true_expr, num_locals + (*create_narrow_local as usize), f);
+            walk_expr_mut(false_expr, num_locals, f);
+        }
+        ExprKind::PrimAppl { prim_inst: _, args } | ExprKind::DirectAppl { funcidx: _, args } => {
+            for arg in args.iter_mut() {
+                walk_expr_mut(arg, num_locals, f);
+            }
+        }
+        ExprKind::Appl {
+            func,
+            args,
+            location: _,
+        } => {
+            walk_expr_mut(func, num_locals, f);
+            for arg in args.iter_mut() {
+                walk_expr_mut(arg, num_locals, f);
+            }
+        }
+        ExprKind::Conditional {
+            cond,
+            true_expr,
+            false_expr,
+        } => {
+            walk_expr_mut(cond, num_locals, f);
+            walk_expr_mut(true_expr, num_locals, f);
+            walk_expr_mut(false_expr, num_locals, f);
+        }
+        ExprKind::Declaration {
+            local: _,
+            init,
+            contained_expr,
+        } => {
+            if let Some(init_expr) = init {
+                walk_expr_mut(init_expr, num_locals, f);
+            }
+            walk_expr_mut(contained_expr, num_locals + 1, f);
+        }
+        ExprKind::Assign {
+            target: _,
+            expr: expr2,
+        }
+        | ExprKind::Return { expr: expr2 }
+        | ExprKind::Break {
+            num_frames: _,
+            expr: expr2,
+        }
+        | ExprKind::Block { expr: expr2 }
+        | ExprKind::Loop { expr: expr2 } => walk_expr_mut(expr2, num_locals, f),
+        ExprKind::Sequence { content } => {
+            for expr2 in content.iter_mut() {
+                walk_expr_mut(expr2, num_locals, f);
+            }
+        }
+    }
+}
+
+/**
+ * Like `walk_expr_mut`, but for an expr that will not be modified.
+ */
+pub fn walk_expr<F: FnMut(&Expr, usize)>(expr: &Expr, num_locals: usize, f: &mut F) {
+    f(expr, num_locals);
+    // Note: we explicitly list out all possibilities so we will get a compile error if a new exprkind is added.
+    match &expr.kind {
+        ExprKind::PrimUndefined
+        | ExprKind::PrimNull
+        | ExprKind::PrimNumber { val: _ }
+        | ExprKind::PrimBoolean { val: _ }
+        | ExprKind::PrimStructT { typeidx: _ }
+        | ExprKind::PrimString { val: _ }
+        | ExprKind::VarName { source: _ }
+        | ExprKind::Trap {
+            code: _,
+            location: _,
+        }
+        | ExprKind::SourceMarker { location: _ } => {}
+        ExprKind::PrimFunc {
+            funcidxs: _,
+            closure,
+        } => walk_expr(closure, num_locals, f),
+        ExprKind::TypeCast {
+            test,
+            expected: _,
+            create_narrow_local,
+            true_expr,
+            false_expr,
+        } => {
+            walk_expr(test, num_locals, f);
+            walk_expr(true_expr, num_locals + (*create_narrow_local as usize), f);
+            walk_expr(false_expr, num_locals, f);
+        }
+        ExprKind::PrimAppl { prim_inst: _, args } | ExprKind::DirectAppl { funcidx: _, args } => {
+            for arg in args.iter() {
+                walk_expr(arg, num_locals, f);
+            }
+        }
+        ExprKind::Appl {
+            func,
+            args,
+            location: _,
+        } => {
+            walk_expr(func, num_locals, f);
+            for arg in args.iter() {
+                walk_expr(arg, num_locals, f);
+            }
+        }
+        ExprKind::Conditional {
+            cond,
+            true_expr,
+            false_expr,
+        } => {
+            walk_expr(cond, num_locals, f);
+            walk_expr(true_expr, num_locals, f);
+            walk_expr(false_expr, num_locals, f);
+        }
+        ExprKind::Declaration {
+            local: _,
+            init,
+            contained_expr,
+        } => {
+            if let Some(init_expr) = init {
+                walk_expr(init_expr, num_locals, f);
+            }
+            walk_expr(contained_expr, num_locals + 1, f);
+        }
+        ExprKind::Assign {
+            target: _,
+            expr: expr2,
+        }
+        | ExprKind::Return { expr: expr2 }
+        | ExprKind::Break {
+            num_frames: _,
+            expr: expr2,
+        }
+        | ExprKind::Block { expr: expr2 }
+        | ExprKind::Loop { expr: expr2 } => walk_expr(expr2, num_locals, f),
+        ExprKind::Sequence { content } => {
+            for expr2 in content.iter() {
+                walk_expr(expr2, num_locals, f);
+            }
+        }
+    }
+}
+
+/**
+ * Returns true if the given local is the target of an Assign anywhere in the expr.
+ * Assignments to a field of the local (i.e. with `next`) do not count, because they do not change the type of the local.
+ */
+pub fn assigns_local(expr: &Expr, localidx: usize) -> bool {
+    let mut ret = false;
+    walk_expr(expr, 0, &mut |expr2, _| {
+        if let ExprKind::Assign {
+            target:
+                TargetExpr::Local {
+                    localidx: target_localidx,
+                    next: None,
+                },
+            expr: _,
+        } = &expr2.kind
+        {
+            ret |= *target_localidx == localidx;
+        }
+    });
+    ret
+}
+
+/**
+ * Narrows the type of all reads (that are currently Any) of the given local in the expr.
+ * The caller must ensure that the local always contains a value of the given type.
+ */
+pub fn retype_local(expr: &mut Expr, localidx: usize, vartype: VarType) {
+    walk_expr_mut(expr, 0, &mut |expr2, _| {
+        if let ExprKind::VarName {
+            source:
+                TargetExpr::Local {
+                    localidx: source_localidx,
+                    next: None,
+                },
+        } = &expr2.kind
+        {
+            if *source_localidx == localidx && expr2.vartype == Some(VarType::Any) {
+                expr2.vartype = Some(vartype);
+            }
+        }
+    });
+}
@@ -0,0 +1,175 @@
+/**
+ * A compact, human-readable text format for the IR, which can be printed (with `Display`) and parsed back (with `FromStr`).
+ * Printing and then parsing a Program gives back an identical Program.
+ *
+ * Every Expr is written as `(kind:vartype operands...)`, where vartype is `void` if the Expr never returns, e.g.:
+ * `(prim:number number_add (var:number local 0) (number:number 1.0))`.
+ *
+ * The syntax is as follows (`;` starts a comment that extends to the end of the line):
+ * * VarType: `any`, `unassigned`, `undefined`, `number`, `boolean`, `string`, `func`, `array`, `null`, `struct#<typeidx>`
+ * * TargetExpr: `local <localidx>` or `global <globalidx>`, followed by zero or more `.<typeidx>.<fieldidx>` for the struct fields
+ * * OverloadEntry: `<funcidx>`, or `<funcidx> closure` if has_closure_param is true
+ * * SourceLocation: `@<file>:<line>:<column>-<line>:<column>`, which may be omitted if it is the default location
+ * * Expr kinds:
+ *   * `(undefined:T)`, `(null:T)`, `(number:T <val>)`, `(boolean:T <val>)`, `(string:T "<val>")`, `(struct:T <typeidx>)`
+ *   * `(func:T [<overload entries>] <closure>)`
+ *   * `(typecast:T <expected> narrow|keep <test> <true_expr> <false_expr>)`
+ *   * `(var:T <target>)`
+ *   * `(prim:T <prim_inst> <args>...)`, where prim_inst is in snake_case (e.g. `number_add`)
+ *   * `(appl:T <func> [<args>] <location>)`
+ *   * `(direct:T <funcidx> <args>...)`
+ *   * `(if:T <cond> <true_expr> <false_expr>)`
+ *   * `(let:T <local> [= <init>] <contained_expr>)`
+ *   * `(assign:T <target> <expr>)`
+ *   * `(return:T <expr>)`, `(break:T <num_frames> <expr>)`, `(block:T <expr>)`, `(loop:T <expr>)`
+ *   * `(seq:T <content>...)`
+ *   * `(trap:T <code> <location>)`
+ *   * `(marker:T <location>)`
+ * * Func: `func ["<name>"] (<params>) -> <result> filter (<params>) -> <result> = <funcidx> ... { <expr> }`
+ * * Program: a sequence of the following items, where the index of each item is implied by its position:
+ *   * `struct (<fields>)`
+ *   * `import "<module_name>" "<entity_name>" (<params>) -> <result>`, where the types are `undefined`, `number`, `string`, `boolean`, `func` or `any`
+ *   * `global <vartype>`
+ *   * `<func>`
+ *   * `entry <funcidx>`
+ */
+mod parse;
+mod print;
+
+pub use parse::*;
+
+use super::*;
+
+const ALL_PRIM_INSTS: [PrimInst; NUM_PRIM_INST as usize] = [
+    PrimInst::NumberAdd,
+    PrimInst::NumberSub,
+    PrimInst::NumberMul,
+    PrimInst::NumberDiv,
+    PrimInst::NumberRem,
+    PrimInst::NumberEq,
+    PrimInst::NumberNeq,
+    PrimInst::NumberGt,
+    PrimInst::NumberLt,
+    PrimInst::NumberGe,
+    PrimInst::NumberLe,
+    PrimInst::BooleanEq,
+    PrimInst::BooleanNeq,
+    PrimInst::BooleanAnd,
+    PrimInst::BooleanOr,
+    PrimInst::BooleanNot,
+    PrimInst::NumberNegate,
+    PrimInst::StringAdd,
+    PrimInst::StringEq,
+    PrimInst::StringNeq,
+    PrimInst::StringGt,
+    PrimInst::StringLt,
+    PrimInst::StringGe,
+    PrimInst::StringLe,
+    PrimInst::ArrayNew,
+    PrimInst::ArrayGet,
+    PrimInst::ArraySet,
+];
+
+fn prim_inst_name(prim_inst: PrimInst) -> &'static str {
+    match prim_inst {
+        PrimInst::NumberAdd => "number_add",
+        PrimInst::NumberSub => "number_sub",
+        PrimInst::NumberMul => "number_mul",
+        PrimInst::NumberDiv => "number_div",
+        PrimInst::NumberRem => "number_rem",
+        PrimInst::NumberEq => "number_eq",
+        PrimInst::NumberNeq => "number_neq",
+        PrimInst::NumberGt => "number_gt",
+        PrimInst::NumberLt => "number_lt",
+        PrimInst::NumberGe => "number_ge",
+        PrimInst::NumberLe => "number_le",
+        PrimInst::BooleanEq => "boolean_eq",
+        PrimInst::BooleanNeq => "boolean_neq",
+        PrimInst::BooleanAnd => "boolean_and",
+        PrimInst::BooleanOr => "boolean_or",
+        PrimInst::BooleanNot => "boolean_not",
+        PrimInst::NumberNegate => "number_negate",
+        PrimInst::StringAdd => "string_add",
+        PrimInst::StringEq => "string_eq",
+        PrimInst::StringNeq => "string_neq",
+        PrimInst::StringGt => "string_gt",
+        PrimInst::StringLt => "string_lt",
+        PrimInst::StringGe => "string_ge",
+        PrimInst::StringLe => "string_le",
+        PrimInst::ArrayNew => "array_new",
+        PrimInst::ArrayGet => "array_get",
+        PrimInst::ArraySet => "array_set",
+    }
+}
+
+fn import_val_type_name(ivt: ImportValType) -> &'static str {
+    match ivt {
+        ImportValType::Undefined => "undefined",
+        ImportValType::Number => "number",
+        ImportValType::String => "string",
+        ImportValType::Boolean => "boolean",
+        ImportValType::Func => "func",
+        ImportValType::Any => "any",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip(text: &str) {
+        let program: Program = text.parse().unwrap();
+        let printed = program.to_string();
+        let reparsed: Program = printed.parse().unwrap();
+        assert_eq!(printed, reparsed.to_string());
+    }
+
+    #[test]
+    fn round_trip_program() {
+        assert_round_trip(
+            r#"
+            struct (any, number)
+            struct ()
+            import "misc" "display" (string) -> undefined
+            import "misc" "apply" (func, any, boolean) -> any
+            global any
+            global struct#0
+            ; funcidx 2
+            func (struct#1, any) -> any
+            filter (struct#1, number) -> number = 3
+            {
+              (return:void (typecast:any number narrow (var:any local 1)
+                (prim:number number_add (var:number local 2) (number:number 1.5))
+                (trap:void 3 @0:1:2-3:4)))
+            }
+            ; funcidx 3
+            func "negate" (struct#1, number) -> number {
+              (return:void (prim:number number_negate (var:number local 1)))
+            }
+            ; funcidx 4
+            func () -> undefined {
+              (seq:undefined
+                (marker:undefined @0:2:0-2:10)
+                (assign:undefined global 1 (let:struct#0 struct#0 = (struct:struct#0 0)
+                  (seq:struct#0 (assign:undefined local 0 .0.1 (number:number -0.0)) (var:struct#0 local 0))))
+                (assign:undefined global 0 (let:func struct#1 = (struct:struct#1 1)
+                  (func:func [2 closure, 3 closure] (var:struct#1 local 0))))
+                (direct:undefined 0 (string:string "a \"quoted\"\n string"))
+                (block:any (loop:undefined (if:undefined (boolean:boolean true)
+                  (break:void 0 (undefined:undefined))
+                  (break:void 1 (appl:any (var:func global 0) [(null:null) (number:number NaN)])))))
+                (undefined:undefined))
+            }
+            entry 4
+            "#,
+        );
+    }
+
+    #[test]
+    fn parse_error_location() {
+        let err = "func () -> any {\n  (foo:any)\n}"
+            .parse::<Program>()
+            .unwrap_err();
+        assert_eq!((err.line, err.column), (2, 4));
+    }
+}
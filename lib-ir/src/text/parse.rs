@@ -0,0 +1,803 @@
+use super::*;
+use std::fmt;
+use std::str::FromStr;
+
+/**
+ * Error returned when parsing the text format fails.
+ * `line` and `column` are one-based, and point to the start of the offending token.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/**
+ * Parses a whole Program from the text format.
+ */
+pub fn parse_program(text: &str) -> Result<Program, ParseError> {
+    text.parse()
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parse_all(text, Parser::parse_program)
+    }
+}
+
+impl FromStr for Func {
+    type Err = ParseError;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parse_all(text, Parser::parse_func)
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ParseError;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parse_all(text, Parser::parse_expr)
+    }
+}
+
+impl FromStr for TargetExpr {
+    type Err = ParseError;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parse_all(text, Parser::parse_target)
+    }
+}
+
+impl FromStr for OverloadEntry {
+    type Err = ParseError;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parse_all(text, Parser::parse_overload_entry)
+    }
+}
+
+impl FromStr for VarType {
+    type Err = ParseError;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parse_all(text, Parser::parse_vartype)
+    }
+}
+
+/**
+ * Parses the text with the given function, and ensures that all the text is consumed.
+ */
+fn parse_all<T, F: FnOnce(&mut Parser) -> Result<T, ParseError>>(
+    text: &str,
+    f: F,
+) -> Result<T, ParseError> {
+    let mut parser = Parser::new(tokenize(text)?);
+    let ret = f(&mut parser)?;
+    parser.expect_end()?;
+    Ok(ret)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    OpenBrace,
+    CloseBrace,
+    Comma,
+    Colon,
+    Equals,
+    Arrow,
+    Str(String),
+    Word(String),
+    End,
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::OpenParen => write!(f, "`(`"),
+            TokenKind::CloseParen => write!(f, "`)`"),
+            TokenKind::OpenBracket => write!(f, "`[`"),
+            TokenKind::CloseBracket => write!(f, "`]`"),
+            TokenKind::OpenBrace => write!(f, "`{{`"),
+            TokenKind::CloseBrace => write!(f, "`}}`"),
+            TokenKind::Comma => write!(f, "`,`"),
+            TokenKind::Colon => write!(f, "`:`"),
+            TokenKind::Equals => write!(f, "`=`"),
+            TokenKind::Arrow => write!(f, "`->`"),
+            TokenKind::Str(s) => write!(f, "string {:?}", s),
+            TokenKind::Word(s) => write!(f, "`{}`", s),
+            TokenKind::End => write!(f, "end of input"),
+        }
+    }
+}
+
+struct Token {
+    kind: TokenKind,
+    line: usize,
+    column: usize,
+}
+
+fn is_word_char(c: char) -> bool {
+    !c.is_whitespace() && !"()[]{},:=\";".contains(c)
+}
+
+/**
+ * Iterator over the chars of the text, that keeps track of the current position.
+ */
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ret = self.chars.next();
+        if ret == Some('\n') {
+            self.line += 1;
+            self.column = 1;
+        } else if ret.is_some() {
+            self.column += 1;
+        }
+        ret
+    }
+
+    fn error<T>(&self, line: usize, column: usize, message: &str) -> Result<T, ParseError> {
+        Err(ParseError {
+            line: line,
+            column: column,
+            message: message.to_owned(),
+        })
+    }
+
+    /**
+     * Lexes the rest of a string literal (after the opening quote), which uses the same escapes as Rust.
+     */
+    fn lex_string(&mut self, line: usize, column: usize) -> Result<String, ParseError> {
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                None => return self.error(line, column, "unterminated string"),
+                Some('"') => return Ok(s),
+                Some('\\') => s.push(match self.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some('\\') => '\\',
+                    Some('"') => '"',
+                    Some('\'') => '\'',
+                    Some('u') if self.next() == Some('{') => {
+                        let mut hex = String::new();
+                        while let Some(c) = self.next() {
+                            if c == '}' {
+                                break;
+                            }
+                            hex.push(c);
+                        }
+                        match u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(std::char::from_u32)
+                        {
+                            Some(c) => c,
+                            None => return self.error(line, column, "invalid unicode escape"),
+                        }
+                    }
+                    _ => return self.error(line, column, "invalid escape sequence"),
+                }),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ParseError> {
+    let mut lexer = Lexer {
+        chars: text.chars().peekable(),
+        line: 1,
+        column: 1,
+    };
+    let mut tokens: Vec<Token> = Vec::new();
+    while let Some(c) = lexer.peek() {
+        let (line, column) = (lexer.line, lexer.column);
+        if c.is_whitespace() {
+            lexer.next();
+            continue;
+        }
+        if c == ';' {
+            // comment until the end of the line
+            while let Some(c) = lexer.peek() {
+                if c == '\n' {
+                    break;
+                }
+                lexer.next();
+            }
+            continue;
+        }
+        lexer.next();
+        let kind = match c {
+            '(' => TokenKind::OpenParen,
+            ')' => TokenKind::CloseParen,
+            '[' => TokenKind::OpenBracket,
+            ']' => TokenKind::CloseBracket,
+            '{' => TokenKind::OpenBrace,
+            '}' => TokenKind::CloseBrace,
+            ',' => TokenKind::Comma,
+            ':' => TokenKind::Colon,
+            '=' => TokenKind::Equals,
+            '"' => TokenKind::Str(lexer.lex_string(line, column)?),
+            '-' if lexer.peek() == Some('>') => {
+                lexer.next();
+                TokenKind::Arrow
+            }
+            _ => {
+                let mut s = String::new();
+                s.push(c);
+                // source locations (starting with `@`) contain colons
+                let is_location = c == '@';
+                while let Some(c) = lexer.peek() {
+                    if !(is_word_char(c) || (is_location && c == ':')) {
+                        break;
+                    }
+                    lexer.next();
+                    s.push(c);
+                }
+                TokenKind::Word(s)
+            }
+        };
+        tokens.push(Token {
+            kind: kind,
+            line: line,
+            column: column,
+        });
+    }
+    tokens.push(Token {
+        kind: TokenKind::End,
+        line: lexer.line,
+        column: lexer.column,
+    });
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>) -> Self {
+        Self {
+            tokens: tokens,
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> &TokenKind {
+        &self.tokens[self.pos].kind
+    }
+
+    fn next(&mut self) -> TokenKind {
+        let ret = self.tokens[self.pos].kind.clone();
+        if ret != TokenKind::End {
+            self.pos += 1;
+        }
+        ret
+    }
+
+    /**
+     * Makes an error at the next token.
+     */
+    fn error<T>(&self, message: String) -> Result<T, ParseError> {
+        self.error_at(self.pos, message)
+    }
+
+    /**
+     * Makes an error at the token with the given index.
+     */
+    fn error_at<T>(&self, pos: usize, message: String) -> Result<T, ParseError> {
+        let token = &self.tokens[pos];
+        Err(ParseError {
+            line: token.line,
+            column: token.column,
+            message: message,
+        })
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, ParseError> {
+        self.error(format!("expected {}, found {}", expected, self.peek()))
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> Result<(), ParseError> {
+        if *self.peek() == kind {
+            self.next();
+            Ok(())
+        } else {
+            self.unexpected(&kind.to_string())
+        }
+    }
+
+    fn expect_end(&self) -> Result<(), ParseError> {
+        if *self.peek() == TokenKind::End {
+            Ok(())
+        } else {
+            self.unexpected("end of input")
+        }
+    }
+
+    fn peek_word(&self) -> Option<&str> {
+        if let TokenKind::Word(s) = self.peek() {
+            Some(s)
+        } else {
+            None
+        }
+    }
+
+    fn parse_word(&mut self, expected: &str) -> Result<String, ParseError> {
+        if let TokenKind::Word(s) = self.peek() {
+            let s = s.clone();
+            self.next();
+            Ok(s)
+        } else {
+            self.unexpected(expected)
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), ParseError> {
+        if self.peek_word() == Some(word) {
+            self.next();
+            Ok(())
+        } else {
+            self.unexpected(&format!("`{}`", word))
+        }
+    }
+
+    /**
+     * Parses a word with `FromStr`, e.g. a number.
+     */
+    fn parse_value<T: FromStr>(&mut self, expected: &str) -> Result<T, ParseError> {
+        match self.peek_word().map(|s| s.parse::<T>()) {
+            Some(Ok(val)) => {
+                self.next();
+                Ok(val)
+            }
+            _ => self.unexpected(expected),
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, ParseError> {
+        if let TokenKind::Str(s) = self.peek() {
+            let s = s.clone();
+            self.next();
+            Ok(s)
+        } else {
+            self.unexpected("string")
+        }
+    }
+
+    /**
+     * Parses a comma-separated list of items, until (but not including) the given closing token.
+     */
+    fn parse_list<T, F: FnMut(&mut Parser) -> Result<T, ParseError>>(
+        &mut self,
+        close: TokenKind,
+        mut f: F,
+    ) -> Result<Vec<T>, ParseError> {
+        let mut ret = Vec::new();
+        if *self.peek() != close {
+            ret.push(f(self)?);
+            while *self.peek() == TokenKind::Comma {
+                self.next();
+                ret.push(f(self)?);
+            }
+        }
+        Ok(ret)
+    }
+
+    fn parse_vartype(&mut self) -> Result<VarType, ParseError> {
+        let vartype = match self.peek_word() {
+            Some("any") => VarType::Any,
+            Some("unassigned") => VarType::Unassigned,
+            Some("undefined") => VarType::Undefined,
+            Some("number") => VarType::Number,
+            Some("boolean") => VarType::Boolean,
+            Some("string") => VarType::String,
+            Some("func") => VarType::Func,
+            Some("array") => VarType::Array,
+            Some("null") => VarType::Null,
+            Some(s) if s.starts_with("struct#") => match s["struct#".len()..].parse() {
+                Ok(typeidx) => VarType::StructT { typeidx: typeidx },
+                Err(_) => return self.unexpected("type"),
+            },
+            _ => return self.unexpected("type"),
+        };
+        self.next();
+        Ok(vartype)
+    }
+
+    /**
+     * Parses a type, or `void` (i.e. None).
+     */
+    fn parse_opt_vartype(&mut self) -> Result<Option<VarType>, ParseError> {
+        if self.peek_word() == Some("void") {
+            self.next();
+            Ok(None)
+        } else {
+            self.parse_vartype().map(Some)
+        }
+    }
+
+    fn parse_import_val_type(&mut self) -> Result<ImportValType, ParseError> {
+        let ivt = match self.peek_word() {
+            Some("undefined") => ImportValType::Undefined,
+            Some("number") => ImportValType::Number,
+            Some("string") => ImportValType::String,
+            Some("boolean") => ImportValType::Boolean,
+            Some("func") => ImportValType::Func,
+            Some("any") => ImportValType::Any,
+            _ => return self.unexpected("import type"),
+        };
+        self.next();
+        Ok(ivt)
+    }
+
+    fn parse_params(&mut self) -> Result<Box<[VarType]>, ParseError> {
+        self.expect(TokenKind::OpenParen)?;
+        let params = self.parse_list(TokenKind::CloseParen, Parser::parse_vartype)?;
+        self.expect(TokenKind::CloseParen)?;
+        Ok(params.into_boxed_slice())
+    }
+
+    fn parse_target(&mut self) -> Result<TargetExpr, ParseError> {
+        let is_global = match self.peek_word() {
+            Some("global") => true,
+            Some("local") => false,
+            _ => return self.unexpected("`global` or `local`"),
+        };
+        self.next();
+        let idx: usize = self.parse_value("index")?;
+        let mut fields: Vec<(usize, usize)> = Vec::new();
+        while let Some(s) = self.peek_word() {
+            if !s.starts_with('.') {
+                break;
+            }
+            let parts: Vec<Option<usize>> = s[1..].split('.').map(|x| x.parse().ok()).collect();
+            match &*parts {
+                [Some(typeidx), Some(fieldidx)] => fields.push((*typeidx, *fieldidx)),
+                _ => return self.unexpected("struct field"),
+            }
+            self.next();
+        }
+        let next: Option<Box<StructField>> =
+            fields
+                .into_iter()
+                .rev()
+                .fold(None, |next, (typeidx, fieldidx)| {
+                    Some(Box::new(StructField {
+                        typeidx: typeidx,
+                        fieldidx: fieldidx,
+                        next: next,
+                    }))
+                });
+        Ok(if is_global {
+            TargetExpr::Global {
+                globalidx: idx,
+                next: next,
+            }
+        } else {
+            TargetExpr::Local {
+                localidx: idx,
+                next: next,
+            }
+        })
+    }
+
+    fn parse_overload_entry(&mut self) -> Result<OverloadEntry, ParseError> {
+        let funcidx: FuncIdx = self.parse_value("funcidx")?;
+        let has_closure_param = if self.peek_word() == Some("closure") {
+            self.next();
+            true
+        } else {
+            false
+        };
+        Ok(OverloadEntry {
+            funcidx: funcidx,
+            has_closure_param: has_closure_param,
+        })
+    }
+
+    /**
+     * Parses an optional source location (the default location is used if it is absent).
+     */
+    fn parse_location(&mut self) -> Result<SourceLocation, ParseError> {
+        let s = match self.peek_word() {
+            Some(s) if s.starts_with('@') => s[1..].to_owned(),
+            _ => return Ok(SourceLocation::default()),
+        };
+        // format: file:line:column-line:column
+        let parse = || -> Option<SourceLocation> {
+            let (file, rest) = s.split_once(':')?;
+            let (start, end) = rest.split_once('-')?;
+            let parse_position = |pos: &str| -> Option<Position> {
+                let (line, column) = pos.split_once(':')?;
+                Some(Position {
+                    line: line.parse().ok()?,
+                    column: column.parse().ok()?,
+                })
+            };
+            Some(SourceLocation {
+                file: file.parse().ok()?,
+                start: parse_position(start)?,
+                end: parse_position(end)?,
+            })
+        };
+        match parse() {
+            Some(location) => {
+                self.next();
+                Ok(location)
+            }
+            None => self.unexpected("source location"),
+        }
+    }
+
+    /**
+     * Parses exprs until the closing token (which is not consumed).
+     */
+    fn parse_exprs_until(&mut self, close: TokenKind) -> Result<Vec<Expr>, ParseError> {
+        let mut ret = Vec::new();
+        while *self.peek() != close {
+            ret.push(self.parse_expr()?);
+        }
+        Ok(ret)
+    }
+
+    fn parse_boxed_expr(&mut self) -> Result<Box<Expr>, ParseError> {
+        self.parse_expr().map(Box::new)
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        self.expect(TokenKind::OpenParen)?;
+        let name_pos = self.pos;
+        let name = self.parse_word("expr kind")?;
+        self.expect(TokenKind::Colon)?;
+        let vartype = self.parse_opt_vartype()?;
+        let kind = match name.as_str() {
+            "undefined" => ExprKind::PrimUndefined,
+            "null" => ExprKind::PrimNull,
+            "number" => ExprKind::PrimNumber {
+                val: self.parse_value("number")?,
+            },
+            "boolean" => ExprKind::PrimBoolean {
+                val: self.parse_value("`true` or `false`")?,
+            },
+            "string" => ExprKind::PrimString {
+                val: self.parse_string()?,
+            },
+            "struct" => ExprKind::PrimStructT {
+                typeidx: self.parse_value("typeidx")?,
+            },
+            "func" => {
+                self.expect(TokenKind::OpenBracket)?;
+                let funcidxs =
+                    self.parse_list(TokenKind::CloseBracket, Parser::parse_overload_entry)?;
+                self.expect(TokenKind::CloseBracket)?;
+                ExprKind::PrimFunc {
+                    funcidxs: funcidxs.into_boxed_slice(),
+                    closure: self.parse_boxed_expr()?,
+                }
+            }
+            "typecast" => {
+                let expected = self.parse_vartype()?;
+                let create_narrow_local = match self.peek_word() {
+                    Some("narrow") => true,
+                    Some("keep") => false,
+                    _ => return self.unexpected("`narrow` or `keep`"),
+                };
+                self.next();
+                ExprKind::TypeCast {
+                    test: self.parse_boxed_expr()?,
+                    expected: expected,
+                    create_narrow_local: create_narrow_local,
+                    true_expr: self.parse_boxed_expr()?,
+                    false_expr: self.parse_boxed_expr()?,
+                }
+            }
+            "var" => ExprKind::VarName {
+                source: self.parse_target()?,
+            },
+            "prim" => {
+                let prim_inst = match self.peek_word().and_then(|s| {
+                    ALL_PRIM_INSTS
+                        .iter()
+                        .copied()
+                        .find(|prim_inst| prim_inst_name(*prim_inst) == s)
+                }) {
+                    Some(prim_inst) => prim_inst,
+                    None => return self.unexpected("primitive instruction"),
+                };
+                self.next();
+                ExprKind::PrimAppl {
+                    prim_inst: prim_inst,
+                    args: self
+                        .parse_exprs_until(TokenKind::CloseParen)?
+                        .into_boxed_slice(),
+                }
+            }
+            "appl" => {
+                let func = self.parse_boxed_expr()?;
+                self.expect(TokenKind::OpenBracket)?;
+                let args = self.parse_exprs_until(TokenKind::CloseBracket)?;
+                self.expect(TokenKind::CloseBracket)?;
+                ExprKind::Appl {
+                    func: func,
+                    args: args.into_boxed_slice(),
+                    location: self.parse_location()?,
+                }
+            }
+            "direct" => ExprKind::DirectAppl {
+                funcidx: self.parse_value("funcidx")?,
+                args: self
+                    .parse_exprs_until(TokenKind::CloseParen)?
+                    .into_boxed_slice(),
+            },
+            "if" => ExprKind::Conditional {
+                cond: self.parse_boxed_expr()?,
+                true_expr: self.parse_boxed_expr()?,
+                false_expr: self.parse_boxed_expr()?,
+            },
+            "let" => {
+                let local = self.parse_vartype()?;
+                let init = if *self.peek() == TokenKind::Equals {
+                    self.next();
+                    Some(self.parse_boxed_expr()?)
+                } else {
+                    None
+                };
+                ExprKind::Declaration {
+                    local: local,
+                    init: init,
+                    contained_expr: self.parse_boxed_expr()?,
+                }
+            }
+            "assign" => ExprKind::Assign {
+                target: self.parse_target()?,
+                expr: self.parse_boxed_expr()?,
+            },
+            "return" => ExprKind::Return {
+                expr: self.parse_boxed_expr()?,
+            },
+            "break" => ExprKind::Break {
+                num_frames: self.parse_value("number of frames")?,
+                expr: self.parse_boxed_expr()?,
+            },
+            "block" => ExprKind::Block {
+                expr: self.parse_boxed_expr()?,
+            },
+            "loop" => ExprKind::Loop {
+                expr: self.parse_boxed_expr()?,
+            },
+            "seq" => ExprKind::Sequence {
+                content: self.parse_exprs_until(TokenKind::CloseParen)?,
+            },
+            "trap" => ExprKind::Trap {
+                code: self.parse_value("trap code")?,
+                location: self.parse_location()?,
+            },
+            "marker" => ExprKind::SourceMarker {
+                location: self.parse_location()?,
+            },
+            _ => return self.error_at(name_pos, format!("unknown expr kind `{}`", name)),
+        };
+        self.expect(TokenKind::CloseParen)?;
+        Ok(Expr {
+            vartype: vartype,
+            kind: kind,
+        })
+    }
+
+    fn parse_func(&mut self) -> Result<Func, ParseError> {
+        self.expect_word("func")?;
+        let name = if let TokenKind::Str(_) = self.peek() {
+            Some(self.parse_string()?)
+        } else {
+            None
+        };
+        let params = self.parse_params()?;
+        self.expect(TokenKind::Arrow)?;
+        let result = self.parse_opt_vartype()?;
+        let mut signature_filter = Vec::new();
+        while self.peek_word() == Some("filter") {
+            self.next();
+            let filter_params = self.parse_params()?;
+            self.expect(TokenKind::Arrow)?;
+            let filter_result = self.parse_vartype()?;
+            self.expect(TokenKind::Equals)?;
+            let funcidx: FuncIdx = self.parse_value("funcidx")?;
+            signature_filter.push((filter_params, filter_result, funcidx));
+        }
+        self.expect(TokenKind::OpenBrace)?;
+        let expr = self.parse_expr()?;
+        self.expect(TokenKind::CloseBrace)?;
+        Ok(Func {
+            name: name,
+            params: params,
+            param_names: Default::default(),
+            result: result,
+            expr: expr,
+            signature_filter: signature_filter,
+        })
+    }
+
+    fn parse_program(&mut self) -> Result<Program, ParseError> {
+        let mut struct_types = Vec::new();
+        let mut imports = Vec::new();
+        let mut funcs = Vec::new();
+        let mut globals = Vec::new();
+        let mut entry_point: Option<FuncIdx> = None;
+        while *self.peek() != TokenKind::End {
+            match self.peek_word() {
+                Some("struct") => {
+                    self.next();
+                    struct_types.push(self.parse_params()?);
+                }
+                Some("import") => {
+                    if !funcs.is_empty() {
+                        return self.error("imports must come before all funcs".to_owned());
+                    }
+                    self.next();
+                    let module_name = self.parse_string()?;
+                    let entity_name = self.parse_string()?;
+                    self.expect(TokenKind::OpenParen)?;
+                    let params =
+                        self.parse_list(TokenKind::CloseParen, Parser::parse_import_val_type)?;
+                    self.expect(TokenKind::CloseParen)?;
+                    self.expect(TokenKind::Arrow)?;
+                    imports.push(Import {
+                        module_name: module_name,
+                        entity_name: entity_name,
+                        params: params.into_boxed_slice(),
+                        result: self.parse_import_val_type()?,
+                    });
+                }
+                Some("global") => {
+                    self.next();
+                    globals.push(self.parse_vartype()?);
+                }
+                Some("func") => {
+                    funcs.push(self.parse_func()?);
+                }
+                Some("entry") => {
+                    if entry_point.is_some() {
+                        return self.error("duplicate entry point".to_owned());
+                    }
+                    self.next();
+                    entry_point = Some(self.parse_value("funcidx")?);
+                }
+                _ => return self.unexpected("`struct`, `import`, `global`, `func` or `entry`"),
+            }
+        }
+        let entry_point = match entry_point {
+            Some(funcidx) => funcidx,
+            None => return self.error("missing entry point".to_owned()),
+        };
+        Ok(Program {
+            struct_types: struct_types,
+            imports: imports.into_boxed_slice(),
+            funcs: funcs,
+            globals: globals,
+            entry_point: entry_point,
+        })
+    }
+}
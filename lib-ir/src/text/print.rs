@@ -0,0 +1,384 @@
+use super::*;
+use std::fmt;
+
+impl fmt::Display for VarType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VarType::Any => write!(f, "any"),
+            VarType::Unassigned => write!(f, "unassigned"),
+            VarType::Undefined => write!(f, "undefined"),
+            VarType::Number => write!(f, "number"),
+            VarType::Boolean => write!(f, "boolean"),
+            VarType::String => write!(f, "string"),
+            VarType::Func => write!(f, "func"),
+            VarType::Array => write!(f, "array"),
+            VarType::Null => write!(f, "null"),
+            VarType::StructT { typeidx } => write!(f, "struct#{}", typeidx),
+        }
+    }
+}
+
+impl fmt::Display for TargetExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let next = match self {
+            TargetExpr::Global { globalidx, next } => {
+                write!(f, "global {}", globalidx)?;
+                next
+            }
+            TargetExpr::Local { localidx, next } => {
+                write!(f, "local {}", localidx)?;
+                next
+            }
+        };
+        let mut curr: &Option<Box<StructField>> = next;
+        while let Some(field) = curr {
+            write!(f, " .{}.{}", field.typeidx, field.fieldidx)?;
+            curr = &field.next;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for OverloadEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.has_closure_param {
+            write!(f, "{} closure", self.funcidx)
+        } else {
+            write!(f, "{}", self.funcidx)
+        }
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "@{}:{}:{}-{}:{}",
+            self.file, self.start.line, self.start.column, self.end.line, self.end.column
+        )
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_expr(f, self, 0)
+    }
+}
+
+impl fmt::Display for Func {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "func ")?;
+        if let Some(name) = &self.name {
+            write!(f, "{:?} ", name)?;
+        }
+        write_signature(f, &self.params, self.result)?;
+        writeln!(f)?;
+        for (params, result, funcidx) in &self.signature_filter {
+            write!(f, "filter ")?;
+            write_signature(f, params, Some(*result))?;
+            writeln!(f, " = {}", funcidx)?;
+        }
+        writeln!(f, "{{")?;
+        write!(f, "  ")?;
+        write_expr(f, &self.expr, 1)?;
+        writeln!(f)?;
+        write!(f, "}}")
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (typeidx, fields) in self.struct_types.iter().enumerate() {
+            write!(f, "struct (")?;
+            write_list(f, fields.iter())?;
+            writeln!(f, ") ; struct#{}", typeidx)?;
+        }
+        for (funcidx, import) in self.imports.iter().enumerate() {
+            write!(
+                f,
+                "import {:?} {:?} (",
+                import.module_name, import.entity_name
+            )?;
+            write_list(
+                f,
+                import.params.iter().map(|ivt| import_val_type_name(*ivt)),
+            )?;
+            writeln!(
+                f,
+                ") -> {} ; funcidx {}",
+                import_val_type_name(import.result),
+                funcidx
+            )?;
+        }
+        for (globalidx, vartype) in self.globals.iter().enumerate() {
+            writeln!(f, "global {} ; global {}", vartype, globalidx)?;
+        }
+        for (i, func) in self.funcs.iter().enumerate() {
+            writeln!(f)?;
+            writeln!(f, "; funcidx {}", self.imports.len() + i)?;
+            writeln!(f, "{}", func)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "entry {}", self.entry_point)
+    }
+}
+
+fn write_list<T: fmt::Display>(
+    f: &mut fmt::Formatter,
+    mut it: impl Iterator<Item = T>,
+) -> fmt::Result {
+    if let Some(first) = it.next() {
+        write!(f, "{}", first)?;
+        for item in it {
+            write!(f, ", {}", item)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_signature(
+    f: &mut fmt::Formatter,
+    params: &[VarType],
+    result: Option<VarType>,
+) -> fmt::Result {
+    write!(f, "(")?;
+    write_list(f, params.iter())?;
+    write!(f, ") -> ")?;
+    write_opt_vartype(f, result)
+}
+
+fn write_opt_vartype(f: &mut fmt::Formatter, vartype: Option<VarType>) -> fmt::Result {
+    match vartype {
+        Some(vartype) => write!(f, "{}", vartype),
+        None => write!(f, "void"),
+    }
+}
+
+fn write_location(f: &mut fmt::Formatter, location: &SourceLocation) -> fmt::Result {
+    if *location != SourceLocation::default() {
+        write!(f, " {}", location)?;
+    }
+    Ok(())
+}
+
+/**
+ * Returns true if the expr has no sub-exprs, so that it can be written on the same line as its parent.
+ */
+fn is_leaf(expr: &Expr) -> bool {
+    matches!(
+        &expr.kind,
+        ExprKind::PrimUndefined
+            | ExprKind::PrimNull
+            | ExprKind::PrimNumber { val: _ }
+            | ExprKind::PrimBoolean { val: _ }
+            | ExprKind::PrimString { val: _ }
+            | ExprKind::PrimStructT { typeidx: _ }
+            | ExprKind::VarName { source: _ }
+            | ExprKind::Trap {
+                code: _,
+                location: _,
+            }
+            | ExprKind::SourceMarker { location: _ }
+    )
+}
+
+/**
+ * Writes the sub-exprs of an expr.
+ * If they are all leaves, they are written on the same line, otherwise each of them is written on its own line.
+ * If `bracketed` is true, the sub-exprs are written directly after an opening bracket, so the first one needs no leading space.
+ */
+fn write_children<'a>(
+    f: &mut fmt::Formatter,
+    children: impl Iterator<Item = &'a Expr> + Clone,
+    indent: usize,
+    bracketed: bool,
+) -> fmt::Result {
+    let inline = children.clone().all(is_leaf);
+    for (i, child) in children.enumerate() {
+        if inline {
+            if i > 0 || !bracketed {
+                write!(f, " ")?;
+            }
+        } else {
+            writeln!(f)?;
+            write!(f, "{:width$}", "", width = (indent + 1) * 2)?;
+        }
+        write_expr(f, child, indent + 1)?;
+    }
+    Ok(())
+}
+
+/**
+ * Writes the expr, where `indent` is the nesting level of the line that the expr starts on.
+ */
+fn write_expr(f: &mut fmt::Formatter, expr: &Expr, indent: usize) -> fmt::Result {
+    let name = match &expr.kind {
+        ExprKind::PrimUndefined => "undefined",
+        ExprKind::PrimNull => "null",
+        ExprKind::PrimNumber { val: _ } => "number",
+        ExprKind::PrimBoolean { val: _ } => "boolean",
+        ExprKind::PrimString { val: _ } => "string",
+        ExprKind::PrimStructT { typeidx: _ } => "struct",
+        ExprKind::PrimFunc {
+            funcidxs: _,
+            closure: _,
+        } => "func",
+        ExprKind::TypeCast {
+            test: _,
+            expected: _,
+            create_narrow_local: _,
+            true_expr: _,
+            false_expr: _,
+        } => "typecast",
+        ExprKind::VarName { source: _ } => "var",
+        ExprKind::PrimAppl {
+            prim_inst: _,
+            args: _,
+        } => "prim",
+        ExprKind::Appl {
+            func: _,
+            args: _,
+            location: _,
+        } => "appl",
+        ExprKind::DirectAppl {
+            funcidx: _,
+            args: _,
+        } => "direct",
+        ExprKind::Conditional {
+            cond: _,
+            true_expr: _,
+            false_expr: _,
+        } => "if",
+        ExprKind::Declaration {
+            local: _,
+            init: _,
+            contained_expr: _,
+        } => "let",
+        ExprKind::Assign { target: _, expr: _ } => "assign",
+        ExprKind::Return { expr: _ } => "return",
+        ExprKind::Break {
+            num_frames: _,
+            expr: _,
+        } => "break",
+        ExprKind::Block { expr: _ } => "block",
+        ExprKind::Loop { expr: _ } => "loop",
+        ExprKind::Sequence { content: _ } => "seq",
+        ExprKind::Trap {
+            code: _,
+            location: _,
+        } => "trap",
+        ExprKind::SourceMarker { location: _ } => "marker",
+    };
+    write!(f, "({}:", name)?;
+    write_opt_vartype(f, expr.vartype)?;
+    // Note: we explicitly list out all possibilities so we will get a compile error if a new exprkind is added.
+    match &expr.kind {
+        ExprKind::PrimUndefined | ExprKind::PrimNull => {}
+        ExprKind::PrimNumber { val } => write!(f, " {:?}", val)?,
+        ExprKind::PrimBoolean { val } => write!(f, " {}", val)?,
+        ExprKind::PrimString { val } => write!(f, " {:?}", val)?,
+        ExprKind::PrimStructT { typeidx } => write!(f, " {}", typeidx)?,
+        ExprKind::PrimFunc { funcidxs, closure } => {
+            write!(f, " [")?;
+            write_list(f, funcidxs.iter())?;
+            write!(f, "]")?;
+            write_children(f, std::iter::once(&**closure), indent, false)?;
+        }
+        ExprKind::TypeCast {
+            test,
+            expected,
+            create_narrow_local,
+            true_expr,
+            false_expr,
+        } => {
+            write!(
+                f,
+                " {} {}",
+                expected,
+                if *create_narrow_local {
+                    "narrow"
+                } else {
+                    "keep"
+                }
+            )?;
+            write_children(
+                f,
+                [&**test, &**true_expr, &**false_expr].iter().copied(),
+                indent,
+                false,
+            )?;
+        }
+        ExprKind::VarName { source } => write!(f, " {}", source)?,
+        ExprKind::PrimAppl { prim_inst, args } => {
+            write!(f, " {}", prim_inst_name(*prim_inst))?;
+            write_children(f, args.iter(), indent, false)?;
+        }
+        ExprKind::Appl {
+            func,
+            args,
+            location,
+        } => {
+            write_children(f, std::iter::once(&**func), indent, false)?;
+            write!(f, " [")?;
+            write_children(f, args.iter(), indent, true)?;
+            write!(f, "]")?;
+            write_location(f, location)?;
+        }
+        ExprKind::DirectAppl { funcidx, args } => {
+            write!(f, " {}", funcidx)?;
+            write_children(f, args.iter(), indent, false)?;
+        }
+        ExprKind::Conditional {
+            cond,
+            true_expr,
+            false_expr,
+        } => {
+            write_children(
+                f,
+                [&**cond, &**true_expr, &**false_expr].iter().copied(),
+                indent,
+                false,
+            )?;
+        }
+        ExprKind::Declaration {
+            local,
+            init,
+            contained_expr,
+        } => {
+            write!(f, " {}", local)?;
+            if let Some(init_expr) = init {
+                write!(f, " =")?;
+                write_children(f, std::iter::once(&**init_expr), indent, false)?;
+            }
+            write_children(f, std::iter::once(&**contained_expr), indent, false)?;
+        }
+        ExprKind::Assign {
+            target,
+            expr: expr2,
+        } => {
+            write!(f, " {}", target)?;
+            write_children(f, std::iter::once(&**expr2), indent, false)?;
+        }
+        ExprKind::Return { expr: expr2 }
+        | ExprKind::Block { expr: expr2 }
+        | ExprKind::Loop { expr: expr2 } => {
+            write_children(f, std::iter::once(&**expr2), indent, false)?;
+        }
+        ExprKind::Break {
+            num_frames,
+            expr: expr2,
+        } => {
+            write!(f, " {}", num_frames)?;
+            write_children(f, std::iter::once(&**expr2), indent, false)?;
+        }
+        ExprKind::Sequence { content } => {
+            write_children(f, content.iter(), indent, false)?;
+        }
+        ExprKind::Trap { code, location } => {
+            write!(f, " {}", code)?;
+            write_location(f, location)?;
+        }
+        ExprKind::SourceMarker { location } => write_location(f, location)?,
+    }
+    write!(f, ")")
+}
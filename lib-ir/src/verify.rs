@@ -0,0 +1,742 @@
+use super::superset::Superset;
+use super::*;
+use std::fmt;
+
+/**
+ * An invariant of the IR that does not hold.
+ * `funcidx` is the function containing the error (or None if the error is not inside any function, e.g. the entry point).
+ * `path` is the sequence of fields that lead from the function to the offending item, e.g. `expr.content[2].contained_expr`.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    pub funcidx: Option<FuncIdx>,
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(funcidx) = self.funcidx {
+            write!(f, "funcidx {}: ", funcidx)?;
+        }
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/**
+ * Checks that the program satisfies the type and structural invariants that the backend relies on, e.g.:
+ * * All FuncIdxs, typeidxs, globalidxs and localidxs are in range.
+ * * Every Expr has the vartype required by its kind (e.g. PrimAppl must have the result type of its PrimInst).
+ * * Sub-exprs only ever need to be widened (to Any) into the place that uses them, never narrowed.
+ * * Every Break targets an enclosing Block or Loop.
+ * * Every signature_filter entry is a subtype of the params, and refers to a function with exactly that signature.
+ *
+ * Returns all the errors found, so that broken passes can be diagnosed without going through the backend.
+ *
+ * Note: The vartypes only become consistent when the optimizer reaches its fixpoint,
+ * so between passes, use `verify_between_passes()` instead.
+ */
+pub fn verify(program: &Program) -> Result<(), Vec<VerifyError>> {
+    run_verifier(program, TypeCheck::Exact)
+}
+
+/**
+ * Like `verify()`, but only checks the invariants that do not depend on the vartypes of exprs (e.g. the indices and Break targets),
+ * which also hold for the output of the frontend (e.g. it may emit void statements after a Return, until the mandatory passes remove them).
+ */
+pub fn verify_structure(program: &Program) -> Result<(), Vec<VerifyError>> {
+    run_verifier(program, TypeCheck::Off)
+}
+
+/**
+ * Like `verify()`, but tolerates the vartypes that a pass may leave stale for `propagate` to update later:
+ * * An Expr may have a wider vartype than its kind requires (e.g. a DirectAppl whose callee's result type was just narrowed).
+ * * The test of a TypeCast, the func of an Appl, and the cond of a Conditional may be narrower than required
+ *   (e.g. reading a param that `interproc` just narrowed).
+ *
+ * Stale vartypes are always too wide, so narrowing anything too much is still an error.
+ */
+pub fn verify_between_passes(program: &Program) -> Result<(), Vec<VerifyError>> {
+    run_verifier(program, TypeCheck::Stale)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum TypeCheck {
+    Off,   // only check the structure
+    Stale, // allow the stale vartypes described in `verify_between_passes()`
+    Exact, // the vartypes must be exactly those required
+}
+
+fn run_verifier(program: &Program, type_check: TypeCheck) -> Result<(), Vec<VerifyError>> {
+    let mut verifier = Verifier {
+        program: program,
+        type_check: type_check,
+        errors: Vec::new(),
+        funcidx: None,
+        path: Vec::new(),
+        locals: Vec::new(),
+        landings: Vec::new(),
+        result: None,
+    };
+    verifier.verify_program();
+    if verifier.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(verifier.errors)
+    }
+}
+
+struct Verifier<'a> {
+    program: &'a Program,
+    type_check: TypeCheck,
+    errors: Vec<VerifyError>,
+    funcidx: Option<FuncIdx>,
+    path: Vec<String>,              // fields leading to the current item
+    locals: Vec<VarType>,           // types of the params and locals in scope, indexed by localidx
+    landings: Vec<Option<VarType>>, // vartypes of the enclosing Blocks and Loops (innermost last) that a Break may carry
+    result: Option<VarType>,        // result type of the current function
+}
+
+impl<'a> Verifier<'a> {
+    fn error(&mut self, message: String) {
+        self.errors.push(VerifyError {
+            funcidx: self.funcidx,
+            path: self.path.join("."),
+            message: message,
+        });
+    }
+
+    fn type_error(&mut self, message: String) {
+        if self.type_check != TypeCheck::Off {
+            self.error(message);
+        }
+    }
+
+    /**
+     * Calls `f` with `segment` appended to the path.
+     */
+    fn nested<R, F: FnOnce(&mut Self) -> R>(&mut self, segment: String, f: F) -> R {
+        self.path.push(segment);
+        let ret = f(self);
+        self.path.pop();
+        ret
+    }
+
+    fn func_signature(&self, funcidx: FuncIdx) -> (Box<[VarType]>, Option<VarType>) {
+        if funcidx < self.program.imports.len() {
+            let import = &self.program.imports[funcidx];
+            (
+                import.params.iter().map(|ivt| (*ivt).into()).collect(),
+                Some(import.result.into()),
+            )
+        } else {
+            let func = self.program.get_func(funcidx);
+            (func.params.clone(), func.result)
+        }
+    }
+
+    fn is_valid_funcidx(&self, funcidx: FuncIdx) -> bool {
+        funcidx < self.program.imports.len() + self.program.funcs.len()
+    }
+
+    fn is_valid_nonimport_funcidx(&self, funcidx: FuncIdx) -> bool {
+        funcidx >= self.program.imports.len() && self.is_valid_funcidx(funcidx)
+    }
+
+    fn verify_vartype(&mut self, vartype: VarType) {
+        if let VarType::StructT { typeidx } = vartype {
+            if typeidx >= self.program.struct_types.len() {
+                self.error(format!("struct typeidx {} is out of range", typeidx));
+            }
+        }
+    }
+
+    fn verify_program(&mut self) {
+        let program = self.program;
+        for (typeidx, fields) in program.struct_types.iter().enumerate() {
+            self.nested(format!("struct_types[{}]", typeidx), |this| {
+                for vartype in fields.iter() {
+                    this.verify_vartype(*vartype);
+                }
+            });
+        }
+        for (globalidx, vartype) in program.globals.iter().enumerate() {
+            self.nested(format!("globals[{}]", globalidx), |this| {
+                this.verify_vartype(*vartype)
+            });
+        }
+        if !self.is_valid_nonimport_funcidx(program.entry_point) {
+            self.nested("entry_point".to_owned(), |this| {
+                this.error(format!(
+                    "funcidx {} is not a non-imported function",
+                    program.entry_point
+                ))
+            });
+        }
+        for (i, func) in program.funcs.iter().enumerate() {
+            self.funcidx = Some(program.imports.len() + i);
+            self.verify_func(func);
+        }
+        self.funcidx = None;
+    }
+
+    fn verify_func(&mut self, func: &Func) {
+        self.nested("params".to_owned(), |this| {
+            for vartype in func.params.iter() {
+                this.verify_vartype(*vartype);
+            }
+        });
+        if let Some(vartype) = func.result {
+            self.nested("result".to_owned(), |this| this.verify_vartype(vartype));
+        }
+        for (i, (params, result, target)) in func.signature_filter.iter().enumerate() {
+            self.nested(format!("signature_filter[{}]", i), |this| {
+                if !func.params.superset(params) {
+                    this.type_error("params are not a subtype of the function params".to_owned());
+                }
+                if !this.is_valid_nonimport_funcidx(*target) {
+                    this.error(format!("funcidx {} is not a non-imported function", target));
+                } else if this.func_signature(*target) != (params.clone(), Some(*result)) {
+                    this.type_error(format!(
+                        "funcidx {} does not have the signature of this entry",
+                        target
+                    ));
+                }
+            });
+        }
+        self.locals = func.params.to_vec();
+        self.landings.clear();
+        self.result = func.result;
+        self.nested("expr".to_owned(), |this| {
+            this.verify_expr(&func.expr);
+            this.expect_fits(func.result, func.expr.vartype, "function result");
+        });
+    }
+
+    /**
+     * Reports an error if a value of type `actual` cannot be widened into `expected`.
+     * A noreturn value fits anywhere.
+     */
+    fn expect_fits(&mut self, expected: Option<VarType>, actual: Option<VarType>, what: &str) {
+        if let Some(actual_vartype) = actual {
+            match expected {
+                Some(expected_vartype) => {
+                    if !expected_vartype.superset(&actual_vartype) {
+                        self.type_error(format!(
+                            "{} cannot be narrowed from {} to {}",
+                            what, actual_vartype, expected_vartype
+                        ));
+                    }
+                }
+                None => self.type_error(format!(
+                    "{} is void but the value has type {}",
+                    what, actual_vartype
+                )),
+            }
+        }
+    }
+
+    fn expect_vartype(&mut self, expr: &Expr, expected: Option<VarType>, kind_name: &str) {
+        let valid = if self.type_check == TypeCheck::Stale {
+            match (expr.vartype, expected) {
+                (_, None) => true,
+                (None, Some(_)) => false,
+                (Some(vartype), Some(expected_vartype)) => vartype.superset(&expected_vartype),
+            }
+        } else {
+            expr.vartype == expected
+        };
+        if !valid {
+            self.type_error(format!(
+                "{} must have type {}, but has type {}",
+                kind_name,
+                OptVarType(expected),
+                OptVarType(expr.vartype)
+            ));
+        }
+    }
+
+    /**
+     * Like `expect_vartype()`, but for an operand that is used as `expected` (e.g. the test of a TypeCast),
+     * which may be stale in the opposite direction.
+     */
+    fn expect_operand(&mut self, operand: &Expr, expected: VarType, what: &str) {
+        if self.type_check == TypeCheck::Stale {
+            self.expect_not_void(operand, what);
+            self.expect_fits(Some(expected), operand.vartype, what);
+        } else {
+            self.expect_vartype(operand, Some(expected), what);
+        }
+    }
+
+    fn expect_not_void(&mut self, expr: &Expr, what: &str) {
+        if expr.vartype.is_none() {
+            self.type_error(format!("{} cannot be void", what));
+        }
+    }
+
+    /**
+     * Verifies the target and returns its vartype, or None if it is invalid (in which case an error has been reported).
+     */
+    fn verify_target(&mut self, target: &TargetExpr) -> Option<VarType> {
+        let (mut vartype, mut next) = match target {
+            TargetExpr::Global { globalidx, next } => {
+                if *globalidx >= self.program.globals.len() {
+                    self.error(format!("globalidx {} is out of range", globalidx));
+                    return None;
+                }
+                (self.program.globals[*globalidx], next)
+            }
+            TargetExpr::Local { localidx, next } => {
+                if *localidx >= self.locals.len() {
+                    self.error(format!("localidx {} is out of range", localidx));
+                    return None;
+                }
+                (self.locals[*localidx], next)
+            }
+        };
+        while let Some(field) = next {
+            if field.typeidx >= self.program.struct_types.len() {
+                self.error(format!("struct typeidx {} is out of range", field.typeidx));
+                return None;
+            }
+            if vartype
+                != (VarType::StructT {
+                    typeidx: field.typeidx,
+                })
+            {
+                self.error(format!(
+                    "field of struct#{} is accessed on a value of type {}",
+                    field.typeidx, vartype
+                ));
+                return None;
+            }
+            let fields = &self.program.struct_types[field.typeidx];
+            if field.fieldidx >= fields.len() {
+                self.error(format!(
+                    "fieldidx {} is out of range for struct#{}",
+                    field.fieldidx, field.typeidx
+                ));
+                return None;
+            }
+            vartype = fields[field.fieldidx];
+            next = &field.next;
+        }
+        Some(vartype)
+    }
+
+    fn verify_args(&mut self, params: &[VarType], args: &[Expr], what: &str) {
+        if params.len() != args.len() {
+            self.error(format!(
+                "{} expects {} arguments, but got {}",
+                what,
+                params.len(),
+                args.len()
+            ));
+        }
+        for (i, arg) in args.iter().enumerate() {
+            self.nested(format!("args[{}]", i), |this| {
+                this.verify_expr(arg);
+                this.expect_not_void(arg, "argument");
+                if let Some(param) = params.get(i) {
+                    this.expect_fits(Some(*param), arg.vartype, "argument");
+                }
+            });
+        }
+    }
+
+    fn verify_expr(&mut self, expr: &Expr) {
+        if let Some(vartype) = expr.vartype {
+            self.verify_vartype(vartype);
+        }
+        // Note: we explicitly list out all possibilities so we will get a compile error if a new exprkind is added.
+        match &expr.kind {
+            ExprKind::PrimUndefined => {
+                self.expect_vartype(expr, Some(VarType::Undefined), "PrimUndefined")
+            }
+            ExprKind::PrimNull => self.expect_vartype(expr, Some(VarType::Null), "PrimNull"),
+            ExprKind::PrimNumber { val: _ } => {
+                self.expect_vartype(expr, Some(VarType::Number), "PrimNumber")
+            }
+            ExprKind::PrimBoolean { val: _ } => {
+                self.expect_vartype(expr, Some(VarType::Boolean), "PrimBoolean")
+            }
+            ExprKind::PrimString { val: _ } => {
+                self.expect_vartype(expr, Some(VarType::String), "PrimString")
+            }
+            ExprKind::PrimStructT { typeidx } => {
+                self.expect_vartype(
+                    expr,
+                    Some(VarType::StructT { typeidx: *typeidx }),
+                    "PrimStructT",
+                );
+            }
+            ExprKind::PrimFunc { funcidxs, closure } => {
+                self.expect_vartype(expr, Some(VarType::Func), "PrimFunc");
+                self.nested("closure".to_owned(), |this| {
+                    this.verify_expr(closure);
+                    this.expect_not_void(closure, "closure");
+                });
+                if funcidxs.is_empty() {
+                    self.error("PrimFunc must have at least one overload".to_owned());
+                }
+                for (i, oe) in funcidxs.iter().enumerate() {
+                    self.nested(format!("funcidxs[{}]", i), |this| {
+                        if !this.is_valid_funcidx(oe.funcidx) {
+                            this.error(format!("funcidx {} is out of range", oe.funcidx));
+                        } else if oe.has_closure_param
+                            && oe.funcidx < this.program.imports.len()
+                        {
+                            this.error(format!(
+                                "imported funcidx {} cannot have a closure param",
+                                oe.funcidx
+                            ));
+                        } else if oe.has_closure_param {
+                            let (params, _) = this.func_signature(oe.funcidx);
+                            if params.first().copied() != closure.vartype {
+                                this.type_error(format!(
+                                    "closure param of funcidx {} does not have the type of the closure",
+                                    oe.funcidx
+                                ));
+                            }
+                        }
+                    });
+                }
+            }
+            ExprKind::TypeCast {
+                test,
+                expected,
+                create_narrow_local,
+                true_expr,
+                false_expr,
+            } => {
+                self.nested("test".to_owned(), |this| {
+                    this.verify_expr(test);
+                    this.expect_operand(test, VarType::Any, "TypeCast test");
+                });
+                if *expected == VarType::Any {
+                    self.error("TypeCast cannot test for Any".to_owned());
+                }
+                self.verify_vartype(*expected);
+                self.nested("true_expr".to_owned(), |this| {
+                    if *create_narrow_local {
+                        this.locals.push(*expected);
+                    }
+                    this.verify_expr(true_expr);
+                    if *create_narrow_local {
+                        this.locals.pop();
+                    }
+                    this.expect_fits(expr.vartype, true_expr.vartype, "TypeCast branch");
+                });
+                self.nested("false_expr".to_owned(), |this| {
+                    this.verify_expr(false_expr);
+                    this.expect_fits(expr.vartype, false_expr.vartype, "TypeCast branch");
+                });
+            }
+            ExprKind::VarName { source } => {
+                self.expect_not_void(expr, "VarName");
+                if let Some(source_vartype) = self.verify_target(source) {
+                    // reading a variable may narrow it from Any, if the type has been proven by the optimiser
+                    if let Some(vartype) = expr.vartype {
+                        if !source_vartype.superset(&vartype) {
+                            self.type_error(format!(
+                                "variable of type {} cannot be read as {}",
+                                source_vartype, vartype
+                            ));
+                        }
+                    }
+                }
+            }
+            ExprKind::PrimAppl { prim_inst, args } => {
+                let (params, result) = prim_inst.signature();
+                self.expect_vartype(expr, result, "PrimAppl");
+                self.verify_args(params, args, "PrimInst");
+            }
+            ExprKind::Appl {
+                func,
+                args,
+                location: _,
+            } => {
+                self.expect_vartype(expr, Some(VarType::Any), "Appl");
+                self.nested("func".to_owned(), |this| {
+                    this.verify_expr(func);
+                    this.expect_operand(func, VarType::Func, "Appl func");
+                });
+                for (i, arg) in args.iter().enumerate() {
+                    self.nested(format!("args[{}]", i), |this| {
+                        this.verify_expr(arg);
+                        this.expect_not_void(arg, "argument");
+                    });
+                }
+            }
+            ExprKind::DirectAppl { funcidx, args } => {
+                if !self.is_valid_funcidx(*funcidx) {
+                    self.error(format!("funcidx {} is out of range", funcidx));
+                    return;
+                }
+                let (params, result) = self.func_signature(*funcidx);
+                self.expect_vartype(expr, result, "DirectAppl");
+                self.verify_args(&params, args, "function");
+            }
+            ExprKind::Conditional {
+                cond,
+                true_expr,
+                false_expr,
+            } => {
+                self.nested("cond".to_owned(), |this| {
+                    this.verify_expr(cond);
+                    this.expect_operand(cond, VarType::Boolean, "Conditional cond");
+                });
+                self.nested("true_expr".to_owned(), |this| {
+                    this.verify_expr(true_expr);
+                    this.expect_fits(expr.vartype, true_expr.vartype, "Conditional branch");
+                });
+                self.nested("false_expr".to_owned(), |this| {
+                    this.verify_expr(false_expr);
+                    this.expect_fits(expr.vartype, false_expr.vartype, "Conditional branch");
+                });
+            }
+            ExprKind::Declaration {
+                local,
+                init,
+                contained_expr,
+            } => {
+                self.verify_vartype(*local);
+                if let Some(init_expr) = init {
+                    self.nested("init".to_owned(), |this| {
+                        this.verify_expr(init_expr);
+                        this.expect_not_void(init_expr, "Declaration init");
+                        this.expect_fits(Some(*local), init_expr.vartype, "Declaration init");
+                    });
+                }
+                self.nested("contained_expr".to_owned(), |this| {
+                    this.locals.push(*local);
+                    this.verify_expr(contained_expr);
+                    this.locals.pop();
+                });
+                self.expect_vartype(expr, contained_expr.vartype, "Declaration");
+            }
+            ExprKind::Assign {
+                target,
+                expr: expr2,
+            } => {
+                self.expect_vartype(expr, Some(VarType::Undefined), "Assign");
+                let target_vartype =
+                    self.nested("target".to_owned(), |this| this.verify_target(target));
+                self.nested("expr".to_owned(), |this| {
+                    this.verify_expr(expr2);
+                    this.expect_not_void(expr2, "assigned value");
+                    if let Some(vartype) = target_vartype {
+                        this.expect_fits(Some(vartype), expr2.vartype, "assigned value");
+                    }
+                });
+            }
+            ExprKind::Return { expr: expr2 } => {
+                self.expect_vartype(expr, None, "Return");
+                self.nested("expr".to_owned(), |this| {
+                    this.verify_expr(expr2);
+                    this.expect_not_void(expr2, "returned value");
+                    let result = this.result;
+                    this.expect_fits(result, expr2.vartype, "returned value");
+                });
+            }
+            ExprKind::Break {
+                num_frames,
+                expr: expr2,
+            } => {
+                self.expect_vartype(expr, None, "Break");
+                self.nested("expr".to_owned(), |this| {
+                    this.verify_expr(expr2);
+                    this.expect_not_void(expr2, "Break value");
+                });
+                if *num_frames >= self.landings.len() {
+                    self.error(format!(
+                        "Break of {} frames but there are only {} enclosing Blocks and Loops",
+                        num_frames,
+                        self.landings.len()
+                    ));
+                } else {
+                    let landing_vartype = self.landings[self.landings.len() - 1 - *num_frames];
+                    self.expect_fits(landing_vartype, expr2.vartype, "Break value");
+                }
+            }
+            ExprKind::Block { expr: expr2 } => {
+                // a void Block never completes, so no Break may target it
+                self.nested("expr".to_owned(), |this| {
+                    this.landings.push(expr.vartype);
+                    this.verify_expr(expr2);
+                    this.landings.pop();
+                    this.expect_fits(expr.vartype, expr2.vartype, "Block value");
+                });
+            }
+            ExprKind::Loop { expr: expr2 } => {
+                self.nested("expr".to_owned(), |this| {
+                    // breaks that re-enter the loop carry undefined
+                    this.landings.push(Some(VarType::Undefined));
+                    this.verify_expr(expr2);
+                    this.landings.pop();
+                });
+                self.expect_vartype(expr, expr2.vartype, "Loop");
+            }
+            ExprKind::Sequence { content } => {
+                for (i, expr2) in content.iter().enumerate() {
+                    self.nested(format!("content[{}]", i), |this| {
+                        this.verify_expr(expr2);
+                        if i + 1 < content.len() {
+                            this.expect_not_void(expr2, "non-last expression in a Sequence");
+                        }
+                    });
+                }
+                let last_vartype = match content.last() {
+                    Some(last) => last.vartype,
+                    None => Some(VarType::Undefined),
+                };
+                self.expect_vartype(expr, last_vartype, "Sequence");
+            }
+            ExprKind::Trap {
+                code: _,
+                location: _,
+            } => self.expect_vartype(expr, None, "Trap"),
+            ExprKind::SourceMarker { location: _ } => {
+                self.expect_vartype(expr, Some(VarType::Undefined), "SourceMarker")
+            }
+        }
+    }
+}
+
+/**
+ * Formats an optional vartype, where None is written as `void`.
+ */
+struct OptVarType(Option<VarType>);
+
+impl fmt::Display for OptVarType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(vartype) => write!(f, "{}", vartype),
+            None => write!(f, "void"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_program() {
+        let program: Program = r#"
+            global any
+            func (number) -> number {
+              (seq:void
+                (assign:undefined global 0 (var:number local 0))
+                (return:void (block:number (break:void 0 (var:number local 0)))))
+            }
+            entry 0
+            "#
+        .parse()
+        .unwrap();
+        assert_eq!(verify(&program), Ok(()));
+    }
+
+    #[test]
+    fn stale_types_are_only_allowed_between_passes() {
+        // f has been narrowed to take and return a number, but neither its body nor its call site has been updated yet
+        let stale: Program = r#"
+            func (number) -> number {
+              (return:void
+                (typecast:number number narrow (var:number local 0)
+                  (var:number local 1)
+                  (trap:void 17 @0:1:0-1:1)))
+            }
+            func () -> any {
+              (direct:any 0 (number:number 1.0))
+            }
+            entry 1
+            "#
+        .parse()
+        .unwrap();
+        assert_eq!(verify_between_passes(&stale), Ok(()));
+        assert_eq!(verify(&stale).unwrap_err().len(), 2);
+
+        // a DirectAppl that is narrower than its callee is never valid
+        let narrowed: Program = r#"
+            func (number) -> any {
+              (return:void (var:number local 0))
+            }
+            func () -> any {
+              (direct:number 0 (number:number 1.0))
+            }
+            entry 1
+            "#
+        .parse()
+        .unwrap();
+        assert!(verify_between_passes(&narrowed).is_err());
+    }
+
+    #[test]
+    fn prim_func_needs_an_overload() {
+        let program: Program = r#"
+            func () -> any {
+              (func:func [] (undefined:undefined))
+            }
+            entry 0
+            "#
+        .parse()
+        .unwrap();
+        let errors = verify_structure(&program).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "PrimFunc must have at least one overload"
+        );
+    }
+
+    #[test]
+    fn void_block_cannot_be_targeted() {
+        let program: Program = r#"
+            func (boolean) -> number {
+              (if:void (var:boolean local 0)
+                (block:void (trap:void 17 @0:1:0-1:1))
+                (block:void (break:void 0 (var:boolean local 0))))
+            }
+            entry 0
+            "#
+        .parse()
+        .unwrap();
+        let errors = verify(&program).unwrap_err();
+        let paths: Vec<(Option<FuncIdx>, &str)> = errors
+            .iter()
+            .map(|e| (e.funcidx, e.path.as_str()))
+            .collect();
+        assert_eq!(paths, vec![(Some(0), "expr.false_expr.expr")]);
+    }
+
+    #[test]
+    fn reports_path_of_invalid_expr() {
+        let program: Program = r#"
+            global number
+            func (any) -> any {
+              (seq:void
+                (assign:undefined global 0 (var:any local 0))
+                (loop:void (break:void 1 (undefined:undefined))))
+            }
+            entry 0
+            "#
+        .parse()
+        .unwrap();
+        let errors = verify(&program).unwrap_err();
+        let paths: Vec<(Option<FuncIdx>, &str)> = errors
+            .iter()
+            .map(|e| (e.funcidx, e.path.as_str()))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                (Some(0), "expr.content[0].expr"),
+                (Some(0), "expr.content[1].expr"),
+            ]
+        );
+    }
+}
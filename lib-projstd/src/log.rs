@@ -128,7 +128,7 @@ impl std::fmt::Display for Severity {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CompileMessage<E> {
     location: SourceLocation,
     severity: Severity,
@@ -7,6 +7,7 @@ use projstd::iter::SequentialCountAdapter;
 #[derive(Default)]
 pub struct ExprBuilder {
     bytecode: Vec<u8>,
+    markers: Vec<(u32, u32)>, // (byte offset in bytecode, marker id), in increasing order of byte offset
 }
 
 pub struct LocalsManager {
@@ -60,13 +61,20 @@ impl CodeBuilder {
             expr: Default::default(),
         }
     }
-    pub fn build(self) -> (FuncType, Box<[u8]>) {
+    // Returns the functype, the serialized function body, and the markers (with byte offsets relative to the start of the function body)
+    pub fn build(self) -> (FuncType, Box<[u8]>, Box<[(u32, u32)]>) {
         let mut receiver = Vec::<u8>::new();
         serialize_locals(self.locals_builder.locals, &mut receiver);
         let locals_len = receiver.len();
+        let markers: Box<[(u32, u32)]> = self
+            .expr
+            .markers
+            .iter()
+            .map(|(offset, id)| (offset + locals_len as u32, *id))
+            .collect();
         receiver.resize_with(locals_len + self.expr.len(), Default::default);
         self.expr.write_to_slice(&mut receiver[locals_len..]);
-        (self.functype, receiver.into_boxed_slice())
+        (self.functype, receiver.into_boxed_slice(), markers)
     }
     pub fn split(&mut self) -> (&mut LocalsManager, &mut ExprBuilder) {
         (&mut self.locals_builder, &mut self.expr)
@@ -91,7 +99,8 @@ fn serialize_locals(locals: Vec<ValType>, receiver: &mut Vec<u8>) {
     });
 }
 
-enum OpCode {
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) enum OpCode {
     Unreachable,
     Nop,
     Block,
@@ -483,10 +492,443 @@ impl OpCode {
     }
 }
 
+// All the opcodes, for decoding (see instr.rs)
+pub(crate) const ALL_OPCODES: [OpCode; 178] = [
+    OpCode::Unreachable,
+    OpCode::Nop,
+    OpCode::Block,
+    OpCode::Loop,
+    OpCode::If,
+    OpCode::Else,
+    OpCode::End,
+    OpCode::Br,
+    OpCode::BrIf,
+    OpCode::BrTable,
+    OpCode::Return,
+    OpCode::Call,
+    OpCode::CallIndirect,
+    OpCode::ReturnCall,
+    OpCode::ReturnCallIndirect,
+    OpCode::Drop,
+    OpCode::Select,
+    OpCode::LocalGet,
+    OpCode::LocalSet,
+    OpCode::LocalTee,
+    OpCode::GlobalGet,
+    OpCode::GlobalSet,
+    OpCode::I32Load,
+    OpCode::I64Load,
+    OpCode::F32Load,
+    OpCode::F64Load,
+    OpCode::I32Load8S,
+    OpCode::I32Load8U,
+    OpCode::I32Load16S,
+    OpCode::I32Load16U,
+    OpCode::I64Load8S,
+    OpCode::I64Load8U,
+    OpCode::I64Load16S,
+    OpCode::I64Load16U,
+    OpCode::I64Load32S,
+    OpCode::I64Load32U,
+    OpCode::I32Store,
+    OpCode::I64Store,
+    OpCode::F32Store,
+    OpCode::F64Store,
+    OpCode::I32Store8,
+    OpCode::I32Store16,
+    OpCode::I64Store8,
+    OpCode::I64Store16,
+    OpCode::I64Store32,
+    OpCode::MemorySize,
+    OpCode::MemoryGrow,
+    OpCode::MemoryInit,
+    OpCode::DataDrop,
+    OpCode::MemoryCopy,
+    OpCode::MemoryFill,
+    OpCode::I32Const,
+    OpCode::I64Const,
+    OpCode::F32Const,
+    OpCode::F64Const,
+    OpCode::I32Eqz,
+    OpCode::I32Eq,
+    OpCode::I32Ne,
+    OpCode::I32LtS,
+    OpCode::I32LtU,
+    OpCode::I32GtS,
+    OpCode::I32GtU,
+    OpCode::I32LeS,
+    OpCode::I32LeU,
+    OpCode::I32GeS,
+    OpCode::I32GeU,
+    OpCode::I64Eqz,
+    OpCode::I64Eq,
+    OpCode::I64Ne,
+    OpCode::I64LtS,
+    OpCode::I64LtU,
+    OpCode::I64GtS,
+    OpCode::I64GtU,
+    OpCode::I64LeS,
+    OpCode::I64LeU,
+    OpCode::I64GeS,
+    OpCode::I64GeU,
+    OpCode::F32Eq,
+    OpCode::F32Ne,
+    OpCode::F32Lt,
+    OpCode::F32Gt,
+    OpCode::F32Le,
+    OpCode::F32Ge,
+    OpCode::F64Eq,
+    OpCode::F64Ne,
+    OpCode::F64Lt,
+    OpCode::F64Gt,
+    OpCode::F64Le,
+    OpCode::F64Ge,
+    OpCode::I32Clz,
+    OpCode::I32Ctz,
+    OpCode::I32Popcnt,
+    OpCode::I32Add,
+    OpCode::I32Sub,
+    OpCode::I32Mul,
+    OpCode::I32DivS,
+    OpCode::I32DivU,
+    OpCode::I32RemS,
+    OpCode::I32RemU,
+    OpCode::I32And,
+    OpCode::I32Or,
+    OpCode::I32Xor,
+    OpCode::I32Shl,
+    OpCode::I32ShrS,
+    OpCode::I32ShrU,
+    OpCode::I32Rotl,
+    OpCode::I32Rotr,
+    OpCode::I64Clz,
+    OpCode::I64Ctz,
+    OpCode::I64Popcnt,
+    OpCode::I64Add,
+    OpCode::I64Sub,
+    OpCode::I64Mul,
+    OpCode::I64DivS,
+    OpCode::I64DivU,
+    OpCode::I64RemS,
+    OpCode::I64RemU,
+    OpCode::I64And,
+    OpCode::I64Or,
+    OpCode::I64Xor,
+    OpCode::I64Shl,
+    OpCode::I64ShrS,
+    OpCode::I64ShrU,
+    OpCode::I64Rotl,
+    OpCode::I64Rotr,
+    OpCode::F32Abs,
+    OpCode::F32Neg,
+    OpCode::F32Ceil,
+    OpCode::F32Floor,
+    OpCode::F32Trunc,
+    OpCode::F32Nearest,
+    OpCode::F32Sqrt,
+    OpCode::F32Add,
+    OpCode::F32Sub,
+    OpCode::F32Mul,
+    OpCode::F32Div,
+    OpCode::F32Min,
+    OpCode::F32Max,
+    OpCode::F32Copysign,
+    OpCode::F64Abs,
+    OpCode::F64Neg,
+    OpCode::F64Ceil,
+    OpCode::F64Floor,
+    OpCode::F64Trunc,
+    OpCode::F64Nearest,
+    OpCode::F64Sqrt,
+    OpCode::F64Add,
+    OpCode::F64Sub,
+    OpCode::F64Mul,
+    OpCode::F64Div,
+    OpCode::F64Min,
+    OpCode::F64Max,
+    OpCode::F64Copysign,
+    OpCode::I32WrapI64,
+    OpCode::I32TruncF32S,
+    OpCode::I32TruncF32U,
+    OpCode::I32TruncF64S,
+    OpCode::I32TruncF64U,
+    OpCode::I64ExtendI32S,
+    OpCode::I64ExtendI32U,
+    OpCode::I64TruncF32S,
+    OpCode::I64TruncF32U,
+    OpCode::I64TruncF64S,
+    OpCode::I64TruncF64U,
+    OpCode::F32ConvertI32S,
+    OpCode::F32ConvertI32U,
+    OpCode::F32ConvertI64S,
+    OpCode::F32ConvertI64U,
+    OpCode::F32DemoteF64,
+    OpCode::F64ConvertI32S,
+    OpCode::F64ConvertI32U,
+    OpCode::F64ConvertI64S,
+    OpCode::F64ConvertI64U,
+    OpCode::F64PromoteF32,
+    OpCode::I32ReinterpretF32,
+    OpCode::I64ReinterpretF64,
+    OpCode::F32ReinterpretI32,
+    OpCode::F64ReinterpretI64,
+];
+
+// The immediate operands that follow an opcode in the bytecode
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) enum ImmediateKind {
+    None,
+    BlockType,
+    Label,        // labelidx
+    LabelTable,   // vec(labelidx) followed by the default labelidx
+    Func,         // funcidx
+    CallIndirect, // typeidx followed by tableidx
+    Local,        // localidx
+    Global,       // globalidx
+    MemArg,       // align followed by offset
+    Mem,          // memidx (must be zero)
+    DataMem,      // dataidx followed by memidx (must be zero)
+    Data,         // dataidx
+    MemMem,       // two memidxs (must be zero)
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+impl OpCode {
+    // Returns the name of the instruction in the text format
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            OpCode::Unreachable => "unreachable",
+            OpCode::Nop => "nop",
+            OpCode::Block => "block",
+            OpCode::Loop => "loop",
+            OpCode::If => "if",
+            OpCode::Else => "else",
+            OpCode::End => "end",
+            OpCode::Br => "br",
+            OpCode::BrIf => "br_if",
+            OpCode::BrTable => "br_table",
+            OpCode::Return => "return",
+            OpCode::Call => "call",
+            OpCode::CallIndirect => "call_indirect",
+            OpCode::ReturnCall => "return_call",
+            OpCode::ReturnCallIndirect => "return_call_indirect",
+            OpCode::Drop => "drop",
+            OpCode::Select => "select",
+            OpCode::LocalGet => "local.get",
+            OpCode::LocalSet => "local.set",
+            OpCode::LocalTee => "local.tee",
+            OpCode::GlobalGet => "global.get",
+            OpCode::GlobalSet => "global.set",
+            OpCode::I32Load => "i32.load",
+            OpCode::I64Load => "i64.load",
+            OpCode::F32Load => "f32.load",
+            OpCode::F64Load => "f64.load",
+            OpCode::I32Load8S => "i32.load8_s",
+            OpCode::I32Load8U => "i32.load8_u",
+            OpCode::I32Load16S => "i32.load16_s",
+            OpCode::I32Load16U => "i32.load16_u",
+            OpCode::I64Load8S => "i64.load8_s",
+            OpCode::I64Load8U => "i64.load8_u",
+            OpCode::I64Load16S => "i64.load16_s",
+            OpCode::I64Load16U => "i64.load16_u",
+            OpCode::I64Load32S => "i64.load32_s",
+            OpCode::I64Load32U => "i64.load32_u",
+            OpCode::I32Store => "i32.store",
+            OpCode::I64Store => "i64.store",
+            OpCode::F32Store => "f32.store",
+            OpCode::F64Store => "f64.store",
+            OpCode::I32Store8 => "i32.store8",
+            OpCode::I32Store16 => "i32.store16",
+            OpCode::I64Store8 => "i64.store8",
+            OpCode::I64Store16 => "i64.store16",
+            OpCode::I64Store32 => "i64.store32",
+            OpCode::MemorySize => "memory.size",
+            OpCode::MemoryGrow => "memory.grow",
+            OpCode::MemoryInit => "memory.init",
+            OpCode::DataDrop => "data.drop",
+            OpCode::MemoryCopy => "memory.copy",
+            OpCode::MemoryFill => "memory.fill",
+            OpCode::I32Const => "i32.const",
+            OpCode::I64Const => "i64.const",
+            OpCode::F32Const => "f32.const",
+            OpCode::F64Const => "f64.const",
+            OpCode::I32Eqz => "i32.eqz",
+            OpCode::I32Eq => "i32.eq",
+            OpCode::I32Ne => "i32.ne",
+            OpCode::I32LtS => "i32.lt_s",
+            OpCode::I32LtU => "i32.lt_u",
+            OpCode::I32GtS => "i32.gt_s",
+            OpCode::I32GtU => "i32.gt_u",
+            OpCode::I32LeS => "i32.le_s",
+            OpCode::I32LeU => "i32.le_u",
+            OpCode::I32GeS => "i32.ge_s",
+            OpCode::I32GeU => "i32.ge_u",
+            OpCode::I64Eqz => "i64.eqz",
+            OpCode::I64Eq => "i64.eq",
+            OpCode::I64Ne => "i64.ne",
+            OpCode::I64LtS => "i64.lt_s",
+            OpCode::I64LtU => "i64.lt_u",
+            OpCode::I64GtS => "i64.gt_s",
+            OpCode::I64GtU => "i64.gt_u",
+            OpCode::I64LeS => "i64.le_s",
+            OpCode::I64LeU => "i64.le_u",
+            OpCode::I64GeS => "i64.ge_s",
+            OpCode::I64GeU => "i64.ge_u",
+            OpCode::F32Eq => "f32.eq",
+            OpCode::F32Ne => "f32.ne",
+            OpCode::F32Lt => "f32.lt",
+            OpCode::F32Gt => "f32.gt",
+            OpCode::F32Le => "f32.le",
+            OpCode::F32Ge => "f32.ge",
+            OpCode::F64Eq => "f64.eq",
+            OpCode::F64Ne => "f64.ne",
+            OpCode::F64Lt => "f64.lt",
+            OpCode::F64Gt => "f64.gt",
+            OpCode::F64Le => "f64.le",
+            OpCode::F64Ge => "f64.ge",
+            OpCode::I32Clz => "i32.clz",
+            OpCode::I32Ctz => "i32.ctz",
+            OpCode::I32Popcnt => "i32.popcnt",
+            OpCode::I32Add => "i32.add",
+            OpCode::I32Sub => "i32.sub",
+            OpCode::I32Mul => "i32.mul",
+            OpCode::I32DivS => "i32.div_s",
+            OpCode::I32DivU => "i32.div_u",
+            OpCode::I32RemS => "i32.rem_s",
+            OpCode::I32RemU => "i32.rem_u",
+            OpCode::I32And => "i32.and",
+            OpCode::I32Or => "i32.or",
+            OpCode::I32Xor => "i32.xor",
+            OpCode::I32Shl => "i32.shl",
+            OpCode::I32ShrS => "i32.shr_s",
+            OpCode::I32ShrU => "i32.shr_u",
+            OpCode::I32Rotl => "i32.rotl",
+            OpCode::I32Rotr => "i32.rotr",
+            OpCode::I64Clz => "i64.clz",
+            OpCode::I64Ctz => "i64.ctz",
+            OpCode::I64Popcnt => "i64.popcnt",
+            OpCode::I64Add => "i64.add",
+            OpCode::I64Sub => "i64.sub",
+            OpCode::I64Mul => "i64.mul",
+            OpCode::I64DivS => "i64.div_s",
+            OpCode::I64DivU => "i64.div_u",
+            OpCode::I64RemS => "i64.rem_s",
+            OpCode::I64RemU => "i64.rem_u",
+            OpCode::I64And => "i64.and",
+            OpCode::I64Or => "i64.or",
+            OpCode::I64Xor => "i64.xor",
+            OpCode::I64Shl => "i64.shl",
+            OpCode::I64ShrS => "i64.shr_s",
+            OpCode::I64ShrU => "i64.shr_u",
+            OpCode::I64Rotl => "i64.rotl",
+            OpCode::I64Rotr => "i64.rotr",
+            OpCode::F32Abs => "f32.abs",
+            OpCode::F32Neg => "f32.neg",
+            OpCode::F32Ceil => "f32.ceil",
+            OpCode::F32Floor => "f32.floor",
+            OpCode::F32Trunc => "f32.trunc",
+            OpCode::F32Nearest => "f32.nearest",
+            OpCode::F32Sqrt => "f32.sqrt",
+            OpCode::F32Add => "f32.add",
+            OpCode::F32Sub => "f32.sub",
+            OpCode::F32Mul => "f32.mul",
+            OpCode::F32Div => "f32.div",
+            OpCode::F32Min => "f32.min",
+            OpCode::F32Max => "f32.max",
+            OpCode::F32Copysign => "f32.copysign",
+            OpCode::F64Abs => "f64.abs",
+            OpCode::F64Neg => "f64.neg",
+            OpCode::F64Ceil => "f64.ceil",
+            OpCode::F64Floor => "f64.floor",
+            OpCode::F64Trunc => "f64.trunc",
+            OpCode::F64Nearest => "f64.nearest",
+            OpCode::F64Sqrt => "f64.sqrt",
+            OpCode::F64Add => "f64.add",
+            OpCode::F64Sub => "f64.sub",
+            OpCode::F64Mul => "f64.mul",
+            OpCode::F64Div => "f64.div",
+            OpCode::F64Min => "f64.min",
+            OpCode::F64Max => "f64.max",
+            OpCode::F64Copysign => "f64.copysign",
+            OpCode::I32WrapI64 => "i32.wrap_i64",
+            OpCode::I32TruncF32S => "i32.trunc_f32_s",
+            OpCode::I32TruncF32U => "i32.trunc_f32_u",
+            OpCode::I32TruncF64S => "i32.trunc_f64_s",
+            OpCode::I32TruncF64U => "i32.trunc_f64_u",
+            OpCode::I64ExtendI32S => "i64.extend_i32_s",
+            OpCode::I64ExtendI32U => "i64.extend_i32_u",
+            OpCode::I64TruncF32S => "i64.trunc_f32_s",
+            OpCode::I64TruncF32U => "i64.trunc_f32_u",
+            OpCode::I64TruncF64S => "i64.trunc_f64_s",
+            OpCode::I64TruncF64U => "i64.trunc_f64_u",
+            OpCode::F32ConvertI32S => "f32.convert_i32_s",
+            OpCode::F32ConvertI32U => "f32.convert_i32_u",
+            OpCode::F32ConvertI64S => "f32.convert_i64_s",
+            OpCode::F32ConvertI64U => "f32.convert_i64_u",
+            OpCode::F32DemoteF64 => "f32.demote_f64",
+            OpCode::F64ConvertI32S => "f64.convert_i32_s",
+            OpCode::F64ConvertI32U => "f64.convert_i32_u",
+            OpCode::F64ConvertI64S => "f64.convert_i64_s",
+            OpCode::F64ConvertI64U => "f64.convert_i64_u",
+            OpCode::F64PromoteF32 => "f64.promote_f32",
+            OpCode::I32ReinterpretF32 => "i32.reinterpret_f32",
+            OpCode::I64ReinterpretF64 => "i64.reinterpret_f64",
+            OpCode::F32ReinterpretI32 => "f32.reinterpret_i32",
+            OpCode::F64ReinterpretI64 => "f64.reinterpret_i64",
+        }
+    }
+    pub(crate) fn immediate_kind(&self) -> ImmediateKind {
+        match self {
+            OpCode::Block | OpCode::Loop | OpCode::If => ImmediateKind::BlockType,
+            OpCode::Br | OpCode::BrIf => ImmediateKind::Label,
+            OpCode::BrTable => ImmediateKind::LabelTable,
+            OpCode::Call | OpCode::ReturnCall => ImmediateKind::Func,
+            OpCode::CallIndirect | OpCode::ReturnCallIndirect => ImmediateKind::CallIndirect,
+            OpCode::LocalGet | OpCode::LocalSet | OpCode::LocalTee => ImmediateKind::Local,
+            OpCode::GlobalGet | OpCode::GlobalSet => ImmediateKind::Global,
+            OpCode::I32Load
+            | OpCode::I64Load
+            | OpCode::F32Load
+            | OpCode::F64Load
+            | OpCode::I32Load8S
+            | OpCode::I32Load8U
+            | OpCode::I32Load16S
+            | OpCode::I32Load16U
+            | OpCode::I64Load8S
+            | OpCode::I64Load8U
+            | OpCode::I64Load16S
+            | OpCode::I64Load16U
+            | OpCode::I64Load32S
+            | OpCode::I64Load32U
+            | OpCode::I32Store
+            | OpCode::I64Store
+            | OpCode::F32Store
+            | OpCode::F64Store
+            | OpCode::I32Store8
+            | OpCode::I32Store16
+            | OpCode::I64Store8
+            | OpCode::I64Store16
+            | OpCode::I64Store32 => ImmediateKind::MemArg,
+            OpCode::MemorySize | OpCode::MemoryGrow | OpCode::MemoryFill => ImmediateKind::Mem,
+            OpCode::MemoryInit => ImmediateKind::DataMem,
+            OpCode::DataDrop => ImmediateKind::Data,
+            OpCode::MemoryCopy => ImmediateKind::MemMem,
+            OpCode::I32Const => ImmediateKind::I32,
+            OpCode::I64Const => ImmediateKind::I64,
+            OpCode::F32Const => ImmediateKind::F32,
+            OpCode::F64Const => ImmediateKind::F64,
+            _ => ImmediateKind::None,
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct MemArg {
-    offset: u32,
-    align: u32, // expressed as the logarithm of the actual alignment
+    pub(crate) offset: u32,
+    pub(crate) align: u32, // expressed as the logarithm of the actual alignment
 }
 
 impl MemArg {
@@ -537,6 +979,11 @@ impl MemArg {
 }
 
 impl ExprBuilder {
+    // Records that the instructions appended after this point belong to the marker with the given id (e.g. for source maps).
+    // Markers do not emit any bytecode, and are dropped if this ExprBuilder is not part of a function body.
+    pub fn mark(&mut self, id: u32) {
+        self.markers.push((self.bytecode.len() as u32, id));
+    }
     fn append_bytes(&mut self, bytes: &[u8]) {
         self.bytecode.extend(bytes);
     }
@@ -554,6 +1001,10 @@ impl ExprBuilder {
             self.append_bytes(&[result_type[0].value()]);
         }
     }
+    fn append_type_index(&mut self, typeidx: TypeIdx) {
+        // the multi-value proposal encodes the block type as a type index (in s33 format)
+        (typeidx.idx as i64).leb_serialize(&mut self.bytecode);
+    }
     pub fn unreachable(&mut self) {
         self.append_opcode(OpCode::Unreachable);
     }
@@ -572,6 +1023,27 @@ impl ExprBuilder {
         self.append_opcode(OpCode::If);
         self.append_result_type(blocktype);
     }
+    /**
+     * Like `block()`, but the block type is given as a type index, so it may have more than one result (requires multi-value proposal).
+     */
+    pub fn block_typeidx(&mut self, typeidx: TypeIdx) {
+        self.append_opcode(OpCode::Block);
+        self.append_type_index(typeidx);
+    }
+    /**
+     * Like `loop_()`, but the block type is given as a type index, so it may have more than one result (requires multi-value proposal).
+     */
+    pub fn loop_typeidx(&mut self, typeidx: TypeIdx) {
+        self.append_opcode(OpCode::Loop);
+        self.append_type_index(typeidx);
+    }
+    /**
+     * Like `if_()`, but the block type is given as a type index, so it may have more than one result (requires multi-value proposal).
+     */
+    pub fn if_typeidx(&mut self, typeidx: TypeIdx) {
+        self.append_opcode(OpCode::If);
+        self.append_type_index(typeidx);
+    }
     pub fn else_(&mut self) {
         self.append_opcode(OpCode::Else);
     }
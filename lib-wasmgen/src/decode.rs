@@ -0,0 +1,387 @@
+/**
+ * Decoding of the WebAssembly binary format into a WasmModule (the inverse of WasmSerialize).
+ * It is mainly used for testing, e.g. to check that serializing a decoded module gives back the same bytes,
+ * or to validate the output of the backend (see `WasmModule::validate()`).
+ *
+ * Function bodies are kept in their serialized form, as if they were committed from a CodeBuilder.
+ * Only the sections that a WasmModule can hold are supported, so the data count section is rejected,
+ * and all custom sections other than the name section are moved to the end of the module.
+ */
+use super::instr::*;
+use super::*;
+use std::fmt;
+
+#[derive(Debug)]
+pub struct DecodeError {
+    pub offset: usize, // byte offset in the input where the error was detected
+    pub message: String,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl WasmModule {
+    /**
+     * Decodes a module from its binary format.
+     * The module is not validated, but all instructions in the function bodies must be known to wasmgen.
+     */
+    pub fn decode(bytes: &[u8]) -> Result<WasmModule, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        if reader.read_bytes(4)? != [0x00, 0x61, 0x73, 0x6D] {
+            return reader.error("invalid magic value".to_owned());
+        }
+        if reader.read_bytes(4)? != [0x01, 0x00, 0x00, 0x00] {
+            return reader.error("unsupported version".to_owned());
+        }
+        let mut module = WasmModule::default();
+        let mut last_id: u8 = 0;
+        let mut has_code_section = false;
+        while !reader.is_empty() {
+            let id = reader.read_byte()?;
+            if id != 0 {
+                if id <= last_id {
+                    return reader.error(format!("section {} is out of order", id));
+                }
+                last_id = id;
+            }
+            let size = reader.read_u32()?;
+            let mut section_reader = reader.sub_reader(size as usize)?;
+            match id {
+                0 => decode_custom_section(&mut section_reader, &mut module)?,
+                1 => decode_type_section(&mut section_reader, &mut module.type_section)?,
+                2 => decode_import_section(&mut section_reader, &mut module)?,
+                3 => decode_vec(&mut section_reader, &mut module.func_section.content, |r| {
+                    Ok(TypeIdx { idx: r.read_u32()? })
+                })?,
+                4 => decode_vec(
+                    &mut section_reader,
+                    &mut module.table_section.content,
+                    |r| {
+                        Ok(Table {
+                            table_type: read_table_type(r)?,
+                        })
+                    },
+                )?,
+                5 => decode_vec(&mut section_reader, &mut module.mem_section.content, |r| {
+                    Ok(Mem {
+                        mem_type: MemType {
+                            limits: r.read_limits()?,
+                        },
+                    })
+                })?,
+                6 => decode_vec(
+                    &mut section_reader,
+                    &mut module.global_section.content,
+                    |r| {
+                        let global_type = read_global_type(r)?;
+                        Ok(Global {
+                            global_type: global_type,
+                            init_expr: r.read_expr()?,
+                        })
+                    },
+                )?,
+                7 => decode_vec(
+                    &mut section_reader,
+                    &mut module.export_section.content,
+                    |r| {
+                        let entity_name = r.read_name()?;
+                        Ok(Export {
+                            entity_name: entity_name,
+                            desc: read_export_desc(r)?,
+                        })
+                    },
+                )?,
+                8 => {
+                    module.start_section.start = Some(FuncIdx {
+                        idx: section_reader.read_u32()?,
+                    })
+                }
+                9 => decode_vec(&mut section_reader, &mut module.elem_section.content, |r| {
+                    let table_idx = TableIdx { idx: r.read_u32()? };
+                    let offset = r.read_expr()?;
+                    let mut content: Vec<FuncIdx> = Vec::new();
+                    decode_vec(r, &mut content, |r| Ok(FuncIdx { idx: r.read_u32()? }))?;
+                    Ok(Elem {
+                        table_idx: table_idx,
+                        offset: offset,
+                        content: content.into_boxed_slice(),
+                    })
+                })?,
+                10 => {
+                    has_code_section = true;
+                    decode_vec(&mut section_reader, &mut module.code_section.content, |r| {
+                        let len = r.read_u32()?;
+                        Ok(Code {
+                            func: Some(r.read_bytes(len as usize)?.into()),
+                            markers: Box::new([]),
+                        })
+                    })?;
+                    if module.code_section.content.len() != module.func_section.content.len() {
+                        return section_reader
+                            .error("function and code sections have different lengths".to_owned());
+                    }
+                }
+                11 => decode_vec(&mut section_reader, &mut module.data_section.content, |r| {
+                    let mem_idx = MemIdx { idx: r.read_u32()? };
+                    let offset = r.read_expr()?;
+                    let len = r.read_u32()?;
+                    Ok(Data {
+                        mem_idx: mem_idx,
+                        offset: offset,
+                        content: r.read_bytes(len as usize)?.into(),
+                    })
+                })?,
+                12 => return reader.error("data count section is not supported".to_owned()),
+                _ => return reader.error(format!("unknown section {}", id)),
+            }
+            if !section_reader.is_empty() {
+                return section_reader
+                    .error(format!("unexpected bytes at the end of section {}", id));
+            }
+        }
+        if !has_code_section && !module.func_section.content.is_empty() {
+            return reader.error("missing code section".to_owned());
+        }
+        Ok(module)
+    }
+}
+
+// Reads a vector (a length followed by that number of elements), appending the elements to `out`
+fn decode_vec<'a, T, F: FnMut(&mut Reader<'a>) -> Result<T, DecodeError>>(
+    reader: &mut Reader<'a>,
+    out: &mut Vec<T>,
+    mut read_elem: F,
+) -> Result<(), DecodeError> {
+    let len = reader.read_u32()?;
+    for _ in 0..len {
+        out.push(read_elem(reader)?);
+    }
+    Ok(())
+}
+
+fn read_valtypes(reader: &mut Reader) -> Result<Box<[ValType]>, DecodeError> {
+    let mut valtypes: Vec<ValType> = Vec::new();
+    decode_vec(reader, &mut valtypes, |r| r.read_valtype())?;
+    Ok(valtypes.into_boxed_slice())
+}
+
+fn decode_type_section(
+    reader: &mut Reader,
+    type_section: &mut TypeSection,
+) -> Result<(), DecodeError> {
+    let len = reader.read_u32()?;
+    for i in 0..len {
+        if reader.read_byte()? != 0x60 {
+            return reader.byte_error("invalid function type".to_owned());
+        }
+        let param_types = read_valtypes(reader)?;
+        let result_types = read_valtypes(reader)?;
+        // the TypeSection deduplicates the types, which would shift the indices of the types after the duplicate
+        if type_section
+            .content
+            .insert(FuncType::new(param_types, result_types))
+            != i as usize
+        {
+            return reader.error("duplicate function types are not supported".to_owned());
+        }
+    }
+    Ok(())
+}
+
+fn decode_import_section(reader: &mut Reader, module: &mut WasmModule) -> Result<(), DecodeError> {
+    decode_vec(reader, &mut module.import_section.content, |r| {
+        let module_name = r.read_name()?;
+        let entity_name = r.read_name()?;
+        let desc = match r.read_byte()? {
+            0x00 => ImportDesc::Func(TypeIdx { idx: r.read_u32()? }),
+            0x01 => ImportDesc::Table(read_table_type(r)?),
+            0x02 => ImportDesc::Mem(MemType {
+                limits: r.read_limits()?,
+            }),
+            0x03 => ImportDesc::Global(read_global_type(r)?),
+            byte => return r.byte_error(format!("invalid import kind 0x{:02X}", byte)),
+        };
+        Ok(Import {
+            module_name: module_name,
+            entity_name: entity_name,
+            desc: desc,
+        })
+    })?;
+    // the indices of the entities defined in the module come after the imported ones
+    for import in &module.import_section.content {
+        match import.desc {
+            ImportDesc::Func(_) => module.func_section.idx_offset += 1,
+            ImportDesc::Table(_) => module.table_section.idx_offset += 1,
+            ImportDesc::Mem(_) => module.mem_section.idx_offset += 1,
+            ImportDesc::Global(_) => module.global_section.idx_offset += 1,
+        }
+    }
+    Ok(())
+}
+
+fn read_table_type(reader: &mut Reader) -> Result<TableType, DecodeError> {
+    match reader.read_byte()? {
+        0x70 => Ok(TableType {
+            elem_type: ElemType::FuncRef,
+            limits: reader.read_limits()?,
+        }),
+        byte => reader.byte_error(format!("invalid element type 0x{:02X}", byte)),
+    }
+}
+
+fn read_global_type(reader: &mut Reader) -> Result<GlobalType, DecodeError> {
+    let val_type = reader.read_valtype()?;
+    let mutability = match reader.read_byte()? {
+        0x00 => Mut::Const,
+        0x01 => Mut::Var,
+        byte => return reader.byte_error(format!("invalid mutability 0x{:02X}", byte)),
+    };
+    Ok(GlobalType {
+        val_type: val_type,
+        mutability: mutability,
+    })
+}
+
+fn read_export_desc(reader: &mut Reader) -> Result<ExportDesc, DecodeError> {
+    match reader.read_byte()? {
+        0x00 => Ok(ExportDesc::Func(FuncIdx {
+            idx: reader.read_u32()?,
+        })),
+        0x01 => Ok(ExportDesc::Table(TableIdx {
+            idx: reader.read_u32()?,
+        })),
+        0x02 => Ok(ExportDesc::Mem(MemIdx {
+            idx: reader.read_u32()?,
+        })),
+        0x03 => Ok(ExportDesc::Global(GlobalIdx {
+            idx: reader.read_u32()?,
+        })),
+        kind => reader.byte_error(format!("invalid export kind 0x{:02X}", kind)),
+    }
+}
+
+fn decode_custom_section(reader: &mut Reader, module: &mut WasmModule) -> Result<(), DecodeError> {
+    let name = reader.read_name()?;
+    if name == "name" {
+        return decode_name_section(reader, &mut module.name_section);
+    }
+    let content = reader.read_bytes(reader.remaining())?;
+    module.custom_sections.push(CustomSection {
+        name: name,
+        content: content.into(),
+    });
+    Ok(())
+}
+
+// Subsections other than function and local names (e.g. the module name) are skipped
+fn decode_name_section(
+    reader: &mut Reader,
+    name_section: &mut NameSection,
+) -> Result<(), DecodeError> {
+    while !reader.is_empty() {
+        let id = reader.read_byte()?;
+        let size = reader.read_u32()?;
+        let mut subsection_reader = reader.sub_reader(size as usize)?;
+        match id {
+            1 => read_name_map(&mut subsection_reader, &mut name_section.func_names)?,
+            2 => {
+                let len = subsection_reader.read_u32()?;
+                for _ in 0..len {
+                    let funcidx = subsection_reader.read_u32()?;
+                    read_name_map(
+                        &mut subsection_reader,
+                        name_section.local_names.entry(funcidx).or_default(),
+                    )?;
+                }
+            }
+            _ => {
+                subsection_reader.read_bytes(subsection_reader.remaining())?;
+            }
+        }
+        if !subsection_reader.is_empty() {
+            return subsection_reader
+                .error("unexpected bytes at the end of name subsection".to_owned());
+        }
+    }
+    Ok(())
+}
+
+fn read_name_map(
+    reader: &mut Reader,
+    names: &mut BTreeMap<u32, String>,
+) -> Result<(), DecodeError> {
+    let len = reader.read_u32()?;
+    for _ in 0..len {
+        let idx = reader.read_u32()?;
+        names.insert(idx, reader.read_name()?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(module: &WasmModule) -> Vec<u8> {
+        let mut bytes = Vec::<u8>::new();
+        module.wasm_serialize(&mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut builder = WasmModule::new_builder();
+        let functype = FuncType::new(Box::new([ValType::I32]), Box::new([ValType::I32]));
+        let imported = builder.import_func("core".to_owned(), "f".to_owned(), &functype);
+        let mut module = builder.build();
+        let memidx = module.add_bounded_memory(2, 4);
+        module.add_data(memidx, 8, b"data");
+        module.export_mem(memidx, "memory".to_owned());
+        let globalidx = module.add_f64_global(Mut::Var, 1.5);
+        let (_, funcidx) = module.register_func(&functype);
+        let mut code_builder = CodeBuilder::new(functype);
+        {
+            let (locals_builder, expr_builder) = code_builder.split();
+            let localidx = locals_builder.add(ValType::F64);
+            expr_builder.global_get(globalidx);
+            expr_builder.local_set(localidx);
+            expr_builder.local_get(LocalIdx { idx: 0 });
+            expr_builder.call(imported);
+            expr_builder.end();
+        }
+        module.commit_func(funcidx, code_builder);
+        let tableidx = module.get_or_add_table();
+        let elem_offset = module.reserve_table_elements(tableidx, 2);
+        module.commit_table_elements(tableidx, elem_offset, Box::new([imported, funcidx]));
+        module.export_func(funcidx, "main".to_owned());
+        module.set_func_name(funcidx, "main".to_owned());
+        module.set_local_name(funcidx, LocalIdx { idx: 1 }, "x".to_owned());
+        module.add_custom_section("extra".to_owned(), Box::new([1, 2, 3]));
+
+        let bytes = serialize(&module);
+        let decoded = WasmModule::decode(&bytes).unwrap();
+        assert_eq!(serialize(&decoded), bytes);
+        assert_eq!(decoded.to_string(), module.to_string());
+    }
+
+    #[test]
+    fn errors() {
+        let bytes = serialize(&WasmModule::new_builder().build());
+        assert!(WasmModule::decode(&bytes).is_ok());
+        assert!(WasmModule::decode(&bytes[..7]).is_err());
+        assert_eq!(
+            WasmModule::decode(b"\0asm\x01\0\0\0\x01\x05\x01\x60\x00\x01\x7B")
+                .err()
+                .unwrap()
+                .to_string(),
+            "at byte 14: invalid value type 0x7B"
+        );
+        // a function without a code section
+        assert!(
+            WasmModule::decode(b"\0asm\x01\0\0\0\x01\x04\x01\x60\x00\x00\x03\x02\x01\x00").is_err()
+        );
+    }
+}
@@ -0,0 +1,349 @@
+/**
+ * Decoding of instructions from bytecode (the inverse of the ExprBuilder), used by the text format printer, the decoder and the validator.
+ */
+use super::*;
+
+pub(crate) struct Instr {
+    pub opcode: OpCode,
+    pub immediate: Immediate,
+}
+
+pub(crate) enum Immediate {
+    None,
+    BlockType(BlockType),
+    Label(u32),
+    LabelTable(Box<[u32]>, u32),
+    Func(FuncIdx),
+    CallIndirect(TypeIdx, TableIdx),
+    Local(LocalIdx),
+    Global(GlobalIdx),
+    MemArg(MemArg),
+    Mem(MemIdx),
+    DataMem(u32, MemIdx),
+    Data(u32),
+    MemMem(MemIdx, MemIdx),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+pub(crate) enum BlockType {
+    Empty,
+    Value(ValType),
+    TypeIdx(TypeIdx), // only with the multi-value proposal
+}
+
+/**
+ * Reads values in the WebAssembly binary encoding from a byte slice.
+ */
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader {
+            bytes: bytes,
+            pos: 0,
+        }
+    }
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+    pub fn error<T>(&self, message: String) -> Result<T, DecodeError> {
+        Err(DecodeError {
+            offset: self.pos,
+            message: message,
+        })
+    }
+    // Like `error()`, but reports the offset of the byte that was just read (e.g. an invalid type or flag)
+    pub fn byte_error<T>(&self, message: String) -> Result<T, DecodeError> {
+        Err(DecodeError {
+            offset: self.pos - 1,
+            message: message,
+        })
+    }
+    pub fn read_byte(&mut self) -> Result<u8, DecodeError> {
+        match self.bytes.get(self.pos) {
+            Some(byte) => {
+                self.pos += 1;
+                Ok(*byte)
+            }
+            None => self.error("unexpected end of input".to_owned()),
+        }
+    }
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() - self.pos < len {
+            return self.error("unexpected end of input".to_owned());
+        }
+        let ret = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(ret)
+    }
+    /**
+     * Returns a reader for the next `len` bytes (which keeps reporting offsets from the start of the input), and skips over them.
+     */
+    pub fn sub_reader(&mut self, len: usize) -> Result<Reader<'a>, DecodeError> {
+        let start = self.pos;
+        self.read_bytes(len)?;
+        Ok(Reader {
+            bytes: &self.bytes[..self.pos],
+            pos: start,
+        })
+    }
+    // Reads a signed or unsigned LEB128 integer of at most `bits` bits
+    fn read_leb(&mut self, bits: u32, signed: bool) -> Result<i64, DecodeError> {
+        let mut result: i64 = 0;
+        let mut shift: u32 = 0;
+        loop {
+            let byte = self.read_byte()?;
+            if shift >= bits {
+                return self.error("integer representation too long".to_owned());
+            }
+            result |= ((byte & 0x7F) as i64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if signed && shift < 64 && byte & 0x40 != 0 {
+                    result |= -1i64 << shift;
+                }
+                return Ok(result);
+            }
+        }
+    }
+    pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        let val = self.read_leb(32, false)?;
+        if val > u32::MAX as i64 {
+            return self.error("integer too large".to_owned());
+        }
+        Ok(val as u32)
+    }
+    pub fn read_i32(&mut self) -> Result<i32, DecodeError> {
+        let val = self.read_leb(32, true)?;
+        if val < i32::MIN as i64 || val > i32::MAX as i64 {
+            return self.error("integer too large".to_owned());
+        }
+        Ok(val as i32)
+    }
+    pub fn read_i64(&mut self) -> Result<i64, DecodeError> {
+        self.read_leb(64, true)
+    }
+    pub fn read_f32(&mut self) -> Result<f32, DecodeError> {
+        let bytes = self.read_bytes(4)?;
+        Ok(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    pub fn read_f64(&mut self) -> Result<f64, DecodeError> {
+        let bytes = self.read_bytes(8)?;
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(bytes);
+        Ok(f64::from_le_bytes(buf))
+    }
+    pub fn read_valtype(&mut self) -> Result<ValType, DecodeError> {
+        let byte = self.read_byte()?;
+        match byte {
+            0x7F => Ok(ValType::I32),
+            0x7E => Ok(ValType::I64),
+            0x7D => Ok(ValType::F32),
+            0x7C => Ok(ValType::F64),
+            _ => self.byte_error(format!("invalid value type 0x{:02X}", byte)),
+        }
+    }
+    pub fn read_name(&mut self) -> Result<String, DecodeError> {
+        let len = self.read_u32()?;
+        let start = self.pos;
+        let bytes = self.read_bytes(len as usize)?;
+        match std::str::from_utf8(bytes) {
+            Ok(name) => Ok(name.to_owned()),
+            Err(_) => Err(DecodeError {
+                offset: start,
+                message: "invalid UTF-8 in name".to_owned(),
+            }),
+        }
+    }
+    pub fn read_limits(&mut self) -> Result<Limits, DecodeError> {
+        match self.read_byte()? {
+            0x00 => Ok(Limits::Unbounded {
+                min: self.read_u32()?,
+            }),
+            0x01 => {
+                let min = self.read_u32()?;
+                Ok(Limits::Bounded {
+                    min: min,
+                    max: self.read_u32()?,
+                })
+            }
+            byte => self.byte_error(format!("invalid limits flag 0x{:02X}", byte)),
+        }
+    }
+    /**
+     * Reads the types of the locals at the start of a function body (as written by the CodeBuilder).
+     */
+    pub fn read_locals(&mut self) -> Result<Vec<ValType>, DecodeError> {
+        let num_groups = self.read_u32()?;
+        let mut valtypes: Vec<ValType> = Vec::new();
+        for _ in 0..num_groups {
+            let count = self.read_u32()?;
+            let valtype = self.read_valtype()?;
+            // the limit imposed by the JavaScript API, which also stops us from allocating too much for invalid input
+            if valtypes.len() + count as usize > 50000 {
+                return self.error("too many locals".to_owned());
+            }
+            valtypes.resize(valtypes.len() + count as usize, valtype);
+        }
+        Ok(valtypes)
+    }
+    /**
+     * Reads an expression up to and including its final `end` (without checking that the instructions are valid).
+     */
+    pub fn read_expr(&mut self) -> Result<Expr, DecodeError> {
+        let start = self.pos;
+        let mut depth: usize = 0;
+        loop {
+            match self.read_instr()?.opcode {
+                OpCode::Block | OpCode::Loop | OpCode::If => depth += 1,
+                OpCode::End => {
+                    if depth == 0 {
+                        break;
+                    }
+                    depth -= 1;
+                }
+                _ => {}
+            }
+        }
+        Ok(Expr {
+            bytecode: self.bytes[start..self.pos].into(),
+        })
+    }
+    fn read_zero_byte(&mut self) -> Result<MemIdx, DecodeError> {
+        match self.read_byte()? {
+            0 => Ok(MemIdx { idx: 0 }),
+            _ => self.byte_error("expected a zero byte".to_owned()),
+        }
+    }
+    fn read_blocktype(&mut self) -> Result<BlockType, DecodeError> {
+        match self.bytes.get(self.pos) {
+            Some(0x40) => {
+                self.pos += 1;
+                Ok(BlockType::Empty)
+            }
+            Some(0x7C..=0x7F) => Ok(BlockType::Value(self.read_valtype()?)),
+            _ => {
+                let val = self.read_leb(33, true)?;
+                if val < 0 {
+                    return self.error("invalid block type".to_owned());
+                }
+                Ok(BlockType::TypeIdx(TypeIdx { idx: val as u32 }))
+            }
+        }
+    }
+    /**
+     * Reads one instruction (including its immediates).
+     */
+    pub fn read_instr(&mut self) -> Result<Instr, DecodeError> {
+        let start = self.pos;
+        let opcode: OpCode = match ALL_OPCODES
+            .iter()
+            .find(|opcode| self.bytes[start..].starts_with(opcode.value()))
+        {
+            Some(opcode) => *opcode,
+            None => {
+                return self.error(format!(
+                    "unknown opcode 0x{:02X}",
+                    self.bytes.get(start).copied().unwrap_or(0)
+                ))
+            }
+        };
+        self.pos += opcode.value().len();
+        let immediate = match opcode.immediate_kind() {
+            ImmediateKind::None => Immediate::None,
+            ImmediateKind::BlockType => Immediate::BlockType(self.read_blocktype()?),
+            ImmediateKind::Label => Immediate::Label(self.read_u32()?),
+            ImmediateKind::LabelTable => {
+                let len = self.read_u32()?;
+                let labels = (0..len)
+                    .map(|_| self.read_u32())
+                    .collect::<Result<Box<[u32]>, DecodeError>>()?;
+                Immediate::LabelTable(labels, self.read_u32()?)
+            }
+            ImmediateKind::Func => Immediate::Func(FuncIdx {
+                idx: self.read_u32()?,
+            }),
+            ImmediateKind::CallIndirect => {
+                let typeidx = TypeIdx {
+                    idx: self.read_u32()?,
+                };
+                Immediate::CallIndirect(
+                    typeidx,
+                    TableIdx {
+                        idx: self.read_u32()?,
+                    },
+                )
+            }
+            ImmediateKind::Local => Immediate::Local(LocalIdx {
+                idx: self.read_u32()?,
+            }),
+            ImmediateKind::Global => Immediate::Global(GlobalIdx {
+                idx: self.read_u32()?,
+            }),
+            ImmediateKind::MemArg => {
+                let align = self.read_u32()?;
+                Immediate::MemArg(MemArg {
+                    offset: self.read_u32()?,
+                    align: align,
+                })
+            }
+            ImmediateKind::Mem => Immediate::Mem(self.read_zero_byte()?),
+            ImmediateKind::DataMem => {
+                let dataidx = self.read_u32()?;
+                Immediate::DataMem(dataidx, self.read_zero_byte()?)
+            }
+            ImmediateKind::Data => Immediate::Data(self.read_u32()?),
+            ImmediateKind::MemMem => {
+                let dest = self.read_zero_byte()?;
+                Immediate::MemMem(dest, self.read_zero_byte()?)
+            }
+            ImmediateKind::I32 => Immediate::I32(self.read_i32()?),
+            ImmediateKind::I64 => Immediate::I64(self.read_i64()?),
+            ImmediateKind::F32 => Immediate::F32(self.read_f32()?),
+            ImmediateKind::F64 => Immediate::F64(self.read_f64()?),
+        };
+        Ok(Instr {
+            opcode: opcode,
+            immediate: immediate,
+        })
+    }
+}
+
+// Returns the natural alignment (as the logarithm of the number of bytes) of a load or store instruction
+pub(crate) fn natural_alignment(opcode: OpCode) -> Option<u32> {
+    match opcode {
+        OpCode::I32Load8S
+        | OpCode::I32Load8U
+        | OpCode::I64Load8S
+        | OpCode::I64Load8U
+        | OpCode::I32Store8
+        | OpCode::I64Store8 => Some(0),
+        OpCode::I32Load16S
+        | OpCode::I32Load16U
+        | OpCode::I64Load16S
+        | OpCode::I64Load16U
+        | OpCode::I32Store16
+        | OpCode::I64Store16 => Some(1),
+        OpCode::I32Load
+        | OpCode::F32Load
+        | OpCode::I64Load32S
+        | OpCode::I64Load32U
+        | OpCode::I32Store
+        | OpCode::F32Store
+        | OpCode::I64Store32 => Some(2),
+        OpCode::I64Load | OpCode::F64Load | OpCode::I64Store | OpCode::F64Store => Some(3),
+        _ => None,
+    }
+}
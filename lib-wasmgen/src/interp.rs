@@ -0,0 +1,1251 @@
+/**
+ * A simple interpreter for WasmModules, so that generated code (e.g. the wasmtest suite) can be run natively without a WebAssembly engine.
+ * The module is validated when it is instantiated, so the interpreter can assume that the code is well-typed
+ * (values are stored as untyped 64-bit words, in the same way as they are stored in memory).
+ *
+ * Only functions can be imported (the memory, table and globals must be defined by the module).
+ * Traps use the same messages as the reference interpreter where possible, e.g. "integer divide by zero".
+ */
+use super::instr::*;
+use super::*;
+
+const WASM_PAGE_SIZE: usize = 1 << 16;
+const WASM_MAX_PAGES: usize = 1 << 16;
+const MAX_CALL_DEPTH: usize = 10000;
+
+/**
+ * A value passed to or returned from a function.
+ */
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+impl Value {
+    pub fn valtype(&self) -> ValType {
+        match self {
+            Value::I32(_) => ValType::I32,
+            Value::I64(_) => ValType::I64,
+            Value::F32(_) => ValType::F32,
+            Value::F64(_) => ValType::F64,
+        }
+    }
+    fn from_raw(valtype: ValType, raw: u64) -> Value {
+        match valtype {
+            ValType::I32 => Value::I32(i32::from_raw(raw)),
+            ValType::I64 => Value::I64(i64::from_raw(raw)),
+            ValType::F32 => Value::F32(f32::from_raw(raw)),
+            ValType::F64 => Value::F64(f64::from_raw(raw)),
+        }
+    }
+    fn to_raw(self) -> u64 {
+        match self {
+            Value::I32(val) => val.to_raw(),
+            Value::I64(val) => val.to_raw(),
+            Value::F32(val) => val.to_raw(),
+            Value::F64(val) => val.to_raw(),
+        }
+    }
+}
+
+/**
+ * A trap raised by the code (or by the host), which stops the execution of the invoked function.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trap {
+    pub message: String,
+}
+
+fn trap<T>(message: &str) -> Result<T, Trap> {
+    Err(Trap {
+        message: message.to_owned(),
+    })
+}
+
+/**
+ * The environment that provides the imported functions.
+ * `args` match the param types of the import, and the returned values should match its result types.
+ * `caller` lets the import call back into the instance (and access its memory) before it returns.
+ */
+pub trait Host {
+    fn call_import(
+        &mut self,
+        module_name: &str,
+        entity_name: &str,
+        args: &[Value],
+        caller: &mut Caller,
+    ) -> Result<Box<[Value]>, Trap>;
+}
+
+// Conversion between values of each type and their untyped representation on the stack
+trait Raw: Copy {
+    fn from_raw(raw: u64) -> Self;
+    fn to_raw(self) -> u64;
+}
+
+impl Raw for i32 {
+    fn from_raw(raw: u64) -> Self {
+        raw as u32 as i32
+    }
+    fn to_raw(self) -> u64 {
+        self as u32 as u64
+    }
+}
+
+impl Raw for u32 {
+    fn from_raw(raw: u64) -> Self {
+        raw as u32
+    }
+    fn to_raw(self) -> u64 {
+        self as u64
+    }
+}
+
+impl Raw for i64 {
+    fn from_raw(raw: u64) -> Self {
+        raw as i64
+    }
+    fn to_raw(self) -> u64 {
+        self as u64
+    }
+}
+
+impl Raw for u64 {
+    fn from_raw(raw: u64) -> Self {
+        raw
+    }
+    fn to_raw(self) -> u64 {
+        self
+    }
+}
+
+impl Raw for f32 {
+    fn from_raw(raw: u64) -> Self {
+        f32::from_bits(raw as u32)
+    }
+    fn to_raw(self) -> u64 {
+        self.to_bits() as u64
+    }
+}
+
+impl Raw for f64 {
+    fn from_raw(raw: u64) -> Self {
+        f64::from_bits(raw)
+    }
+    fn to_raw(self) -> u64 {
+        self.to_bits()
+    }
+}
+
+impl Raw for bool {
+    fn from_raw(raw: u64) -> Self {
+        raw != 0
+    }
+    fn to_raw(self) -> u64 {
+        self as u64
+    }
+}
+
+// Information about a block, loop, if or else instruction, computed when the function is decoded
+#[derive(Default, Copy, Clone)]
+struct BlockInfo {
+    end: usize,           // index of the matching `end`
+    else_: Option<usize>, // index of the matching `else` (for `if` only)
+    num_params: usize,
+    num_results: usize,
+}
+
+struct FuncCode {
+    num_params: usize,
+    num_results: usize,
+    locals: Box<[ValType]>, // excluding the params
+    instrs: Box<[Instr]>,
+    blocks: Box<[BlockInfo]>, // indexed by the instruction
+}
+
+#[derive(Copy, Clone)]
+struct Label {
+    cont: usize,   // index of the instruction to continue at after branching to this label
+    arity: usize,  // number of values passed to the label when branching
+    height: usize, // height of the operand stack (excluding the params of the block) when the block was entered
+    is_loop: bool,
+}
+
+#[derive(Copy, Clone)]
+struct Frame {
+    func: usize, // index into `funcs` (i.e. excluding the imported funcs)
+    pc: usize,   // the instruction to continue at when the callee returns
+    locals_base: usize,
+    label_base: usize, // the label of the function body itself
+}
+
+/**
+ * The mutable state of an instance.
+ */
+struct Machine {
+    memory: Vec<u8>,
+    max_pages: usize,
+    globals: Vec<u64>,
+    table: Vec<Option<FuncIdx>>,
+    dropped_data: Vec<bool>,
+    stack: Vec<u64>,
+    locals: Vec<u64>,
+    labels: Vec<Label>,
+    frames: Vec<Frame>,
+}
+
+/**
+ * The code of an instance, which does not change while it runs.
+ */
+struct Code<'a> {
+    module: &'a WasmModule,
+    imported_funcs: Vec<(&'a Import, &'a FuncType)>,
+    funcs: Vec<FuncCode>,
+}
+
+/**
+ * An instantiated module, whose exported functions can be invoked.
+ */
+pub struct Instance<'a> {
+    code: Code<'a>,
+    machine: Machine,
+}
+
+/**
+ * The instance that called an import, which the host can call back into while the import runs.
+ */
+pub struct Caller<'a, 'b> {
+    code: &'b Code<'a>,
+    machine: &'b mut Machine,
+}
+
+impl<'a> Instance<'a> {
+    /**
+     * Validates and instantiates the module, initializes the memory and table, and runs the start function (if any).
+     */
+    pub fn new<H: Host>(module: &'a WasmModule, host: &mut H) -> Result<Self, Trap> {
+        if let Err(e) = module.validate() {
+            return Err(Trap {
+                message: format!("invalid module: {}", e),
+            });
+        }
+        let types: &[FuncType] = module.type_section.content.vec();
+        let mut imported_funcs: Vec<(&'a Import, &'a FuncType)> = Vec::new();
+        for import in &module.import_section.content {
+            match import.desc {
+                ImportDesc::Func(typeidx) => imported_funcs.push((
+                    import,
+                    &module.type_section.content.vec()[typeidx.idx as usize],
+                )),
+                ImportDesc::Table(_) | ImportDesc::Mem(_) | ImportDesc::Global(_) => {
+                    return trap("only functions can be imported")
+                }
+            }
+        }
+        let funcs: Vec<FuncCode> = module
+            .func_section
+            .content
+            .iter()
+            .zip(module.code_section.content.iter())
+            .map(|(typeidx, code)| {
+                decode_func(
+                    types,
+                    &types[typeidx.idx as usize],
+                    code.func.as_ref().unwrap(),
+                )
+            })
+            .collect();
+        let (memory, max_pages) = match module.mem_section.content.first() {
+            Some(mem) => match mem.mem_type.limits {
+                Limits::Unbounded { min } => (min as usize, WASM_MAX_PAGES),
+                Limits::Bounded { min, max } => (min as usize, max as usize),
+            },
+            None => (0, 0),
+        };
+        let table_size = match module.table_section.content.first() {
+            Some(table) => match table.table_type.limits {
+                Limits::Unbounded { min } => min,
+                Limits::Bounded { min, max: _ } => min,
+            },
+            None => 0,
+        };
+        let mut globals: Vec<u64> = Vec::new();
+        for global in &module.global_section.content {
+            let val = eval_const_expr(&global.init_expr, &globals);
+            globals.push(val);
+        }
+        let mut machine = Machine {
+            memory: vec![0; memory * WASM_PAGE_SIZE],
+            max_pages: max_pages,
+            globals: globals,
+            table: vec![None; table_size as usize],
+            dropped_data: vec![false; module.data_section.content.len()],
+            stack: Vec::new(),
+            locals: Vec::new(),
+            labels: Vec::new(),
+            frames: Vec::new(),
+        };
+        for elem in &module.elem_section.content {
+            let offset = eval_const_expr(&elem.offset, &machine.globals) as u32 as usize;
+            match machine.table.get_mut(offset..offset + elem.content.len()) {
+                Some(entries) => {
+                    for (entry, funcidx) in entries.iter_mut().zip(elem.content.iter()) {
+                        *entry = Some(*funcidx);
+                    }
+                }
+                None => return trap("out of bounds table access"),
+            }
+        }
+        for data in &module.data_section.content {
+            let offset = eval_const_expr(&data.offset, &machine.globals) as u32 as usize;
+            match machine.memory.get_mut(offset..offset + data.content.len()) {
+                Some(bytes) => bytes.copy_from_slice(&data.content),
+                None => return trap("out of bounds memory access"),
+            }
+        }
+        let mut instance = Instance {
+            code: Code {
+                module: module,
+                imported_funcs: imported_funcs,
+                funcs: funcs,
+            },
+            machine: machine,
+        };
+        if let Some(funcidx) = module.start_section.start {
+            instance.code.call(&mut instance.machine, funcidx, host)?;
+        }
+        Ok(instance)
+    }
+
+    /**
+     * Invokes an exported function with the given arguments, and returns its results.
+     */
+    pub fn invoke<H: Host>(
+        &mut self,
+        export_name: &str,
+        args: &[Value],
+        host: &mut H,
+    ) -> Result<Box<[Value]>, Trap> {
+        self.code.invoke(&mut self.machine, export_name, args, host)
+    }
+
+    /**
+     * The contents of the linear memory (empty if the module has no memory).
+     */
+    pub fn memory(&self) -> &[u8] {
+        &self.machine.memory
+    }
+}
+
+impl<'a, 'b> Caller<'a, 'b> {
+    /**
+     * Invokes an exported function of the calling instance, in the same way as Instance::invoke().
+     */
+    pub fn invoke<H: Host>(
+        &mut self,
+        export_name: &str,
+        args: &[Value],
+        host: &mut H,
+    ) -> Result<Box<[Value]>, Trap> {
+        self.code.invoke(self.machine, export_name, args, host)
+    }
+
+    /**
+     * The contents of the linear memory of the calling instance.
+     */
+    pub fn memory(&self) -> &[u8] {
+        &self.machine.memory
+    }
+
+    /**
+     * The contents of the linear memory of the calling instance, e.g. to write the arguments of a callback.
+     */
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.machine.memory
+    }
+}
+
+impl<'a> Code<'a> {
+    fn invoke<H: Host>(
+        &self,
+        m: &mut Machine,
+        export_name: &str,
+        args: &[Value],
+        host: &mut H,
+    ) -> Result<Box<[Value]>, Trap> {
+        let funcidx = match self
+            .module
+            .export_section
+            .content
+            .iter()
+            .find(|export| export.entity_name == export_name)
+        {
+            Some(Export {
+                entity_name: _,
+                desc: ExportDesc::Func(funcidx),
+            }) => *funcidx,
+            _ => return trap(&format!("unknown exported function \"{}\"", export_name)),
+        };
+        let functype = self.functype(funcidx);
+        if args
+            .iter()
+            .map(|arg| arg.valtype())
+            .ne(functype.param_types.iter().copied())
+        {
+            return trap("wrong argument types");
+        }
+        m.stack.extend(args.iter().map(|arg| arg.to_raw()));
+        self.call(m, funcidx, host)?;
+        let results_start = m.stack.len() - functype.result_types.len();
+        let results = functype
+            .result_types
+            .iter()
+            .zip(m.stack.drain(results_start..))
+            .map(|(valtype, raw)| Value::from_raw(*valtype, raw))
+            .collect();
+        Ok(results)
+    }
+
+    fn functype(&self, funcidx: FuncIdx) -> &'a FuncType {
+        let funcidx = funcidx.idx as usize;
+        if funcidx < self.imported_funcs.len() {
+            self.imported_funcs[funcidx].1
+        } else {
+            let typeidx = self.module.func_section.content[funcidx - self.imported_funcs.len()];
+            &self.module.type_section.content.vec()[typeidx.idx as usize]
+        }
+    }
+
+    // Calls the function with the arguments on the stack, leaving its results on the stack
+    // If it traps, then everything it left on the machine is removed, so that the instance can be used again
+    // (this also restores the state of the caller, if the function was called back by an import).
+    fn call<H: Host>(&self, m: &mut Machine, funcidx: FuncIdx, host: &mut H) -> Result<(), Trap> {
+        let stack_height = m.stack.len() - self.functype(funcidx).param_types.len();
+        let (locals_height, labels_height, frames_height) =
+            (m.locals.len(), m.labels.len(), m.frames.len());
+        let result = if (funcidx.idx as usize) < self.imported_funcs.len() {
+            call_host(self, m, self.imported_funcs[funcidx.idx as usize], host)
+        } else {
+            self.execute(m, funcidx.idx as usize - self.imported_funcs.len(), host)
+        };
+        if result.is_err() {
+            m.stack.truncate(stack_height);
+            m.locals.truncate(locals_height);
+            m.labels.truncate(labels_height);
+            m.frames.truncate(frames_height);
+        }
+        result
+    }
+
+    // Runs a function defined in the module (not an import)
+    fn execute<H: Host>(&self, m: &mut Machine, func: usize, host: &mut H) -> Result<(), Trap> {
+        let types: &[FuncType] = self.module.type_section.content.vec();
+        let imported_funcs = &self.imported_funcs;
+        let funcs = &self.funcs;
+        let num_imported_funcs = imported_funcs.len();
+        let base_depth = m.frames.len();
+        m.push_frame(funcs, func)?;
+        let mut frame: Frame = *m.frames.last().unwrap();
+        let mut code: &FuncCode = &funcs[func];
+        let mut pc: usize = 0;
+
+        macro_rules! pop {
+            ($t:ty) => {
+                <$t>::from_raw(m.stack.pop().unwrap())
+            };
+        }
+        macro_rules! push {
+            ($val:expr) => {
+                m.stack.push(Raw::to_raw($val))
+            };
+        }
+        macro_rules! unop {
+            ($a:ty, $r:ty, $f:expr) => {{
+                let f: fn($a) -> $r = $f;
+                let a = pop!($a);
+                push!(f(a));
+            }};
+        }
+        macro_rules! unop_trap {
+            ($a:ty, $r:ty, $f:expr) => {{
+                let f: fn($a) -> Result<$r, Trap> = $f;
+                let a = pop!($a);
+                push!(f(a)?);
+            }};
+        }
+        macro_rules! binop {
+            ($a:ty, $r:ty, $f:expr) => {{
+                let f: fn($a, $a) -> $r = $f;
+                let b = pop!($a);
+                let a = pop!($a);
+                push!(f(a, b));
+            }};
+        }
+        macro_rules! binop_trap {
+            ($a:ty, $r:ty, $f:expr) => {{
+                let f: fn($a, $a) -> Result<$r, Trap> = $f;
+                let b = pop!($a);
+                let a = pop!($a);
+                push!(f(a, b)?);
+            }};
+        }
+        macro_rules! memarg {
+            () => {
+                match &code.instrs[pc - 1].immediate {
+                    Immediate::MemArg(memarg) => memarg.offset,
+                    _ => unreachable!(),
+                }
+            };
+        }
+        macro_rules! load {
+            ($n:expr, $f:expr) => {{
+                let f: fn([u8; $n]) -> u64 = $f;
+                let addr = effective_address(pop!(u32), memarg!(), $n, m.memory.len())?;
+                let mut bytes = [0u8; $n];
+                bytes.copy_from_slice(&m.memory[addr..addr + $n]);
+                m.stack.push(f(bytes));
+            }};
+        }
+        macro_rules! store {
+            ($n:expr, $f:expr) => {{
+                let f: fn(u64) -> [u8; $n] = $f;
+                let val = pop!(u64);
+                let addr = effective_address(pop!(u32), memarg!(), $n, m.memory.len())?;
+                m.memory[addr..addr + $n].copy_from_slice(&f(val));
+            }};
+        }
+        // calls the given function, and continues with the next instruction after it returns
+        macro_rules! call {
+            ($funcidx:expr) => {{
+                let funcidx = $funcidx.idx as usize;
+                if funcidx < num_imported_funcs {
+                    call_host(self, m, imported_funcs[funcidx], host)?;
+                } else {
+                    m.frames.last_mut().unwrap().pc = pc;
+                    m.push_frame(funcs, funcidx - num_imported_funcs)?;
+                    frame = *m.frames.last().unwrap();
+                    code = &funcs[frame.func];
+                    pc = 0;
+                }
+            }};
+        }
+        // replaces the current function with the given function (passing it the arguments on the stack)
+        macro_rules! return_call {
+            ($funcidx:expr) => {{
+                let funcidx = $funcidx.idx as usize;
+                if funcidx < num_imported_funcs {
+                    call_host(self, m, imported_funcs[funcidx], host)?;
+                    pc = m.branch(m.labels.len() - 1 - frame.label_base);
+                } else {
+                    let num_args = funcs[funcidx - num_imported_funcs].num_params;
+                    let height = m.labels[frame.label_base].height;
+                    let args_start = m.stack.len() - num_args;
+                    m.stack.copy_within(args_start.., height);
+                    m.stack.truncate(height + num_args);
+                    m.labels.truncate(frame.label_base);
+                    m.locals.truncate(frame.locals_base);
+                    m.frames.pop();
+                    m.push_frame(funcs, funcidx - num_imported_funcs)?;
+                    frame = *m.frames.last().unwrap();
+                    code = &funcs[frame.func];
+                    pc = 0;
+                }
+            }};
+        }
+        // pops the table index, and returns the function after checking its type
+        macro_rules! indirect_callee {
+            ($typeidx:expr) => {{
+                let funcidx = match m.table.get(pop!(u32) as usize) {
+                    Some(Some(funcidx)) => *funcidx,
+                    Some(None) => return trap("uninitialized element"),
+                    None => return trap("undefined element"),
+                };
+                let callee_type: &FuncType = if (funcidx.idx as usize) < num_imported_funcs {
+                    imported_funcs[funcidx.idx as usize].1
+                } else {
+                    let callee = &funcs[funcidx.idx as usize - num_imported_funcs];
+                    let typeidx =
+                        self.module.func_section.content[funcidx.idx as usize - num_imported_funcs];
+                    debug_assert!(
+                        callee.num_params == types[typeidx.idx as usize].param_types.len()
+                    );
+                    &types[typeidx.idx as usize]
+                };
+                if *callee_type != types[$typeidx.idx as usize] {
+                    return trap("indirect call type mismatch");
+                }
+                funcidx
+            }};
+        }
+
+        loop {
+            if pc == code.instrs.len() {
+                // return from the current function (its results are already at the top of the stack)
+                m.locals.truncate(frame.locals_base);
+                m.frames.pop();
+                if m.frames.len() == base_depth {
+                    return Ok(());
+                }
+                frame = *m.frames.last().unwrap();
+                code = &funcs[frame.func];
+                pc = frame.pc;
+                continue;
+            }
+            let instr = &code.instrs[pc];
+            let block = code.blocks[pc];
+            pc += 1;
+            match instr.opcode {
+                OpCode::Unreachable => return trap("unreachable"),
+                OpCode::Nop => {}
+                OpCode::Block => {
+                    m.push_label(block.end + 1, block.num_results, block.num_params, false)
+                }
+                OpCode::Loop => m.push_label(pc, block.num_params, block.num_params, true),
+                OpCode::If => {
+                    if pop!(bool) {
+                        m.push_label(block.end + 1, block.num_results, block.num_params, false);
+                    } else if let Some(else_) = block.else_ {
+                        m.push_label(block.end + 1, block.num_results, block.num_params, false);
+                        pc = else_ + 1;
+                    } else {
+                        pc = block.end + 1;
+                    }
+                }
+                OpCode::Else => {
+                    // we reached the end of the `then` branch
+                    m.labels.pop();
+                    pc = block.end + 1;
+                }
+                OpCode::End => {
+                    m.labels.pop();
+                }
+                OpCode::Br => match instr.immediate {
+                    Immediate::Label(labelidx) => pc = m.branch(labelidx as usize),
+                    _ => unreachable!(),
+                },
+                OpCode::BrIf => match instr.immediate {
+                    Immediate::Label(labelidx) => {
+                        if pop!(bool) {
+                            pc = m.branch(labelidx as usize);
+                        }
+                    }
+                    _ => unreachable!(),
+                },
+                OpCode::BrTable => match &instr.immediate {
+                    Immediate::LabelTable(labelidxs, default_labelidx) => {
+                        let labelidx = *labelidxs
+                            .get(pop!(u32) as usize)
+                            .unwrap_or(default_labelidx);
+                        pc = m.branch(labelidx as usize);
+                    }
+                    _ => unreachable!(),
+                },
+                OpCode::Return => pc = m.branch(m.labels.len() - 1 - frame.label_base),
+                OpCode::Call => match instr.immediate {
+                    Immediate::Func(funcidx) => call!(funcidx),
+                    _ => unreachable!(),
+                },
+                OpCode::CallIndirect => match instr.immediate {
+                    Immediate::CallIndirect(typeidx, _) => call!(indirect_callee!(typeidx)),
+                    _ => unreachable!(),
+                },
+                OpCode::ReturnCall => match instr.immediate {
+                    Immediate::Func(funcidx) => return_call!(funcidx),
+                    _ => unreachable!(),
+                },
+                OpCode::ReturnCallIndirect => match instr.immediate {
+                    Immediate::CallIndirect(typeidx, _) => return_call!(indirect_callee!(typeidx)),
+                    _ => unreachable!(),
+                },
+                OpCode::Drop => {
+                    m.stack.pop();
+                }
+                OpCode::Select => {
+                    let cond = pop!(bool);
+                    let second = pop!(u64);
+                    let first = pop!(u64);
+                    push!(if cond { first } else { second });
+                }
+                OpCode::LocalGet => match instr.immediate {
+                    Immediate::Local(localidx) => {
+                        push!(m.locals[frame.locals_base + localidx.idx as usize])
+                    }
+                    _ => unreachable!(),
+                },
+                OpCode::LocalSet => match instr.immediate {
+                    Immediate::Local(localidx) => {
+                        m.locals[frame.locals_base + localidx.idx as usize] = pop!(u64)
+                    }
+                    _ => unreachable!(),
+                },
+                OpCode::LocalTee => match instr.immediate {
+                    Immediate::Local(localidx) => {
+                        m.locals[frame.locals_base + localidx.idx as usize] =
+                            *m.stack.last().unwrap()
+                    }
+                    _ => unreachable!(),
+                },
+                OpCode::GlobalGet => match instr.immediate {
+                    Immediate::Global(globalidx) => push!(m.globals[globalidx.idx as usize]),
+                    _ => unreachable!(),
+                },
+                OpCode::GlobalSet => match instr.immediate {
+                    Immediate::Global(globalidx) => m.globals[globalidx.idx as usize] = pop!(u64),
+                    _ => unreachable!(),
+                },
+                OpCode::I32Load | OpCode::F32Load => load!(4, |b| u32::from_le_bytes(b) as u64),
+                OpCode::I64Load | OpCode::F64Load => load!(8, u64::from_le_bytes),
+                OpCode::I32Load8S => load!(1, |b| (b[0] as i8 as i32).to_raw()),
+                OpCode::I32Load8U => load!(1, |b| b[0] as u64),
+                OpCode::I32Load16S => load!(2, |b| (i16::from_le_bytes(b) as i32).to_raw()),
+                OpCode::I32Load16U => load!(2, |b| u16::from_le_bytes(b) as u64),
+                OpCode::I64Load8S => load!(1, |b| b[0] as i8 as i64 as u64),
+                OpCode::I64Load8U => load!(1, |b| b[0] as u64),
+                OpCode::I64Load16S => load!(2, |b| i16::from_le_bytes(b) as i64 as u64),
+                OpCode::I64Load16U => load!(2, |b| u16::from_le_bytes(b) as u64),
+                OpCode::I64Load32S => load!(4, |b| i32::from_le_bytes(b) as i64 as u64),
+                OpCode::I64Load32U => load!(4, |b| u32::from_le_bytes(b) as u64),
+                OpCode::I32Store | OpCode::F32Store | OpCode::I64Store32 => {
+                    store!(4, |v| (v as u32).to_le_bytes())
+                }
+                OpCode::I64Store | OpCode::F64Store => store!(8, u64::to_le_bytes),
+                OpCode::I32Store8 | OpCode::I64Store8 => store!(1, |v| [v as u8]),
+                OpCode::I32Store16 | OpCode::I64Store16 => store!(2, |v| (v as u16).to_le_bytes()),
+                OpCode::MemorySize => push!((m.memory.len() / WASM_PAGE_SIZE) as u32),
+                OpCode::MemoryGrow => {
+                    let old_pages = m.memory.len() / WASM_PAGE_SIZE;
+                    let new_pages = old_pages + pop!(u32) as usize;
+                    if new_pages > m.max_pages {
+                        push!(-1i32);
+                    } else {
+                        m.memory.resize(new_pages * WASM_PAGE_SIZE, 0);
+                        push!(old_pages as u32);
+                    }
+                }
+                OpCode::MemoryInit => match instr.immediate {
+                    Immediate::DataMem(dataidx, _) => {
+                        let len = pop!(u32) as usize;
+                        let src = pop!(u32) as usize;
+                        let dest = pop!(u32) as usize;
+                        let data: &[u8] = if m.dropped_data[dataidx as usize] {
+                            &[]
+                        } else {
+                            &self.module.data_section.content[dataidx as usize].content
+                        };
+                        if src + len > data.len() || dest + len > m.memory.len() {
+                            return trap("out of bounds memory access");
+                        }
+                        m.memory[dest..dest + len].copy_from_slice(&data[src..src + len]);
+                    }
+                    _ => unreachable!(),
+                },
+                OpCode::DataDrop => match instr.immediate {
+                    Immediate::Data(dataidx) => m.dropped_data[dataidx as usize] = true,
+                    _ => unreachable!(),
+                },
+                OpCode::MemoryCopy => {
+                    let len = pop!(u32) as usize;
+                    let src = pop!(u32) as usize;
+                    let dest = pop!(u32) as usize;
+                    if src + len > m.memory.len() || dest + len > m.memory.len() {
+                        return trap("out of bounds memory access");
+                    }
+                    m.memory.copy_within(src..src + len, dest);
+                }
+                OpCode::MemoryFill => {
+                    let len = pop!(u32) as usize;
+                    let val = pop!(u32) as u8;
+                    let dest = pop!(u32) as usize;
+                    if dest + len > m.memory.len() {
+                        return trap("out of bounds memory access");
+                    }
+                    for byte in &mut m.memory[dest..dest + len] {
+                        *byte = val;
+                    }
+                }
+                OpCode::I32Const => match instr.immediate {
+                    Immediate::I32(val) => push!(val),
+                    _ => unreachable!(),
+                },
+                OpCode::I64Const => match instr.immediate {
+                    Immediate::I64(val) => push!(val),
+                    _ => unreachable!(),
+                },
+                OpCode::F32Const => match instr.immediate {
+                    Immediate::F32(val) => push!(val),
+                    _ => unreachable!(),
+                },
+                OpCode::F64Const => match instr.immediate {
+                    Immediate::F64(val) => push!(val),
+                    _ => unreachable!(),
+                },
+                OpCode::I32Eqz => unop!(i32, bool, |a| a == 0),
+                OpCode::I32Eq => binop!(i32, bool, |a, b| a == b),
+                OpCode::I32Ne => binop!(i32, bool, |a, b| a != b),
+                OpCode::I32LtS => binop!(i32, bool, |a, b| a < b),
+                OpCode::I32LtU => binop!(u32, bool, |a, b| a < b),
+                OpCode::I32GtS => binop!(i32, bool, |a, b| a > b),
+                OpCode::I32GtU => binop!(u32, bool, |a, b| a > b),
+                OpCode::I32LeS => binop!(i32, bool, |a, b| a <= b),
+                OpCode::I32LeU => binop!(u32, bool, |a, b| a <= b),
+                OpCode::I32GeS => binop!(i32, bool, |a, b| a >= b),
+                OpCode::I32GeU => binop!(u32, bool, |a, b| a >= b),
+                OpCode::I64Eqz => unop!(i64, bool, |a| a == 0),
+                OpCode::I64Eq => binop!(i64, bool, |a, b| a == b),
+                OpCode::I64Ne => binop!(i64, bool, |a, b| a != b),
+                OpCode::I64LtS => binop!(i64, bool, |a, b| a < b),
+                OpCode::I64LtU => binop!(u64, bool, |a, b| a < b),
+                OpCode::I64GtS => binop!(i64, bool, |a, b| a > b),
+                OpCode::I64GtU => binop!(u64, bool, |a, b| a > b),
+                OpCode::I64LeS => binop!(i64, bool, |a, b| a <= b),
+                OpCode::I64LeU => binop!(u64, bool, |a, b| a <= b),
+                OpCode::I64GeS => binop!(i64, bool, |a, b| a >= b),
+                OpCode::I64GeU => binop!(u64, bool, |a, b| a >= b),
+                OpCode::F32Eq => binop!(f32, bool, |a, b| a == b),
+                OpCode::F32Ne => binop!(f32, bool, |a, b| a != b),
+                OpCode::F32Lt => binop!(f32, bool, |a, b| a < b),
+                OpCode::F32Gt => binop!(f32, bool, |a, b| a > b),
+                OpCode::F32Le => binop!(f32, bool, |a, b| a <= b),
+                OpCode::F32Ge => binop!(f32, bool, |a, b| a >= b),
+                OpCode::F64Eq => binop!(f64, bool, |a, b| a == b),
+                OpCode::F64Ne => binop!(f64, bool, |a, b| a != b),
+                OpCode::F64Lt => binop!(f64, bool, |a, b| a < b),
+                OpCode::F64Gt => binop!(f64, bool, |a, b| a > b),
+                OpCode::F64Le => binop!(f64, bool, |a, b| a <= b),
+                OpCode::F64Ge => binop!(f64, bool, |a, b| a >= b),
+                OpCode::I32Clz => unop!(u32, u32, |a| a.leading_zeros()),
+                OpCode::I32Ctz => unop!(u32, u32, |a| a.trailing_zeros()),
+                OpCode::I32Popcnt => unop!(u32, u32, |a| a.count_ones()),
+                OpCode::I32Add => binop!(i32, i32, |a, b| a.wrapping_add(b)),
+                OpCode::I32Sub => binop!(i32, i32, |a, b| a.wrapping_sub(b)),
+                OpCode::I32Mul => binop!(i32, i32, |a, b| a.wrapping_mul(b)),
+                OpCode::I32DivS => binop_trap!(i32, i32, |a, b| match b {
+                    0 => trap("integer divide by zero"),
+                    -1 if a == i32::MIN => trap("integer overflow"),
+                    _ => Ok(a / b),
+                }),
+                OpCode::I32DivU => binop_trap!(u32, u32, |a, b| a
+                    .checked_div(b)
+                    .map_or_else(|| trap("integer divide by zero"), Ok)),
+                OpCode::I32RemS => binop_trap!(i32, i32, |a, b| match b {
+                    0 => trap("integer divide by zero"),
+                    _ => Ok(a.wrapping_rem(b)),
+                }),
+                OpCode::I32RemU => binop_trap!(u32, u32, |a, b| a
+                    .checked_rem(b)
+                    .map_or_else(|| trap("integer divide by zero"), Ok)),
+                OpCode::I32And => binop!(u32, u32, |a, b| a & b),
+                OpCode::I32Or => binop!(u32, u32, |a, b| a | b),
+                OpCode::I32Xor => binop!(u32, u32, |a, b| a ^ b),
+                OpCode::I32Shl => binop!(u32, u32, |a, b| a.wrapping_shl(b)),
+                OpCode::I32ShrS => binop!(i32, i32, |a, b| a.wrapping_shr(b as u32)),
+                OpCode::I32ShrU => binop!(u32, u32, |a, b| a.wrapping_shr(b)),
+                OpCode::I32Rotl => binop!(u32, u32, |a, b| a.rotate_left(b)),
+                OpCode::I32Rotr => binop!(u32, u32, |a, b| a.rotate_right(b)),
+                OpCode::I64Clz => unop!(u64, u64, |a| a.leading_zeros() as u64),
+                OpCode::I64Ctz => unop!(u64, u64, |a| a.trailing_zeros() as u64),
+                OpCode::I64Popcnt => unop!(u64, u64, |a| a.count_ones() as u64),
+                OpCode::I64Add => binop!(i64, i64, |a, b| a.wrapping_add(b)),
+                OpCode::I64Sub => binop!(i64, i64, |a, b| a.wrapping_sub(b)),
+                OpCode::I64Mul => binop!(i64, i64, |a, b| a.wrapping_mul(b)),
+                OpCode::I64DivS => binop_trap!(i64, i64, |a, b| match b {
+                    0 => trap("integer divide by zero"),
+                    -1 if a == i64::MIN => trap("integer overflow"),
+                    _ => Ok(a / b),
+                }),
+                OpCode::I64DivU => binop_trap!(u64, u64, |a, b| a
+                    .checked_div(b)
+                    .map_or_else(|| trap("integer divide by zero"), Ok)),
+                OpCode::I64RemS => binop_trap!(i64, i64, |a, b| match b {
+                    0 => trap("integer divide by zero"),
+                    _ => Ok(a.wrapping_rem(b)),
+                }),
+                OpCode::I64RemU => binop_trap!(u64, u64, |a, b| a
+                    .checked_rem(b)
+                    .map_or_else(|| trap("integer divide by zero"), Ok)),
+                OpCode::I64And => binop!(u64, u64, |a, b| a & b),
+                OpCode::I64Or => binop!(u64, u64, |a, b| a | b),
+                OpCode::I64Xor => binop!(u64, u64, |a, b| a ^ b),
+                OpCode::I64Shl => binop!(u64, u64, |a, b| a.wrapping_shl(b as u32)),
+                OpCode::I64ShrS => binop!(i64, i64, |a, b| a.wrapping_shr(b as u32)),
+                OpCode::I64ShrU => binop!(u64, u64, |a, b| a.wrapping_shr(b as u32)),
+                OpCode::I64Rotl => binop!(u64, u64, |a, b| a.rotate_left((b % 64) as u32)),
+                OpCode::I64Rotr => binop!(u64, u64, |a, b| a.rotate_right((b % 64) as u32)),
+                OpCode::F32Abs => unop!(f32, f32, |a| a.abs()),
+                OpCode::F32Neg => unop!(f32, f32, |a| -a),
+                OpCode::F32Ceil => unop!(f32, f32, |a| a.ceil()),
+                OpCode::F32Floor => unop!(f32, f32, |a| a.floor()),
+                OpCode::F32Trunc => unop!(f32, f32, |a| a.trunc()),
+                OpCode::F32Nearest => unop!(f32, f32, |a| a.round_ties_even()),
+                OpCode::F32Sqrt => unop!(f32, f32, |a| a.sqrt()),
+                OpCode::F32Add => binop!(f32, f32, |a, b| a + b),
+                OpCode::F32Sub => binop!(f32, f32, |a, b| a - b),
+                OpCode::F32Mul => binop!(f32, f32, |a, b| a * b),
+                OpCode::F32Div => binop!(f32, f32, |a, b| a / b),
+                OpCode::F32Min => binop!(f32, f32, |a, b| wasm_min(a as f64, b as f64) as f32),
+                OpCode::F32Max => binop!(f32, f32, |a, b| wasm_max(a as f64, b as f64) as f32),
+                OpCode::F32Copysign => binop!(f32, f32, |a, b| a.copysign(b)),
+                OpCode::F64Abs => unop!(f64, f64, |a| a.abs()),
+                OpCode::F64Neg => unop!(f64, f64, |a| -a),
+                OpCode::F64Ceil => unop!(f64, f64, |a| a.ceil()),
+                OpCode::F64Floor => unop!(f64, f64, |a| a.floor()),
+                OpCode::F64Trunc => unop!(f64, f64, |a| a.trunc()),
+                OpCode::F64Nearest => unop!(f64, f64, |a| a.round_ties_even()),
+                OpCode::F64Sqrt => unop!(f64, f64, |a| a.sqrt()),
+                OpCode::F64Add => binop!(f64, f64, |a, b| a + b),
+                OpCode::F64Sub => binop!(f64, f64, |a, b| a - b),
+                OpCode::F64Mul => binop!(f64, f64, |a, b| a * b),
+                OpCode::F64Div => binop!(f64, f64, |a, b| a / b),
+                OpCode::F64Min => binop!(f64, f64, wasm_min),
+                OpCode::F64Max => binop!(f64, f64, wasm_max),
+                OpCode::F64Copysign => binop!(f64, f64, |a, b| a.copysign(b)),
+                OpCode::I32WrapI64 => unop!(u64, u32, |a| a as u32),
+                OpCode::I32TruncF32S => unop_trap!(f32, i32, |a| trunc_i32(a as f64)),
+                OpCode::I32TruncF32U => unop_trap!(f32, u32, |a| trunc_u32(a as f64)),
+                OpCode::I32TruncF64S => unop_trap!(f64, i32, trunc_i32),
+                OpCode::I32TruncF64U => unop_trap!(f64, u32, trunc_u32),
+                OpCode::I64ExtendI32S => unop!(i32, i64, |a| a as i64),
+                OpCode::I64ExtendI32U => unop!(u32, u64, |a| a as u64),
+                OpCode::I64TruncF32S => unop_trap!(f32, i64, |a| trunc_i64(a as f64)),
+                OpCode::I64TruncF32U => unop_trap!(f32, u64, |a| trunc_u64(a as f64)),
+                OpCode::I64TruncF64S => unop_trap!(f64, i64, trunc_i64),
+                OpCode::I64TruncF64U => unop_trap!(f64, u64, trunc_u64),
+                OpCode::F32ConvertI32S => unop!(i32, f32, |a| a as f32),
+                OpCode::F32ConvertI32U => unop!(u32, f32, |a| a as f32),
+                OpCode::F32ConvertI64S => unop!(i64, f32, |a| a as f32),
+                OpCode::F32ConvertI64U => unop!(u64, f32, |a| a as f32),
+                OpCode::F32DemoteF64 => unop!(f64, f32, |a| a as f32),
+                OpCode::F64ConvertI32S => unop!(i32, f64, |a| a as f64),
+                OpCode::F64ConvertI32U => unop!(u32, f64, |a| a as f64),
+                OpCode::F64ConvertI64S => unop!(i64, f64, |a| a as f64),
+                OpCode::F64ConvertI64U => unop!(u64, f64, |a| a as f64),
+                OpCode::F64PromoteF32 => unop!(f32, f64, |a| a as f64),
+                // the bits are the same, so there is nothing to do
+                OpCode::I32ReinterpretF32
+                | OpCode::I64ReinterpretF64
+                | OpCode::F32ReinterpretI32
+                | OpCode::F64ReinterpretI64 => {}
+            }
+        }
+    }
+}
+
+impl Machine {
+    // Enters a function, with its arguments on the stack
+    fn push_frame(&mut self, funcs: &[FuncCode], func: usize) -> Result<(), Trap> {
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return trap("call stack exhausted");
+        }
+        let code = &funcs[func];
+        let args_start = self.stack.len() - code.num_params;
+        let locals_base = self.locals.len();
+        self.locals.extend_from_slice(&self.stack[args_start..]);
+        self.stack.truncate(args_start);
+        self.locals.resize(self.locals.len() + code.locals.len(), 0); // all zeroes is the default for every type
+        self.push_label(code.instrs.len(), code.num_results, 0, false);
+        self.frames.push(Frame {
+            func: func,
+            pc: 0,
+            locals_base: locals_base,
+            label_base: self.labels.len() - 1,
+        });
+        Ok(())
+    }
+
+    fn push_label(&mut self, cont: usize, arity: usize, num_params: usize, is_loop: bool) {
+        self.labels.push(Label {
+            cont: cont,
+            arity: arity,
+            height: self.stack.len() - num_params,
+            is_loop: is_loop,
+        });
+    }
+
+    // Branches to the label at the given depth (0 is the innermost label), and returns the instruction to continue at
+    fn branch(&mut self, depth: usize) -> usize {
+        let labelidx = self.labels.len() - 1 - depth;
+        let label = self.labels[labelidx];
+        let vals_start = self.stack.len() - label.arity;
+        self.stack.copy_within(vals_start.., label.height);
+        self.stack.truncate(label.height + label.arity);
+        // a loop is entered again, so its label stays
+        self.labels.truncate(if label.is_loop {
+            labelidx + 1
+        } else {
+            labelidx
+        });
+        label.cont
+    }
+}
+
+// Calls an imported function with the arguments on the stack, and pushes its results
+fn call_host<H: Host>(
+    code: &Code,
+    m: &mut Machine,
+    (import, functype): (&Import, &FuncType),
+    host: &mut H,
+) -> Result<(), Trap> {
+    let args_start = m.stack.len() - functype.param_types.len();
+    let args: Box<[Value]> = functype
+        .param_types
+        .iter()
+        .zip(m.stack.drain(args_start..))
+        .map(|(valtype, raw)| Value::from_raw(*valtype, raw))
+        .collect();
+    let results = host.call_import(
+        &import.module_name,
+        &import.entity_name,
+        &args,
+        &mut Caller {
+            code: code,
+            machine: m,
+        },
+    )?;
+    if results
+        .iter()
+        .map(|result| result.valtype())
+        .ne(functype.result_types.iter().copied())
+    {
+        return trap(&format!(
+            "import \"{}\" \"{}\" returned the wrong types",
+            import.module_name, import.entity_name
+        ));
+    }
+    m.stack.extend(results.iter().map(|result| result.to_raw()));
+    Ok(())
+}
+
+fn eval_const_expr(expr: &Expr, globals: &[u64]) -> u64 {
+    // the expr is already validated, so it is a single constant or global.get
+    match Reader::new(&expr.bytecode).read_instr().unwrap().immediate {
+        Immediate::I32(val) => val.to_raw(),
+        Immediate::I64(val) => val.to_raw(),
+        Immediate::F32(val) => val.to_raw(),
+        Immediate::F64(val) => val.to_raw(),
+        Immediate::Global(globalidx) => globals[globalidx.idx as usize],
+        _ => unreachable!(),
+    }
+}
+
+// Decodes a (validated) function body, and finds the matching `else` and `end` of each block
+fn decode_func(types: &[FuncType], functype: &FuncType, body: &[u8]) -> FuncCode {
+    let mut reader = Reader::new(body);
+    let locals = reader.read_locals().unwrap();
+    let mut instrs: Vec<Instr> = Vec::new();
+    let mut blocks: Vec<BlockInfo> = Vec::new();
+    let mut open_blocks: Vec<usize> = Vec::new();
+    while !reader.is_empty() {
+        let instr = reader.read_instr().unwrap();
+        let idx = instrs.len();
+        let mut block = BlockInfo::default();
+        match (instr.opcode, &instr.immediate) {
+            (OpCode::Block, Immediate::BlockType(blocktype))
+            | (OpCode::Loop, Immediate::BlockType(blocktype))
+            | (OpCode::If, Immediate::BlockType(blocktype)) => {
+                let (num_params, num_results) = match blocktype {
+                    BlockType::Empty => (0, 0),
+                    BlockType::Value(_) => (0, 1),
+                    BlockType::TypeIdx(typeidx) => {
+                        let functype = &types[typeidx.idx as usize];
+                        (functype.param_types.len(), functype.result_types.len())
+                    }
+                };
+                block.num_params = num_params;
+                block.num_results = num_results;
+                open_blocks.push(idx);
+            }
+            (OpCode::Else, _) => blocks[*open_blocks.last().unwrap()].else_ = Some(idx),
+            (OpCode::End, _) => {
+                // the `end` of the function body does not have a matching block
+                if let Some(start) = open_blocks.pop() {
+                    blocks[start].end = idx;
+                    if let Some(else_) = blocks[start].else_ {
+                        blocks[else_].end = idx;
+                    }
+                }
+            }
+            _ => {}
+        }
+        instrs.push(instr);
+        blocks.push(block);
+    }
+    FuncCode {
+        num_params: functype.param_types.len(),
+        num_results: functype.result_types.len(),
+        locals: locals.into_boxed_slice(),
+        instrs: instrs.into_boxed_slice(),
+        blocks: blocks.into_boxed_slice(),
+    }
+}
+
+// Returns the index of the first byte accessed, if all `len` bytes are in bounds
+fn effective_address(addr: u32, offset: u32, len: usize, memory_len: usize) -> Result<usize, Trap> {
+    let start = addr as usize + offset as usize;
+    if start + len > memory_len {
+        return trap("out of bounds memory access");
+    }
+    Ok(start)
+}
+
+// f32.min and f64.min (f32 is converted to f64 and back exactly), which propagate NaNs and order -0 before +0
+fn wasm_min(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        if a.is_sign_negative() {
+            a
+        } else {
+            b
+        }
+    } else {
+        a.min(b)
+    }
+}
+
+fn wasm_max(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        if a.is_sign_positive() {
+            a
+        } else {
+            b
+        }
+    } else {
+        a.max(b)
+    }
+}
+
+// Truncates a float to an integer, trapping if the result (after rounding towards zero) is not in (min, max)
+fn trunc_checked(val: f64, min: f64, max: f64) -> Result<f64, Trap> {
+    if val.is_nan() {
+        return trap("invalid conversion to integer");
+    }
+    if val <= min || val >= max {
+        return trap("integer overflow");
+    }
+    Ok(val.trunc())
+}
+
+fn trunc_i32(val: f64) -> Result<i32, Trap> {
+    trunc_checked(val, -2147483649.0, 2147483648.0).map(|val| val as i32)
+}
+
+fn trunc_u32(val: f64) -> Result<u32, Trap> {
+    trunc_checked(val, -1.0, 4294967296.0).map(|val| val as u32)
+}
+
+fn trunc_i64(val: f64) -> Result<i64, Trap> {
+    // -9223372036854777856 is the largest f64 below i64::MIN
+    trunc_checked(val, -9223372036854777856.0, 9223372036854775808.0).map(|val| val as i64)
+}
+
+fn trunc_u64(val: f64) -> Result<u64, Trap> {
+    trunc_checked(val, -1.0, 18446744073709551616.0).map(|val| val as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * Host with a single import that adds its two arguments.
+     */
+    struct AddHost;
+
+    impl Host for AddHost {
+        fn call_import(
+            &mut self,
+            _module_name: &str,
+            entity_name: &str,
+            args: &[Value],
+            _caller: &mut Caller,
+        ) -> Result<Box<[Value]>, Trap> {
+            assert_eq!(entity_name, "add");
+            match args {
+                [Value::I32(a), Value::I32(b)] => Ok(Box::new([Value::I32(a + b)])),
+                _ => panic!("wrong arguments"),
+            }
+        }
+    }
+
+    #[test]
+    fn loops_calls_and_memory() {
+        let mut builder = WasmModule::new_builder();
+        let add_type = FuncType::new(
+            Box::new([ValType::I32, ValType::I32]),
+            Box::new([ValType::I32]),
+        );
+        let add = builder.import_func("env".to_owned(), "add".to_owned(), &add_type);
+        let mut module = builder.build();
+        let memidx = module.add_unbounded_memory(1);
+        module.add_data(memidx, 8, &[5, 0, 0, 0]);
+        // sum(n) = n + (n - 1) + ... + 1, computed with a loop and the imported add, with n loaded from memory
+        let functype = FuncType::new(Box::new([]), Box::new([ValType::I32]));
+        let (_, funcidx) = module.register_func(&functype);
+        let mut code_builder = CodeBuilder::new(functype);
+        {
+            let (locals_builder, expr_builder) = code_builder.split();
+            let n = locals_builder.add(ValType::I32);
+            let sum = locals_builder.add(ValType::I32);
+            expr_builder.i32_const(8);
+            expr_builder.i32_load(MemArg::new4(0));
+            expr_builder.local_set(n);
+            expr_builder.block(&[]);
+            expr_builder.loop_(&[]);
+            expr_builder.local_get(n);
+            expr_builder.i32_eqz();
+            expr_builder.br_if(1);
+            expr_builder.local_get(sum);
+            expr_builder.local_get(n);
+            expr_builder.call(add);
+            expr_builder.local_set(sum);
+            expr_builder.local_get(n);
+            expr_builder.i32_const(1);
+            expr_builder.i32_sub();
+            expr_builder.local_set(n);
+            expr_builder.br(0);
+            expr_builder.end();
+            expr_builder.end();
+            expr_builder.local_get(sum);
+            expr_builder.end();
+        }
+        module.commit_func(funcidx, code_builder);
+        module.export_func(funcidx, "main".to_owned());
+        let mut instance = Instance::new(&module, &mut AddHost).unwrap();
+        let results = instance.invoke("main", &[], &mut AddHost).unwrap();
+        assert_eq!(&*results, &[Value::I32(15)]);
+    }
+
+    #[test]
+    fn traps() {
+        let mut module = WasmModule::new_builder().build();
+        let functype = FuncType::new(Box::new([ValType::I32]), Box::new([ValType::I32]));
+        let (_, funcidx) = module.register_func(&functype);
+        let mut code_builder = CodeBuilder::new(functype);
+        {
+            let (_, expr_builder) = code_builder.split();
+            expr_builder.i32_const(100);
+            expr_builder.local_get(LocalIdx { idx: 0 });
+            expr_builder.i32_div_s();
+            expr_builder.end();
+        }
+        module.commit_func(funcidx, code_builder);
+        module.export_func(funcidx, "div".to_owned());
+        let mut instance = Instance::new(&module, &mut AddHost).unwrap();
+        assert_eq!(
+            instance.invoke("div", &[Value::I32(0)], &mut AddHost),
+            trap("integer divide by zero")
+        );
+        // the instance can still be used after a trap
+        assert_eq!(
+            &*instance
+                .invoke("div", &[Value::I32(-7)], &mut AddHost)
+                .unwrap(),
+            &[Value::I32(-14)]
+        );
+    }
+}
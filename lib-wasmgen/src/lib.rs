@@ -1,4 +1,5 @@
 use projstd::searchablevec::SearchableVec;
+use std::collections::BTreeMap;
 use std::option::Option;
 /**
  * The structs here are equivalent to those in the WebAssembly spec here:
@@ -7,12 +8,19 @@ use std::option::Option;
 use std::vec::Vec;
 
 pub mod codewriter;
+pub mod decode;
+mod instr;
+pub mod interp;
 pub mod scratch;
 pub mod serialize;
+pub mod validate;
+mod wat;
 pub mod write;
 pub use codewriter::*;
+pub use decode::*;
 pub use scratch::*;
 pub use serialize::*;
+pub use validate::*;
 pub use write::*;
 
 #[derive(Default)]
@@ -28,6 +36,8 @@ pub struct WasmModule {
     elem_section: ElemSection,
     code_section: CodeSection,
     data_section: DataSection,
+    name_section: NameSection,
+    custom_sections: Vec<CustomSection>, // emitted after all the other sections, in the order that they were added
 }
 
 pub trait Insert<T> {
@@ -189,6 +199,7 @@ pub struct Code {
     func: Option<Box<[u8]>>,
     // `func` is pre-serialized by the CodeWriter.
     // If `func` is None, then this function has been registered but not yet committed.
+    markers: Box<[(u32, u32)]>, // (byte offset from the start of `func`, marker id), from ExprBuilder::mark()
 }
 
 #[derive(Default)]
@@ -202,6 +213,20 @@ pub struct Data {
     content: Box<[u8]>,
 }
 
+// The "name" custom section, which is used by debuggers and for stack traces
+// (see https://webassembly.github.io/spec/core/appendix/custom.html#name-section)
+// It is not emitted if no names were set.
+#[derive(Default)]
+pub struct NameSection {
+    func_names: BTreeMap<u32, String>, // funcidx (including imports) -> name; must be serialized in increasing order of funcidx
+    local_names: BTreeMap<u32, BTreeMap<u32, String>>, // funcidx -> localidx -> name
+}
+
+pub struct CustomSection {
+    name: String,
+    content: Box<[u8]>,
+}
+
 #[derive(Eq, PartialEq, Copy, Clone, Hash)]
 pub struct TypeIdx {
     pub idx: u32,
@@ -58,6 +58,65 @@ impl WasmSerialize for WasmModule {
         self.elem_section.wasm_serialize(receiver);
         self.code_section.wasm_serialize(receiver);
         self.data_section.wasm_serialize(receiver);
+        self.name_section.wasm_serialize(receiver);
+        for custom_section in &self.custom_sections {
+            custom_section.wasm_serialize(receiver);
+        }
+    }
+}
+
+impl WasmModule {
+    /**
+     * Returns the byte offset (from the start of the serialized module) and the id of each marker set by ExprBuilder::mark(),
+     * in increasing order of byte offset.  This is used to generate source maps.
+     */
+    pub fn marker_offsets(&self) -> Vec<(u32, u32)> {
+        let mut counter = ByteCounter { len: 0 };
+        counter.extend(&[0u8; 8]); // magic value and version
+        self.type_section.wasm_serialize(&mut counter);
+        self.import_section.wasm_serialize(&mut counter);
+        self.func_section.wasm_serialize(&mut counter);
+        self.table_section.wasm_serialize(&mut counter);
+        self.mem_section.wasm_serialize(&mut counter);
+        self.global_section.wasm_serialize(&mut counter);
+        self.export_section.wasm_serialize(&mut counter);
+        self.start_section.wasm_serialize(&mut counter);
+        self.elem_section.wasm_serialize(&mut counter);
+        let mut ret = Vec::new();
+        if self.code_section.content.is_empty() {
+            return ret;
+        }
+        // same layout as serialize_section(): magic value, content length, then the vector of function bodies
+        let mut content_counter = ByteCounter { len: 0 };
+        self.code_section
+            .content
+            .wasm_serialize(&mut content_counter);
+        counter.extend(&[10u8]);
+        (content_counter.len as u32).leb_serialize(&mut counter);
+        (self.code_section.content.len() as u32).leb_serialize(&mut counter);
+        for code in &self.code_section.content {
+            let func_len = code.func.as_ref().unwrap().len();
+            (func_len as u32).leb_serialize(&mut counter);
+            let func_start = counter.len as u32;
+            ret.extend(
+                code.markers
+                    .iter()
+                    .map(|(offset, id)| (func_start + offset, *id)),
+            );
+            counter.len += func_len;
+        }
+        ret
+    }
+}
+
+// A receiver that only counts the number of bytes written to it
+struct ByteCounter {
+    len: usize,
+}
+
+impl<'a> std::iter::Extend<&'a u8> for ByteCounter {
+    fn extend<T: IntoIterator<Item = &'a u8>>(&mut self, iter: T) {
+        self.len += iter.into_iter().count();
     }
 }
 
@@ -490,6 +549,78 @@ impl WasmSerialize for Data {
     }
 }
 
+impl WasmSerialize for NameSection {
+    fn wasm_serialize<Rec>(&self, receiver: &mut Rec)
+    where
+        for<'a> Rec: std::iter::Extend<&'a u8>,
+    {
+        if self.func_names.is_empty() && self.local_names.is_empty() {
+            return;
+        }
+        let mut buf = Vec::<u8>::new();
+        if !self.func_names.is_empty() {
+            buf.push(1u8); // the magic value for the function names subsection
+            serialize_section_content(&NameMap(&self.func_names), &mut buf);
+        }
+        if !self.local_names.is_empty() {
+            buf.push(2u8); // the magic value for the local names subsection
+            serialize_section_content(&IndirectNameMap(&self.local_names), &mut buf);
+        }
+        serialize_custom_section("name", &buf, receiver);
+    }
+}
+
+struct NameMap<'a>(&'a BTreeMap<u32, String>);
+
+impl<'a> WasmSerialize for NameMap<'a> {
+    fn wasm_serialize<Rec>(&self, receiver: &mut Rec)
+    where
+        for<'b> Rec: std::iter::Extend<&'b u8>,
+    {
+        (self.0.len() as u32).leb_serialize(receiver);
+        for (idx, name) in self.0 {
+            idx.leb_serialize(receiver);
+            name.wasm_serialize(receiver);
+        }
+    }
+}
+
+struct IndirectNameMap<'a>(&'a BTreeMap<u32, BTreeMap<u32, String>>);
+
+impl<'a> WasmSerialize for IndirectNameMap<'a> {
+    fn wasm_serialize<Rec>(&self, receiver: &mut Rec)
+    where
+        for<'b> Rec: std::iter::Extend<&'b u8>,
+    {
+        (self.0.len() as u32).leb_serialize(receiver);
+        for (idx, name_map) in self.0 {
+            idx.leb_serialize(receiver);
+            NameMap(name_map).wasm_serialize(receiver);
+        }
+    }
+}
+
+impl WasmSerialize for CustomSection {
+    fn wasm_serialize<Rec>(&self, receiver: &mut Rec)
+    where
+        for<'a> Rec: std::iter::Extend<&'a u8>,
+    {
+        serialize_custom_section(&self.name, &self.content, receiver);
+    }
+}
+
+fn serialize_custom_section<Rec>(name: &str, content: &[u8], receiver: &mut Rec)
+where
+    for<'a> Rec: std::iter::Extend<&'a u8>,
+{
+    let mut buf = Vec::<u8>::new();
+    name.wasm_serialize(&mut buf);
+    buf.extend(content);
+    receiver.extend(&[0u8]); // the magic value for Custom Section
+    (buf.len() as u32).leb_serialize(receiver);
+    receiver.extend(&buf);
+}
+
 impl<T: WasmSerialize> WasmSerialize for [T] {
     fn wasm_serialize<Rec>(&self, receiver: &mut Rec)
     where
@@ -692,6 +823,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn marker_offsets() {
+        let mut module = WasmModule::new_builder().build();
+        let functype = FuncType::new(Box::new([]), Box::new([ValType::I32]));
+        let (_, funcidx) = module.register_func(&functype);
+        let mut code_builder = CodeBuilder::new(functype);
+        {
+            let (locals_builder, expr_builder) = code_builder.split();
+            locals_builder.add(ValType::I64);
+            expr_builder.i32_const(300);
+            expr_builder.drop();
+            expr_builder.mark(7);
+            expr_builder.i32_const(5);
+            expr_builder.end();
+        }
+        module.commit_func(funcidx, code_builder);
+        let bytes = wasm_serializer_wrapper(&module);
+        let offsets = module.marker_offsets();
+        assert_eq!(offsets.len(), 1);
+        let (offset, id) = offsets[0];
+        assert_eq!(id, 7);
+        assert_eq!(bytes[offset as usize..offset as usize + 3], [0x41, 5, 0x0B]);
+    }
+
     #[test]
     fn leb_serialize_unsigned() {
         assert_eq!(leb_serializer_wrapper(0u32), [0]);
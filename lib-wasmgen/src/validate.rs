@@ -0,0 +1,883 @@
+/**
+ * Validation of a WasmModule, following the rules in the WebAssembly spec:
+ * https://webassembly.github.io/spec/core/valid/index.html
+ * Function bodies are type-checked with the algorithm in the appendix of the spec:
+ * https://webassembly.github.io/spec/core/appendix/algorithm.html
+ *
+ * The proposals that wasmgen can emit (multi-value, bulk memory and tail calls) are always allowed,
+ * so a module that passes validation might still need some features to be enabled in the engine.
+ * This is used in tests, so that invalid code is caught before it reaches a WebAssembly engine.
+ */
+use super::instr::*;
+use super::*;
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug)]
+pub struct ValidationError {
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+fn invalid<T>(message: String) -> Result<T, ValidationError> {
+    Err(ValidationError { message: message })
+}
+
+// The entities (including imports) that can be referenced by index from the function bodies and the other sections
+struct Context<'a> {
+    types: &'a [FuncType],
+    funcs: Vec<TypeIdx>,
+    num_tables: u32,
+    num_mems: u32,
+    globals: Vec<GlobalType>,
+    num_imported_globals: u32,
+    num_data: u32,
+}
+
+impl<'a> Context<'a> {
+    fn functype(&self, typeidx: TypeIdx) -> Option<&'a FuncType> {
+        self.types.get(typeidx.idx as usize)
+    }
+    fn func(&self, funcidx: FuncIdx) -> Option<&'a FuncType> {
+        self.funcs
+            .get(funcidx.idx as usize)
+            .and_then(|typeidx| self.functype(*typeidx))
+    }
+}
+
+impl WasmModule {
+    /**
+     * Checks that the module is valid, returning the first error found.
+     */
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut ctx = Context {
+            types: self.type_section.content.vec(),
+            funcs: Vec::new(),
+            num_tables: 0,
+            num_mems: 0,
+            globals: Vec::new(),
+            num_imported_globals: 0,
+            num_data: self.data_section.content.len() as u32,
+        };
+        for import in &self.import_section.content {
+            let name = format!(
+                "import \"{}\" \"{}\"",
+                import.module_name, import.entity_name
+            );
+            match import.desc {
+                ImportDesc::Func(typeidx) => {
+                    if ctx.functype(typeidx).is_none() {
+                        return invalid(format!("{}: unknown type {}", name, typeidx.idx));
+                    }
+                    ctx.funcs.push(typeidx);
+                }
+                ImportDesc::Table(table_type) => {
+                    validate_limits(&table_type.limits, u32::MAX, &name)?;
+                    ctx.num_tables += 1;
+                }
+                ImportDesc::Mem(mem_type) => {
+                    validate_limits(&mem_type.limits, 65536, &name)?;
+                    ctx.num_mems += 1;
+                }
+                ImportDesc::Global(global_type) => {
+                    ctx.globals.push(global_type);
+                    ctx.num_imported_globals += 1;
+                }
+            }
+        }
+        for (i, typeidx) in self.func_section.content.iter().enumerate() {
+            if ctx.functype(*typeidx).is_none() {
+                return invalid(format!(
+                    "func {}: unknown type {}",
+                    self.func_section.idx_offset + i as u32,
+                    typeidx.idx
+                ));
+            }
+            ctx.funcs.push(*typeidx);
+        }
+        for (i, table) in self.table_section.content.iter().enumerate() {
+            let name = format!("table {}", self.table_section.idx_offset + i as u32);
+            validate_limits(&table.table_type.limits, u32::MAX, &name)?;
+            ctx.num_tables += 1;
+        }
+        for (i, mem) in self.mem_section.content.iter().enumerate() {
+            let name = format!("memory {}", self.mem_section.idx_offset + i as u32);
+            validate_limits(&mem.mem_type.limits, 65536, &name)?;
+            ctx.num_mems += 1;
+        }
+        if ctx.num_tables > 1 {
+            return invalid("multiple tables".to_owned());
+        }
+        if ctx.num_mems > 1 {
+            return invalid("multiple memories".to_owned());
+        }
+        // the initializers can only refer to imported globals, so we can check them before adding the globals to the context
+        for (i, global) in self.global_section.content.iter().enumerate() {
+            let name = format!("global {}", self.global_section.idx_offset + i as u32);
+            validate_const_expr(&ctx, &global.init_expr, global.global_type.val_type, &name)?;
+        }
+        ctx.globals.extend(
+            self.global_section
+                .content
+                .iter()
+                .map(|global| global.global_type),
+        );
+        let mut export_names: HashSet<&str> = HashSet::new();
+        for export in &self.export_section.content {
+            let name = format!("export \"{}\"", export.entity_name);
+            if !export_names.insert(&export.entity_name) {
+                return invalid(format!("{}: duplicate export name", name));
+            }
+            let (kind, idx, count) = match export.desc {
+                ExportDesc::Func(funcidx) => ("func", funcidx.idx, ctx.funcs.len() as u32),
+                ExportDesc::Table(tableidx) => ("table", tableidx.idx, ctx.num_tables),
+                ExportDesc::Mem(memidx) => ("memory", memidx.idx, ctx.num_mems),
+                ExportDesc::Global(globalidx) => {
+                    ("global", globalidx.idx, ctx.globals.len() as u32)
+                }
+            };
+            if idx >= count {
+                return invalid(format!("{}: unknown {} {}", name, kind, idx));
+            }
+        }
+        if let Some(funcidx) = self.start_section.start {
+            match ctx.func(funcidx) {
+                Some(functype)
+                    if functype.param_types.is_empty() && functype.result_types.is_empty() => {}
+                Some(_) => return invalid("start function must have type [] -> []".to_owned()),
+                None => return invalid(format!("start: unknown func {}", funcidx.idx)),
+            }
+        }
+        for (i, elem) in self.elem_section.content.iter().enumerate() {
+            let name = format!("elem {}", i);
+            if elem.table_idx.idx >= ctx.num_tables {
+                return invalid(format!("{}: unknown table {}", name, elem.table_idx.idx));
+            }
+            validate_const_expr(&ctx, &elem.offset, ValType::I32, &name)?;
+            for funcidx in elem.content.iter() {
+                if ctx.func(*funcidx).is_none() {
+                    return invalid(format!("{}: unknown func {}", name, funcidx.idx));
+                }
+            }
+        }
+        for (i, data) in self.data_section.content.iter().enumerate() {
+            let name = format!("data {}", i);
+            if data.mem_idx.idx >= ctx.num_mems {
+                return invalid(format!("{}: unknown memory {}", name, data.mem_idx.idx));
+            }
+            validate_const_expr(&ctx, &data.offset, ValType::I32, &name)?;
+        }
+        if self.code_section.content.len() != self.func_section.content.len() {
+            return invalid("function and code sections have different lengths".to_owned());
+        }
+        for (i, (typeidx, code)) in self
+            .func_section
+            .content
+            .iter()
+            .zip(self.code_section.content.iter())
+            .enumerate()
+        {
+            let funcidx = self.func_section.idx_offset + i as u32;
+            let body: &[u8] = match &code.func {
+                Some(body) => body,
+                None => return invalid(format!("func {}: not committed", funcidx)),
+            };
+            let functype = ctx.functype(*typeidx).unwrap();
+            if let Err(e) = FuncValidator::new(&ctx, functype).validate(body) {
+                return invalid(format!("func {}: {}", funcidx, e));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_limits(limits: &Limits, range: u32, name: &str) -> Result<(), ValidationError> {
+    let (min, max) = match *limits {
+        Limits::Unbounded { min } => (min, None),
+        Limits::Bounded { min, max } => (min, Some(max)),
+    };
+    let max_is_valid = match max {
+        Some(max) => max <= range && max >= min,
+        None => true,
+    };
+    if min > range || !max_is_valid {
+        return invalid(format!("{}: invalid limits", name));
+    }
+    Ok(())
+}
+
+// A constant expression is a single constant or global.get of an immutable imported global, which must have the given type
+fn validate_const_expr(
+    ctx: &Context,
+    expr: &Expr,
+    expected: ValType,
+    name: &str,
+) -> Result<(), ValidationError> {
+    let mut reader = Reader::new(&expr.bytecode);
+    let read_instr = |reader: &mut Reader| {
+        reader
+            .read_instr()
+            .or_else(|e| invalid(format!("{}: {}", name, e)))
+    };
+    let instr = read_instr(&mut reader)?;
+    let valtype = match (instr.opcode, instr.immediate) {
+        (OpCode::I32Const, _) => ValType::I32,
+        (OpCode::I64Const, _) => ValType::I64,
+        (OpCode::F32Const, _) => ValType::F32,
+        (OpCode::F64Const, _) => ValType::F64,
+        (OpCode::GlobalGet, Immediate::Global(globalidx))
+            if globalidx.idx < ctx.num_imported_globals =>
+        {
+            let global_type = ctx.globals[globalidx.idx as usize];
+            if global_type.mutability != Mut::Const {
+                return invalid(format!(
+                    "{}: constant expression uses a mutable global",
+                    name
+                ));
+            }
+            global_type.val_type
+        }
+        _ => return invalid(format!("{}: invalid constant expression", name)),
+    };
+    if valtype != expected {
+        return invalid(format!(
+            "{}: expected {} but got {}",
+            name,
+            valtype_name(expected),
+            valtype_name(valtype)
+        ));
+    }
+    match read_instr(&mut reader)?.opcode {
+        OpCode::End if reader.is_empty() => Ok(()),
+        _ => invalid(format!("{}: invalid constant expression", name)),
+    }
+}
+
+fn valtype_name(valtype: ValType) -> &'static str {
+    match valtype {
+        ValType::I32 => "i32",
+        ValType::I64 => "i64",
+        ValType::F32 => "f32",
+        ValType::F64 => "f64",
+    }
+}
+
+// The name of a value on the operand stack, where None is a value of unknown type (only in unreachable code)
+fn operand_name(operand: Option<ValType>) -> &'static str {
+    operand.map_or("unknown", valtype_name)
+}
+
+struct CtrlFrame<'a> {
+    opcode: OpCode, // the instruction that started this frame (Block, Loop, If or Else), or End for the function body itself
+    start_types: &'a [ValType],
+    end_types: &'a [ValType],
+    height: usize,     // the height of the operand stack at the start of the frame
+    unreachable: bool, // whether the rest of the frame is unreachable (so the operand stack is polymorphic)
+}
+
+struct FuncValidator<'a> {
+    ctx: &'a Context<'a>,
+    functype: &'a FuncType,
+    locals: Vec<ValType>, // including the params
+    vals: Vec<Option<ValType>>,
+    ctrls: Vec<CtrlFrame<'a>>,
+    offset: usize, // offset of the current instruction
+}
+
+const EMPTY: &[ValType] = &[];
+const I32: &[ValType] = &[ValType::I32];
+const I64: &[ValType] = &[ValType::I64];
+const F32: &[ValType] = &[ValType::F32];
+const F64: &[ValType] = &[ValType::F64];
+const I32_I32: &[ValType] = &[ValType::I32, ValType::I32];
+const I64_I64: &[ValType] = &[ValType::I64, ValType::I64];
+const F32_F32: &[ValType] = &[ValType::F32, ValType::F32];
+const F64_F64: &[ValType] = &[ValType::F64, ValType::F64];
+const I32_I32_I32: &[ValType] = &[ValType::I32, ValType::I32, ValType::I32];
+const I32_I64: &[ValType] = &[ValType::I32, ValType::I64];
+const I32_F32: &[ValType] = &[ValType::I32, ValType::F32];
+const I32_F64: &[ValType] = &[ValType::I32, ValType::F64];
+
+impl<'a> FuncValidator<'a> {
+    fn new(ctx: &'a Context<'a>, functype: &'a FuncType) -> Self {
+        FuncValidator {
+            ctx: ctx,
+            functype: functype,
+            locals: functype.param_types.to_vec(),
+            vals: Vec::new(),
+            ctrls: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, DecodeError> {
+        Err(DecodeError {
+            offset: self.offset,
+            message: message,
+        })
+    }
+
+    fn push_val(&mut self, val: Option<ValType>) {
+        self.vals.push(val);
+    }
+
+    fn pop_val(&mut self, expected: Option<ValType>) -> Result<Option<ValType>, DecodeError> {
+        let frame = self.ctrls.last().unwrap();
+        if self.vals.len() == frame.height {
+            if frame.unreachable {
+                return Ok(expected);
+            }
+            return self.error(format!(
+                "expected {} but the stack is empty",
+                operand_name(expected)
+            ));
+        }
+        let actual = self.vals.pop().unwrap();
+        match (actual, expected) {
+            (Some(actual), Some(expected)) if actual != expected => self.error(format!(
+                "expected {} but got {}",
+                valtype_name(expected),
+                valtype_name(actual)
+            )),
+            _ => Ok(actual.or(expected)),
+        }
+    }
+
+    fn push_vals(&mut self, valtypes: &[ValType]) {
+        self.vals
+            .extend(valtypes.iter().map(|valtype| Some(*valtype)));
+    }
+
+    fn pop_vals(&mut self, valtypes: &[ValType]) -> Result<Vec<Option<ValType>>, DecodeError> {
+        let mut popped: Vec<Option<ValType>> = Vec::new();
+        for valtype in valtypes.iter().rev() {
+            popped.push(self.pop_val(Some(*valtype))?);
+        }
+        popped.reverse();
+        Ok(popped)
+    }
+
+    fn push_ctrl(&mut self, opcode: OpCode, start_types: &'a [ValType], end_types: &'a [ValType]) {
+        self.ctrls.push(CtrlFrame {
+            opcode: opcode,
+            start_types: start_types,
+            end_types: end_types,
+            height: self.vals.len(),
+            unreachable: false,
+        });
+        self.push_vals(start_types);
+    }
+
+    fn pop_ctrl(&mut self) -> Result<CtrlFrame<'a>, DecodeError> {
+        let end_types = self.ctrls.last().unwrap().end_types;
+        self.pop_vals(end_types)?;
+        if self.vals.len() != self.ctrls.last().unwrap().height {
+            return self.error("too many values on the stack at the end of the block".to_owned());
+        }
+        Ok(self.ctrls.pop().unwrap())
+    }
+
+    fn label_types(&self, labelidx: u32) -> Result<&'a [ValType], DecodeError> {
+        match self.ctrls.iter().rev().nth(labelidx as usize) {
+            Some(frame) if frame.opcode == OpCode::Loop => Ok(frame.start_types),
+            Some(frame) => Ok(frame.end_types),
+            None => self.error(format!("unknown label {}", labelidx)),
+        }
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.ctrls.last_mut().unwrap();
+        self.vals.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn block_types(
+        &self,
+        blocktype: &BlockType,
+    ) -> Result<(&'a [ValType], &'a [ValType]), DecodeError> {
+        match blocktype {
+            BlockType::Empty => Ok((EMPTY, EMPTY)),
+            BlockType::Value(ValType::I32) => Ok((EMPTY, I32)),
+            BlockType::Value(ValType::I64) => Ok((EMPTY, I64)),
+            BlockType::Value(ValType::F32) => Ok((EMPTY, F32)),
+            BlockType::Value(ValType::F64) => Ok((EMPTY, F64)),
+            BlockType::TypeIdx(typeidx) => match self.ctx.functype(*typeidx) {
+                Some(functype) => Ok((&functype.param_types, &functype.result_types)),
+                None => self.error(format!("unknown type {}", typeidx.idx)),
+            },
+        }
+    }
+
+    fn call_types(&self, immediate: &Immediate) -> Result<&'a FuncType, DecodeError> {
+        match immediate {
+            Immediate::Func(funcidx) => match self.ctx.func(*funcidx) {
+                Some(functype) => Ok(functype),
+                None => self.error(format!("unknown func {}", funcidx.idx)),
+            },
+            Immediate::CallIndirect(typeidx, tableidx) => {
+                if tableidx.idx >= self.ctx.num_tables {
+                    return self.error(format!("unknown table {}", tableidx.idx));
+                }
+                match self.ctx.functype(*typeidx) {
+                    Some(functype) => Ok(functype),
+                    None => self.error(format!("unknown type {}", typeidx.idx)),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Checks the immediates that refer to memories or data segments
+    fn check_memory_immediate(
+        &self,
+        opcode: OpCode,
+        immediate: &Immediate,
+    ) -> Result<(), DecodeError> {
+        let (memidxs, dataidx): (&[MemIdx], Option<u32>) = match immediate {
+            Immediate::MemArg(memarg) => {
+                if let Some(natural) =
+                    natural_alignment(opcode).filter(|natural| memarg.align > *natural)
+                {
+                    return self.error(format!(
+                        "alignment must not be larger than natural ({})",
+                        1u32 << natural
+                    ));
+                }
+                (&[MemIdx { idx: 0 }], None)
+            }
+            Immediate::Mem(memidx) => (std::slice::from_ref(memidx), None),
+            Immediate::DataMem(dataidx, memidx) => (std::slice::from_ref(memidx), Some(*dataidx)),
+            Immediate::Data(dataidx) => (&[], Some(*dataidx)),
+            Immediate::MemMem(dest, src) => {
+                if src.idx != dest.idx {
+                    return self.error("memory.copy between different memories".to_owned());
+                }
+                (std::slice::from_ref(dest), None)
+            }
+            _ => (&[], None),
+        };
+        if let Some(memidx) = memidxs
+            .iter()
+            .find(|memidx| memidx.idx >= self.ctx.num_mems)
+        {
+            return self.error(format!("unknown memory {}", memidx.idx));
+        }
+        if let Some(dataidx) = dataidx.filter(|dataidx| *dataidx >= self.ctx.num_data) {
+            return self.error(format!("unknown data segment {}", dataidx));
+        }
+        Ok(())
+    }
+
+    fn local(&self, localidx: LocalIdx) -> Result<ValType, DecodeError> {
+        match self.locals.get(localidx.idx as usize) {
+            Some(valtype) => Ok(*valtype),
+            None => self.error(format!("unknown local {}", localidx.idx)),
+        }
+    }
+
+    fn global(&self, globalidx: GlobalIdx) -> Result<GlobalType, DecodeError> {
+        match self.ctx.globals.get(globalidx.idx as usize) {
+            Some(global_type) => Ok(*global_type),
+            None => self.error(format!("unknown global {}", globalidx.idx)),
+        }
+    }
+
+    /**
+     * Type-checks a function body (as serialized by the CodeBuilder).
+     */
+    fn validate(mut self, body: &[u8]) -> Result<(), DecodeError> {
+        let mut reader = Reader::new(body);
+        let locals = reader.read_locals()?;
+        self.locals.extend(locals);
+        let results: &'a [ValType] = &self.functype.result_types;
+        self.push_ctrl(OpCode::End, EMPTY, results);
+        while !self.ctrls.is_empty() {
+            self.offset = reader.pos();
+            if reader.is_empty() {
+                return self.error("missing end of the function".to_owned());
+            }
+            let instr = reader.read_instr()?;
+            self.validate_instr(&instr)?;
+        }
+        if !reader.is_empty() {
+            return reader
+                .error("unexpected instructions after the end of the function".to_owned());
+        }
+        Ok(())
+    }
+
+    fn validate_instr(&mut self, instr: &Instr) -> Result<(), DecodeError> {
+        match instr.opcode {
+            OpCode::Unreachable => self.set_unreachable(),
+            OpCode::Nop => {}
+            OpCode::Block | OpCode::Loop | OpCode::If => {
+                let (start_types, end_types) = match &instr.immediate {
+                    Immediate::BlockType(blocktype) => self.block_types(blocktype)?,
+                    _ => unreachable!(),
+                };
+                if instr.opcode == OpCode::If {
+                    self.pop_val(Some(ValType::I32))?;
+                }
+                self.pop_vals(start_types)?;
+                self.push_ctrl(instr.opcode, start_types, end_types);
+            }
+            OpCode::Else => {
+                let frame = self.pop_ctrl()?;
+                if frame.opcode != OpCode::If {
+                    return self.error("else without if".to_owned());
+                }
+                self.push_ctrl(OpCode::Else, frame.start_types, frame.end_types);
+            }
+            OpCode::End => {
+                let frame = self.pop_ctrl()?;
+                if frame.opcode == OpCode::If && frame.start_types != frame.end_types {
+                    return self.error(
+                        "if without else must have the same param and result types".to_owned(),
+                    );
+                }
+                self.push_vals(frame.end_types);
+            }
+            OpCode::Br => {
+                let label_types = match instr.immediate {
+                    Immediate::Label(labelidx) => self.label_types(labelidx)?,
+                    _ => unreachable!(),
+                };
+                self.pop_vals(label_types)?;
+                self.set_unreachable();
+            }
+            OpCode::BrIf => {
+                let label_types = match instr.immediate {
+                    Immediate::Label(labelidx) => self.label_types(labelidx)?,
+                    _ => unreachable!(),
+                };
+                self.pop_val(Some(ValType::I32))?;
+                self.pop_vals(label_types)?;
+                self.push_vals(label_types);
+            }
+            OpCode::BrTable => {
+                let (labelidxs, default_labelidx) = match &instr.immediate {
+                    Immediate::LabelTable(labelidxs, default_labelidx) => {
+                        (labelidxs, *default_labelidx)
+                    }
+                    _ => unreachable!(),
+                };
+                self.pop_val(Some(ValType::I32))?;
+                let default_types = self.label_types(default_labelidx)?;
+                for labelidx in labelidxs.iter() {
+                    let label_types = self.label_types(*labelidx)?;
+                    if label_types.len() != default_types.len() {
+                        return self.error("br_table targets have different arities".to_owned());
+                    }
+                    // check the values without consuming them, since they are needed for the other targets
+                    let vals = self.pop_vals(label_types)?;
+                    self.vals.extend(vals);
+                }
+                self.pop_vals(default_types)?;
+                self.set_unreachable();
+            }
+            OpCode::Return => {
+                let results: &'a [ValType] = &self.functype.result_types;
+                self.pop_vals(results)?;
+                self.set_unreachable();
+            }
+            OpCode::Call | OpCode::CallIndirect => {
+                let functype = self.call_types(&instr.immediate)?;
+                if instr.opcode == OpCode::CallIndirect {
+                    self.pop_val(Some(ValType::I32))?;
+                }
+                self.pop_vals(&functype.param_types)?;
+                self.push_vals(&functype.result_types);
+            }
+            OpCode::ReturnCall | OpCode::ReturnCallIndirect => {
+                let functype = self.call_types(&instr.immediate)?;
+                if functype.result_types != self.functype.result_types {
+                    return self
+                        .error("tail call to a function with different result types".to_owned());
+                }
+                if instr.opcode == OpCode::ReturnCallIndirect {
+                    self.pop_val(Some(ValType::I32))?;
+                }
+                self.pop_vals(&functype.param_types)?;
+                self.set_unreachable();
+            }
+            OpCode::Drop => {
+                self.pop_val(None)?;
+            }
+            OpCode::Select => {
+                self.pop_val(Some(ValType::I32))?;
+                let first = self.pop_val(None)?;
+                let second = self.pop_val(first)?;
+                self.push_val(second);
+            }
+            OpCode::LocalGet | OpCode::LocalSet | OpCode::LocalTee => {
+                let valtype = match instr.immediate {
+                    Immediate::Local(localidx) => self.local(localidx)?,
+                    _ => unreachable!(),
+                };
+                if instr.opcode != OpCode::LocalGet {
+                    self.pop_val(Some(valtype))?;
+                }
+                if instr.opcode != OpCode::LocalSet {
+                    self.push_val(Some(valtype));
+                }
+            }
+            OpCode::GlobalGet | OpCode::GlobalSet => {
+                let global_type = match instr.immediate {
+                    Immediate::Global(globalidx) => self.global(globalidx)?,
+                    _ => unreachable!(),
+                };
+                if instr.opcode == OpCode::GlobalGet {
+                    self.push_val(Some(global_type.val_type));
+                } else {
+                    if global_type.mutability != Mut::Var {
+                        return self.error("global.set of an immutable global".to_owned());
+                    }
+                    self.pop_val(Some(global_type.val_type))?;
+                }
+            }
+            opcode => {
+                let (params, results) = simple_signature(opcode).unwrap();
+                self.check_memory_immediate(opcode, &instr.immediate)?;
+                self.pop_vals(params)?;
+                self.push_vals(results);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Returns the param and result types of instructions whose types do not depend on their immediates or on the operand stack
+fn simple_signature(opcode: OpCode) -> Option<(&'static [ValType], &'static [ValType])> {
+    // explicitly list out all possibilities, so that we do not forget to handle new instructions
+    match opcode {
+        OpCode::Unreachable
+        | OpCode::Nop
+        | OpCode::Block
+        | OpCode::Loop
+        | OpCode::If
+        | OpCode::Else
+        | OpCode::End
+        | OpCode::Br
+        | OpCode::BrIf
+        | OpCode::BrTable
+        | OpCode::Return
+        | OpCode::Call
+        | OpCode::CallIndirect
+        | OpCode::ReturnCall
+        | OpCode::ReturnCallIndirect
+        | OpCode::Drop
+        | OpCode::Select
+        | OpCode::LocalGet
+        | OpCode::LocalSet
+        | OpCode::LocalTee
+        | OpCode::GlobalGet
+        | OpCode::GlobalSet => None,
+        OpCode::I32Load
+        | OpCode::I32Load8S
+        | OpCode::I32Load8U
+        | OpCode::I32Load16S
+        | OpCode::I32Load16U => Some((I32, I32)),
+        OpCode::I64Load
+        | OpCode::I64Load8S
+        | OpCode::I64Load8U
+        | OpCode::I64Load16S
+        | OpCode::I64Load16U
+        | OpCode::I64Load32S
+        | OpCode::I64Load32U => Some((I32, I64)),
+        OpCode::F32Load => Some((I32, F32)),
+        OpCode::F64Load => Some((I32, F64)),
+        OpCode::I32Store | OpCode::I32Store8 | OpCode::I32Store16 => Some((I32_I32, EMPTY)),
+        OpCode::I64Store | OpCode::I64Store8 | OpCode::I64Store16 | OpCode::I64Store32 => {
+            Some((I32_I64, EMPTY))
+        }
+        OpCode::F32Store => Some((I32_F32, EMPTY)),
+        OpCode::F64Store => Some((I32_F64, EMPTY)),
+        OpCode::MemorySize => Some((EMPTY, I32)),
+        OpCode::MemoryGrow => Some((I32, I32)),
+        OpCode::MemoryInit | OpCode::MemoryCopy | OpCode::MemoryFill => Some((I32_I32_I32, EMPTY)),
+        OpCode::DataDrop => Some((EMPTY, EMPTY)),
+        OpCode::I32Const => Some((EMPTY, I32)),
+        OpCode::I64Const => Some((EMPTY, I64)),
+        OpCode::F32Const => Some((EMPTY, F32)),
+        OpCode::F64Const => Some((EMPTY, F64)),
+        OpCode::I32Eqz => Some((I32, I32)),
+        OpCode::I32Eq
+        | OpCode::I32Ne
+        | OpCode::I32LtS
+        | OpCode::I32LtU
+        | OpCode::I32GtS
+        | OpCode::I32GtU
+        | OpCode::I32LeS
+        | OpCode::I32LeU
+        | OpCode::I32GeS
+        | OpCode::I32GeU => Some((I32_I32, I32)),
+        OpCode::I64Eqz => Some((I64, I32)),
+        OpCode::I64Eq
+        | OpCode::I64Ne
+        | OpCode::I64LtS
+        | OpCode::I64LtU
+        | OpCode::I64GtS
+        | OpCode::I64GtU
+        | OpCode::I64LeS
+        | OpCode::I64LeU
+        | OpCode::I64GeS
+        | OpCode::I64GeU => Some((I64_I64, I32)),
+        OpCode::F32Eq
+        | OpCode::F32Ne
+        | OpCode::F32Lt
+        | OpCode::F32Gt
+        | OpCode::F32Le
+        | OpCode::F32Ge => Some((F32_F32, I32)),
+        OpCode::F64Eq
+        | OpCode::F64Ne
+        | OpCode::F64Lt
+        | OpCode::F64Gt
+        | OpCode::F64Le
+        | OpCode::F64Ge => Some((F64_F64, I32)),
+        OpCode::I32Clz | OpCode::I32Ctz | OpCode::I32Popcnt => Some((I32, I32)),
+        OpCode::I32Add
+        | OpCode::I32Sub
+        | OpCode::I32Mul
+        | OpCode::I32DivS
+        | OpCode::I32DivU
+        | OpCode::I32RemS
+        | OpCode::I32RemU
+        | OpCode::I32And
+        | OpCode::I32Or
+        | OpCode::I32Xor
+        | OpCode::I32Shl
+        | OpCode::I32ShrS
+        | OpCode::I32ShrU
+        | OpCode::I32Rotl
+        | OpCode::I32Rotr => Some((I32_I32, I32)),
+        OpCode::I64Clz | OpCode::I64Ctz | OpCode::I64Popcnt => Some((I64, I64)),
+        OpCode::I64Add
+        | OpCode::I64Sub
+        | OpCode::I64Mul
+        | OpCode::I64DivS
+        | OpCode::I64DivU
+        | OpCode::I64RemS
+        | OpCode::I64RemU
+        | OpCode::I64And
+        | OpCode::I64Or
+        | OpCode::I64Xor
+        | OpCode::I64Shl
+        | OpCode::I64ShrS
+        | OpCode::I64ShrU
+        | OpCode::I64Rotl
+        | OpCode::I64Rotr => Some((I64_I64, I64)),
+        OpCode::F32Abs
+        | OpCode::F32Neg
+        | OpCode::F32Ceil
+        | OpCode::F32Floor
+        | OpCode::F32Trunc
+        | OpCode::F32Nearest
+        | OpCode::F32Sqrt => Some((F32, F32)),
+        OpCode::F32Add
+        | OpCode::F32Sub
+        | OpCode::F32Mul
+        | OpCode::F32Div
+        | OpCode::F32Min
+        | OpCode::F32Max
+        | OpCode::F32Copysign => Some((F32_F32, F32)),
+        OpCode::F64Abs
+        | OpCode::F64Neg
+        | OpCode::F64Ceil
+        | OpCode::F64Floor
+        | OpCode::F64Trunc
+        | OpCode::F64Nearest
+        | OpCode::F64Sqrt => Some((F64, F64)),
+        OpCode::F64Add
+        | OpCode::F64Sub
+        | OpCode::F64Mul
+        | OpCode::F64Div
+        | OpCode::F64Min
+        | OpCode::F64Max
+        | OpCode::F64Copysign => Some((F64_F64, F64)),
+        OpCode::I32WrapI64 => Some((I64, I32)),
+        OpCode::I32TruncF32S | OpCode::I32TruncF32U | OpCode::I32ReinterpretF32 => Some((F32, I32)),
+        OpCode::I32TruncF64S | OpCode::I32TruncF64U => Some((F64, I32)),
+        OpCode::I64ExtendI32S | OpCode::I64ExtendI32U => Some((I32, I64)),
+        OpCode::I64TruncF32S | OpCode::I64TruncF32U => Some((F32, I64)),
+        OpCode::I64TruncF64S | OpCode::I64TruncF64U | OpCode::I64ReinterpretF64 => Some((F64, I64)),
+        OpCode::F32ConvertI32S | OpCode::F32ConvertI32U | OpCode::F32ReinterpretI32 => {
+            Some((I32, F32))
+        }
+        OpCode::F32ConvertI64S | OpCode::F32ConvertI64U => Some((I64, F32)),
+        OpCode::F32DemoteF64 => Some((F64, F32)),
+        OpCode::F64ConvertI32S | OpCode::F64ConvertI32U => Some((I32, F64)),
+        OpCode::F64ConvertI64S | OpCode::F64ConvertI64U | OpCode::F64ReinterpretI64 => {
+            Some((I64, F64))
+        }
+        OpCode::F64PromoteF32 => Some((F32, F64)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Makes a module with one function of type [i32] -> [i32] with the given body
+    fn make_module<F: FnOnce(&mut ExprBuilder)>(make_body: F) -> WasmModule {
+        let mut module = WasmModule::new_builder().build();
+        let functype = FuncType::new(Box::new([ValType::I32]), Box::new([ValType::I32]));
+        let (_, funcidx) = module.register_func(&functype);
+        let mut code_builder = CodeBuilder::new(functype);
+        {
+            let (_, expr_builder) = code_builder.split();
+            make_body(expr_builder);
+        }
+        module.commit_func(funcidx, code_builder);
+        module
+    }
+
+    #[test]
+    fn valid_func() {
+        let module = make_module(|expr_builder| {
+            expr_builder.block(&[ValType::I32]);
+            expr_builder.local_get(LocalIdx { idx: 0 });
+            expr_builder.local_get(LocalIdx { idx: 0 });
+            expr_builder.br_if(0);
+            expr_builder.drop();
+            expr_builder.unreachable();
+            expr_builder.end();
+            expr_builder.end();
+        });
+        module.validate().unwrap();
+    }
+
+    #[test]
+    fn invalid_funcs() {
+        let module = make_module(|expr_builder| {
+            expr_builder.f64_const(1.0);
+            expr_builder.end();
+        });
+        assert_eq!(
+            module.validate().unwrap_err().to_string(),
+            "func 0: at byte 10: expected i32 but got f64"
+        );
+        let module = make_module(|expr_builder| {
+            expr_builder.local_get(LocalIdx { idx: 0 });
+            expr_builder.br(1);
+            expr_builder.end();
+        });
+        assert!(module.validate().is_err());
+        let module = make_module(|expr_builder| {
+            expr_builder.local_get(LocalIdx { idx: 0 });
+            expr_builder.i32_load(MemArg::new4(0)); // there is no memory
+            expr_builder.end();
+        });
+        assert!(module.validate().is_err());
+        let module = make_module(|expr_builder| {
+            expr_builder.local_get(LocalIdx { idx: 0 });
+            expr_builder.local_get(LocalIdx { idx: 0 });
+            expr_builder.end();
+        });
+        assert!(module.validate().is_err());
+    }
+}
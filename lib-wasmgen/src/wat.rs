@@ -0,0 +1,496 @@
+/**
+ * Prints a WasmModule in the WebAssembly text format (with `Display`), for debugging and for tests.
+ * The output is similar to that of `wasm2wat`: one instruction per line (not folded), with the index of each item in a comment.
+ * Functions named in the name section get an identifier (e.g. `$main`), which is also used by the `call` instructions.
+ * Custom sections (other than the name section) are listed in comments at the end.
+ */
+use super::instr::*;
+use super::*;
+use std::collections::HashSet;
+use std::fmt;
+
+impl fmt::Display for WasmModule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let func_ids = make_func_ids(&self.name_section);
+        let types: &[FuncType] = self.type_section.content.vec();
+        writeln!(f, "(module")?;
+        for (i, functype) in types.iter().enumerate() {
+            write!(f, "  (type (;{};) (func", i)?;
+            write_functype(f, functype)?;
+            writeln!(f, "))")?;
+        }
+        let mut num_imported_funcs: u32 = 0;
+        let mut num_imported_tables: u32 = 0;
+        let mut num_imported_mems: u32 = 0;
+        let mut num_imported_globals: u32 = 0;
+        for import in &self.import_section.content {
+            write!(f, "  (import ")?;
+            write_string(f, import.module_name.as_bytes())?;
+            write!(f, " ")?;
+            write_string(f, import.entity_name.as_bytes())?;
+            match import.desc {
+                ImportDesc::Func(typeidx) => {
+                    write!(f, " (func")?;
+                    write_func_id(f, &func_ids, num_imported_funcs)?;
+                    write!(f, " (;{};) (type {})", num_imported_funcs, typeidx.idx)?;
+                    num_imported_funcs += 1;
+                }
+                ImportDesc::Table(table_type) => {
+                    write!(f, " (table (;{};)", num_imported_tables)?;
+                    write_table_type(f, &table_type)?;
+                    num_imported_tables += 1;
+                }
+                ImportDesc::Mem(mem_type) => {
+                    write!(f, " (memory (;{};)", num_imported_mems)?;
+                    write_limits(f, &mem_type.limits)?;
+                    num_imported_mems += 1;
+                }
+                ImportDesc::Global(global_type) => {
+                    write!(f, " (global (;{};) ", num_imported_globals)?;
+                    write_global_type(f, &global_type)?;
+                    num_imported_globals += 1;
+                }
+            }
+            writeln!(f, "))")?;
+        }
+        for (i, (typeidx, code)) in self
+            .func_section
+            .content
+            .iter()
+            .zip(self.code_section.content.iter())
+            .enumerate()
+        {
+            let funcidx = self.func_section.idx_offset + i as u32;
+            write!(f, "  (func")?;
+            write_func_id(f, &func_ids, funcidx)?;
+            write!(f, " (;{};) (type {})", funcidx, typeidx.idx)?;
+            if let Some(functype) = types.get(typeidx.idx as usize) {
+                write_functype(f, functype)?;
+            }
+            writeln!(f)?;
+            match &code.func {
+                Some(bytes) => write_func_body(f, bytes, &func_ids)?,
+                None => writeln!(f, "    ;; not committed")?,
+            }
+            writeln!(f, "  )")?;
+        }
+        for (i, table) in self.table_section.content.iter().enumerate() {
+            write!(
+                f,
+                "  (table (;{};)",
+                self.table_section.idx_offset + i as u32
+            )?;
+            write_table_type(f, &table.table_type)?;
+            writeln!(f, ")")?;
+        }
+        for (i, mem) in self.mem_section.content.iter().enumerate() {
+            write!(
+                f,
+                "  (memory (;{};)",
+                self.mem_section.idx_offset + i as u32
+            )?;
+            write_limits(f, &mem.mem_type.limits)?;
+            writeln!(f, ")")?;
+        }
+        for (i, global) in self.global_section.content.iter().enumerate() {
+            write!(
+                f,
+                "  (global (;{};) ",
+                self.global_section.idx_offset + i as u32
+            )?;
+            write_global_type(f, &global.global_type)?;
+            write_const_expr(f, &global.init_expr, &func_ids)?;
+            writeln!(f, ")")?;
+        }
+        for export in &self.export_section.content {
+            write!(f, "  (export ")?;
+            write_string(f, export.entity_name.as_bytes())?;
+            match export.desc {
+                ExportDesc::Func(funcidx) => {
+                    write!(f, " (func ")?;
+                    write_func_ref(f, &func_ids, funcidx)?;
+                }
+                ExportDesc::Table(tableidx) => write!(f, " (table {}", tableidx.idx)?,
+                ExportDesc::Mem(memidx) => write!(f, " (memory {}", memidx.idx)?,
+                ExportDesc::Global(globalidx) => write!(f, " (global {}", globalidx.idx)?,
+            }
+            writeln!(f, "))")?;
+        }
+        if let Some(funcidx) = self.start_section.start {
+            write!(f, "  (start ")?;
+            write_func_ref(f, &func_ids, funcidx)?;
+            writeln!(f, ")")?;
+        }
+        for (i, elem) in self.elem_section.content.iter().enumerate() {
+            write!(f, "  (elem (;{};)", i)?;
+            if elem.table_idx.idx != 0 {
+                write!(f, " (table {})", elem.table_idx.idx)?;
+            }
+            write_const_expr(f, &elem.offset, &func_ids)?;
+            write!(f, " func")?;
+            for funcidx in elem.content.iter() {
+                write!(f, " ")?;
+                write_func_ref(f, &func_ids, *funcidx)?;
+            }
+            writeln!(f, ")")?;
+        }
+        for (i, data) in self.data_section.content.iter().enumerate() {
+            write!(f, "  (data (;{};)", i)?;
+            if data.mem_idx.idx != 0 {
+                write!(f, " (memory {})", data.mem_idx.idx)?;
+            }
+            write_const_expr(f, &data.offset, &func_ids)?;
+            write!(f, " ")?;
+            write_string(f, &data.content)?;
+            writeln!(f, ")")?;
+        }
+        for custom_section in &self.custom_sections {
+            write!(f, "  ;; custom section ")?;
+            write_string(f, custom_section.name.as_bytes())?;
+            writeln!(f, " ({} bytes)", custom_section.content.len())?;
+        }
+        write!(f, ")")
+    }
+}
+
+/**
+ * Returns the identifier (without the `$`) of each named function.
+ * Characters that are not allowed in identifiers are replaced by `_`,
+ * and a function does not get an identifier if it would be the same as that of a function with a smaller index.
+ */
+fn make_func_ids(name_section: &NameSection) -> BTreeMap<u32, String> {
+    let mut used: HashSet<String> = HashSet::new();
+    name_section
+        .func_names
+        .iter()
+        .filter_map(|(funcidx, name)| {
+            let id: String = name
+                .chars()
+                .map(|c| if is_id_char(c) { c } else { '_' })
+                .collect();
+            if !id.is_empty() && used.insert(id.clone()) {
+                Some((*funcidx, id))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn is_id_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-./:<=>?@\\^_`|~".contains(c)
+}
+
+// Writes the identifier of the function (with a leading space), if it has one
+fn write_func_id(
+    f: &mut fmt::Formatter,
+    func_ids: &BTreeMap<u32, String>,
+    funcidx: u32,
+) -> fmt::Result {
+    match func_ids.get(&funcidx) {
+        Some(id) => write!(f, " ${}", id),
+        None => Ok(()),
+    }
+}
+
+// Writes a reference to the function, by identifier if it has one, or by index otherwise
+fn write_func_ref(
+    f: &mut fmt::Formatter,
+    func_ids: &BTreeMap<u32, String>,
+    funcidx: FuncIdx,
+) -> fmt::Result {
+    match func_ids.get(&funcidx.idx) {
+        Some(id) => write!(f, "${}", id),
+        None => write!(f, "{}", funcidx.idx),
+    }
+}
+
+fn valtype_name(valtype: ValType) -> &'static str {
+    match valtype {
+        ValType::I32 => "i32",
+        ValType::I64 => "i64",
+        ValType::F32 => "f32",
+        ValType::F64 => "f64",
+    }
+}
+
+// Writes the params and results (with a leading space for each group), e.g. ` (param i32 i32) (result i64)`
+fn write_functype(f: &mut fmt::Formatter, functype: &FuncType) -> fmt::Result {
+    write_valtypes(f, "param", &functype.param_types)?;
+    write_valtypes(f, "result", &functype.result_types)
+}
+
+fn write_valtypes(f: &mut fmt::Formatter, keyword: &str, valtypes: &[ValType]) -> fmt::Result {
+    if !valtypes.is_empty() {
+        write!(f, " ({}", keyword)?;
+        for valtype in valtypes {
+            write!(f, " {}", valtype_name(*valtype))?;
+        }
+        write!(f, ")")?;
+    }
+    Ok(())
+}
+
+fn write_limits(f: &mut fmt::Formatter, limits: &Limits) -> fmt::Result {
+    match limits {
+        Limits::Unbounded { min } => write!(f, " {}", min),
+        Limits::Bounded { min, max } => write!(f, " {} {}", min, max),
+    }
+}
+
+fn write_table_type(f: &mut fmt::Formatter, table_type: &TableType) -> fmt::Result {
+    write_limits(f, &table_type.limits)?;
+    match table_type.elem_type {
+        ElemType::FuncRef => write!(f, " funcref"),
+    }
+}
+
+fn write_global_type(f: &mut fmt::Formatter, global_type: &GlobalType) -> fmt::Result {
+    match global_type.mutability {
+        Mut::Const => write!(f, "{}", valtype_name(global_type.val_type)),
+        Mut::Var => write!(f, "(mut {})", valtype_name(global_type.val_type)),
+    }
+}
+
+// Writes a string literal, escaping everything that is not printable ASCII
+fn write_string(f: &mut fmt::Formatter, bytes: &[u8]) -> fmt::Result {
+    write!(f, "\"")?;
+    for byte in bytes {
+        match byte {
+            b'"' | b'\\' => write!(f, "\\{}", *byte as char)?,
+            0x20..=0x7E => write!(f, "{}", *byte as char)?,
+            _ => write!(f, "\\{:02x}", byte)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+// Writes a constant expression (e.g. the offset of a data segment) in folded form, with a leading space
+fn write_const_expr(
+    f: &mut fmt::Formatter,
+    expr: &Expr,
+    func_ids: &BTreeMap<u32, String>,
+) -> fmt::Result {
+    let mut reader = Reader::new(&expr.bytecode);
+    while !reader.is_empty() {
+        match reader.read_instr() {
+            Ok(Instr {
+                opcode: OpCode::End,
+                immediate: _,
+            }) => break,
+            Ok(instr) => {
+                write!(f, " (")?;
+                write_instr(f, &instr, func_ids)?;
+                write!(f, ")")?;
+            }
+            Err(e) => return write!(f, " (;invalid expression {};)", e),
+        }
+    }
+    Ok(())
+}
+
+// Writes the locals and instructions of a function body (as serialized by the CodeBuilder), one per line
+fn write_func_body(
+    f: &mut fmt::Formatter,
+    bytes: &[u8],
+    func_ids: &BTreeMap<u32, String>,
+) -> fmt::Result {
+    let mut reader = Reader::new(bytes);
+    match reader.read_locals() {
+        Ok(locals) => {
+            if !locals.is_empty() {
+                write!(f, "   ")?;
+                write_valtypes(f, "local", &locals)?;
+                writeln!(f)?;
+            }
+        }
+        Err(e) => return writeln!(f, "    ;; invalid locals {}", e),
+    }
+    let mut depth: usize = 1; // the function body itself is a block that is closed by the last `end`
+    while !reader.is_empty() {
+        let instr = match reader.read_instr() {
+            Ok(instr) => instr,
+            Err(e) => return writeln!(f, "    ;; invalid instruction {}", e),
+        };
+        let indent = match instr.opcode {
+            OpCode::End => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    // the `end` of the function body is implied by the closing parenthesis
+                    continue;
+                }
+                depth
+            }
+            OpCode::Else => depth - 1,
+            _ => depth,
+        };
+        write!(f, "{:width$}", "", width = 2 + indent * 2)?;
+        write_instr(f, &instr, func_ids)?;
+        writeln!(f)?;
+        if let OpCode::Block | OpCode::Loop | OpCode::If = instr.opcode {
+            depth += 1;
+        }
+    }
+    Ok(())
+}
+
+fn write_instr(
+    f: &mut fmt::Formatter,
+    instr: &Instr,
+    func_ids: &BTreeMap<u32, String>,
+) -> fmt::Result {
+    write!(f, "{}", instr.opcode.name())?;
+    match &instr.immediate {
+        Immediate::None => {}
+        Immediate::BlockType(BlockType::Empty) => {}
+        Immediate::BlockType(BlockType::Value(valtype)) => {
+            write!(f, " (result {})", valtype_name(*valtype))?
+        }
+        Immediate::BlockType(BlockType::TypeIdx(typeidx)) => write!(f, " (type {})", typeidx.idx)?,
+        Immediate::Label(labelidx) => write!(f, " {}", labelidx)?,
+        Immediate::LabelTable(labelidxs, default_labelidx) => {
+            for labelidx in labelidxs.iter() {
+                write!(f, " {}", labelidx)?;
+            }
+            write!(f, " {}", default_labelidx)?;
+        }
+        Immediate::Func(funcidx) => {
+            write!(f, " ")?;
+            write_func_ref(f, func_ids, *funcidx)?;
+        }
+        Immediate::CallIndirect(typeidx, tableidx) => {
+            if tableidx.idx != 0 {
+                write!(f, " {}", tableidx.idx)?;
+            }
+            write!(f, " (type {})", typeidx.idx)?;
+        }
+        Immediate::Local(localidx) => write!(f, " {}", localidx.idx)?,
+        Immediate::Global(globalidx) => write!(f, " {}", globalidx.idx)?,
+        Immediate::MemArg(memarg) => {
+            if memarg.offset != 0 {
+                write!(f, " offset={}", memarg.offset)?;
+            }
+            if Some(memarg.align) != natural_alignment(instr.opcode) {
+                write!(f, " align={}", 1u64 << memarg.align.min(63))?;
+            }
+        }
+        Immediate::Mem(memidx) => write_memidx(f, *memidx)?,
+        Immediate::DataMem(dataidx, memidx) => {
+            write_memidx(f, *memidx)?;
+            write!(f, " {}", dataidx)?;
+        }
+        Immediate::Data(dataidx) => write!(f, " {}", dataidx)?,
+        Immediate::MemMem(dest_memidx, src_memidx) => {
+            write_memidx(f, *dest_memidx)?;
+            write_memidx(f, *src_memidx)?;
+        }
+        Immediate::I32(val) => write!(f, " {}", val)?,
+        Immediate::I64(val) => write!(f, " {}", val)?,
+        Immediate::F32(val) => write_float(
+            f,
+            *val as f64,
+            val.is_nan(),
+            val.to_bits() as u64 & 0x7F_FFFF,
+            0x40_0000,
+        )?,
+        Immediate::F64(val) => write_float(
+            f,
+            *val,
+            val.is_nan(),
+            val.to_bits() & 0xF_FFFF_FFFF_FFFF,
+            0x8_0000_0000_0000,
+        )?,
+    }
+    Ok(())
+}
+
+// Writes a memory index (only used with the multi-memory proposal), with a leading space
+fn write_memidx(f: &mut fmt::Formatter, memidx: MemIdx) -> fmt::Result {
+    if memidx.idx != 0 {
+        write!(f, " {}", memidx.idx)?;
+    }
+    Ok(())
+}
+
+// Writes a float constant (with a leading space), so that it can be read back exactly
+fn write_float(
+    f: &mut fmt::Formatter,
+    val: f64,
+    is_nan: bool,
+    nan_payload: u64,
+    canonical_nan_payload: u64,
+) -> fmt::Result {
+    let sign = if val.is_sign_negative() { "-" } else { "" };
+    if is_nan {
+        if nan_payload == canonical_nan_payload {
+            write!(f, " {}nan", sign)
+        } else {
+            write!(f, " {}nan:0x{:x}", sign, nan_payload)
+        }
+    } else if val.is_infinite() {
+        write!(f, " {}inf", sign)
+    } else {
+        // Debug prints the shortest representation that converts back to the same value (and keeps the sign of zero)
+        write!(f, " {:?}", val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn print_module() {
+        let mut builder = WasmModule::new_builder();
+        let display_type = FuncType::new(Box::new([ValType::F64]), Box::new([]));
+        let display = builder.import_func("misc".to_owned(), "display".to_owned(), &display_type);
+        let mut module = builder.build();
+        let memidx = module.add_unbounded_memory(1);
+        module.add_data(memidx, 16, b"hi\"\n");
+        let globalidx = module.add_i32_global(Mut::Var, 16);
+        let functype = FuncType::new(Box::new([ValType::I32]), Box::new([ValType::I32]));
+        let (_, funcidx) = module.register_func(&functype);
+        let mut code_builder = CodeBuilder::new(functype);
+        {
+            let (locals_builder, expr_builder) = code_builder.split();
+            locals_builder.add(ValType::F64);
+            expr_builder.local_get(LocalIdx { idx: 0 });
+            expr_builder.if_(&[ValType::I32]);
+            expr_builder.f64_const(-0.5);
+            expr_builder.call(display);
+            expr_builder.i32_const(1);
+            expr_builder.else_();
+            expr_builder.global_get(globalidx);
+            expr_builder.i32_load(MemArg::new1(4));
+            expr_builder.end();
+            expr_builder.end();
+        }
+        module.commit_func(funcidx, code_builder);
+        module.set_func_name(funcidx, "main func".to_owned());
+        module.export_func(funcidx, "main".to_owned());
+        assert_eq!(
+            module.to_string(),
+            r#"(module
+  (type (;0;) (func (param f64)))
+  (type (;1;) (func (param i32) (result i32)))
+  (import "misc" "display" (func (;0;) (type 0)))
+  (func $main_func (;1;) (type 1) (param i32) (result i32)
+    (local f64)
+    local.get 0
+    if (result i32)
+      f64.const -0.5
+      call 0
+      i32.const 1
+    else
+      global.get 0
+      i32.load offset=4 align=1
+    end
+  )
+  (memory (;0;) 1)
+  (global (;0;) (mut i32) (i32.const 16))
+  (export "main" (func $main_func))
+  (data (;0;) (i32.const 16) "hi\"\0a")
+)"#
+        );
+    }
+}
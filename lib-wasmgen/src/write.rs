@@ -34,14 +34,19 @@ impl WasmModule {
     pub fn register_func(&mut self, functype: &FuncType) -> (TypeIdx, FuncIdx) {
         let typeidx = self.type_section.insert_copy(functype);
         let funcidx = self.func_section.push(typeidx);
-        self.code_section.push(Code { func: None });
+        self.code_section.push(Code {
+            func: None,
+            markers: Default::default(),
+        });
         (typeidx, funcidx)
     }
     // Commit a function that has been previously registered
     pub fn commit_func(&mut self, funcidx: FuncIdx, code_builder: CodeBuilder) {
-        let (_functype, bytes) = code_builder.build();
-        self.code_section.content[self.func_section.plain_index_without_offset(funcidx) as usize]
-            .func = Some(bytes);
+        let (_functype, bytes, markers) = code_builder.build();
+        let code = &mut self.code_section.content
+            [self.func_section.plain_index_without_offset(funcidx) as usize];
+        code.func = Some(bytes);
+        code.markers = markers;
     }
     // Export a function so that the environment (i.e. JavaScript) can call it
     pub fn export_func(&mut self, funcidx: FuncIdx, exported_name: String) {
@@ -106,6 +111,48 @@ impl WasmModule {
     pub fn add_data(&mut self, memidx: MemIdx, offset: u32, content: &[u8]) {
         self.data_section.add(memidx, offset, content);
     }
+    // Set the name of a function (which may be an import) in the name section, replacing any previous name
+    pub fn set_func_name(&mut self, funcidx: FuncIdx, name: String) {
+        self.name_section.func_names.insert(funcidx.idx, name);
+    }
+    // Set the name of a local (including params) of a function in the name section, replacing any previous name
+    pub fn set_local_name(&mut self, funcidx: FuncIdx, localidx: LocalIdx, name: String) {
+        self.name_section
+            .local_names
+            .entry(funcidx.idx)
+            .or_default()
+            .insert(localidx.idx, name);
+    }
+    // Get the names of all the named functions (including imports) from the name section, in increasing order of funcidx
+    pub fn func_names(&self) -> impl Iterator<Item = (FuncIdx, &str)> {
+        self.name_section
+            .func_names
+            .iter()
+            .map(|(idx, name)| (FuncIdx { idx: *idx }, name.as_str()))
+    }
+    // Get the names of all the named locals (including params) of a function from the name section, in increasing order of localidx
+    pub fn local_names(&self, funcidx: FuncIdx) -> impl Iterator<Item = (LocalIdx, &str)> {
+        self.name_section
+            .local_names
+            .get(&funcidx.idx)
+            .into_iter()
+            .flatten()
+            .map(|(idx, name)| (LocalIdx { idx: *idx }, name.as_str()))
+    }
+    // Add a custom section with the given name and content
+    // Note: the name section should be set with set_func_name() and set_local_name() instead.
+    pub fn add_custom_section(&mut self, name: String, content: Box<[u8]>) {
+        self.custom_sections.push(CustomSection {
+            name: name,
+            content: content,
+        });
+    }
+    // Add the "sourceMappingURL" custom section, which tells debuggers where to find the source map for this module
+    pub fn set_source_mapping_url(&mut self, url: &str) {
+        let mut content = Vec::<u8>::new();
+        url.wasm_serialize(&mut content);
+        self.add_custom_section("sourceMappingURL".to_string(), content.into_boxed_slice());
+    }
 }
 
 impl WasmImportBuilderModule {
@@ -0,0 +1,336 @@
+/**
+ * Native command line driver for Sourceror.
+ * It compiles a validated ESTree (in JSON format) into a WebAssembly binary, without needing a JavaScript host.
+ *
+ * Usage: sourceror <input.json> [-o <output.wasm>] [-I <import_dir>] [--enable-<feature>...] [--trampoline]
+ *                  [-O0|-O1|-O2] [--enable-pass <pass>...] [--disable-pass <pass>...] [--inline-budget <cost>] [--opt-stats]
+ *                  [--source-map <output.wasm.map>] [--source-file <input.js>] [--wat]
+ *
+ * Features are WebAssembly proposals that the generated code may use: multi-value, bulk-memory, tail-call.
+ * `--trampoline` uses trampolines for tail calls if the tail-call proposal is not enabled.
+ *
+ * `-O<level>` picks the set of discretionary optimization passes (default: -O2), which can then be changed pass by pass.
+ * Passes: dead_code, propagate, inline, interproc.
+ * `--opt-stats` prints the time taken and the changes made by each optimization pass to stderr.
+ *
+ * `--source-map` writes a source map that maps the statements of the generated code back to the input program,
+ * and adds a "sourceMappingURL" section that refers to it (by file name, so it should be placed next to the output).
+ * Only statements of the input program itself are mapped; code from imported files and the prelude is not.
+ * `--source-file` names the Source file that the ESTree input was generated from, which is listed in the source map
+ * (defaults to the input file).
+ * `--wat` writes the module in the WebAssembly text format instead of the binary format (the default output is then <input.wat>).
+ *
+ * Imports are resolved as files in `import_dir` (which defaults to the directory containing the input file).
+ * Names in the standard library location are looked up relative to `import_dir` too, so a local copy of the
+ * standard library can be used for offline builds.
+ */
+use frontend_estree::fetcher::ChainFetcher;
+use frontend_estree::fetcher::FileSystemFetcher;
+use projstd::log;
+use std::cell::Cell;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process;
+
+const STDLIB_PREFIX: &str = frontend_estree::import_name_resolver::DEFAULT_STDLIB_ROOT;
+
+struct CliOptions {
+    input: PathBuf,
+    output: PathBuf,
+    import_dir: PathBuf,
+    backend_options: backend_wasm::Options,
+    opt_options: ir::opt::OptOptions,
+    opt_stats: bool,
+    source_map: Option<(PathBuf, String)>, // path to write the source map to, and its file name (for the "sourceMappingURL" section)
+    source_file: Option<String>,
+    wat: bool,
+}
+
+fn print_usage() {
+    eprintln!("Usage: sourceror <input.json> [-o <output.wasm>] [-I <import_dir>] [--enable-<feature>...] [--trampoline]");
+    eprintln!("Features: multi-value, bulk-memory, tail-call");
+    eprintln!("--trampoline: use trampolines for tail calls if tail-call is not enabled");
+    eprintln!("Optimization: [-O0|-O1|-O2] [--enable-pass <pass>...] [--disable-pass <pass>...] [--inline-budget <cost>] [--opt-stats]");
+    eprintln!("Passes: dead_code, propagate, inline, interproc");
+    eprintln!("Debugging: [--source-map <output.wasm.map>] [--source-file <input.js>] [--wat]");
+    eprintln!(
+        "--source-map: only maps the statements of the input file, not those of imported files"
+    );
+}
+
+fn parse_args() -> Result<CliOptions, String> {
+    let mut input: Option<PathBuf> = None;
+    let mut output: Option<PathBuf> = None;
+    let mut import_dir: Option<PathBuf> = None;
+    let mut backend_options = backend_wasm::Options::new();
+    let mut opt_level = ir::opt::OptLevel::default();
+    let mut pass_overrides: Vec<(ir::opt::Pass, bool)> = Vec::new();
+    let mut inlining_budget: usize = ir::opt::DEFAULT_INLINING_BUDGET;
+    let mut opt_stats = false;
+    let mut source_map: Option<PathBuf> = None;
+    let mut source_file: Option<String> = None;
+    let mut wat = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => {
+                output = Some(
+                    args.next()
+                        .ok_or_else(|| format!("Missing argument after \"{}\"", arg))?
+                        .into(),
+                );
+            }
+            "-I" | "--import-dir" => {
+                import_dir = Some(
+                    args.next()
+                        .ok_or_else(|| format!("Missing argument after \"{}\"", arg))?
+                        .into(),
+                );
+            }
+            "--enable-multi-value" => backend_options = backend_options.wasm_multi_value(true),
+            "--enable-bulk-memory" => backend_options = backend_options.wasm_bulk_memory(true),
+            "--enable-tail-call" => backend_options = backend_options.wasm_tail_call(true),
+            "--trampoline" => {
+                backend_options =
+                    backend_options.tail_call_strategy(backend_wasm::TailCallStrategy::Trampoline)
+            }
+            "-O0" => opt_level = ir::opt::OptLevel::O0,
+            "-O1" => opt_level = ir::opt::OptLevel::O1,
+            "-O2" => opt_level = ir::opt::OptLevel::O2,
+            "--enable-pass" | "--disable-pass" => {
+                let pass: ir::opt::Pass = args
+                    .next()
+                    .ok_or_else(|| format!("Missing argument after \"{}\"", arg))?
+                    .parse()?;
+                pass_overrides.push((pass, arg == "--enable-pass"));
+            }
+            "--inline-budget" => {
+                let budget = args
+                    .next()
+                    .ok_or_else(|| format!("Missing argument after \"{}\"", arg))?;
+                inlining_budget = budget
+                    .parse()
+                    .map_err(|_| format!("Invalid inlining budget \"{}\"", budget))?;
+            }
+            "--opt-stats" => opt_stats = true,
+            "--source-map" => {
+                source_map = Some(
+                    args.next()
+                        .ok_or_else(|| format!("Missing argument after \"{}\"", arg))?
+                        .into(),
+                );
+            }
+            "--source-file" => {
+                source_file = Some(
+                    args.next()
+                        .ok_or_else(|| format!("Missing argument after \"{}\"", arg))?,
+                );
+            }
+            "--wat" => wat = true,
+            "-h" | "--help" => {
+                print_usage();
+                process::exit(0);
+            }
+            _ if arg.starts_with('-') => {
+                return Err(format!("Unknown option \"{}\"", arg));
+            }
+            _ => {
+                if input.is_some() {
+                    return Err(format!("Unexpected argument \"{}\"", arg));
+                }
+                input = Some(arg.into());
+            }
+        }
+    }
+    let input: PathBuf = input.ok_or_else(|| "No input file".to_owned())?;
+    let output: PathBuf =
+        output.unwrap_or_else(|| input.with_extension(if wat { "wat" } else { "wasm" }));
+    let import_dir: PathBuf = import_dir.unwrap_or_else(|| {
+        input
+            .parent()
+            .map_or_else(|| PathBuf::from("."), |p| p.to_owned())
+    });
+    let source_map: Option<(PathBuf, String)> = match source_map {
+        Some(path) => {
+            let file_name: String = path
+                .file_name()
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid source map path \"{}\": it must name a file",
+                        path.to_string_lossy()
+                    )
+                })?
+                .to_string_lossy()
+                .into_owned();
+            Some((path, file_name))
+        }
+        None => None,
+    };
+    // the level is applied first, so that individual passes can be toggled regardless of the order of the arguments
+    let opt_options = pass_overrides.into_iter().fold(
+        ir::opt::OptOptions::new()
+            .level(opt_level)
+            .inlining_budget(inlining_budget),
+        |opt_options, (pass, enable)| opt_options.pass(pass, enable),
+    );
+    Ok(CliOptions {
+        input: input,
+        output: output,
+        import_dir: import_dir,
+        backend_options: backend_options,
+        opt_options: opt_options,
+        opt_stats: opt_stats,
+        source_map: source_map,
+        source_file: source_file,
+        wat: wat,
+    })
+}
+
+/**
+ * Logger that prints diagnostics to stderr in the usual `file:line:col: severity: message` format.
+ * It also remembers whether any error has been logged, so that we can set the exit code.
+ */
+struct CliLogger<'a> {
+    main_filename: &'a str,
+    has_error: Cell<bool>,
+}
+
+impl<'a, 'b> log::Logger for &'b CliLogger<'a> {
+    fn log<L: log::Loggable>(&self, content: L) {
+        let loc = content.location();
+        let severity = content.severity();
+        if severity == log::Severity::Error {
+            self.has_error.set(true);
+        }
+        let filename = loc.source.unwrap_or(self.main_filename);
+        if loc.start.line > 0 {
+            eprintln!(
+                "{}:{}:{}: {}: {}",
+                filename,
+                loc.start.line,
+                loc.start.column + 1,
+                severity_name(severity),
+                content.message()
+            );
+        } else {
+            eprintln!(
+                "{}: {}: {}",
+                filename,
+                severity_name(severity),
+                content.message()
+            );
+        }
+    }
+}
+
+fn severity_name(severity: log::Severity) -> &'static str {
+    match severity {
+        log::Severity::Hint => "hint",
+        log::Severity::Note => "note",
+        log::Severity::Info => "info",
+        log::Severity::Warning => "warning",
+        log::Severity::Error => "error",
+    }
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|msg| {
+        eprintln!("sourceror: {}", msg);
+        print_usage();
+        process::exit(2);
+    });
+
+    let input_name: String = options.input.to_string_lossy().into_owned();
+    let source_code = std::fs::read_to_string(&options.input).unwrap_or_else(|e| {
+        eprintln!("sourceror: cannot read \"{}\": {}", input_name, e);
+        process::exit(2);
+    });
+
+    // names in the standard library location are looked up in the import directory first
+    let fetcher: ChainFetcher<String> = ChainFetcher::new(vec![
+        Box::new(FileSystemFetcher::with_prefix(
+            options.import_dir.clone(),
+            STDLIB_PREFIX.to_owned(),
+        )),
+        Box::new(FileSystemFetcher::new(options.import_dir.clone())),
+    ]);
+    let backend_options = options.backend_options;
+    let opt_options = options.opt_options;
+    let opt_stats = options.opt_stats;
+    let wat = options.wat;
+    let source_map_path: Option<&Path> =
+        options.source_map.as_ref().map(|(path, _)| path.as_path());
+    let source_map_url: Option<&str> = options.source_map.as_ref().map(|(_, url)| url.as_str());
+    let source_file: &str = options
+        .source_file
+        .as_deref()
+        .unwrap_or(input_name.as_str());
+
+    let logger = CliLogger {
+        main_filename: input_name.as_str(),
+        has_error: Cell::new(false),
+    };
+
+    // the serialized module (in the binary or text format), and the source map (if requested)
+    let result: Result<(Vec<u8>, Option<String>), ()> = futures::executor::block_on(async {
+        use wasmgen::WasmSerialize;
+
+        let ir_program = frontend_estree::run_frontend(
+            source_code,
+            fetcher,
+            frontend_estree::import_name_resolver::StandardResolver::new(),
+            &logger,
+        )
+        .await?;
+        let ir_program_opt = if opt_stats {
+            let (ir_program_opt, stats) = ir::opt::optimize_with_stats(ir_program, &opt_options);
+            eprintln!("{}", stats);
+            ir_program_opt
+        } else {
+            ir::opt::optimize(ir_program, &opt_options)
+        };
+        let (wasm_module, source_map_json) = match source_map_url {
+            Some(url) => {
+                let (mut wasm_module, source_map) =
+                    backend_wasm::run_backend_with_source_map(&ir_program_opt, backend_options);
+                // the custom section goes after the code section, so it does not shift the code offsets in the source map
+                wasm_module.set_source_mapping_url(url);
+                (wasm_module, Some(source_map.to_json(&[source_file])))
+            }
+            None => (
+                backend_wasm::run_backend(&ir_program_opt, backend_options),
+                None,
+            ),
+        };
+        if wat {
+            return Ok((wasm_module.to_string().into_bytes(), source_map_json));
+        }
+        let mut receiver = std::vec::Vec::<u8>::new();
+        wasm_module.wasm_serialize(&mut receiver);
+        Ok((receiver, source_map_json))
+    });
+
+    match result {
+        Ok((output_content, source_map_json)) if !logger.has_error.get() => {
+            if let (Some(path), Some(json)) = (source_map_path, source_map_json) {
+                if let Err(e) = std::fs::write(path, json) {
+                    eprintln!(
+                        "sourceror: cannot write \"{}\": {}",
+                        path.to_string_lossy(),
+                        e
+                    );
+                    process::exit(2);
+                }
+            }
+            if let Err(e) = std::fs::write(&options.output, output_content) {
+                eprintln!(
+                    "sourceror: cannot write \"{}\": {}",
+                    options.output.to_string_lossy(),
+                    e
+                );
+                process::exit(2);
+            }
+        }
+        _ => process::exit(1),
+    }
+}
@@ -73,10 +73,16 @@ impl projstd::log::Logger for MainLogger {
  * The entry function for compilation.
  * `context` is an opaque value so that the host code can associate our calls to compiler_log() with the correct call to compile().
  * `source_code`: ESTree JSON representation of validated program
- * `import_spec`: list of imports following the import file format
+ * `features`: bitmask of WebAssembly proposals supported by the host (see `backend_wasm::FEATURE_*`), or 0 for plain WebAssembly 1.0
+ * `opt_level`: optimization level (0, 1 or 2, see `ir::opt::OptLevel`)
  */
 #[wasm_bindgen]
-pub async fn compile(context: i32, source_code: String) -> js_sys::Uint8Array {
+pub async fn compile(
+    context: i32,
+    source_code: String,
+    features: u32,
+    opt_level: u32,
+) -> js_sys::Uint8Array {
     // nice console errors in debug mode
     #[cfg(all(debug_assertions, target_arch = "wasm32"))]
     console_error_panic_hook::set_once();
@@ -87,13 +93,19 @@ pub async fn compile(context: i32, source_code: String) -> js_sys::Uint8Array {
         //let ir_imports = frontend_estree::parse_imports(import_spec, MainLogger::new(context))?;
         let ir_program = frontend_estree::run_frontend(
             source_code,
-            move |name| fetch_dep_proxy(context, name),
+            frontend_estree::fetcher::FnFetcher::new(move |name| fetch_dep_proxy(context, name)),
+            frontend_estree::import_name_resolver::StandardResolver::new(),
             MainLogger::new(context),
         )
         .await?;
-        let ir_program_opt = ir::opt::optimize_all(ir_program);
-        let wasm_module =
-            backend_wasm::run_backend(&ir_program_opt, backend_wasm::Options::default());
+        let ir_program_opt = ir::opt::optimize(
+            ir_program,
+            &ir::opt::OptOptions::new().level(ir::opt::OptLevel::from_number(opt_level)),
+        );
+        let wasm_module = backend_wasm::run_backend(
+            &ir_program_opt,
+            backend_wasm::Options::from_feature_flags(features),
+        );
         let mut receiver = std::vec::Vec::<u8>::new();
         wasm_module.wasm_serialize(&mut receiver);
         Ok(js_sys::Uint8Array::from(receiver.as_slice()))
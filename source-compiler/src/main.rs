@@ -69,21 +69,25 @@ fn main() {
         use wasmgen::WasmSerialize;
 
         //let ir_imports = frontend_estree::parse_imports(import_spec, MainLogger::new(context))?;
-        let ir_program =
-            frontend_estree::run_frontend(SOURCE_CODE.to_owned(), fetch_dep_proxy, MainLogger {})
-                .await?;
+        let ir_program = frontend_estree::run_frontend(
+            SOURCE_CODE.to_owned(),
+            frontend_estree::fetcher::FnFetcher::new(fetch_dep_proxy),
+            frontend_estree::import_name_resolver::StandardResolver::new(),
+            MainLogger {},
+        )
+        .await?;
         {
             use std::io::prelude::*;
             let mut file = std::fs::File::create("out-noop.ir").unwrap();
-            file.write_all(format!("{:#?}", &ir_program).as_bytes())
+            file.write_all(format!("{}", &ir_program).as_bytes())
                 .unwrap();
         }
         let ir_program_opt = ir::opt::optimize_all(ir_program);
-        println!("{:#?}", &ir_program_opt);
+        println!("{}", &ir_program_opt);
         {
             use std::io::prelude::*;
             let mut file = std::fs::File::create("out.ir").unwrap();
-            file.write_all(format!("{:#?}", &ir_program_opt).as_bytes())
+            file.write_all(format!("{}", &ir_program_opt).as_bytes())
                 .unwrap();
         }
         let wasm_module =
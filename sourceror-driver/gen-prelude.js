@@ -0,0 +1,22 @@
+const fs = require('fs');
+const acorn = require('acorn');
+
+// regenerates the ESTree of the prelude (../lib-frontend-estree/src/prelude.json) from prelude.source,
+// which must be done whenever prelude.source changes
+
+// the prelude declares several overloads with the same name, which acorn would reject
+// (same as SyntacticParser in src/index.ts)
+class SyntacticParser extends acorn.Parser {
+  raiseRecoverable(pos, message) {
+    if (message.includes("Identifier ") && message.includes(" has already been declared")) return;
+    acorn.Parser.prototype.raiseRecoverable.call(this, pos, message);
+  }
+}
+
+const source = fs.readFileSync("../lib-frontend-estree/src/prelude.source", "utf8");
+const program = SyntacticParser.parse(source, {
+  sourceType: 'module',
+  ecmaVersion: 6,
+  locations: true,
+});
+fs.writeFileSync("../lib-frontend-estree/src/prelude.json", JSON.stringify(program));
@@ -5,7 +5,7 @@ import { parse as slang_parse } from "js-slang/dist/parser/parser";
 import { Options as AcornOptions, Parser as AcornParser } from "acorn";
 import * as es from "estree";
 export { makePlatformImports } from "./platform";
-import { Transcoder } from "./transcoder";
+import { Transcoder, decodeAny } from "./transcoder";
 export { Transcoder };
 import { cachedGetFile } from "./cache";
 
@@ -15,6 +15,25 @@ export class CompileError extends Error {
   }
 }
 
+// WebAssembly proposals that the generated code may use.
+// The values must match the FEATURE_* constants in lib-backend-wasm.
+export interface WasmFeatures {
+  multiValue?: boolean;
+  bulkMemory?: boolean;
+  tailCall?: boolean;
+  // Use trampolines for proper tail calls if tailCall is not available
+  trampolineTailCall?: boolean;
+}
+
+function encodeWasmFeatures(features: WasmFeatures): number {
+  return (
+    (features.multiValue ? 1 << 0 : 0) |
+    (features.bulkMemory ? 1 << 1 : 0) |
+    (features.tailCall ? 1 << 2 : 0) |
+    (features.trampolineTailCall ? 1 << 3 : 0)
+  );
+}
+
 export class RuntimeError extends Error {
   constructor(message: string) {
     super(message);
@@ -64,7 +83,9 @@ function parseImport(code: string): es.Program | undefined {
 
 export async function compile(
   code: string,
-  context: Context
+  context: Context,
+  features: WasmFeatures = {},
+  optLevel: number = 2 // 0: no optimizations (useful to check for miscompiles), 1: local optimizations only, 2: all optimizations
 ): Promise<WebAssembly.Module> {
   //context.chapter = 3;
   let estree: es.Program | undefined = slang_parse(code, context);
@@ -113,7 +134,7 @@ export async function compile(
           }
         }));
   });
-  return Sourceror.compile(wasm_context, es_str)
+  return Sourceror.compile(wasm_context, es_str, encodeWasmFeatures(features), optLevel)
     .then((wasm_binary: Uint8Array) => {
       if (wasm_binary.byteLength > 0) {
         return WebAssembly.compile(wasm_binary).catch((err: string) => {
@@ -146,33 +167,22 @@ export async function compile(
     });
 }
 
-function read_js_result(linear_memory: WebAssembly.Memory): any {
+// `returned` is the value returned by main(), which is only used if multi-value returns are enabled
+// (otherwise the result is on the unprotected stack in linear memory)
+function read_js_result(linear_memory: WebAssembly.Memory, returned: any): any {
   const mem = new DataView(linear_memory.buffer);
-  const tag = mem.getUint32((1 << 20) - 12, true);
-  const data_offset = (1 << 20) - 8;
-  switch (tag) {
-    case 0:
-      return "(unassigned variable was returned)";
-    case 1:
-      return undefined;
-    case 2:
-      return mem.getFloat64(data_offset, true);
-    case 3:
-      return mem.getUint32(data_offset, true) !== 0;
-    case 4: {
-      const ptr = mem.getUint32(data_offset, true);
-      const len = mem.getUint32(ptr, true);
-      const decoder = new TextDecoder();
-      const res = decoder.decode(
-        new Uint8Array(linear_memory.buffer, ptr + 4, len)
-      );
-      return res;
-    }
-    case 5:
-      return "(function was returned)";
-    default:
-      return "(struct or invalid type (" + tag + ") was returned)";
+  let tag: number;
+  let data: DataView;
+  if (Array.isArray(returned)) {
+    // multi-value returns: [i64 data, i32 tag]
+    tag = returned[1];
+    data = new DataView(new ArrayBuffer(8));
+    data.setBigInt64(0, BigInt(returned[0]), true);
+  } else {
+    tag = mem.getUint32((1 << 20) - 12, true);
+    data = new DataView(linear_memory.buffer, (1 << 20) - 8, 8);
   }
+  return decodeAny(mem, tag, data);
 }
 
 function stringifySourcerorRuntimeErrorCode(code: number): [string, string] {
@@ -198,6 +208,10 @@ function stringifySourcerorRuntimeErrorCode(code: number): [string, string] {
       return ["If statement has a non-boolean condition", ""];
     case 0x1A:
       return ["Variable used before initialization", ""];
+    case 0x1B:
+      return ["Array access on a non-array", ""];
+    case 0x1C:
+      return ["Array index is not a non-negative integer", ""];
     default:
       return [
         "Unknown runtime error",
@@ -270,10 +284,19 @@ export async function run(
   return WebAssembly.instantiate(wasm_module, real_imports).then((instance) => {
     transcoder.setMem(new DataView((instance.exports.linear_memory as WebAssembly.Memory).buffer));
     transcoder.setAllocateStringFunc(instance.exports.allocate_string as (len: number) => number);
+    if (instance.exports.call_function) {
+      // the module has imports that take or return a function or any (see the note on host imports in lib-backend-wasm)
+      transcoder.setCallbackFuncs(
+        instance.exports.linear_memory as WebAssembly.Memory,
+        instance.exports.stack_pointer as () => number,
+        instance.exports.call_function as (handle: number, num_args: number) => any
+      );
+    }
     try {
-      (instance.exports.main as Function)();
+      const returned = (instance.exports.main as Function)();
       return read_js_result(
-        instance.exports.linear_memory as WebAssembly.Memory
+        instance.exports.linear_memory as WebAssembly.Memory,
+        returned
       );
     } catch (e) {
       if (e === propagationToken) {
@@ -3,9 +3,46 @@
  * This is needed because we can only transmit numbers directly through the FFI.
  */
 
+/**
+ * Decodes an Any (given its tag and the 8 bytes of its data) into a JS value.
+ * Values that have no JS equivalent are decoded into a description of the value.
+ */
+export function decodeAny(mem: DataView, tag: number, data: DataView): any {
+  switch (tag) {
+    case 0:
+      return "(unassigned variable was returned)";
+    case 1:
+      return undefined;
+    case 2:
+      return data.getFloat64(0, true);
+    case 3:
+      return data.getUint32(0, true) !== 0;
+    case 4: {
+      const ptr = data.getUint32(0, true);
+      const len = mem.getUint32(ptr, true);
+      const decoder = new TextDecoder();
+      const res = decoder.decode(
+        new Uint8Array(mem.buffer, ptr + 4, len)
+      );
+      return res;
+    }
+    case 5:
+      return "(function was returned)";
+    case 6:
+      return "(array was returned)";
+    case 7:
+      return null;
+    default:
+      return "(struct or invalid type (" + tag + ") was returned)";
+  }
+}
+
 export class Transcoder {
   mem: DataView;
   allocate_string: (len: number) => number;
+  linear_memory: WebAssembly.Memory | undefined;
+  stack_pointer: (() => number) | undefined;
+  call_function: ((handle: number, num_args: number) => any) | undefined;
   constructor() {}
   setMem(mem: DataView) {
     this.mem = mem;
@@ -13,6 +50,18 @@ export class Transcoder {
   setAllocateStringFunc(func: (len: number) => number) {
     this.allocate_string = func;
   }
+  /*
+   * The module only exports stack_pointer and call_function if some import takes or returns a function or any.
+   */
+  setCallbackFuncs(
+    linear_memory: WebAssembly.Memory,
+    stack_pointer: () => number,
+    call_function: (handle: number, num_args: number) => any
+  ) {
+    this.linear_memory = linear_memory;
+    this.stack_pointer = stack_pointer;
+    this.call_function = call_function;
+  }
   decodeString(handle: number): string {
     const len = this.mem.getUint32(handle, true);
     const decoder = new TextDecoder();
@@ -31,4 +80,39 @@ export class Transcoder {
     (new Uint8Array(this.mem.buffer, handle + 4, bytes.length)).set(bytes);
     return handle;
   }
-}
\ No newline at end of file
+  /*
+   * Calls the function referred to by a handle (received as a function or any param of an import) with the given args,
+   * which may be numbers, booleans or undefined, and returns the decoded result.
+   * Note: Like encodeString(), this might run the GC, so any string handles held by the caller become invalid.
+   */
+  callFunction(handle: number, args: (number | boolean | undefined)[]): any {
+    if (!this.linear_memory || !this.stack_pointer || !this.call_function) {
+      throw new Error("The module does not support calling back into it");
+    }
+    // the first arg is at [stackptr - 12, stackptr), the next at [stackptr - 24, stackptr - 12), and so on
+    const stackptr = this.stack_pointer();
+    let mem = new DataView(this.linear_memory.buffer);
+    args.forEach((arg, i) => {
+      const ptr = stackptr - 12 * (i + 1);
+      if (typeof arg === "number") {
+        mem.setUint32(ptr, 2, true);
+        mem.setFloat64(ptr + 4, arg, true);
+      } else if (typeof arg === "boolean") {
+        mem.setUint32(ptr, 3, true);
+        mem.setUint32(ptr + 4, arg ? 1 : 0, true);
+      } else {
+        mem.setUint32(ptr, 1, true);
+      }
+    });
+    const returned = this.call_function(handle, args.length);
+    // the memory might have grown during the call
+    mem = new DataView(this.linear_memory.buffer);
+    if (Array.isArray(returned)) {
+      // multi-value returns: [i64 data, i32 tag]
+      const data = new DataView(new ArrayBuffer(8));
+      data.setBigInt64(0, BigInt(returned[0]), true);
+      return decodeAny(mem, returned[1], data);
+    }
+    return decodeAny(mem, mem.getUint32(stackptr - 12, true), new DataView(mem.buffer, stackptr - 8, 8));
+  }
+}
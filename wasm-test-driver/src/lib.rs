@@ -15,6 +15,6 @@ Returns a valid wasm binary that should be executed by invoking "main" function.
 */
 #[wasm_bindgen]
 pub fn build_tests() {
-    let mut ctx = NormalContext::new(|binary| add_test(binary));
+    let mut ctx = NormalContext::new(|_name, binary| add_test(binary));
     backend_wasm::wasmtest(&mut ctx);
 }
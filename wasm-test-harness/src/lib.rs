@@ -23,7 +23,7 @@ extern "C" {
 pub trait TestContext {
     fn add_test<F: FnOnce(&mut CodeBuilder, &mut WasmModule, wasmgen::FuncIdx, &NormalTester)>(
         &mut self,
-        name_: &str,
+        name: &str,
         f: F,
     );
 }
@@ -33,7 +33,7 @@ pub trait Tester {
     fn i32_assert_eq(&self, scratch: &mut Scratch, expr_builder: &mut ExprBuilder);
 }
 
-pub struct NormalContext<A: Fn(Box<[u8]>) -> ()> {
+pub struct NormalContext<A: Fn(&str, Box<[u8]>) -> ()> {
     add_to_js: A,
 }
 
@@ -66,7 +66,7 @@ impl Tester for NormalTester {
     }
 }
 
-impl<A: Fn(Box<[u8]>) -> ()> NormalContext<A> {
+impl<A: Fn(&str, Box<[u8]>) -> ()> NormalContext<A> {
     pub fn new(add_to_js: A) -> NormalContext<A> {
         NormalContext {
             add_to_js: add_to_js,
@@ -74,7 +74,7 @@ impl<A: Fn(Box<[u8]>) -> ()> NormalContext<A> {
     }
 }
 
-impl<A: Fn(Box<[u8]>) -> ()> TestContext for NormalContext<A> {
+impl<A: Fn(&str, Box<[u8]>) -> ()> TestContext for NormalContext<A> {
     /*
     Adds a test.
     `f` should emit code that has net wasm stack [] -> [], even though the CodeBuilder might have a different signature.  This is because the test harness might return different bookkeeping information.
@@ -82,7 +82,7 @@ impl<A: Fn(Box<[u8]>) -> ()> TestContext for NormalContext<A> {
     */
     fn add_test<F: FnOnce(&mut CodeBuilder, &mut WasmModule, wasmgen::FuncIdx, &NormalTester)>(
         &mut self,
-        name_: &str,
+        name: &str,
         f: F,
     ) {
         let mut wasm_builder = WasmModule::new_builder();
@@ -146,6 +146,6 @@ impl<A: Fn(Box<[u8]>) -> ()> TestContext for NormalContext<A> {
 
         let mut receiver = std::vec::Vec::<u8>::new();
         wasm_module.wasm_serialize(&mut receiver);
-        (self.add_to_js)(receiver.into_boxed_slice());
+        (self.add_to_js)(name, receiver.into_boxed_slice());
     }
 }
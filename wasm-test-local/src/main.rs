@@ -1,13 +1,97 @@
-use backend_wasm;
+use wasmgen::interp::{Caller, Host, Instance, Trap, Value};
 use wasmgen::*;
 
 use wasm_test_harness::*;
 
-pub fn main() {
-    std::io::stdin().read_line(&mut String::new()).unwrap(); // wait so I can attach debugger to process
-    let mut ctx = NormalContext::new();
+/**
+ * Provides the imports of a test module (the same ones as static-wasmtest/index.js).
+ * Every import reports a failure, so it traps to stop the test.
+ */
+struct TestHost;
+
+impl Host for TestHost {
+    fn call_import(
+        &mut self,
+        module_name: &str,
+        entity_name: &str,
+        args: &[Value],
+        _caller: &mut Caller,
+    ) -> Result<Box<[Value]>, Trap> {
+        let message = match (module_name, entity_name, args) {
+            ("platform", "assert_fail", [Value::I32(lhs), Value::I32(rhs), Value::I32(count)]) => {
+                format!("assertion {} failed: {} == {}", count + 1, lhs, rhs)
+            }
+            ("platform", "test_fail", []) => "test failed".to_owned(),
+            ("core", "error", [Value::I32(code), ..]) => format!("error code {} raised", code),
+            _ => format!("unknown import \"{}\" \"{}\"", module_name, entity_name),
+        };
+        Err(Trap { message: message })
+    }
+}
+
+/**
+ * Runs one test module, and returns the number of assertions that passed.
+ */
+fn run_test(binary: &[u8]) -> Result<i32, String> {
+    let module = WasmModule::decode(binary).map_err(|e| format!("invalid binary: {}", e))?;
+    let mut instance = Instance::new(&module, &mut TestHost).map_err(|trap| trap.message)?;
+    match &*instance
+        .invoke("main", &[], &mut TestHost)
+        .map_err(|trap| trap.message)?
+    {
+        [Value::I32(assert_count)] => Ok(*assert_count),
+        _ => Err("main returned the wrong types".to_owned()),
+    }
+}
+
+/**
+ * Builds and runs all the tests in backend-wasm, and returns the result of each test (in order).
+ */
+fn run_tests() -> Vec<(String, Result<i32, String>)> {
+    let results = std::cell::RefCell::new(Vec::new());
+    let mut ctx = NormalContext::new(|name, binary| {
+        results
+            .borrow_mut()
+            .push((name.to_owned(), run_test(&binary)))
+    });
     backend_wasm::wasmtest(&mut ctx);
-    let module = ctx.build();
-    let mut receiver = std::vec::Vec::<u8>::new();
-    module.wasm_serialize(&mut receiver);
+    results.into_inner()
+}
+
+pub fn main() {
+    let results = run_tests();
+    let mut num_failed = 0;
+    for (name, result) in &results {
+        match result {
+            Ok(assert_count) => println!("PASS {} ({} assertions)", name, assert_count),
+            Err(message) => {
+                println!("FAIL {}: {}", name, message);
+                num_failed += 1;
+            }
+        }
+    }
+    println!(
+        "{} passed, {} failed",
+        results.len() - num_failed,
+        num_failed
+    );
+    if num_failed != 0 {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_tests_pass() {
+        let results = run_tests();
+        assert!(!results.is_empty());
+        for (name, result) in results {
+            if let Err(message) = result {
+                panic!("{}: {}", name, message);
+            }
+        }
+    }
 }